@@ -5,10 +5,13 @@ use http::{Request, StatusCode};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use structopt::StructOpt;
 use tokio::{fs::File, io::AsyncReadExt};
-use tracing::{error, info, trace_span};
+use tracing::{error, info};
 
-use h3::{error::ErrorLevel, quic::BidiStream, server::RequestStream};
-use h3_quinn::quinn::{self, crypto::rustls::QuicServerConfig};
+use h3::{quic::BidiStream, server::RequestStream};
+use h3_quinn::{
+    quinn::{self, crypto::rustls::QuicServerConfig},
+    server::Server,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "server")]
@@ -100,61 +103,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("listening on {}", opt.listen);
 
-    // handle incoming connections and requests
-
-    while let Some(new_conn) = endpoint.accept().await {
-        trace_span!("New connection being attempted");
+    // handle incoming connections and requests, until ctrl-c is pressed
 
+    let handler = move |req, stream| {
         let root = root.clone();
-
-        tokio::spawn(async move {
-            match new_conn.await {
-                Ok(conn) => {
-                    info!("new connection established");
-
-                    let mut h3_conn = h3::server::Connection::new(h3_quinn::Connection::new(conn))
-                        .await
-                        .unwrap();
-
-                    loop {
-                        match h3_conn.accept().await {
-                            Ok(Some((req, stream))) => {
-                                info!("new request: {:#?}", req);
-
-                                let root = root.clone();
-
-                                tokio::spawn(async {
-                                    if let Err(e) = handle_request(req, stream, root).await {
-                                        error!("handling request failed: {}", e);
-                                    }
-                                });
-                            }
-
-                            // indicating no more streams to be received
-                            Ok(None) => {
-                                break;
-                            }
-
-                            Err(err) => {
-                                error!("error on accept {}", err);
-                                match err.get_error_level() {
-                                    ErrorLevel::ConnectionError => break,
-                                    ErrorLevel::StreamError => continue,
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(err) => {
-                    error!("accepting connection failed: {:?}", err);
-                }
+        async move {
+            info!("new request: {:#?}", req);
+            if let Err(e) = handle_request(req, stream, root).await {
+                error!("handling request failed: {}", e);
             }
-        });
-    }
+        }
+    };
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("shutting down gracefully");
+    };
 
-    // shut down gracefully
-    // wait for connections to be closed before exiting
-    endpoint.wait_idle().await;
+    // waits for the connections to be closed before returning
+    Server::new(endpoint)
+        .serve_with_shutdown(handler, shutdown)
+        .await;
 
     Ok(())
 }
@@ -9,7 +9,9 @@ use h3::{
 use h3_datagram::quic_traits::{RecvDatagramExt, SendDatagramExt};
 use h3_quinn::quinn::{self, crypto::rustls::QuicServerConfig};
 use h3_webtransport::{
-    server::{self, WebTransportSession},
+    capsule::Capsule,
+    protocol,
+    server::{self, SessionManager, WebTransportSession},
     stream,
 };
 use http::Method;
@@ -120,7 +122,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .enable_webtransport(true)
                         .enable_connect(true)
                         .enable_datagram(true)
-                        .max_webtransport_sessions(1)
+                        .max_webtransport_sessions(16)
                         .send_grease(true)
                         .build(h3_quinn::Connection::new(conn))
                         .await
@@ -157,13 +159,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn handle_connection(mut conn: Connection<h3_quinn::Connection, Bytes>) -> Result<()> {
-    // 3. TODO: Conditionally, if the client indicated that this is a webtransport session, we should accept it here, else use regular h3.
-    // if this is a webtransport session, then h3 needs to stop handing the datagrams, bidirectional streams, and unidirectional streams and give them
-    // to the webtransport session.
+macro_rules! log_result {
+    ($expr:expr) => {
+        if let Err(err) = $expr {
+            tracing::error!("{err:?}");
+        }
+    };
+}
+
+async fn handle_connection(conn: Connection<h3_quinn::Connection, Bytes>) -> Result<()> {
+    // The session manager routes the datagrams, bidirectional streams, and unidirectional
+    // streams to their webtransport session, and hands back the other requests.
+    let manager = SessionManager::new(conn);
 
     loop {
-        match conn.accept().await {
+        match manager.accept().await {
             Ok(Some((req, stream))) => {
                 info!("new request: {:#?}", req);
 
@@ -172,14 +182,29 @@ async fn handle_connection(mut conn: Connection<h3_quinn::Connection, Bytes>) ->
                     &Method::CONNECT if ext.get::<Protocol>() == Some(&Protocol::WEB_TRANSPORT) => {
                         tracing::info!("Peer wants to initiate a webtransport session");
 
-                        tracing::info!("Handing over connection to WebTransport");
-                        let session = WebTransportSession::accept(req, stream, conn).await?;
-                        tracing::info!("Established webtransport session");
-                        // 4. Get datagrams, bidirectional streams, and unidirectional streams and wait for client requests here.
-                        // h3_conn needs to hand over the datagrams, bidirectional streams, and unidirectional streams to the webtransport session.
-                        handle_session_and_echo_all_inbound_messages(session).await?;
+                        // Pick the first protocol offered by the client, if any
+                        let mut response = http::Response::new(());
+                        if let Some(value) = protocol::available_protocols(&req)
+                            .first()
+                            .and_then(|p| protocol::protocol_header_value(p))
+                        {
+                            response.headers_mut().insert(protocol::WT_PROTOCOL, value);
+                        }
 
-                        return Ok(());
+                        let session = match manager.accept_session_with(req, stream, response).await
+                        {
+                            Ok(session) => session,
+                            Err(err) => {
+                                error!("Failed to accept webtransport session: {}", err);
+                                continue;
+                            }
+                        };
+                        tracing::info!("Established webtransport session");
+                        tokio::spawn(async move {
+                            log_result!(
+                                handle_session_and_echo_all_inbound_messages(session).await
+                            );
+                        });
                     }
                     _ => {
                         tracing::info!(?req, "Received request");
@@ -204,14 +229,6 @@ async fn handle_connection(mut conn: Connection<h3_quinn::Connection, Bytes>) ->
     Ok(())
 }
 
-macro_rules! log_result {
-    ($expr:expr) => {
-        if let Err(err) = $expr {
-            tracing::error!("{err:?}");
-        }
-    };
-}
-
 async fn echo_stream<T, R>(send: T, recv: R) -> anyhow::Result<()>
 where
     T: AsyncWrite,
@@ -288,7 +305,7 @@ where
         Unpin + AsyncRead + Send + Sync,
     C::SendStream: Send + Unpin,
     C::RecvStream: Send + Unpin,
-    C::BidiStream: Send + Unpin,
+    C::BidiStream: Send + Unpin + quic::BidiStream<Bytes>,
     stream::SendStream<C::SendStream, Bytes>: AsyncWrite,
     C::BidiStream: SendStreamUnframed<Bytes>,
     C::SendStream: SendStreamUnframed<Bytes>,
@@ -313,7 +330,7 @@ where
                     let mut resp = BytesMut::from(&b"Response: "[..]);
                     resp.put(datagram);
 
-                    session.send_datagram(resp.freeze())?;
+                    session.send_datagram_wait(resp.freeze()).await?;
                     tracing::info!("Finished sending datagram");
                 }
             }
@@ -329,6 +346,16 @@ where
                     tokio::spawn( async move { log_result!(echo_stream(send, recv).await); });
                 }
             }
+            capsule = session.recv_capsule() => {
+                match capsule? {
+                    Some(Capsule::CloseSession { code, reason }) => {
+                        tracing::info!("Session closed by the client: {code} {reason:?}");
+                        break;
+                    }
+                    Some(capsule) => tracing::info!("Received capsule {capsule:?}"),
+                    None => break,
+                }
+            }
             else => {
                 break
             }
@@ -56,6 +56,11 @@ where
         &self.payload
     }
 
+    /// Returns the size of the datagram in wire format
+    pub fn encoded_len(&self) -> usize {
+        (VarInt::from(self.stream_id) / 4).size() + self.payload.remaining()
+    }
+
     /// Encode the datagram to wire format
     pub fn encode<D: bytes::BufMut>(self, buf: &mut D) {
         (VarInt::from(self.stream_id) / 4).encode(buf);
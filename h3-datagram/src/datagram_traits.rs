@@ -2,12 +2,12 @@
 //! These traits are implemented for the client and server types in the `h3` crate.
 
 use bytes::Buf;
-use h3::{
-    quic::{self, StreamId},
-    Error,
-};
+use h3::quic::{self, StreamId};
 
-use crate::server::ReadDatagram;
+use crate::{
+    quic_traits::SendDatagramError,
+    server::{ReadDatagram, SendDatagram},
+};
 
 pub trait HandleDatagramsExt<C, B>
 where
@@ -15,7 +15,15 @@ where
     C: quic::Connection<B>,
 {
     /// Sends a datagram
-    fn send_datagram(&mut self, stream_id: StreamId, data: B) -> Result<(), Error>;
+    ///
+    /// Previously queued datagrams may be dropped to make room for this one.
+    fn send_datagram(&mut self, stream_id: StreamId, data: B) -> Result<(), SendDatagramError>;
+    /// Sends a datagram, waiting for room in the send buffer
+    fn send_datagram_wait(&mut self, stream_id: StreamId, data: B) -> SendDatagram<'_, C, B>;
+    /// Returns the maximum payload size of a datagram associated with `stream_id`
+    ///
+    /// Returns `None` if datagrams are not supported by the peer or locally disabled.
+    fn max_datagram_size(&self, stream_id: StreamId) -> Option<usize>;
     /// Reads an incoming datagram
     fn read_datagram(&mut self) -> ReadDatagram<C, B>;
 }
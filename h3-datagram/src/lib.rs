@@ -1,4 +1,5 @@
 pub mod datagram;
 pub mod datagram_traits;
 pub mod quic_traits;
+pub mod router;
 pub mod server;
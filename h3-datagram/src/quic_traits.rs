@@ -4,7 +4,7 @@
 //! QUIC implementation.
 
 use core::task;
-use std::{error::Error, task::Poll};
+use std::{error::Error, fmt, task::Poll};
 
 use bytes::Buf;
 
@@ -15,10 +15,28 @@ use crate::datagram::Datagram;
 /// See: <https://www.rfc-editor.org/rfc/rfc9297>
 pub trait SendDatagramExt<B: Buf> {
     /// The error type that can occur when sending a datagram
-    type Error: Into<Box<dyn Error>>;
+    type Error: Into<SendDatagramError>;
 
     /// Send a datagram
+    ///
+    /// Previously queued datagrams may be dropped to make room for this one.
     fn send_datagram(&mut self, data: Datagram<B>) -> Result<(), Self::Error>;
+
+    /// Queues a datagram to be sent once there is room in the send buffer
+    ///
+    /// [`SendDatagramExt::poll_send_datagram()`] must return `Ready` before another datagram
+    /// is queued.
+    fn queue_datagram(&mut self, data: Datagram<B>) -> Result<(), Self::Error>;
+
+    /// Polls until the queued datagram has been handed to the transport
+    ///
+    /// Returns `Ready` right away if no datagram is queued.
+    fn poll_send_datagram(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>>;
+
+    /// Returns the maximum size of a datagram, including its quarter stream ID
+    ///
+    /// Returns `None` if datagrams are not supported by the peer or locally disabled.
+    fn max_datagram_size(&self) -> Option<usize>;
 }
 
 /// Extends the `Connection` trait for receiving datagrams
@@ -36,3 +54,48 @@ pub trait RecvDatagramExt {
         cx: &mut task::Context<'_>,
     ) -> Poll<Result<Option<Self::Buf>, Self::Error>>;
 }
+
+/// Types of errors when sending a datagram
+#[derive(Debug)]
+pub enum SendDatagramError {
+    /// Datagrams are not supported by the peer
+    UnsupportedByPeer,
+    /// Datagrams are locally disabled
+    Disabled,
+    /// The datagram is larger than [`SendDatagramExt::max_datagram_size()`]
+    TooLarge,
+    /// A queued datagram is still waiting for room in the send buffer
+    NotReady,
+    /// The connection was lost
+    ConnectionLost(Box<dyn h3::quic::Error>),
+}
+
+impl fmt::Display for SendDatagramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendDatagramError::UnsupportedByPeer => write!(f, "datagrams not supported by peer"),
+            SendDatagramError::Disabled => write!(f, "datagram support disabled"),
+            SendDatagramError::TooLarge => write!(f, "datagram too large"),
+            SendDatagramError::NotReady => write!(f, "a datagram is already queued"),
+            SendDatagramError::ConnectionLost(_) => write!(f, "connection lost"),
+        }
+    }
+}
+
+impl Error for SendDatagramError {}
+
+impl h3::quic::Error for SendDatagramError {
+    fn is_timeout(&self) -> bool {
+        match self {
+            Self::ConnectionLost(err) => err.is_timeout(),
+            _ => false,
+        }
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        match self {
+            Self::ConnectionLost(err) => err.err_code(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Routes incoming datagrams to the request stream they belong to
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use bytes::{Buf, Bytes};
+use h3::{
+    quic::{self, StreamId},
+    server::{Connection, RequestStream},
+    Error,
+};
+use http::Request;
+
+use crate::{datagram::Datagram, quic_traits::RecvDatagramExt};
+
+/// Number of datagrams buffered per stream by default
+const DEFAULT_CAPACITY: usize = 32;
+
+/// Routes incoming datagrams by the request stream they are associated with
+///
+/// A [`DatagramReceiver`] is registered for each request stream expecting datagrams, such as
+/// an extended CONNECT handler. Datagrams for streams which are not registered are dropped,
+/// as well as datagrams arriving while the buffer of their stream is full.
+///
+/// Datagrams are read from the connection with [`DatagramRouter::accept()`], which accepts
+/// the requests at the same time, or with [`DatagramRouter::poll_route()`].
+pub struct DatagramRouter<D = Bytes> {
+    flows: Arc<Mutex<Flows<D>>>,
+    capacity: usize,
+}
+
+struct Flows<D> {
+    flows: HashMap<StreamId, Flow<D>>,
+    // Tells apart successive registrations of a stream
+    next_registration: u64,
+}
+
+struct Flow<D> {
+    registration: u64,
+    datagrams: VecDeque<D>,
+    waker: Option<Waker>,
+}
+
+impl<D> DatagramRouter<D> {
+    /// Creates a router buffering up to 32 datagrams per stream
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates a router buffering up to `capacity` datagrams per stream
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            flows: Arc::new(Mutex::new(Flows {
+                flows: HashMap::new(),
+                next_registration: 0,
+            })),
+            capacity,
+        }
+    }
+
+    /// Starts receiving the datagrams associated with a request stream
+    ///
+    /// Registering a stream again replaces the previous receiver, which does not get any
+    /// datagram anymore.
+    pub fn register(&self, stream_id: StreamId) -> DatagramReceiver<D> {
+        let mut flows = self.flows.lock().unwrap();
+        let registration = flows.next_registration;
+        flows.next_registration += 1;
+
+        if let Some(previous) = flows.flows.insert(
+            stream_id,
+            Flow {
+                registration,
+                datagrams: VecDeque::new(),
+                waker: None,
+            },
+        ) {
+            if let Some(waker) = previous.waker {
+                waker.wake();
+            }
+        }
+
+        DatagramReceiver {
+            stream_id,
+            registration,
+            flows: self.flows.clone(),
+        }
+    }
+
+    /// Hands a datagram to the receiver of its stream
+    ///
+    /// Returns `false` when the datagram was dropped.
+    pub fn route(&self, datagram: Datagram<D>) -> bool
+    where
+        D: Buf,
+    {
+        let mut flows = self.flows.lock().unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9297#section-2.1
+        //# If an HTTP/3 Datagram is received and its Quarter Stream ID field
+        //# maps to a stream that has not yet been created, the receiver SHALL
+        //# either drop that datagram silently or buffer it temporarily (on the
+        //# order of a round trip) while awaiting the creation of the
+        //# corresponding stream.
+        let Some(flow) = flows.flows.get_mut(&datagram.stream_id()) else {
+            return false;
+        };
+
+        if flow.datagrams.len() >= self.capacity {
+            return false;
+        }
+
+        flow.datagrams.push_back(datagram.into_payload());
+        if let Some(waker) = flow.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Reads and routes the datagrams available on the connection
+    ///
+    /// Returns `Ready` once the connection can't receive datagrams anymore.
+    pub fn poll_route<C, B>(
+        &self,
+        conn: &mut Connection<C, B>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>>
+    where
+        C: quic::Connection<B> + RecvDatagramExt<Buf = D>,
+        <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+        B: Buf,
+        D: Buf,
+    {
+        loop {
+            match conn.inner.conn.poll_accept_datagram(cx)? {
+                Poll::Ready(Some(datagram)) => {
+                    let datagram = Datagram::decode(datagram)?;
+                    self.route(datagram);
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Accepts an incoming request, routing the datagrams received in the meantime
+    ///
+    /// This is a replacement for [`Connection::accept()`], so the datagrams are read from the
+    /// connection while the accept loop runs. A malformed datagram closes the connection.
+    pub async fn accept<C, B>(
+        &self,
+        conn: &mut Connection<C, B>,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error>
+    where
+        C: quic::Connection<B> + RecvDatagramExt<Buf = D>,
+        <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+        B: Buf,
+        D: Buf,
+    {
+        conn.accept_with(|conn, cx| match self.poll_route(conn, cx) {
+            Poll::Ready(Err(err)) => Err(err),
+            Poll::Ready(Ok(())) | Poll::Pending => Ok(()),
+        })
+        .await
+    }
+}
+
+impl<D> Default for DatagramRouter<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> Clone for DatagramRouter<D> {
+    fn clone(&self) -> Self {
+        Self {
+            flows: self.flows.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// Receives the datagrams associated with a request stream
+///
+/// Created with [`DatagramRouter::register()`]. The stream is unregistered when this is
+/// dropped, and its datagrams are dropped from then on.
+pub struct DatagramReceiver<D = Bytes> {
+    stream_id: StreamId,
+    registration: u64,
+    flows: Arc<Mutex<Flows<D>>>,
+}
+
+impl<D> DatagramReceiver<D> {
+    /// Polls for the next datagram payload
+    ///
+    /// Returns `None` if the stream was registered again since.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<D>> {
+        let mut flows = self.flows.lock().unwrap();
+        match flows.flows.get_mut(&self.stream_id) {
+            Some(flow) if flow.registration == self.registration => {
+                match flow.datagrams.pop_front() {
+                    Some(datagram) => Poll::Ready(Some(datagram)),
+                    None => {
+                        flow.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            }
+            _ => Poll::Ready(None),
+        }
+    }
+
+    /// Receives the next datagram payload
+    ///
+    /// Returns `None` if the stream was registered again since.
+    pub async fn recv(&mut self) -> Option<D> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Takes the next datagram payload, if one is already buffered
+    pub fn try_recv(&mut self) -> Option<D> {
+        let mut flows = self.flows.lock().unwrap();
+        match flows.flows.get_mut(&self.stream_id) {
+            Some(flow) if flow.registration == self.registration => flow.datagrams.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Returns the request stream the datagrams are associated with
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+impl<D> Drop for DatagramReceiver<D> {
+    fn drop(&mut self) {
+        let mut flows = self.flows.lock().unwrap();
+        if matches!(flows.flows.get(&self.stream_id), Some(flow) if flow.registration == self.registration)
+        {
+            flows.flows.remove(&self.stream_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(stream_id: u64, payload: &'static str) -> Datagram {
+        Datagram::new(StreamId::try_from(stream_id).unwrap(), Bytes::from(payload))
+    }
+
+    #[test]
+    fn routes_by_stream() {
+        let router = DatagramRouter::new();
+        let mut first = router.register(StreamId::try_from(0).unwrap());
+        let mut second = router.register(StreamId::try_from(4).unwrap());
+
+        assert!(router.route(datagram(4, "second")));
+        assert!(router.route(datagram(0, "first")));
+
+        assert_eq!(first.try_recv(), Some(Bytes::from("first")));
+        assert_eq!(second.try_recv(), Some(Bytes::from("second")));
+        assert_eq!(first.try_recv(), None);
+    }
+
+    #[test]
+    fn drops_unknown_stream() {
+        let router = DatagramRouter::new();
+        let _receiver = router.register(StreamId::try_from(0).unwrap());
+
+        assert!(!router.route(datagram(8, "unknown")));
+    }
+
+    #[test]
+    fn drops_when_full() {
+        let router = DatagramRouter::with_capacity(1);
+        let mut receiver = router.register(StreamId::try_from(0).unwrap());
+
+        assert!(router.route(datagram(0, "kept")));
+        assert!(!router.route(datagram(0, "dropped")));
+        assert_eq!(receiver.try_recv(), Some(Bytes::from("kept")));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn drops_after_receiver_dropped() {
+        let router = DatagramRouter::new();
+        let receiver = router.register(StreamId::try_from(0).unwrap());
+        drop(receiver);
+
+        assert!(!router.route(datagram(0, "closed")));
+    }
+
+    #[test]
+    fn register_again_replaces_receiver() {
+        let router = DatagramRouter::new();
+        let mut old = router.register(StreamId::try_from(0).unwrap());
+        let mut new = router.register(StreamId::try_from(0).unwrap());
+
+        assert!(router.route(datagram(0, "new")));
+        assert_eq!(old.try_recv(), None);
+        drop(old);
+        assert_eq!(new.try_recv(), Some(Bytes::from("new")));
+    }
+}
@@ -8,6 +8,7 @@ use std::{
 
 use bytes::Buf;
 use h3::{
+    proto::varint::VarInt,
     quic::{self, StreamId},
     server::Connection,
     Error,
@@ -17,7 +18,7 @@ use pin_project_lite::pin_project;
 use crate::{
     datagram::Datagram,
     datagram_traits::HandleDatagramsExt,
-    quic_traits::{self, RecvDatagramExt, SendDatagramExt},
+    quic_traits::{self, RecvDatagramExt, SendDatagramError, SendDatagramExt},
 };
 
 impl<B, C> HandleDatagramsExt<C, B> for Connection<C, B>
@@ -25,14 +26,30 @@ where
     B: Buf,
     C: quic::Connection<B> + SendDatagramExt<B> + RecvDatagramExt,
     <C as quic_traits::RecvDatagramExt>::Error: h3::quic::Error + 'static,
-    <C as quic_traits::SendDatagramExt<B>>::Error: h3::quic::Error + 'static,
 {
     /// Sends a datagram
-    fn send_datagram(&mut self, stream_id: StreamId, data: B) -> Result<(), Error> {
+    fn send_datagram(&mut self, stream_id: StreamId, data: B) -> Result<(), SendDatagramError> {
         self.inner
             .conn
-            .send_datagram(Datagram::new(stream_id, data))?;
-        Ok(())
+            .send_datagram(Datagram::new(stream_id, data))
+            .map_err(Into::into)
+    }
+
+    /// Sends a datagram, waiting for room in the send buffer
+    fn send_datagram_wait(&mut self, stream_id: StreamId, data: B) -> SendDatagram<'_, C, B> {
+        SendDatagram {
+            conn: self,
+            datagram: Some(Datagram::new(stream_id, data)),
+        }
+    }
+
+    /// Returns the maximum payload size of a datagram associated with `stream_id`
+    fn max_datagram_size(&self, stream_id: StreamId) -> Option<usize> {
+        let quarter_stream_id = VarInt::from(stream_id) / 4;
+        self.inner
+            .conn
+            .max_datagram_size()?
+            .checked_sub(quarter_stream_id.size())
     }
 
     /// Reads an incoming datagram
@@ -48,12 +65,12 @@ pin_project! {
     /// Future for [`Connection::read_datagram`]
     pub struct ReadDatagram<'a, C, B>
     where
-            C: quic::Connection<B>,
-            B: Buf,
-        {
-            conn: &'a mut Connection<C, B>,
-            _marker: PhantomData<B>,
-        }
+        C: quic::Connection<B>,
+        B: Buf,
+    {
+        conn: &'a mut Connection<C, B>,
+        _marker: PhantomData<B>,
+    }
 }
 
 impl<'a, C, B> Future for ReadDatagram<'a, C, B>
@@ -71,3 +88,43 @@ where
         }
     }
 }
+
+pin_project! {
+    /// Future for [`HandleDatagramsExt::send_datagram_wait()`]
+    pub struct SendDatagram<'a, C, B>
+    where
+        C: quic::Connection<B>,
+        B: Buf,
+    {
+        conn: &'a mut Connection<C, B>,
+        datagram: Option<Datagram<B>>,
+    }
+}
+
+impl<'a, C, B> Future for SendDatagram<'a, C, B>
+where
+    C: quic::Connection<B> + SendDatagramExt<B>,
+    B: Buf,
+{
+    type Output = Result<(), SendDatagramError>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let conn = &mut this.conn.inner.conn;
+
+        if let Some(datagram) = this.datagram.take() {
+            // A datagram queued by a previous call goes first
+            if conn
+                .poll_send_datagram(cx)
+                .map_err(Into::into)?
+                .is_pending()
+            {
+                *this.datagram = Some(datagram);
+                return Poll::Pending;
+            }
+            conn.queue_datagram(datagram).map_err(Into::into)?;
+        }
+
+        conn.poll_send_datagram(cx).map_err(Into::into)
+    }
+}
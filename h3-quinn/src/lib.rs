@@ -7,7 +7,7 @@ use std::{
     convert::TryInto,
     fmt::{self, Display},
     future::Future,
-    pin::Pin,
+    pin::{pin, Pin},
     sync::Arc,
     task::{self, Poll},
 };
@@ -20,6 +20,8 @@ use futures::{
     Stream, StreamExt,
 };
 
+#[cfg(feature = "datagram")]
+pub use h3_datagram::quic_traits::SendDatagramError;
 #[cfg(feature = "datagram")]
 use h3_datagram::{datagram::Datagram, quic_traits};
 
@@ -27,14 +29,19 @@ pub use quinn::{self, AcceptBi, AcceptUni, Endpoint, OpenBi, OpenUni, VarInt, Wr
 use quinn::{ApplicationClose, ClosedStream, ReadDatagram};
 
 use h3::quic::{self, Error, StreamId, WriteBuf};
-use tokio_util::sync::ReusableBoxFuture;
 
 #[cfg(feature = "tracing")]
 use tracing::instrument;
 
+#[cfg(feature = "server")]
+pub mod server;
+
 /// BoxStream with Sync trait
 type BoxStreamSync<'a, T> = Pin<Box<dyn Stream<Item = T> + Sync + Send + 'a>>;
 
+/// BoxFuture with Sync trait
+type BoxFutureSync<'a, T> = Pin<Box<dyn Future<Output = T> + Sync + Send + 'a>>;
+
 /// A QUIC connection backed by Quinn
 ///
 /// Implements a [`quic::Connection`] backed by a [`quinn::Connection`].
@@ -45,6 +52,10 @@ pub struct Connection {
     incoming_uni: BoxStreamSync<'static, <AcceptUni<'static> as Future>::Output>,
     opening_uni: Option<BoxStreamSync<'static, <OpenUni<'static> as Future>::Output>>,
     datagrams: BoxStreamSync<'static, <ReadDatagram<'static> as Future>::Output>,
+    // Datagram waiting for room in the send buffer
+    sending_datagram: Option<BoxFutureSync<'static, Result<(), quinn::SendDatagramError>>>,
+    // Reused to encode outgoing datagrams
+    datagram_buf: BytesMut,
 }
 
 impl Connection {
@@ -63,6 +74,8 @@ impl Connection {
             datagrams: Box::pin(stream::unfold(conn, |conn| async {
                 Some((conn.read_datagram().await, conn))
             })),
+            sending_datagram: None,
+            datagram_buf: BytesMut::new(),
         }
     }
 }
@@ -102,58 +115,6 @@ impl From<quinn::ConnectionError> for ConnectionError {
     }
 }
 
-/// Types of errors when sending a datagram.
-#[derive(Debug)]
-pub enum SendDatagramError {
-    /// Datagrams are not supported by the peer
-    UnsupportedByPeer,
-    /// Datagrams are locally disabled
-    Disabled,
-    /// The datagram was too large to be sent.
-    TooLarge,
-    /// Network error
-    ConnectionLost(Box<dyn Error>),
-}
-
-impl fmt::Display for SendDatagramError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SendDatagramError::UnsupportedByPeer => write!(f, "datagrams not supported by peer"),
-            SendDatagramError::Disabled => write!(f, "datagram support disabled"),
-            SendDatagramError::TooLarge => write!(f, "datagram too large"),
-            SendDatagramError::ConnectionLost(_) => write!(f, "connection lost"),
-        }
-    }
-}
-
-impl std::error::Error for SendDatagramError {}
-
-impl Error for SendDatagramError {
-    fn is_timeout(&self) -> bool {
-        false
-    }
-
-    fn err_code(&self) -> Option<u64> {
-        match self {
-            Self::ConnectionLost(err) => err.err_code(),
-            _ => None,
-        }
-    }
-}
-
-impl From<quinn::SendDatagramError> for SendDatagramError {
-    fn from(value: quinn::SendDatagramError) -> Self {
-        match value {
-            quinn::SendDatagramError::UnsupportedByPeer => Self::UnsupportedByPeer,
-            quinn::SendDatagramError::Disabled => Self::Disabled,
-            quinn::SendDatagramError::TooLarge => Self::TooLarge,
-            quinn::SendDatagramError::ConnectionLost(err) => {
-                Self::ConnectionLost(ConnectionError::from(err).into())
-            }
-        }
-    }
-}
-
 impl<B> quic::Connection<B> for Connection
 where
     B: Buf,
@@ -249,6 +210,34 @@ where
     }
 }
 
+#[cfg(feature = "datagram")]
+fn send_datagram_error(err: quinn::SendDatagramError) -> SendDatagramError {
+    match err {
+        quinn::SendDatagramError::UnsupportedByPeer => SendDatagramError::UnsupportedByPeer,
+        quinn::SendDatagramError::Disabled => SendDatagramError::Disabled,
+        quinn::SendDatagramError::TooLarge => SendDatagramError::TooLarge,
+        quinn::SendDatagramError::ConnectionLost(err) => {
+            SendDatagramError::ConnectionLost(Box::new(ConnectionError::from(err)))
+        }
+    }
+}
+
+#[cfg(feature = "datagram")]
+impl Connection {
+    /// Encodes a datagram, reusing the same buffer when the previous datagrams are sent
+    fn encode_datagram<B: Buf>(&mut self, data: Datagram<B>) -> Result<Bytes, SendDatagramError> {
+        let len = data.encoded_len();
+        match self.conn.max_datagram_size() {
+            Some(max) if len > max => return Err(SendDatagramError::TooLarge),
+            _ => (),
+        }
+
+        self.datagram_buf.reserve(len);
+        data.encode(&mut self.datagram_buf);
+        Ok(self.datagram_buf.split().freeze())
+    }
+}
+
 #[cfg(feature = "datagram")]
 impl<B> quic_traits::SendDatagramExt<B> for Connection
 where
@@ -258,13 +247,42 @@ where
 
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     fn send_datagram(&mut self, data: Datagram<B>) -> Result<(), SendDatagramError> {
-        // TODO investigate static buffer from known max datagram size
-        let mut buf = BytesMut::new();
-        data.encode(&mut buf);
-        self.conn.send_datagram(buf.freeze())?;
+        let buf = self.encode_datagram(data)?;
+        self.conn.send_datagram(buf).map_err(send_datagram_error)?;
 
         Ok(())
     }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    fn queue_datagram(&mut self, data: Datagram<B>) -> Result<(), SendDatagramError> {
+        if self.sending_datagram.is_some() {
+            return Err(SendDatagramError::NotReady);
+        }
+
+        let buf = self.encode_datagram(data)?;
+        let conn = self.conn.clone();
+        self.sending_datagram = Some(Box::pin(async move { conn.send_datagram_wait(buf).await }));
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    fn poll_send_datagram(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), SendDatagramError>> {
+        if let Some(sending) = self.sending_datagram.as_mut() {
+            let res = ready!(sending.as_mut().poll(cx));
+            self.sending_datagram = None;
+            res.map_err(send_datagram_error)?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn max_datagram_size(&self) -> Option<usize> {
+        self.conn.max_datagram_size()
+    }
 }
 
 #[cfg(feature = "datagram")]
@@ -441,29 +459,29 @@ where
     }
 }
 
+impl<B> quic::SendStreamPriority<B> for BidiStream<B>
+where
+    B: Buf,
+{
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error> {
+        self.send.set_priority(priority)
+    }
+
+    fn priority(&self) -> Result<i32, Self::Error> {
+        self.send.priority()
+    }
+}
+
 /// Quinn-backed receive stream
 ///
 /// Implements a [`quic::RecvStream`] backed by a [`quinn::RecvStream`].
 pub struct RecvStream {
-    stream: Option<quinn::RecvStream>,
-    read_chunk_fut: ReadChunkFuture,
+    stream: quinn::RecvStream,
 }
 
-type ReadChunkFuture = ReusableBoxFuture<
-    'static,
-    (
-        quinn::RecvStream,
-        Result<Option<quinn::Chunk>, quinn::ReadError>,
-    ),
->;
-
 impl RecvStream {
     fn new(stream: quinn::RecvStream) -> Self {
-        Self {
-            stream: Some(stream),
-            // Should only allocate once the first time it's used
-            read_chunk_fut: ReusableBoxFuture::new(async { unreachable!() }),
-        }
+        Self { stream }
     }
 }
 
@@ -476,36 +494,22 @@ impl quic::RecvStream for RecvStream {
         &mut self,
         cx: &mut task::Context<'_>,
     ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
-        if let Some(mut stream) = self.stream.take() {
-            self.read_chunk_fut.set(async move {
-                let chunk = stream.read_chunk(usize::MAX, true).await;
-                (stream, chunk)
-            })
-        };
-
-        let (stream, chunk) = ready!(self.read_chunk_fut.poll(cx));
-        self.stream = Some(stream);
+        // `read_chunk` is cancel-safe, so the stream stays available to `stop_sending` while
+        // a read is pending
+        let chunk = ready!(pin!(self.stream.read_chunk(usize::MAX, true)).poll(cx));
         Poll::Ready(Ok(chunk?.map(|c| c.bytes)))
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     fn stop_sending(&mut self, error_code: u64) {
         self.stream
-            .as_mut()
-            .unwrap()
             .stop(VarInt::from_u64(error_code).expect("invalid error_code"))
             .ok();
     }
 
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     fn recv_id(&self) -> StreamId {
-        self.stream
-            .as_ref()
-            .unwrap()
-            .id()
-            .0
-            .try_into()
-            .expect("invalid stream id")
+        self.stream.id().0.try_into().expect("invalid stream id")
     }
 }
 
@@ -595,8 +599,8 @@ where
         if let Some(ref mut data) = self.writing {
             while data.has_remaining() {
                 let stream = Pin::new(&mut self.stream);
-                let written = ready!(stream.poll_write(cx, data.chunk()))
-                    .map_err(|err| SendStreamError::Write(err))?;
+                let written =
+                    ready!(stream.poll_write(cx, data.chunk())).map_err(SendStreamError::Write)?;
                 data.advance(written);
             }
         }
@@ -632,6 +636,21 @@ where
     }
 }
 
+impl<B> quic::SendStreamPriority<B> for SendStream<B>
+where
+    B: Buf,
+{
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error> {
+        self.stream.set_priority(priority).map_err(Into::into)
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    fn priority(&self) -> Result<i32, Self::Error> {
+        self.stream.priority().map_err(Into::into)
+    }
+}
+
 impl<B> quic::SendStreamUnframed<B> for SendStream<B>
 where
     B: Buf,
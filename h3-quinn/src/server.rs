@@ -0,0 +1,249 @@
+//! HTTP/3 server running on a quinn endpoint
+//!
+//! [`Server`] accepts the QUIC connections of an [`Endpoint`], runs an HTTP/3 connection on
+//! each of them and hands every request to a handler, on its own task.
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use h3::{
+    error::ErrorLevel,
+    rt::{Sleep, Timer},
+    server::{self, RequestStream},
+};
+use http::Request;
+use quinn::Endpoint;
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+
+use crate::{BidiStream, Connection};
+
+/// Time given to the requests in flight to complete when the server shuts down, by default
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A [`Timer`] for the tokio runtime
+///
+/// [`Server`] sets it on the builders which do not have a timer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioTimer;
+
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Sleep>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// An HTTP/3 server, accepting the connections of a quinn [`Endpoint`]
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # async fn doc(endpoint: h3_quinn::Endpoint, shutdown: tokio::sync::oneshot::Receiver<()>) {
+/// use h3_quinn::server::Server;
+///
+/// Server::new(endpoint)
+///     .max_connections(1000)
+///     .serve_with_shutdown(
+///         |_request, mut stream| async move {
+///             let response = http::Response::new(());
+///             let _ = stream.send_response(response).await;
+///             let _ = stream.finish().await;
+///         },
+///         async {
+///             let _ = shutdown.await;
+///         },
+///     )
+///     .await;
+/// # }
+/// ```
+pub struct Server {
+    endpoint: Endpoint,
+    builder: Arc<server::Builder>,
+    max_connections: Option<usize>,
+    shutdown_timeout: Duration,
+}
+
+impl Server {
+    /// Creates a server with the default HTTP/3 settings
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self::with_builder(endpoint, server::builder())
+    }
+
+    /// Creates a server whose HTTP/3 connections are built by `builder`
+    ///
+    /// The timeouts and the graceful shutdown need a timer, [`TokioTimer`] is used when
+    /// `builder` does not have one.
+    pub fn with_builder(endpoint: Endpoint, mut builder: server::Builder) -> Self {
+        if !builder.has_timer() {
+            builder.timer(TokioTimer);
+        }
+        Self {
+            endpoint,
+            builder: Arc::new(builder),
+            max_connections: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+
+    /// Limits the number of concurrent connections
+    ///
+    /// Connections attempted beyond the limit are refused.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the time given to the requests in flight to complete when shutting down
+    ///
+    /// The connections are closed once it elapses. 30 seconds by default.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Serves the requests with `handler`, until the endpoint is closed
+    pub async fn serve<H, F>(self, handler: H)
+    where
+        H: Fn(Request<()>, RequestStream<BidiStream<Bytes>, Bytes>) -> F,
+        H: Clone + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.serve_with_shutdown(handler, std::future::pending())
+            .await
+    }
+
+    /// Serves the requests with `handler`, until `signal` resolves
+    ///
+    /// When `signal` resolves, new connections are refused and every connection is shut down
+    /// gracefully: the clients are sent a GOAWAY frame, the requests they sent before
+    /// receiving it are still served during the graceful shutdown interval of the builder,
+    /// and the requests in flight are then given the shutdown timeout to complete. Returns
+    /// once all the connections are closed.
+    pub async fn serve_with_shutdown<H, F, S>(self, handler: H, signal: S)
+    where
+        H: Fn(Request<()>, RequestStream<BidiStream<Bytes>, Bytes>) -> F,
+        H: Clone + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+        S: Future<Output = ()>,
+    {
+        let (shutdown_send, shutdown_recv) = watch::channel(false);
+        let permits = self
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let mut connections = JoinSet::new();
+        let mut signal = std::pin::pin!(signal);
+
+        loop {
+            let incoming = tokio::select! {
+                incoming = self.endpoint.accept() => incoming,
+                () = &mut signal => break,
+                // Reap the finished connections as we go
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+            let Some(incoming) = incoming else {
+                break;
+            };
+
+            let permit = match &permits {
+                Some(permits) => match permits.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        #[cfg(feature = "tracing")]
+                        warn!("refusing connection: too many connections");
+
+                        incoming.refuse();
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let builder = self.builder.clone();
+            let handler = handler.clone();
+            let shutdown = shutdown_recv.clone();
+            let shutdown_timeout = self.shutdown_timeout;
+            connections.spawn(async move {
+                let _permit = permit;
+                match incoming.await {
+                    Ok(conn) => {
+                        serve_connection(conn, &builder, handler, shutdown, shutdown_timeout).await
+                    }
+                    Err(_err) => {
+                        #[cfg(feature = "tracing")]
+                        debug!("connection failed: {}", _err);
+                    }
+                }
+            });
+        }
+
+        // Stop accepting connections, and shut the running ones down
+        self.endpoint.set_server_config(None);
+        let _ = shutdown_send.send(true);
+        while connections.join_next().await.is_some() {}
+        self.endpoint.wait_idle().await;
+    }
+}
+
+/// Runs an HTTP/3 connection, spawning a task for each request
+async fn serve_connection<H, F>(
+    conn: quinn::Connection,
+    builder: &server::Builder,
+    handler: H,
+    mut shutdown: watch::Receiver<bool>,
+    shutdown_timeout: Duration,
+) where
+    H: Fn(Request<()>, RequestStream<BidiStream<Bytes>, Bytes>) -> F,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut conn = match builder.build(Connection::new(conn)).await {
+        Ok(conn) => conn,
+        Err(_err) => {
+            #[cfg(feature = "tracing")]
+            debug!("HTTP/3 connection setup failed: {}", _err);
+            return;
+        }
+    };
+
+    let mut announced = false;
+    loop {
+        // Cancelling `accept()` keeps a request whose headers are being read, it is resumed
+        // by the next one
+        let accepted = tokio::select! {
+            accepted = conn.accept() => Some(accepted),
+            _ = shutdown.wait_for(|shutdown| *shutdown), if !announced => None,
+        };
+        let Some(accepted) = accepted else {
+            // Requests keep being accepted until the client had time to see the GOAWAY
+            announced = true;
+            match conn.announce_shutdown().await {
+                Ok(()) => continue,
+                Err(_) => break,
+            }
+        };
+        match accepted {
+            Ok(Some((request, stream))) => {
+                tokio::spawn(handler(request, stream));
+            }
+            // The interval of the announced shutdown elapsed
+            Ok(None) if announced => break,
+            // The connection is closed, or was shut down by the client
+            Ok(None) => return,
+            Err(err) => match err.get_error_level() {
+                ErrorLevel::ConnectionError => return,
+                ErrorLevel::StreamError => continue,
+            },
+        }
+    }
+
+    let _stats = conn
+        .graceful_shutdown(tokio::time::sleep(shutdown_timeout))
+        .await;
+
+    #[cfg(feature = "tracing")]
+    debug!("connection shut down: {:?}", _stats);
+}
@@ -0,0 +1,59 @@
+//! Loopback quinn endpoints for the tests
+
+use std::{net::Ipv6Addr, sync::Arc};
+
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    ClientConfig, Endpoint, ServerConfig, TransportConfig,
+};
+use rustls::pki_types::PrivateKeyDer;
+
+/// Builds a server endpoint and a client endpoint trusting it, with `h3` as ALPN
+pub fn endpoints(server: TransportConfig, client: TransportConfig) -> (Endpoint, Endpoint) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+    let cert = cert.cert.der().clone();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    let mut server_config =
+        ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+    server_config.transport_config(Arc::new(server));
+    let server = Endpoint::server(server_config, (Ipv6Addr::LOCALHOST, 0).into()).unwrap();
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    let mut client_config =
+        ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).unwrap()));
+    client_config.transport_config(Arc::new(client));
+    let mut client = Endpoint::client((Ipv6Addr::LOCALHOST, 0).into()).unwrap();
+    client.set_default_client_config(client_config);
+
+    (server, client)
+}
+
+/// Connects a client to a server built with [`endpoints`]
+#[allow(dead_code)]
+pub async fn connect(
+    server: &Endpoint,
+    client: &Endpoint,
+) -> (quinn::Connection, quinn::Connection) {
+    let addr = server.local_addr().unwrap();
+    let (server_conn, client_conn) = tokio::join!(
+        async { server.accept().await.unwrap().await.unwrap() },
+        async { client.connect(addr, "localhost").unwrap().await.unwrap() },
+    );
+    (server_conn, client_conn)
+}
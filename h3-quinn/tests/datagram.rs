@@ -0,0 +1,110 @@
+#![cfg(feature = "datagram")]
+
+mod common;
+
+use std::future::poll_fn;
+
+use bytes::Bytes;
+use h3::quic::StreamId;
+use h3_datagram::{
+    datagram::Datagram,
+    quic_traits::{RecvDatagramExt, SendDatagramError, SendDatagramExt},
+};
+use h3_quinn::{quinn::TransportConfig, Connection};
+
+fn datagram(payload: &'static str) -> Datagram<Bytes> {
+    Datagram::new(StreamId::try_from(4).unwrap(), Bytes::from(payload))
+}
+
+async fn recv(conn: &mut Connection) -> Datagram<Bytes> {
+    let buf = poll_fn(|cx| conn.poll_accept_datagram(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    Datagram::decode(buf).unwrap()
+}
+
+#[tokio::test]
+async fn queue_then_send() {
+    let (server, client) =
+        common::endpoints(TransportConfig::default(), TransportConfig::default());
+    let (server_conn, client_conn) = common::connect(&server, &client).await;
+    let mut server_conn = Connection::new(server_conn);
+    let mut client_conn = Connection::new(client_conn);
+
+    // Nothing queued yet
+    poll_fn(|cx| SendDatagramExt::<Bytes>::poll_send_datagram(&mut client_conn, cx))
+        .await
+        .unwrap();
+
+    client_conn.queue_datagram(datagram("first")).unwrap();
+    assert!(matches!(
+        client_conn.queue_datagram(datagram("second")),
+        Err(SendDatagramError::NotReady)
+    ));
+    poll_fn(|cx| SendDatagramExt::<Bytes>::poll_send_datagram(&mut client_conn, cx))
+        .await
+        .unwrap();
+    client_conn.queue_datagram(datagram("second")).unwrap();
+    poll_fn(|cx| SendDatagramExt::<Bytes>::poll_send_datagram(&mut client_conn, cx))
+        .await
+        .unwrap();
+
+    for payload in ["first", "second"] {
+        let received = recv(&mut server_conn).await;
+        assert_eq!(received.stream_id(), StreamId::try_from(4).unwrap());
+        assert_eq!(received.payload(), payload);
+    }
+}
+
+#[tokio::test]
+async fn too_large() {
+    let (server, client) =
+        common::endpoints(TransportConfig::default(), TransportConfig::default());
+    let (_server_conn, client_conn) = common::connect(&server, &client).await;
+    let mut client_conn = Connection::new(client_conn);
+
+    // The quarter stream id takes one byte
+    let max = SendDatagramExt::<Bytes>::max_datagram_size(&client_conn).unwrap();
+    let payload = Bytes::from(vec![0; max]);
+    let datagram = Datagram::new(StreamId::try_from(4).unwrap(), payload);
+    assert!(matches!(
+        client_conn.send_datagram(datagram),
+        Err(SendDatagramError::TooLarge)
+    ));
+
+    let payload = Bytes::from(vec![0; max - 1]);
+    let datagram = Datagram::new(StreamId::try_from(4).unwrap(), payload.clone());
+    client_conn.send_datagram(datagram).unwrap();
+    let datagram = Datagram::new(StreamId::try_from(4).unwrap(), payload);
+    client_conn.queue_datagram(datagram).unwrap();
+}
+
+#[tokio::test]
+async fn disabled_and_unsupported_by_peer() {
+    let mut transport = TransportConfig::default();
+    transport.datagram_receive_buffer_size(None);
+    let (server, client) = common::endpoints(transport, TransportConfig::default());
+    let (server_conn, client_conn) = common::connect(&server, &client).await;
+    let mut server_conn = Connection::new(server_conn);
+    let mut client_conn = Connection::new(client_conn);
+
+    assert!(matches!(
+        server_conn.send_datagram(datagram("disabled")),
+        Err(SendDatagramError::Disabled)
+    ));
+
+    assert_eq!(
+        SendDatagramExt::<Bytes>::max_datagram_size(&client_conn),
+        None
+    );
+    assert!(matches!(
+        client_conn.send_datagram(datagram("unsupported")),
+        Err(SendDatagramError::UnsupportedByPeer)
+    ));
+    client_conn.queue_datagram(datagram("unsupported")).unwrap();
+    assert!(matches!(
+        poll_fn(|cx| SendDatagramExt::<Bytes>::poll_send_datagram(&mut client_conn, cx)).await,
+        Err(SendDatagramError::UnsupportedByPeer)
+    ));
+}
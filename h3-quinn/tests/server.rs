@@ -0,0 +1,123 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use std::{future::poll_fn, time::Duration};
+
+use bytes::Buf;
+use h3_quinn::{quinn::TransportConfig, server::Server, Connection};
+use http::{Request, Response, StatusCode};
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn serves_requests_then_shuts_down() {
+    let (server, client) =
+        common::endpoints(TransportConfig::default(), TransportConfig::default());
+    let addr = server.local_addr().unwrap();
+    let (shutdown, signal) = oneshot::channel();
+
+    let serving = tokio::spawn(Server::new(server).max_connections(1).serve_with_shutdown(
+        |_request, mut stream| async move {
+            let response = Response::builder()
+                .status(StatusCode::IM_A_TEAPOT)
+                .body(())
+                .unwrap();
+            stream.send_response(response).await.unwrap();
+            stream.finish().await.unwrap();
+        },
+        async {
+            let _ = signal.await;
+        },
+    ));
+
+    let conn = client.connect(addr, "localhost").unwrap().await.unwrap();
+    let (mut driver, mut send_request) = h3::client::new(Connection::new(conn)).await.unwrap();
+    let driver = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let mut stream = send_request
+        .send_request(Request::get("https://localhost/").body(()).unwrap())
+        .await
+        .unwrap();
+    stream.finish().await.unwrap();
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    assert!(stream
+        .recv_data()
+        .await
+        .unwrap()
+        .map(|b| b.remaining())
+        .is_none());
+
+    // Beyond the connection limit
+    let refused = client.connect(addr, "localhost").unwrap().await;
+    assert!(refused.is_err());
+
+    shutdown.send(()).unwrap();
+    drop(send_request);
+    drop(stream);
+    let closed = tokio::time::timeout(Duration::from_secs(5), driver)
+        .await
+        .expect("client connection not closed")
+        .unwrap();
+    assert!(closed.is_ok());
+
+    tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("server not shut down")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn serves_requests_sent_before_the_goaway_was_received() {
+    let (server, client) =
+        common::endpoints(TransportConfig::default(), TransportConfig::default());
+    let addr = server.local_addr().unwrap();
+    let (shutdown, signal) = oneshot::channel();
+
+    // No timer is set, the server installs one
+    let mut builder = h3::server::builder();
+    builder.graceful_shutdown_interval(Duration::from_millis(500));
+    let serving = tokio::spawn(Server::with_builder(server, builder).serve_with_shutdown(
+        |_request, mut stream| async move {
+            let response = Response::builder()
+                .status(StatusCode::IM_A_TEAPOT)
+                .body(())
+                .unwrap();
+            stream.send_response(response).await.unwrap();
+            stream.finish().await.unwrap();
+        },
+        async {
+            let _ = signal.await;
+        },
+    ));
+
+    let conn = client.connect(addr, "localhost").unwrap().await.unwrap();
+    let (mut driver, mut send_request) = h3::client::new(Connection::new(conn)).await.unwrap();
+
+    // The driver is not polled yet, so the client has not processed the first GOAWAY when
+    // it sends the request
+    shutdown.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut stream = send_request
+        .send_request(Request::get("https://localhost/").body(()).unwrap())
+        .await
+        .unwrap();
+    stream.finish().await.unwrap();
+    let driver = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+
+    drop(send_request);
+    drop(stream);
+    let closed = tokio::time::timeout(Duration::from_secs(5), driver)
+        .await
+        .expect("client connection not closed")
+        .unwrap();
+    assert!(closed.is_ok());
+
+    tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("server not shut down")
+        .unwrap();
+}
@@ -0,0 +1,230 @@
+//! Capsules exchanged on the *CONNECT* stream of a WebTransport session
+//!
+//! See: <https://www.rfc-editor.org/rfc/rfc9297#section-3.2>
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use h3::{
+    error::{Code, ErrorLevel},
+    proto::varint::VarInt,
+    Error,
+};
+
+use crate::error::ErrorCode;
+
+const CLOSE_WEBTRANSPORT_SESSION: u64 = 0x2843;
+const DRAIN_WEBTRANSPORT_SESSION: u64 = 0x78ae;
+const WT_MAX_DATA: u64 = 0x190b4d3d;
+const WT_MAX_STREAMS_BIDI: u64 = 0x190b4d3f;
+const WT_MAX_STREAMS_UNI: u64 = 0x190b4d40;
+const WT_DATA_BLOCKED: u64 = 0x190b4d41;
+const WT_STREAMS_BLOCKED_BIDI: u64 = 0x190b4d43;
+const WT_STREAMS_BLOCKED_UNI: u64 = 0x190b4d44;
+
+/// Maximum length of the reason of a `CLOSE_WEBTRANSPORT_SESSION` capsule
+const MAX_CLOSE_REASON_LEN: usize = 1024;
+
+/// A capsule of the WebTransport session
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Capsule {
+    /// `CLOSE_WEBTRANSPORT_SESSION`, the session is terminated
+    CloseSession {
+        /// The application error code
+        code: ErrorCode,
+        /// The reason, at most 1024 bytes long
+        reason: String,
+    },
+    /// `DRAIN_WEBTRANSPORT_SESSION`, the session is about to be closed
+    DrainSession,
+    /// `WT_MAX_DATA`, the amount of data which can be sent on the streams of the session
+    MaxData(u64),
+    /// `WT_MAX_STREAMS`, the number of bidirectional streams which can be opened
+    MaxStreamsBidi(u64),
+    /// `WT_MAX_STREAMS`, the number of unidirectional streams which can be opened
+    MaxStreamsUni(u64),
+    /// `WT_DATA_BLOCKED`, the sender is blocked by the session data limit
+    DataBlocked(u64),
+    /// `WT_STREAMS_BLOCKED`, the sender is blocked by the bidirectional stream limit
+    StreamsBlockedBidi(u64),
+    /// `WT_STREAMS_BLOCKED`, the sender is blocked by the unidirectional stream limit
+    StreamsBlockedUni(u64),
+    /// A capsule of an unknown type, which is ignored
+    Unknown {
+        /// The capsule type
+        ty: u64,
+        /// The capsule value
+        payload: Bytes,
+    },
+}
+
+impl Capsule {
+    /// Creates a `CLOSE_WEBTRANSPORT_SESSION` capsule, truncating the reason to 1024 bytes
+    pub fn close_session(code: ErrorCode, reason: &str) -> Self {
+        let mut len = reason.len().min(MAX_CLOSE_REASON_LEN);
+        while !reason.is_char_boundary(len) {
+            len -= 1;
+        }
+        Self::CloseSession {
+            code,
+            reason: reason[..len].to_owned(),
+        }
+    }
+
+    /// Encodes the capsule
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        let (ty, value) = match self {
+            Self::CloseSession { code, reason } => {
+                let mut value = BytesMut::with_capacity(4 + reason.len());
+                value.put_u32(code.value());
+                value.put_slice(reason.as_bytes());
+                (CLOSE_WEBTRANSPORT_SESSION, value.freeze())
+            }
+            Self::DrainSession => (DRAIN_WEBTRANSPORT_SESSION, Bytes::new()),
+            Self::MaxData(max) => (WT_MAX_DATA, encode_varint(*max)),
+            Self::MaxStreamsBidi(max) => (WT_MAX_STREAMS_BIDI, encode_varint(*max)),
+            Self::MaxStreamsUni(max) => (WT_MAX_STREAMS_UNI, encode_varint(*max)),
+            Self::DataBlocked(max) => (WT_DATA_BLOCKED, encode_varint(*max)),
+            Self::StreamsBlockedBidi(max) => (WT_STREAMS_BLOCKED_BIDI, encode_varint(*max)),
+            Self::StreamsBlockedUni(max) => (WT_STREAMS_BLOCKED_UNI, encode_varint(*max)),
+            Self::Unknown { ty, payload } => (*ty, payload.clone()),
+        };
+
+        VarInt::from_u64(ty)
+            .expect("capsule type overflow")
+            .encode(buf);
+        VarInt::from_u64(value.len() as u64)
+            .expect("capsule length overflow")
+            .encode(buf);
+        buf.put(value);
+    }
+
+    /// Decodes a capsule from the start of `buf`
+    ///
+    /// Returns `None` without consuming anything if the capsule is not complete yet.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let mut peek = &buf[..];
+        let (Ok(ty), Ok(len)) = (VarInt::decode(&mut peek), VarInt::decode(&mut peek)) else {
+            return Ok(None);
+        };
+        let header_len = buf.len() - peek.len();
+        let len = usize::try_from(len.into_inner()).map_err(|_| malformed("capsule too large"))?;
+        if peek.len() < len {
+            return Ok(None);
+        }
+
+        buf.advance(header_len);
+        let mut value = buf.split_to(len).freeze();
+
+        let capsule = match ty.into_inner() {
+            CLOSE_WEBTRANSPORT_SESSION => {
+                if value.len() < 4 || value.len() > 4 + MAX_CLOSE_REASON_LEN {
+                    return Err(malformed("invalid CLOSE_WEBTRANSPORT_SESSION capsule"));
+                }
+                let code = ErrorCode::new(value.get_u32());
+                let reason = String::from_utf8(value.to_vec())
+                    .map_err(|_| malformed("CLOSE_WEBTRANSPORT_SESSION reason is not UTF-8"))?;
+                Self::CloseSession { code, reason }
+            }
+            DRAIN_WEBTRANSPORT_SESSION => Self::DrainSession,
+            WT_MAX_DATA => Self::MaxData(decode_varint(value)?),
+            WT_MAX_STREAMS_BIDI => Self::MaxStreamsBidi(decode_varint(value)?),
+            WT_MAX_STREAMS_UNI => Self::MaxStreamsUni(decode_varint(value)?),
+            WT_DATA_BLOCKED => Self::DataBlocked(decode_varint(value)?),
+            WT_STREAMS_BLOCKED_BIDI => Self::StreamsBlockedBidi(decode_varint(value)?),
+            WT_STREAMS_BLOCKED_UNI => Self::StreamsBlockedUni(decode_varint(value)?),
+            ty => Self::Unknown { ty, payload: value },
+        };
+        Ok(Some(capsule))
+    }
+}
+
+fn encode_varint(value: u64) -> Bytes {
+    let mut buf = BytesMut::with_capacity(VarInt::MAX_SIZE);
+    VarInt::from_u64(value)
+        .expect("capsule value overflow")
+        .encode(&mut buf);
+    buf.freeze()
+}
+
+fn decode_varint(mut value: Bytes) -> Result<u64, Error> {
+    let decoded = VarInt::decode(&mut value).map_err(|_| malformed("truncated capsule"))?;
+    if value.has_remaining() {
+        return Err(malformed("capsule longer than its value"));
+    }
+    Ok(decoded.into_inner())
+}
+
+fn malformed(reason: &'static str) -> Error {
+    Error::application(Code::H3_DATAGRAM_ERROR, reason, ErrorLevel::ConnectionError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(capsule: Capsule) {
+        let mut buf = BytesMut::new();
+        capsule.encode(&mut buf);
+        assert_eq!(Capsule::decode(&mut buf).unwrap(), Some(capsule));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn codec() {
+        round_trip(Capsule::close_session(ErrorCode::new(42), "bye"));
+        round_trip(Capsule::DrainSession);
+        round_trip(Capsule::MaxData(1 << 40));
+        round_trip(Capsule::MaxStreamsBidi(100));
+        round_trip(Capsule::MaxStreamsUni(0));
+        round_trip(Capsule::DataBlocked(16384));
+        round_trip(Capsule::StreamsBlockedBidi(100));
+        round_trip(Capsule::StreamsBlockedUni(1));
+        round_trip(Capsule::Unknown {
+            ty: 0x21,
+            payload: Bytes::from_static(b"grease"),
+        });
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut encoded = BytesMut::new();
+        Capsule::MaxData(1 << 40).encode(&mut encoded);
+        Capsule::DrainSession.encode(&mut encoded);
+
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in encoded {
+            buf.put_u8(byte);
+            while let Some(capsule) = Capsule::decode(&mut buf).unwrap() {
+                decoded.push(capsule);
+            }
+        }
+        assert_eq!(
+            decoded,
+            vec![Capsule::MaxData(1 << 40), Capsule::DrainSession]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn truncates_close_reason() {
+        let reason = "é".repeat(MAX_CLOSE_REASON_LEN);
+        let Capsule::CloseSession { reason, .. } =
+            Capsule::close_session(ErrorCode::new(0), &reason)
+        else {
+            unreachable!()
+        };
+        assert_eq!(reason.len(), MAX_CLOSE_REASON_LEN);
+    }
+
+    #[test]
+    fn malformed_value() {
+        let mut buf = BytesMut::new();
+        Capsule::Unknown {
+            ty: WT_MAX_DATA,
+            payload: Bytes::from_static(&[0x40]),
+        }
+        .encode(&mut buf);
+        assert!(Capsule::decode(&mut buf).is_err());
+    }
+}
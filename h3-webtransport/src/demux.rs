@@ -0,0 +1,1216 @@
+//! Routes the streams and datagrams of a connection to its WebTransport sessions
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+
+use bytes::Buf;
+use h3::{
+    error::{Code, ErrorLevel, Kind},
+    frame::FrameStream,
+    proto::frame::Frame,
+    quic::{self, RecvStream as _, SendStream as _, StreamId},
+    server::{Connection, ResolveRequest},
+    stream::BufRecvStream,
+    webtransport::SessionId,
+    Error,
+};
+use h3_datagram::{
+    datagram::Datagram,
+    quic_traits::RecvDatagramExt,
+    router::{DatagramReceiver, DatagramRouter},
+};
+
+use crate::{
+    flow::SessionFlow,
+    stream::{BidiStream, RecvStream},
+};
+
+/// Streams buffered for a session which is not established yet, per session
+const MAX_BUFFERED_STREAMS: usize = 8;
+
+/// Sessions which are not established yet for which streams are buffered
+const MAX_BUFFERED_SESSIONS: usize = 16;
+
+/// State shared between a [`crate::server::SessionManager`] and its sessions
+pub(crate) struct Shared<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    pub(crate) state: Mutex<State<C, B>>,
+    pub(crate) opener: Mutex<C::OpenStreams>,
+    wakers: Arc<WakerSet>,
+}
+
+pub(crate) struct State<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    pub(crate) conn: Connection<C, B>,
+    sessions: HashMap<SessionId, SessionQueues<C, B>>,
+    // Streams which arrived before their session was accepted
+    buffered: HashMap<SessionId, Buffered<C, B>>,
+    datagrams: DatagramRouter<C::Buf>,
+    // Bidirectional streams whose first frame has not been received yet
+    pending_bidi: Vec<FrameStream<C::BidiStream, B>>,
+    // HTTP requests whose headers wait for QPACK entries from the encoder stream
+    decoding: Vec<ResolveRequest<C, B>>,
+    // HTTP requests, to be handed to the accept loop
+    requests: VecDeque<ResolveRequest<C, B>>,
+    // Set once no more streams can be accepted
+    closed: bool,
+    error: Option<Error>,
+}
+
+/// Incoming streams and datagrams for a session
+pub(crate) struct SessionQueues<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    pub(crate) bidi: VecDeque<BidiStream<C::BidiStream, B>>,
+    pub(crate) uni: VecDeque<RecvStream<C::RecvStream, B>>,
+    pub(crate) datagrams: DatagramReceiver<C::Buf>,
+    flow: SessionFlow,
+}
+
+/// Streams of a session which is not established yet
+struct Buffered<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    bidi: Vec<BufRecvStream<C::BidiStream, B>>,
+    uni: Vec<BufRecvStream<C::RecvStream, B>>,
+}
+
+impl<C, B> Buffered<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    fn len(&self) -> usize {
+        self.bidi.len() + self.uni.len()
+    }
+
+    fn reject(self, code: Code) {
+        for mut stream in self.bidi {
+            stream.stop_sending(code.value());
+            stream.reset(code.value());
+        }
+        for mut stream in self.uni {
+            stream.stop_sending(code.value());
+        }
+    }
+}
+
+impl<C, B> SessionQueues<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    B: Buf,
+{
+    fn push_bidi(&mut self, session_id: SessionId, mut stream: BufRecvStream<C::BidiStream, B>) {
+        match self.flow.lock().unwrap().accept_stream(true) {
+            Ok(()) => self
+                .bidi
+                .push_back(BidiStream::new(stream).with_flow(self.flow.clone())),
+            Err(err) => {
+                tracing::debug!("rejecting bidi stream of {:?}: {}", session_id, err);
+                stream.stop_sending(Code::WT_FLOW_CONTROL_ERROR.value());
+                stream.reset(Code::WT_FLOW_CONTROL_ERROR.value());
+            }
+        }
+    }
+
+    fn push_uni(&mut self, session_id: SessionId, mut stream: BufRecvStream<C::RecvStream, B>) {
+        match self.flow.lock().unwrap().accept_stream(false) {
+            Ok(()) => self
+                .uni
+                .push_back(RecvStream::new(stream).with_flow(self.flow.clone())),
+            Err(err) => {
+                tracing::debug!("rejecting uni stream of {:?}: {}", session_id, err);
+                stream.stop_sending(Code::WT_FLOW_CONTROL_ERROR.value());
+            }
+        }
+    }
+}
+
+impl<C, B> Shared<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+    B: Buf,
+{
+    pub(crate) fn new(conn: Connection<C, B>) -> Self {
+        let opener = Mutex::new(conn.inner.conn.opener());
+        Self {
+            state: Mutex::new(State {
+                conn,
+                sessions: HashMap::new(),
+                buffered: HashMap::new(),
+                datagrams: DatagramRouter::new(),
+                pending_bidi: Vec::new(),
+                decoding: Vec::new(),
+                requests: VecDeque::new(),
+                closed: false,
+                error: None,
+            }),
+            opener,
+            wakers: Arc::new(WakerSet::default()),
+        }
+    }
+
+    /// Drives the connection, then takes an item with `f`
+    ///
+    /// Returns `None` once `f` can't return anything anymore because the connection is closed.
+    pub(crate) fn poll_with<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(&mut State<C, B>) -> Option<T>,
+    ) -> Poll<Result<Option<T>, Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        // Every task waiting on this connection is woken on progress, as whichever one
+        // polls it routes the items for all the others.
+        self.wakers.register(cx.waker());
+        let waker = Waker::from(self.wakers.clone());
+        let progress = state.poll_drive(&mut Context::from_waker(&waker));
+
+        if progress {
+            self.wakers.wake_all();
+        }
+
+        if let Some(item) = f(&mut state) {
+            return Poll::Ready(Ok(Some(item)));
+        }
+        if let Some(error) = &state.error {
+            return Poll::Ready(Err(error.clone()));
+        }
+        if state.closed {
+            return Poll::Ready(Ok(None));
+        }
+        Poll::Pending
+    }
+
+    /// Polls the connection with `f`, waking every task waiting on it on progress
+    pub(crate) fn poll_conn<T>(
+        &self,
+        cx: &mut Context<'_>,
+        f: impl FnOnce(&mut Connection<C, B>, &mut Context<'_>) -> Poll<T>,
+    ) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        self.wakers.register(cx.waker());
+        let waker = Waker::from(self.wakers.clone());
+        f(&mut state.conn, &mut Context::from_waker(&waker))
+    }
+
+    pub(crate) fn add_session(
+        &self,
+        session_id: SessionId,
+        connect_stream_id: StreamId,
+        flow: SessionFlow,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let datagrams = state.datagrams.register(connect_stream_id);
+        let mut session = SessionQueues {
+            bidi: VecDeque::new(),
+            uni: VecDeque::new(),
+            datagrams,
+            flow,
+        };
+        if let Some(buffered) = state.buffered.remove(&session_id) {
+            for stream in buffered.bidi {
+                session.push_bidi(session_id, stream);
+            }
+            for stream in buffered.uni {
+                session.push_uni(session_id, stream);
+            }
+        }
+        state.sessions.insert(session_id, session);
+    }
+
+    /// Rejects the streams buffered for a session which won't be established
+    pub(crate) fn refuse_session(&self, session_id: SessionId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(buffered) = state.buffered.remove(&session_id) {
+            tracing::debug!(
+                "rejecting {} streams of refused {:?}",
+                buffered.len(),
+                session_id
+            );
+            buffered.reject(Code::H3_REQUEST_REJECTED);
+        }
+    }
+
+    pub(crate) fn remove_session(&self, session_id: SessionId) {
+        let mut state = self.state.lock().unwrap();
+        state.sessions.remove(&session_id);
+    }
+}
+
+impl<C, B> State<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+    B: Buf,
+{
+    pub(crate) fn session(&mut self, session_id: SessionId) -> Option<&mut SessionQueues<C, B>> {
+        self.sessions.get_mut(&session_id)
+    }
+
+    pub(crate) fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Takes the next request, whose headers are decoded already
+    ///
+    /// The headers are decoded as the connection is driven, so resolving the request does not
+    /// wait for the encoder stream, which nothing would read meanwhile.
+    pub(crate) fn pop_request(&mut self) -> Option<ResolveRequest<C, B>> {
+        self.requests.pop_front()
+    }
+
+    /// Accepts and routes everything available on the connection
+    ///
+    /// Returns whether anything was routed.
+    fn poll_drive(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.closed {
+            return false;
+        }
+
+        match self.poll_route(cx) {
+            Ok(progress) => progress,
+            Err(err) => {
+                self.closed = true;
+                if !is_closed(&err) {
+                    self.error = Some(err);
+                }
+                true
+            }
+        }
+    }
+
+    fn poll_route(&mut self, cx: &mut Context<'_>) -> Result<bool, Error> {
+        let mut progress = false;
+
+        // Incoming bidirectional streams, either requests or WebTransport streams
+        loop {
+            match self.conn.poll_accept_request_stream(cx) {
+                Poll::Ready(Ok(Some(stream))) => self
+                    .pending_bidi
+                    .push(FrameStream::new(BufRecvStream::new(stream))),
+                Poll::Ready(Ok(None)) => {
+                    self.closed = true;
+                    return Ok(true);
+                }
+                Poll::Ready(Err(err)) => return Err(self.close_on_error(err)),
+                Poll::Pending => break,
+            }
+        }
+
+        // The first frame tells what a bidirectional stream is for
+        let mut index = 0;
+        while index < self.pending_bidi.len() {
+            match self.pending_bidi[index].poll_next(cx) {
+                Poll::Ready(frame) => {
+                    let stream = self.pending_bidi.swap_remove(index);
+                    self.route_bidi(stream, frame)?;
+                    progress = true;
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+
+        self.conn.inner.poll_accept_recv(cx)?;
+        self.conn.inner.poll_qpack(cx)?;
+
+        // The entries the encoder stream brought may unblock request headers
+        let mut index = 0;
+        while index < self.decoding.len() {
+            match self.decoding[index].poll_decode(cx) {
+                Poll::Ready(Ok(())) => {
+                    let request = self.decoding.remove(index);
+                    self.requests.push_back(request);
+                    progress = true;
+                }
+                Poll::Ready(Err(err)) if err.get_error_level() == ErrorLevel::StreamError => {
+                    tracing::debug!("dropping request with undecodable headers: {}", err);
+                    self.decoding.remove(index);
+                    progress = true;
+                }
+                Poll::Ready(Err(err)) => return Err(self.close_on_error(err)),
+                Poll::Pending => index += 1,
+            }
+        }
+
+        let streams = std::mem::take(&mut self.conn.inner.accepted_streams_mut().wt_uni_streams);
+        for (session_id, mut stream) in streams {
+            progress = true;
+            match self.sessions.get_mut(&session_id) {
+                Some(session) => session.push_uni(session_id, stream),
+                None => {
+                    if let Some(buffered) = self.buffer(session_id) {
+                        buffered.uni.push(stream);
+                    } else {
+                        stream.stop_sending(Code::WT_BUFFERED_STREAM_REJECTED.value());
+                    }
+                }
+            }
+        }
+
+        loop {
+            match self.conn.inner.conn.poll_accept_datagram(cx) {
+                Poll::Ready(Ok(Some(datagram))) => {
+                    progress = true;
+                    let datagram = Datagram::decode(datagram).map_err(|_| {
+                        self.conn
+                            .close(Code::H3_DATAGRAM_ERROR, "malformed datagram")
+                    })?;
+                    let stream_id = datagram.stream_id();
+                    if !self.datagrams.route(datagram) {
+                        tracing::debug!("dropping datagram for stream {:?}", stream_id);
+                    }
+                }
+                Poll::Ready(Ok(None)) | Poll::Pending => break,
+                Poll::Ready(Err(err)) => return Err(Error::from(err)),
+            }
+        }
+
+        Ok(progress)
+    }
+
+    fn route_bidi(
+        &mut self,
+        stream: FrameStream<C::BidiStream, B>,
+        frame: Result<Option<Frame<h3::proto::frame::PayloadLen>>, h3::frame::FrameStreamError>,
+    ) -> Result<(), Error> {
+        if let Ok(Some(Frame::WebTransportStream(session_id))) = frame {
+            let mut stream = stream.into_inner();
+            match self.sessions.get_mut(&session_id) {
+                Some(session) => session.push_bidi(session_id, stream),
+                None => {
+                    if let Some(buffered) = self.buffer(session_id) {
+                        buffered.bidi.push(stream);
+                    } else {
+                        let code = Code::WT_BUFFERED_STREAM_REJECTED.value();
+                        stream.stop_sending(code);
+                        stream.reset(code);
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        // Make the underlying HTTP/3 connection handle the rest
+        match self.conn.accept_with_frame(stream, frame) {
+            Ok(Some(request)) => self.decoding.push(request),
+            Ok(None) => (),
+            Err(err) if err.get_error_level() == ErrorLevel::StreamError => {
+                tracing::debug!("dropping malformed request: {}", err);
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Makes room for a stream of a session which is not established yet
+    ///
+    /// The CONNECT request may still be on its way or waiting to be accepted, so its streams
+    /// are kept until the session is accepted or refused. Returns `None` once the bounds are
+    /// reached, the stream is then rejected.
+    fn buffer(&mut self, session_id: SessionId) -> Option<&mut Buffered<C, B>> {
+        if !self.buffered.contains_key(&session_id) && self.buffered.len() >= MAX_BUFFERED_SESSIONS
+        {
+            tracing::debug!("rejecting stream, too many sessions buffered");
+            return None;
+        }
+        let buffered = self.buffered.entry(session_id).or_insert_with(|| Buffered {
+            bidi: Vec::new(),
+            uni: Vec::new(),
+        });
+        if buffered.len() >= MAX_BUFFERED_STREAMS {
+            tracing::debug!("rejecting stream, too many buffered for {:?}", session_id);
+            return None;
+        }
+        Some(buffered)
+    }
+
+    fn close_on_error(&mut self, err: Error) -> Error {
+        match err.kind() {
+            Kind::Application {
+                code,
+                reason,
+                level: ErrorLevel::ConnectionError,
+                ..
+            } => self.conn.close(
+                code,
+                reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
+            ),
+            _ => err,
+        }
+    }
+}
+
+fn is_closed(err: &Error) -> bool {
+    matches!(err.kind(), Kind::Closed)
+}
+
+/// Wakes every task waiting on the connection
+#[derive(Default)]
+struct WakerSet {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl WakerSet {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Wake for WakerSet {
+    fn wake(self: Arc<Self>) {
+        self.wake_all();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, sync::Arc, time::Duration};
+
+    use bytes::{Bytes, BytesMut};
+    use futures_util::future::poll_fn;
+    use h3::{
+        error::Code,
+        ext::Protocol,
+        proto::{
+            coding::Encode as _,
+            frame::{Frame, SettingId, Settings},
+            stream::StreamType,
+            varint::VarInt,
+        },
+        qpack::{self, HeaderField},
+        quic::SendStreamPriority,
+        webtransport::WebTransportDraft,
+    };
+    use http::{Method, Request, Response};
+    use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+    use rustls::pki_types::PrivateKeyDer;
+    use tokio::io::AsyncReadExt;
+
+    use crate::{
+        capsule::Capsule,
+        server::{AcceptedBi, SessionManager, WebTransportSession},
+    };
+
+    fn endpoints() -> (quinn::Endpoint, quinn::Endpoint) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into());
+        let cert = cert.cert.der().clone();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+        let server_config =
+            quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+        let server =
+            quinn::Endpoint::server(server_config, (Ipv6Addr::LOCALHOST, 0).into()).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+        let mut client = quinn::Endpoint::client((Ipv6Addr::LOCALHOST, 0).into()).unwrap();
+        client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(crypto).unwrap(),
+        )));
+
+        (server, client)
+    }
+
+    fn connect_request(path: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::CONNECT)
+            .uri(format!("https://localhost{}", path))
+            .extension(Protocol::WEB_TRANSPORT)
+            .body(())
+            .unwrap()
+    }
+
+    /// Writes a WebTransport stream header, `ty` being the stream or signal type
+    fn stream_header(ty: u64, session_id: u64) -> Bytes {
+        let mut buf = BytesMut::new();
+        VarInt::from_u64(ty).unwrap().encode(&mut buf);
+        VarInt::from_u64(session_id).unwrap().encode(&mut buf);
+        buf.freeze()
+    }
+
+    fn datagram(session_id: u64, payload: &str) -> Bytes {
+        let mut buf = BytesMut::new();
+        VarInt::from_u64(session_id / 4).unwrap().encode(&mut buf);
+        buf.extend_from_slice(payload.as_bytes());
+        buf.freeze()
+    }
+
+    #[tokio::test]
+    async fn routes_to_sessions() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            let mut sessions = Vec::new();
+            for path in ["/first", "/second"] {
+                let mut stream = client.send_request(connect_request(path)).await.unwrap();
+                let response = stream.recv_response().await.unwrap();
+                assert_eq!(response.status(), http::StatusCode::OK);
+                sessions.push((stream.id().into_inner(), stream));
+            }
+            let (first, second) = (sessions[0].0, sessions[1].0);
+
+            // Interleave the sessions, so they are not accepted in order
+            for (session_id, payload) in [(second, "second"), (first, "first")] {
+                let (mut send, _recv) = quic.open_bi().await.unwrap();
+                send.write_all(&stream_header(0x41, session_id))
+                    .await
+                    .unwrap();
+                send.write_all(payload.as_bytes()).await.unwrap();
+                send.finish().unwrap();
+
+                let mut send = quic.open_uni().await.unwrap();
+                send.write_all(&stream_header(0x54, session_id))
+                    .await
+                    .unwrap();
+                send.write_all(payload.as_bytes()).await.unwrap();
+                send.finish().unwrap();
+
+                quic.send_datagram(datagram(session_id, payload)).unwrap();
+            }
+
+            // Keep the sessions open until the server is done
+            quic.closed().await;
+            drop(sessions);
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(2)
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let mut sessions = Vec::new();
+            for path in ["/first", "/second"] {
+                let (request, stream) = manager.accept().await.unwrap().unwrap();
+                assert_eq!(request.uri().path(), path);
+                let session = manager.accept_session(request, stream).await.unwrap();
+                sessions.push((path.trim_start_matches('/'), session));
+            }
+
+            for (payload, session) in &sessions {
+                let Some(AcceptedBi::BidiStream(session_id, mut stream)) =
+                    session.accept_bi().await.unwrap()
+                else {
+                    panic!("expected a bidi stream");
+                };
+                assert_eq!(session_id, session.session_id());
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data).await.unwrap();
+                assert_eq!(data, payload.as_bytes());
+
+                let (session_id, mut stream) = session.accept_uni().await.unwrap().unwrap();
+                assert_eq!(session_id, session.session_id());
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data).await.unwrap();
+                assert_eq!(data, payload.as_bytes());
+
+                let (session_id, datagram) = session.accept_datagram().await.unwrap().unwrap();
+                assert_eq!(session_id, session.session_id());
+                assert_eq!(datagram, payload.as_bytes());
+            }
+
+            quic.close(0u32.into(), b"done");
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn buffers_streams_until_session_is_accepted() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            // Open the streams of both sessions without waiting for the responses
+            let mut sessions = Vec::new();
+            for path in ["/accepted", "/refused"] {
+                let stream = client.send_request(connect_request(path)).await.unwrap();
+                let session_id = stream.id().into_inner();
+
+                let (mut bidi, _recv) = quic.open_bi().await.unwrap();
+                bidi.write_all(&stream_header(0x41, session_id))
+                    .await
+                    .unwrap();
+                bidi.write_all(b"bidi").await.unwrap();
+
+                let mut uni = quic.open_uni().await.unwrap();
+                uni.write_all(&stream_header(0x54, session_id))
+                    .await
+                    .unwrap();
+                uni.write_all(b"uni").await.unwrap();
+
+                sessions.push((stream, bidi, uni));
+            }
+
+            let (mut refused, mut bidi, mut uni) = sessions.pop().unwrap();
+            let response = refused.recv_response().await.unwrap();
+            assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            for stopped in [bidi.stopped().await, uni.stopped().await] {
+                assert_eq!(
+                    stopped.unwrap().map(|code| code.into_inner()),
+                    Some(Code::H3_REQUEST_REJECTED.value())
+                );
+            }
+
+            let (mut accepted, mut bidi, mut uni) = sessions.pop().unwrap();
+            let response = accepted.recv_response().await.unwrap();
+            assert_eq!(response.status(), http::StatusCode::OK);
+            bidi.finish().unwrap();
+            uni.finish().unwrap();
+
+            // Keep the session open until the server is done
+            quic.closed().await;
+            drop(accepted);
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(2)
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (accepted, accepted_stream) = manager.accept().await.unwrap().unwrap();
+            let (refused, refused_stream) = manager.accept().await.unwrap().unwrap();
+            assert_eq!(refused.uri().path(), "/refused");
+
+            // Route the streams which arrive before the sessions are established
+            let idle = tokio::time::timeout(Duration::from_millis(200), manager.accept()).await;
+            assert!(idle.is_err());
+
+            manager
+                .reject_session(refused_stream, http::StatusCode::FORBIDDEN)
+                .await
+                .unwrap();
+            let session = manager
+                .accept_session(accepted, accepted_stream)
+                .await
+                .unwrap();
+
+            let Some(AcceptedBi::BidiStream(_, mut stream)) = session.accept_bi().await.unwrap()
+            else {
+                panic!("expected a bidi stream");
+            };
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"bidi");
+
+            let (_, mut stream) = session.accept_uni().await.unwrap().unwrap();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"uni");
+
+            quic.close(0u32.into(), b"done");
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    /// Sends a *CONNECT* request then a GET request, whose headers reference QPACK entries
+    /// sent only once the server received them
+    async fn send_blocked_requests(quic: quinn::Connection) {
+        let mut settings = Settings::default();
+        settings.insert(SettingId::ENABLE_WEBTRANSPORT, 1).unwrap();
+        settings.insert(SettingId::H3_DATAGRAM, 1).unwrap();
+        let mut control_stream = quic.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::CONTROL.encode(&mut buf);
+        Frame::<Bytes>::Settings(settings).encode(&mut buf);
+        control_stream.write_all(&buf).await.unwrap();
+
+        let mut encoder = qpack::Encoder::new();
+        encoder.set_max_table_capacity(4096).unwrap();
+        encoder.set_max_blocked_streams(2).unwrap();
+        let mut instructions = BytesMut::new();
+        StreamType::ENCODER.encode(&mut instructions);
+        encoder.set_table_capacity(4096, &mut instructions).unwrap();
+        let mut encoder_stream = quic.open_uni().await.unwrap();
+
+        let connect = [
+            HeaderField::new(":method", "CONNECT"),
+            HeaderField::new(":protocol", "webtransport"),
+            HeaderField::new(":scheme", "https"),
+            HeaderField::new(":authority", "localhost"),
+            HeaderField::new(":path", "/session"),
+            HeaderField::new("x-session", "4f8a9c2d1e"),
+        ];
+        let get = [
+            HeaderField::new(":method", "GET"),
+            HeaderField::new(":scheme", "https"),
+            HeaderField::new(":authority", "localhost"),
+            HeaderField::new(":path", "/request"),
+            HeaderField::new("x-session", "7b3e0a5c9f"),
+        ];
+        let mut streams = Vec::new();
+        for fields in [&connect[..], &get[..]] {
+            let (mut send, mut recv) = quic.open_bi().await.unwrap();
+            let mut block = BytesMut::new();
+            encoder
+                .encode(
+                    quinn::VarInt::from(send.id()).into_inner(),
+                    &mut block,
+                    &mut instructions,
+                    fields,
+                )
+                .unwrap();
+            // A non-zero required insert count, the headers can't be decoded without the entries
+            assert_ne!(block[0], 0);
+            let mut buf = BytesMut::new();
+            Frame::<Bytes>::Headers(block.clone().freeze()).encode(&mut buf);
+            buf.extend_from_slice(&block);
+            send.write_all(&buf).await.unwrap();
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            encoder_stream.write_all(&instructions).await.unwrap();
+            instructions.clear();
+
+            // The response headers
+            let mut data = [0; 1];
+            assert_eq!(recv.read(&mut data).await.unwrap(), Some(1));
+            streams.push((send, recv));
+        }
+
+        quic.close(0u32.into(), b"done");
+        drop((control_stream, encoder_stream, streams));
+    }
+
+    fn blocked_requests_builder() -> h3::server::Builder {
+        let mut builder = h3::server::builder();
+        builder
+            .enable_webtransport(true)
+            .enable_connect(true)
+            .enable_datagram(true)
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(2);
+        builder
+    }
+
+    #[tokio::test]
+    async fn manager_accepts_blocked_requests() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            send_blocked_requests(quic).await;
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = blocked_requests_builder()
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (request, stream) = manager.accept().await.unwrap().unwrap();
+            assert_eq!(request.uri().path(), "/session");
+            let _session = manager.accept_session(request, stream).await.unwrap();
+
+            let (request, mut stream) = manager.accept().await.unwrap().unwrap();
+            assert_eq!(request.uri().path(), "/request");
+            stream.send_response(Response::new(())).await.unwrap();
+
+            quic.closed().await;
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn session_accepts_blocked_requests() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            send_blocked_requests(quic).await;
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let mut conn: h3::server::Connection<_, Bytes> = blocked_requests_builder()
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+
+            let (request, stream) = conn.accept().await.unwrap().unwrap();
+            assert_eq!(request.uri().path(), "/session");
+            let session = WebTransportSession::accept(request, stream, conn)
+                .await
+                .unwrap();
+
+            let Some(AcceptedBi::Request(request, mut stream)) = session.accept_bi().await.unwrap()
+            else {
+                panic!("expected a request");
+            };
+            assert_eq!(request.uri().path(), "/request");
+            stream.send_response(Response::new(())).await.unwrap();
+
+            quic.closed().await;
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn malformed_datagram_closes_connection() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            let mut stream = client.send_request(connect_request("/")).await.unwrap();
+            stream.recv_response().await.unwrap();
+
+            // Too short for the quarter stream id
+            quic.send_datagram(Bytes::from_static(&[0x40])).unwrap();
+
+            match quic.closed().await {
+                quinn::ConnectionError::ApplicationClosed(close) => {
+                    assert_eq!(
+                        close.error_code.into_inner(),
+                        Code::H3_DATAGRAM_ERROR.value()
+                    )
+                }
+                err => panic!("unexpected close: {:?}", err),
+            }
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(1)
+                .build(h3_quinn::Connection::new(quic))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (request, stream) = manager.accept().await.unwrap().unwrap();
+            let session = manager.accept_session(request, stream).await.unwrap();
+            let err = session.accept_datagram().await.unwrap_err();
+            assert_eq!(err.try_get_code(), Some(Code::H3_DATAGRAM_ERROR));
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn peer_exceeding_stream_limit_fails_session() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(1)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            let mut stream = client.send_request(connect_request("/")).await.unwrap();
+            stream.recv_response().await.unwrap();
+            let session_id = stream.id().into_inner();
+
+            let mut first = quic.open_uni().await.unwrap();
+            first
+                .write_all(&stream_header(0x54, session_id))
+                .await
+                .unwrap();
+            first.write_all(b"data").await.unwrap();
+            first.finish().unwrap();
+
+            let mut second = quic.open_uni().await.unwrap();
+            second
+                .write_all(&stream_header(0x54, session_id))
+                .await
+                .unwrap();
+            assert_eq!(
+                second
+                    .stopped()
+                    .await
+                    .unwrap()
+                    .map(|code| code.into_inner()),
+                Some(Code::WT_FLOW_CONTROL_ERROR.value())
+            );
+
+            // The session is terminated with the CONNECT stream
+            let err = stream.recv_data().await.err().unwrap();
+            assert_eq!(err.try_get_code(), Some(Code::WT_FLOW_CONTROL_ERROR));
+
+            quic.close(0u32.into(), b"done");
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(1)
+                .wt_initial_max_streams_uni(1)
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (request, stream) = manager.accept().await.unwrap().unwrap();
+            let session = manager.accept_session(request, stream).await.unwrap();
+            assert_eq!(session.webtransport_draft(), WebTransportDraft::Draft07);
+
+            let (_, mut stream) = session.accept_uni().await.unwrap().unwrap();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"data");
+
+            let err = session.accept_uni().await.err().unwrap();
+            assert_eq!(err.try_get_code(), Some(Code::WT_FLOW_CONTROL_ERROR));
+            let err = session.recv_capsule().await.unwrap_err();
+            assert_eq!(err.try_get_code(), Some(Code::WT_FLOW_CONTROL_ERROR));
+
+            quic.closed().await;
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn peer_exceeding_data_limit_fails_stream() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(1)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            let mut stream = client.send_request(connect_request("/")).await.unwrap();
+            stream.recv_response().await.unwrap();
+            let session_id = stream.id().into_inner();
+
+            let (mut send, _recv) = quic.open_bi().await.unwrap();
+            send.write_all(&stream_header(0x41, session_id))
+                .await
+                .unwrap();
+            send.write_all(b"too much data").await.unwrap();
+            send.finish().unwrap();
+
+            quic.closed().await;
+            drop(stream);
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .max_webtransport_sessions(1)
+                .wt_initial_max_data(4)
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (request, stream) = manager.accept().await.unwrap().unwrap();
+            let session = manager.accept_session(request, stream).await.unwrap();
+
+            let Some(AcceptedBi::BidiStream(_, mut stream)) = session.accept_bi().await.unwrap()
+            else {
+                panic!("expected a bidi stream");
+            };
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap_err();
+
+            let err = session.recv_capsule().await.unwrap_err();
+            assert_eq!(err.try_get_code(), Some(Code::WT_FLOW_CONTROL_ERROR));
+
+            quic.close(0u32.into(), b"done");
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+
+    #[tokio::test]
+    async fn send_order_reaches_quic_stream() {
+        let (server_endpoint, client_endpoint) = endpoints();
+        let addr = server_endpoint.local_addr().unwrap();
+
+        let client_fut = async {
+            let quic = client_endpoint
+                .connect(addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut driver, mut client) = h3::client::builder()
+                .enable_webtransport(true)
+                .enable_datagram(true)
+                .build::<_, _, Bytes>(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let drive = tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+            let mut stream = client.send_request(connect_request("/")).await.unwrap();
+            stream.recv_response().await.unwrap();
+
+            // The server is done once it drains the session
+            stream.recv_data().await.unwrap();
+            quic.close(0u32.into(), b"done");
+            drive.abort();
+        };
+
+        let server_fut = async {
+            let quic = server_endpoint.accept().await.unwrap().await.unwrap();
+            let conn: h3::server::Connection<_, Bytes> = h3::server::builder()
+                .enable_webtransport(true)
+                .enable_connect(true)
+                .enable_datagram(true)
+                .build(h3_quinn::Connection::new(quic.clone()))
+                .await
+                .unwrap();
+            let manager = SessionManager::new(conn);
+
+            let (request, stream) = manager.accept().await.unwrap().unwrap();
+            let session = manager.accept_session(request, stream).await.unwrap();
+
+            // The priority is read back from the quinn stream
+            let mut send = session.open_uni(session.session_id()).await.unwrap();
+            assert_eq!(send.priority().unwrap(), 0);
+            send.set_send_order(7).unwrap();
+            assert_eq!(send.priority().unwrap(), 7);
+            send.set_send_order(i64::MIN).unwrap();
+            assert_eq!(send.priority().unwrap(), i32::MIN);
+
+            session.send_capsule(&Capsule::DrainSession).await.unwrap();
+            quic.closed().await;
+        };
+
+        tokio::join!(client_fut, server_fut);
+    }
+}
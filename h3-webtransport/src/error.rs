@@ -0,0 +1,196 @@
+//! WebTransport error codes
+
+use std::fmt;
+
+use h3::quic;
+
+/// First HTTP/3 error code reserved for WebTransport application error codes
+const FIRST: u64 = 0x52e4a40fa8db;
+/// Last HTTP/3 error code reserved for WebTransport application error codes
+const LAST: u64 = 0x52e5ac983162;
+
+/// An application error code, used to reset WebTransport streams or close a session
+///
+/// WebTransport application error codes are 32-bit values, carried in the HTTP/3 error space.
+/// The conversion skips the reserved codes used for greasing.
+///
+/// See: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ErrorCode(u32);
+
+impl ErrorCode {
+    /// Creates an application error code
+    pub const fn new(code: u32) -> Self {
+        Self(code)
+    }
+
+    /// Returns the application error code
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the HTTP/3 error code carrying this application error code
+    pub const fn to_http3(self) -> u64 {
+        let code = self.0 as u64;
+        FIRST + code + code / 0x1e
+    }
+
+    /// Decodes an application error code from an HTTP/3 error code
+    ///
+    /// Returns `None` if `code` is outside of the WebTransport range or is a reserved code.
+    pub const fn from_http3(code: u64) -> Option<Self> {
+        if code < FIRST || code > LAST {
+            return None;
+        }
+        let shifted = code - FIRST;
+        // Every 0x1f-th code, of the form 0x1f * N + 0x21, is reserved
+        if shifted % 0x1f == 0x1e {
+            return None;
+        }
+        Some(Self((shifted - shifted / 0x1f) as u32))
+    }
+
+    /// Converts an error code passed to the QUIC stream traits
+    ///
+    /// Values which do not fit in 32 bits are clamped.
+    pub(crate) fn from_stream_code(code: u64) -> Self {
+        Self(u32::try_from(code).unwrap_or(u32::MAX))
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        Self(code)
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        code.0
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// An error on a WebTransport stream
+///
+/// Wraps the error of the QUIC stream, decoding the application error code the peer reset or
+/// stopped the stream with.
+#[derive(Debug)]
+pub struct StreamError {
+    inner: Box<dyn quic::Error>,
+}
+
+impl StreamError {
+    pub(crate) fn new(inner: impl Into<Box<dyn quic::Error>>) -> Self {
+        Self {
+            inner: inner.into(),
+        }
+    }
+
+    /// Returns the application error code the peer reset or stopped the stream with
+    ///
+    /// Returns `None` if the error was not caused by the peer, or if the peer used an HTTP/3
+    /// error code outside of the WebTransport range.
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.inner.err_code().and_then(ErrorCode::from_http3)
+    }
+
+    /// Returns the error of the QUIC stream
+    pub fn into_inner(self) -> Box<dyn quic::Error> {
+        self.inner
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl quic::Error for StreamError {
+    fn is_timeout(&self) -> bool {
+        self.inner.is_timeout()
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        self.inner.err_code()
+    }
+}
+
+/// A session-level flow control limit was exceeded, by the peer or by data sent on a stream
+#[derive(Debug)]
+pub(crate) struct FlowControlError {
+    reason: &'static str,
+}
+
+impl FlowControlError {
+    pub(crate) fn new(reason: &'static str) -> Self {
+        Self { reason }
+    }
+}
+
+impl fmt::Display for FlowControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason)
+    }
+}
+
+impl std::error::Error for FlowControlError {}
+
+impl quic::Error for FlowControlError {
+    fn is_timeout(&self) -> bool {
+        false
+    }
+
+    fn err_code(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl From<FlowControlError> for std::io::Error {
+    fn from(err: FlowControlError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_and_last_codes() {
+        assert_eq!(ErrorCode::new(0).to_http3(), FIRST);
+        assert_eq!(ErrorCode::new(u32::MAX).to_http3(), LAST);
+        assert_eq!(ErrorCode::from_http3(FIRST), Some(ErrorCode::new(0)));
+        assert_eq!(ErrorCode::from_http3(LAST), Some(ErrorCode::new(u32::MAX)));
+    }
+
+    #[test]
+    fn skips_reserved_codes() {
+        assert_eq!(ErrorCode::new(0x1d).to_http3(), FIRST + 0x1d);
+        assert_eq!(ErrorCode::new(0x1e).to_http3(), FIRST + 0x1f);
+        assert_eq!(ErrorCode::from_http3(FIRST + 0x1e), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        for code in (0..1000).chain([u32::MAX / 2, u32::MAX - 1, u32::MAX]) {
+            let code = ErrorCode::new(code);
+            assert_eq!(ErrorCode::from_http3(code.to_http3()), Some(code));
+        }
+    }
+
+    #[test]
+    fn outside_of_range() {
+        assert_eq!(ErrorCode::from_http3(0x100), None);
+        assert_eq!(ErrorCode::from_http3(FIRST - 1), None);
+        assert_eq!(ErrorCode::from_http3(LAST + 1), None);
+    }
+}
@@ -0,0 +1,337 @@
+//! Session-level flow control of WebTransport sessions
+//!
+//! See: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
+
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use h3::{
+    error::{Code, ErrorLevel},
+    Error,
+};
+
+use crate::{capsule::Capsule, error::FlowControlError};
+
+/// Flow control state shared by a session and its streams
+pub(crate) type SessionFlow = Arc<Mutex<FlowControl>>;
+
+/// Initial limits of a session
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) max_data: u64,
+    pub(crate) max_streams_bidi: u64,
+    pub(crate) max_streams_uni: u64,
+}
+
+impl Limits {
+    /// Limits of the drafts without session-level flow control
+    pub(crate) const UNLIMITED: Self = Self {
+        max_data: u64::MAX,
+        max_streams_bidi: u64::MAX,
+        max_streams_uni: u64::MAX,
+    };
+}
+
+/// Tracks what the session sent and received against the limits of both endpoints
+pub(crate) struct FlowControl {
+    // Limits set by the peer, raised by the capsules it sends
+    peer: Limits,
+    sent_data: u64,
+    opened_bidi: u64,
+    opened_uni: u64,
+    // Tasks sending data or opening streams, waiting for the peer to raise its limits
+    blocked: Vec<Waker>,
+    // `WT_DATA_BLOCKED` and `WT_STREAMS_BLOCKED` capsules to send, and those already sent
+    // for the current limits
+    blocked_capsules: Vec<Capsule>,
+    reported: Vec<Capsule>,
+    // Task sending the capsules, see `WebTransportSession::recv_capsule()`
+    capsule_waker: Option<Waker>,
+    // Limits advertised to the peer, raised by the capsules sent by the session
+    local: Limits,
+    received_data: u64,
+    accepted_bidi: u64,
+    accepted_uni: u64,
+    // Set once the peer exceeded one of the local limits
+    error: Option<Error>,
+}
+
+impl FlowControl {
+    pub(crate) fn new(peer: Limits, local: Limits) -> Self {
+        Self {
+            peer,
+            sent_data: 0,
+            opened_bidi: 0,
+            opened_uni: 0,
+            blocked: Vec::new(),
+            blocked_capsules: Vec::new(),
+            reported: Vec::new(),
+            capsule_waker: None,
+            local,
+            received_data: 0,
+            accepted_bidi: 0,
+            accepted_uni: 0,
+            error: None,
+        }
+    }
+
+    pub(crate) fn peer_max_data(&self) -> u64 {
+        self.peer.max_data
+    }
+
+    /// Applies the limits carried by a capsule of the peer
+    pub(crate) fn on_capsule(&mut self, capsule: &Capsule) {
+        // Limits are never lowered, capsules may arrive out of order
+        let raised = match *capsule {
+            Capsule::MaxData(max) => raise(&mut self.peer.max_data, max),
+            Capsule::MaxStreamsBidi(max) => raise(&mut self.peer.max_streams_bidi, max),
+            Capsule::MaxStreamsUni(max) => raise(&mut self.peer.max_streams_uni, max),
+            _ => false,
+        };
+        if raised {
+            self.reported.clear();
+            self.blocked.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Applies the limits carried by a capsule sent to the peer
+    pub(crate) fn on_sent_capsule(&mut self, capsule: &Capsule) {
+        match *capsule {
+            Capsule::MaxData(max) => raise(&mut self.local.max_data, max),
+            Capsule::MaxStreamsBidi(max) => raise(&mut self.local.max_streams_bidi, max),
+            Capsule::MaxStreamsUni(max) => raise(&mut self.local.max_streams_uni, max),
+            _ => false,
+        };
+    }
+
+    /// Counts a new stream, or registers the task until the peer raises the limit
+    pub(crate) fn poll_reserve(&mut self, cx: &mut Context<'_>, bidi: bool) -> Poll<()> {
+        let (opened, max) = match bidi {
+            true => (&mut self.opened_bidi, self.peer.max_streams_bidi),
+            false => (&mut self.opened_uni, self.peer.max_streams_uni),
+        };
+        if *opened < max {
+            *opened += 1;
+            return Poll::Ready(());
+        }
+        self.report(match bidi {
+            true => Capsule::StreamsBlockedBidi(max),
+            false => Capsule::StreamsBlockedUni(max),
+        });
+        self.register(cx);
+        Poll::Pending
+    }
+
+    /// Reserves up to `len` bytes of the session data limit, or registers the task until the
+    /// peer raises it
+    pub(crate) fn poll_reserve_data(&mut self, cx: &mut Context<'_>, len: u64) -> Poll<u64> {
+        let credit = self.peer.max_data - self.sent_data;
+        if credit == 0 && len > 0 {
+            self.report(Capsule::DataBlocked(self.peer.max_data));
+            self.register(cx);
+            return Poll::Pending;
+        }
+        let reserved = credit.min(len);
+        self.sent_data += reserved;
+        Poll::Ready(reserved)
+    }
+
+    /// Gives back the part of a reservation which was not sent
+    pub(crate) fn release_data(&mut self, len: u64) {
+        self.sent_data -= len;
+        if len > 0 {
+            self.blocked.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Counts data sent on a stream of the session
+    ///
+    /// Fails without counting anything if the peer does not allow that much data.
+    pub(crate) fn send(&mut self, len: u64) -> Result<(), FlowControlError> {
+        match self.sent_data.checked_add(len) {
+            Some(sent) if sent <= self.peer.max_data => {
+                self.sent_data = sent;
+                Ok(())
+            }
+            _ => {
+                self.report(Capsule::DataBlocked(self.peer.max_data));
+                Err(FlowControlError::new(
+                    "sending more than the session data limit",
+                ))
+            }
+        }
+    }
+
+    /// Counts data received on a stream of the session
+    pub(crate) fn recv(&mut self, len: u64) -> Result<(), FlowControlError> {
+        self.received_data = self.received_data.saturating_add(len);
+        if self.received_data > self.local.max_data {
+            return Err(self.fail("peer exceeded the session data limit"));
+        }
+        Ok(())
+    }
+
+    /// Counts a stream opened by the peer
+    pub(crate) fn accept_stream(&mut self, bidi: bool) -> Result<(), FlowControlError> {
+        let (accepted, max) = match bidi {
+            true => (&mut self.accepted_bidi, self.local.max_streams_bidi),
+            false => (&mut self.accepted_uni, self.local.max_streams_uni),
+        };
+        if *accepted >= max {
+            return Err(self.fail("peer exceeded the session stream limit"));
+        }
+        *accepted += 1;
+        Ok(())
+    }
+
+    /// Returns the error failing the session, once the peer exceeded one of the limits
+    pub(crate) fn error(&self) -> Option<Error> {
+        self.error.clone()
+    }
+
+    /// Takes the next blocked capsule to send, or registers the task sending them
+    pub(crate) fn poll_blocked_capsule(&mut self, cx: &mut Context<'_>) -> Poll<Capsule> {
+        if self.blocked_capsules.is_empty() {
+            self.capsule_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(self.blocked_capsules.remove(0))
+    }
+
+    fn report(&mut self, capsule: Capsule) {
+        if self.reported.contains(&capsule) {
+            return;
+        }
+        self.reported.push(capsule.clone());
+        self.blocked_capsules.push(capsule);
+        if let Some(waker) = self.capsule_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn register(&mut self, cx: &mut Context<'_>) {
+        if !self.blocked.iter().any(|w| w.will_wake(cx.waker())) {
+            self.blocked.push(cx.waker().clone());
+        }
+    }
+
+    fn fail(&mut self, reason: &'static str) -> FlowControlError {
+        if self.error.is_none() {
+            self.error = Some(Error::application(
+                Code::WT_FLOW_CONTROL_ERROR,
+                reason,
+                ErrorLevel::StreamError,
+            ));
+        }
+        if let Some(waker) = self.capsule_waker.take() {
+            waker.wake();
+        }
+        FlowControlError::new(reason)
+    }
+}
+
+fn raise(limit: &mut u64, max: u64) -> bool {
+    let raised = max > *limit;
+    *limit = (*limit).max(max);
+    raised
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::task::noop_waker_ref;
+
+    use super::*;
+
+    fn limits(max_data: u64, max_streams_bidi: u64, max_streams_uni: u64) -> Limits {
+        Limits {
+            max_data,
+            max_streams_bidi,
+            max_streams_uni,
+        }
+    }
+
+    #[test]
+    fn stream_limits_raised_by_capsules() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut flow = FlowControl::new(limits(u64::MAX, 1, 0), Limits::UNLIMITED);
+
+        assert!(flow.poll_reserve(&mut cx, true).is_ready());
+        assert!(flow.poll_reserve(&mut cx, true).is_pending());
+        assert!(flow.poll_reserve(&mut cx, false).is_pending());
+
+        flow.on_capsule(&Capsule::MaxStreamsBidi(2));
+        assert!(flow.blocked.is_empty());
+        assert!(flow.poll_reserve(&mut cx, true).is_ready());
+        assert!(flow.poll_reserve(&mut cx, false).is_pending());
+    }
+
+    #[test]
+    fn limits_are_never_lowered() {
+        let mut flow = FlowControl::new(limits(100, 10, 10), Limits::UNLIMITED);
+        flow.on_capsule(&Capsule::MaxData(50));
+        flow.on_capsule(&Capsule::MaxStreamsUni(5));
+        assert_eq!(flow.peer.max_data, 100);
+        assert_eq!(flow.peer.max_streams_uni, 10);
+
+        flow.on_capsule(&Capsule::MaxData(200));
+        assert_eq!(flow.peer.max_data, 200);
+    }
+
+    #[test]
+    fn blocked_senders_report_once_per_limit() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut flow = FlowControl::new(limits(10, 0, u64::MAX), Limits::UNLIMITED);
+
+        assert_eq!(flow.poll_reserve_data(&mut cx, 4), Poll::Ready(4));
+        assert_eq!(flow.poll_reserve_data(&mut cx, 20), Poll::Ready(6));
+        assert!(flow.poll_reserve_data(&mut cx, 1).is_pending());
+        assert!(flow.poll_reserve_data(&mut cx, 1).is_pending());
+        assert!(flow.poll_reserve(&mut cx, true).is_pending());
+
+        assert_eq!(
+            flow.poll_blocked_capsule(&mut cx),
+            Poll::Ready(Capsule::DataBlocked(10))
+        );
+        assert_eq!(
+            flow.poll_blocked_capsule(&mut cx),
+            Poll::Ready(Capsule::StreamsBlockedBidi(0))
+        );
+        assert!(flow.poll_blocked_capsule(&mut cx).is_pending());
+
+        flow.on_capsule(&Capsule::MaxData(15));
+        assert_eq!(flow.poll_reserve_data(&mut cx, 20), Poll::Ready(5));
+        flow.release_data(2);
+        flow.send(2).unwrap();
+        assert!(flow.send(1).is_err());
+        assert_eq!(
+            flow.poll_blocked_capsule(&mut cx),
+            Poll::Ready(Capsule::DataBlocked(15))
+        );
+    }
+
+    #[test]
+    fn peer_exceeding_local_limits_fails_the_session() {
+        let mut flow = FlowControl::new(Limits::UNLIMITED, limits(10, 1, 0));
+
+        flow.accept_stream(true).unwrap();
+        flow.recv(10).unwrap();
+        assert!(flow.error().is_none());
+
+        assert!(flow.accept_stream(false).is_err());
+        let err = flow.error().unwrap();
+        assert_eq!(err.try_get_code(), Some(Code::WT_FLOW_CONTROL_ERROR));
+    }
+
+    #[test]
+    fn local_limits_raised_by_sent_capsules() {
+        let mut flow = FlowControl::new(Limits::UNLIMITED, limits(10, 0, 0));
+
+        flow.on_sent_capsule(&Capsule::MaxData(20));
+        flow.on_sent_capsule(&Capsule::MaxStreamsUni(1));
+        flow.recv(20).unwrap();
+        flow.accept_stream(false).unwrap();
+        assert!(flow.recv(1).is_err());
+    }
+}
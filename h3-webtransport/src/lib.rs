@@ -5,6 +5,14 @@
 //! WebTransport over HTTP/3: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
 #![deny(missing_docs)]
 
+/// Capsules of WebTransport sessions
+pub mod capsule;
+mod demux;
+/// WebTransport error codes
+pub mod error;
+mod flow;
+/// WebTransport application protocol negotiation
+pub mod protocol;
 /// Server side WebTransport session support
 pub mod server;
 /// Webtransport stream types
@@ -0,0 +1,121 @@
+//! Application protocol negotiation for WebTransport sessions
+//!
+//! The client lists the protocols it supports in the `wt-available-protocols` header of the
+//! *CONNECT* request, and the server picks one in the `wt-protocol` header of the response.
+//! Both are Structured Fields (RFC 8941), a list of strings and a string respectively.
+
+use http::{HeaderValue, Request};
+
+/// Header listing the protocols offered by the client
+pub const WT_AVAILABLE_PROTOCOLS: &str = "wt-available-protocols";
+
+/// Header carrying the protocol selected by the server
+pub const WT_PROTOCOL: &str = "wt-protocol";
+
+/// Returns the protocols offered by the client, in order of preference
+///
+/// Returns an empty list if the header is absent or malformed.
+pub fn available_protocols<T>(request: &Request<T>) -> Vec<String> {
+    let mut protocols = Vec::new();
+    for value in request.headers().get_all(WT_AVAILABLE_PROTOCOLS) {
+        match value.to_str().ok().and_then(parse_string_list) {
+            Some(list) => protocols.extend(list),
+            None => return Vec::new(),
+        }
+    }
+    protocols
+}
+
+/// Encodes the protocol selected by the server as a `wt-protocol` header value
+///
+/// Returns `None` if `protocol` contains characters which are not printable ASCII.
+pub fn protocol_header_value(protocol: &str) -> Option<HeaderValue> {
+    if !protocol.bytes().all(|b| (0x20..=0x7e).contains(&b)) {
+        return None;
+    }
+
+    let mut value = String::with_capacity(protocol.len() + 2);
+    value.push('"');
+    for c in protocol.chars() {
+        if c == '"' || c == '\\' {
+            value.push('\\');
+        }
+        value.push(c);
+    }
+    value.push('"');
+    HeaderValue::from_str(&value).ok()
+}
+
+/// Parses a Structured Field list of strings, ignoring parameters
+fn parse_string_list(input: &str) -> Option<Vec<String>> {
+    let mut list = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut after_comma = false;
+
+    loop {
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        if chars.peek().is_none() {
+            // Trailing commas are invalid
+            return (!after_comma).then_some(list);
+        }
+
+        if chars.next() != Some('"') {
+            return None;
+        }
+        let mut item = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    c @ ('"' | '\\') => item.push(c),
+                    _ => return None,
+                },
+                c if (' '..='~').contains(&c) => item.push(c),
+                _ => return None,
+            }
+        }
+        list.push(item);
+
+        // Skip the parameters of the item
+        after_comma = chars.by_ref().any(|c| c == ',');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(values: &[&'static str]) -> Request<()> {
+        let mut request = Request::new(());
+        for value in values {
+            request
+                .headers_mut()
+                .append(WT_AVAILABLE_PROTOCOLS, HeaderValue::from_static(value));
+        }
+        request
+    }
+
+    #[test]
+    fn parse_available_protocols() {
+        let request = request(&[r#""moq-00", "chat\"v2\"";q=1"#, r#""echo""#]);
+        assert_eq!(
+            available_protocols(&request),
+            vec!["moq-00", "chat\"v2\"", "echo"]
+        );
+    }
+
+    #[test]
+    fn malformed_available_protocols() {
+        assert!(available_protocols(&request(&["moq-00"])).is_empty());
+        assert!(available_protocols(&request(&[r#""moq-00", "#])).is_empty());
+        assert!(available_protocols(&request(&[r#""moq-00"#])).is_empty());
+        assert!(available_protocols(&request(&[])).is_empty());
+    }
+
+    #[test]
+    fn encode_protocol() {
+        assert_eq!(protocol_header_value("moq-00").unwrap(), r#""moq-00""#);
+        assert_eq!(protocol_header_value(r#"a"b\c"#).unwrap(), r#""a\"b\\c""#);
+        assert!(protocol_header_value("caf\u{e9}").is_none());
+    }
+}
@@ -1,21 +1,18 @@
 //! Provides the server side WebTransport session
 
 use std::{
-    marker::PhantomData,
     pin::Pin,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
-use bytes::Buf;
-use futures_util::{future::poll_fn, ready, Future};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::{future::poll_fn, lock::Mutex as AsyncMutex, ready, Future};
 use h3::{
     connection::ConnectionState,
     error::{Code, ErrorLevel},
     ext::Protocol,
-    frame::FrameStream,
-    proto::frame::Frame,
-    quic::{self, OpenStreams, WriteBuf},
+    quic::{self, OpenStreams, StreamId, WriteBuf},
     server::Connection,
     server::RequestStream,
     Error,
@@ -27,51 +24,104 @@ use h3::{
 use h3_datagram::{
     datagram::Datagram,
     datagram_traits::HandleDatagramsExt,
-    quic_traits::{RecvDatagramExt, SendDatagramExt},
+    quic_traits::{RecvDatagramExt, SendDatagramError, SendDatagramExt},
 };
-use http::{Method, Request, Response, StatusCode};
+use http::{HeaderValue, Method, Request, Response, StatusCode};
 
-use h3::webtransport::SessionId;
+use h3::webtransport::{SessionId, WebTransportDraft};
 use pin_project_lite::pin_project;
 
-use crate::stream::{BidiStream, RecvStream, SendStream};
+use crate::{
+    capsule::Capsule,
+    demux::Shared,
+    error::ErrorCode,
+    flow::{FlowControl, Limits, SessionFlow},
+    stream::{BidiStream, RecvStream, SendStream},
+};
 
-/// WebTransport session driver.
-///
-/// Maintains the session using the underlying HTTP/3 connection.
+/// Manages the WebTransport sessions of an HTTP/3 connection
 ///
-/// Similar to [`h3::server::Connection`](https://docs.rs/h3/latest/h3/server/struct.Connection.html) it is generic over the QUIC implementation and Buffer.
-pub struct WebTransportSession<C, B>
+/// The connection is shared between the manager and every session established with
+/// [`SessionManager::accept_session()`]. Incoming streams and datagrams are routed to their
+/// session by [`SessionId`], while other requests are returned by [`SessionManager::accept()`].
+pub struct SessionManager<C, B>
 where
-    C: quic::Connection<B>,
-    Connection<C, B>: HandleDatagramsExt<C, B>,
+    C: quic::Connection<B> + RecvDatagramExt,
     B: Buf,
 {
-    // See: https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-2-3
-    session_id: SessionId,
-    /// The underlying HTTP/3 connection
-    server_conn: Mutex<Connection<C, B>>,
-    connect_stream: RequestStream<C::BidiStream, B>,
-    opener: Mutex<C::OpenStreams>,
+    shared: Arc<Shared<C, B>>,
 }
 
-impl<C, B> WebTransportSession<C, B>
+impl<C, B> SessionManager<C, B>
 where
-    Connection<C, B>: HandleDatagramsExt<C, B>,
-    C: quic::Connection<B>,
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
     B: Buf,
 {
-    /// Accepts a *CONNECT* request for establishing a WebTransport session.
+    /// Takes over an HTTP/3 connection to host WebTransport sessions
+    pub fn new(conn: Connection<C, B>) -> Self {
+        Self {
+            shared: Arc::new(Shared::new(conn)),
+        }
+    }
+
+    /// Accepts an incoming request which does not belong to a WebTransport session
     ///
-    /// TODO: is the API or the user responsible for validating the CONNECT request?
+    /// This includes the *CONNECT* requests establishing new sessions, which can be handed to
+    /// [`SessionManager::accept_session()`].
     pub async fn accept(
+        &self,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error> {
+        let request = poll_fn(|cx| self.shared.poll_with(cx, |state| state.pop_request())).await?;
+        match request {
+            Some(request) => Ok(Some(request.resolve().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Accepts a *CONNECT* request for establishing a WebTransport session.
+    ///
+    /// Responds with `200 OK`, see [`SessionManager::accept_session_with()`].
+    pub async fn accept_session(
+        &self,
+        request: Request<()>,
+        stream: RequestStream<C::BidiStream, B>,
+    ) -> Result<WebTransportSession<C, B>, Error>
+    where
+        Connection<C, B>: HandleDatagramsExt<C, B>,
+        C::BidiStream: quic::BidiStream<B>,
+    {
+        self.accept_session_with(request, stream, Response::new(()))
+            .await
+    }
+
+    /// Accepts a *CONNECT* request for establishing a WebTransport session with a custom
+    /// response.
+    ///
+    /// The application is responsible for inspecting the request beforehand, such as its
+    /// origin, path or offered protocols (see [`crate::protocol`]), and for rejecting it with
+    /// [`SessionManager::reject_session()`]. `response` may carry additional headers, like the
+    /// selected `wt-protocol`.
+    ///
+    /// Requests which are not WebTransport *CONNECT* requests are rejected with
+    /// `400 Bad Request`, and a `response` without a `2xx` status rejects the request.
+    pub async fn accept_session_with(
+        &self,
         request: Request<()>,
         mut stream: RequestStream<C::BidiStream, B>,
-        mut conn: Connection<C, B>,
-    ) -> Result<Self, Error> {
-        let shared = conn.shared_state().clone();
-        {
-            let config = shared.write("Read WebTransport support").peer_config;
+        mut response: Response<()>,
+    ) -> Result<WebTransportSession<C, B>, Error>
+    where
+        Connection<C, B>: HandleDatagramsExt<C, B>,
+        C::BidiStream: quic::BidiStream<B>,
+    {
+        let (max_sessions, draft, flow) = {
+            let mut state = self.shared.state.lock().unwrap();
+            let conn = &mut state.conn;
+            let config = conn
+                .shared_state()
+                .read("Read WebTransport support")
+                .peer_config;
 
             if !config.enable_webtransport() {
                 return Err(conn.close(
@@ -86,156 +136,307 @@ where
                     "datagrams are not supported by client",
                 ));
             }
+
+            // The peer is responsible for validating our side of the webtransport support.
+            //
+            // However, it is still advantageous to show a log on the server as (attempting) to
+            // establish a WebTransportSession without the proper h3 config is usually a mistake.
+            if !conn.inner.config.settings.enable_webtransport() {
+                tracing::warn!("Server does not support webtransport");
+            }
+
+            if !conn.inner.config.settings.enable_datagram() {
+                tracing::warn!("Server does not support datagrams");
+            }
+
+            if !conn.inner.config.settings.enable_extended_connect() {
+                tracing::warn!("Server does not support CONNECT");
+            }
+
+            let draft = config
+                .webtransport_draft()
+                .unwrap_or(WebTransportDraft::Draft02);
+            let local = &conn.inner.config.settings;
+            let flow = match draft {
+                WebTransportDraft::Draft02 => {
+                    FlowControl::new(Limits::UNLIMITED, Limits::UNLIMITED)
+                }
+                _ => FlowControl::new(
+                    Limits {
+                        max_data: config.wt_initial_max_data(),
+                        max_streams_bidi: config.wt_initial_max_streams_bidi(),
+                        max_streams_uni: config.wt_initial_max_streams_uni(),
+                    },
+                    Limits {
+                        max_data: local.wt_initial_max_data(),
+                        max_streams_bidi: local.wt_initial_max_streams_bidi(),
+                        max_streams_uni: local.wt_initial_max_streams_uni(),
+                    },
+                ),
+            };
+
+            let max = conn.inner.config.settings.max_webtransport_sessions();
+            let max_sessions = (max > 0 && state.session_count() as u64 >= max).then_some(max);
+            (max_sessions, draft, flow)
+        };
+
+        if let Some(max) = max_sessions {
+            self.reject_session(stream, StatusCode::TOO_MANY_REQUESTS)
+                .await?;
+            return Err(Error::application(
+                Code::H3_REQUEST_REJECTED,
+                format!("more than {} webtransport sessions", max),
+                ErrorLevel::StreamError,
+            ));
         }
 
-        // The peer is responsible for validating our side of the webtransport support.
-        //
-        // However, it is still advantageous to show a log on the server as (attempting) to
-        // establish a WebTransportSession without the proper h3 config is usually a mistake.
-        if !conn.inner.config.settings.enable_webtransport() {
-            tracing::warn!("Server does not support webtransport");
+        //= https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-3.3
+        if !validate_wt_connect(&request) {
+            self.reject_session(stream, StatusCode::BAD_REQUEST).await?;
+            return Err(Error::application(
+                Code::H3_REQUEST_REJECTED,
+                "not a webtransport CONNECT request",
+                ErrorLevel::StreamError,
+            ));
         }
 
-        if !conn.inner.config.settings.enable_datagram() {
-            tracing::warn!("Server does not support datagrams");
+        if !response.status().is_success() {
+            let status = response.status();
+            self.reject_session(stream, status).await?;
+            return Err(Error::application(
+                Code::H3_REQUEST_REJECTED,
+                format!("webtransport session rejected with {}", status),
+                ErrorLevel::StreamError,
+            ));
         }
 
-        if !conn.inner.config.settings.enable_extended_connect() {
-            tracing::warn!("Server does not support CONNECT");
+        // Older clients, such as chrome before the newer drafts, only accept the session if
+        // the response carries the draft version.
+        if draft == WebTransportDraft::Draft02 {
+            response
+                .headers_mut()
+                .entry("sec-webtransport-http3-draft")
+                .or_insert(HeaderValue::from_static("draft02"));
         }
 
-        // Respond to the CONNECT request.
+        // Register the session first, the client may use it as soon as it gets the response
+        let connect_stream_id = stream.id();
+        let session_id = connect_stream_id.into();
+        let flow = Arc::new(Mutex::new(flow));
+        self.shared
+            .add_session(session_id, connect_stream_id, flow.clone());
+        if let Err(err) = stream.send_response(response).await {
+            self.shared.remove_session(session_id);
+            return Err(err);
+        }
 
-        //= https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-3.3
-        let response = if validate_wt_connect(&request) {
-            Response::builder()
-                // This is the only header that chrome cares about.
-                .header("sec-webtransport-http3-draft", "draft02")
-                .status(StatusCode::OK)
-                .body(())
-                .unwrap()
-        } else {
-            Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(())
-                .unwrap()
-        };
+        let (send, recv) = stream.split();
+        Ok(WebTransportSession {
+            session_id,
+            shared: self.shared.clone(),
+            connect_stream_id,
+            connect_send: AsyncMutex::new(send),
+            connect_recv: Mutex::new(CapsuleReader {
+                stream: recv,
+                buf: BytesMut::new(),
+            }),
+            draft,
+            flow,
+            serve_requests: false,
+        })
+    }
 
+    /// Rejects a *CONNECT* request for establishing a WebTransport session with `status`
+    ///
+    /// The streams the peer opened for the session in the meantime are rejected as well.
+    pub async fn reject_session(
+        &self,
+        mut stream: RequestStream<C::BidiStream, B>,
+        status: StatusCode,
+    ) -> Result<(), Error> {
+        self.shared.refuse_session(stream.id().into());
+        let response = Response::builder().status(status).body(()).unwrap();
         stream.send_response(response).await?;
+        stream.finish().await
+    }
+}
+
+/// WebTransport session driver.
+///
+/// Maintains the session using the underlying HTTP/3 connection.
+///
+/// Similar to [`h3::server::Connection`](https://docs.rs/h3/latest/h3/server/struct.Connection.html) it is generic over the QUIC implementation and Buffer.
+pub struct WebTransportSession<C, B>
+where
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+    Connection<C, B>: HandleDatagramsExt<C, B>,
+    C::BidiStream: quic::BidiStream<B>,
+    B: Buf,
+{
+    // See: https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-2-3
+    session_id: SessionId,
+    /// The underlying HTTP/3 connection, shared with the other sessions
+    shared: Arc<Shared<C, B>>,
+    connect_stream_id: StreamId,
+    // Capsules are sent while others are being received, so the CONNECT stream is split
+    connect_send: AsyncMutex<RequestStream<SendHalf<C, B>, B>>,
+    connect_recv: Mutex<CapsuleReader<RecvHalf<C, B>, B>>,
+    draft: WebTransportDraft,
+    // Shared with the streams of the session and the connection routing them
+    flow: SessionFlow,
+    // Should the requests not belonging to a session be returned by `accept_bi`?
+    serve_requests: bool,
+}
 
-        let session_id = stream.send_id().into();
-        let conn_inner = &mut conn.inner.conn;
-        let opener = Mutex::new(conn_inner.opener());
+type SendHalf<C, B> = <<C as quic::OpenStreams<B>>::BidiStream as quic::BidiStream<B>>::SendStream;
+type RecvHalf<C, B> = <<C as quic::OpenStreams<B>>::BidiStream as quic::BidiStream<B>>::RecvStream;
 
-        Ok(Self {
-            session_id,
-            opener,
-            server_conn: Mutex::new(conn),
-            connect_stream: stream,
-        })
+/// Receiving side of the CONNECT stream, with the data of the capsules not complete yet
+struct CapsuleReader<S, B> {
+    stream: RequestStream<S, B>,
+    buf: BytesMut,
+}
+
+impl<C, B> WebTransportSession<C, B>
+where
+    Connection<C, B>: HandleDatagramsExt<C, B>,
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+    C::BidiStream: quic::BidiStream<B>,
+    B: Buf,
+{
+    /// Accepts a *CONNECT* request for establishing a WebTransport session.
+    ///
+    /// The session takes over the connection, and the other requests are returned by
+    /// [`WebTransportSession::accept_bi()`]. Use a [`SessionManager`] to host several sessions
+    /// on a connection.
+    pub async fn accept(
+        request: Request<()>,
+        stream: RequestStream<C::BidiStream, B>,
+        conn: Connection<C, B>,
+    ) -> Result<Self, Error> {
+        let manager = SessionManager::new(conn);
+        let mut session = manager.accept_session(request, stream).await?;
+        session.serve_requests = true;
+        Ok(session)
     }
 
     /// Receive a datagram from the client
     pub fn accept_datagram(&self) -> ReadDatagram<C, B> {
         ReadDatagram {
-            conn: &self.server_conn,
-            _marker: PhantomData,
+            shared: &self.shared,
+            session_id: self.session_id,
         }
     }
 
     /// Sends a datagram
     ///
-    /// TODO: maybe make async. `quinn` does not require an async send
-    pub fn send_datagram(&self, data: B) -> Result<(), Error>
+    /// Previously queued datagrams may be dropped to make room for this one.
+    pub fn send_datagram(&self, data: B) -> Result<(), SendDatagramError>
     where
         C: SendDatagramExt<B>,
     {
-        self.server_conn
+        self.shared
+            .state
             .lock()
             .unwrap()
-            .send_datagram(self.connect_stream.id(), data)?;
+            .conn
+            .send_datagram(self.connect_stream_id, data)
+    }
 
-        Ok(())
+    /// Sends a datagram, waiting for room in the send buffer
+    pub async fn send_datagram_wait(&self, data: B) -> Result<(), SendDatagramError>
+    where
+        C: SendDatagramExt<B>,
+    {
+        let mut datagram = Some(Datagram::new(self.connect_stream_id, data));
+        poll_fn(|cx| {
+            self.shared.poll_conn(cx, |conn, cx| {
+                let conn = &mut conn.inner.conn;
+                if let Some(data) = datagram.take() {
+                    // Datagrams queued by other sessions go first
+                    if conn
+                        .poll_send_datagram(cx)
+                        .map_err(Into::into)?
+                        .is_pending()
+                    {
+                        datagram = Some(data);
+                        return Poll::Pending;
+                    }
+                    conn.queue_datagram(data).map_err(Into::into)?;
+                }
+                conn.poll_send_datagram(cx).map_err(Into::into)
+            })
+        })
+        .await
+    }
+
+    /// Returns the maximum payload size of a datagram
+    ///
+    /// Returns `None` if datagrams are not supported by the peer or locally disabled.
+    pub fn max_datagram_size(&self) -> Option<usize>
+    where
+        C: SendDatagramExt<B>,
+    {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .conn
+            .max_datagram_size(self.connect_stream_id)
     }
 
     /// Accept an incoming unidirectional stream from the client, it reads the stream until EOF.
     pub fn accept_uni(&self) -> AcceptUni<C, B> {
         AcceptUni {
-            conn: &self.server_conn,
+            shared: &self.shared,
+            flow: &self.flow,
+            session_id: self.session_id,
         }
     }
 
     /// Accepts an incoming bidirectional stream or request
+    ///
+    /// Requests are only returned when the session was established with
+    /// [`WebTransportSession::accept()`], otherwise they are returned by
+    /// [`SessionManager::accept()`].
+    ///
+    /// Fails with `WT_FLOW_CONTROL_ERROR` once the client exceeded the limits of the session.
     pub async fn accept_bi(&self) -> Result<Option<AcceptedBi<C, B>>, Error> {
-        // Get the next stream
-        // Accept the incoming stream
-        let stream = poll_fn(|cx| {
-            let mut conn = self.server_conn.lock().unwrap();
-            conn.poll_accept_request_stream(cx)
+        let accepted = poll_fn(|cx| {
+            self.shared.poll_with(cx, |state| {
+                // Streams accepted within the limits are still handed out
+                if let Some(stream) = state.session(self.session_id)?.bidi.pop_front() {
+                    return Some(Ok(Ok(stream)));
+                }
+                if let Some(err) = self.flow.lock().unwrap().error() {
+                    return Some(Err(err));
+                }
+                if self.serve_requests {
+                    return state.pop_request().map(|request| Ok(Err(request)));
+                }
+                None
+            })
         })
-        .await;
+        .await?;
 
-        let mut stream = match stream {
-            Ok(Some(s)) => FrameStream::new(BufRecvStream::new(s)),
-            Ok(None) => {
-                // FIXME: is proper HTTP GoAway shutdown required?
-                return Ok(None);
-            }
-            Err(err) => {
-                match err.kind() {
-                    h3::error::Kind::Closed => return Ok(None),
-                    h3::error::Kind::Application {
-                        code,
-                        reason,
-                        level: ErrorLevel::ConnectionError,
-                        ..
-                    } => {
-                        return Err(self.server_conn.lock().unwrap().close(
-                            code,
-                            reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
-                        ))
-                    }
-                    _ => return Err(err),
-                };
-            }
-        };
-
-        // Read the first frame.
-        //
-        // This will determine if it is a webtransport bi-stream or a request stream
-        let frame = poll_fn(|cx| stream.poll_next(cx)).await;
-
-        match frame {
-            Ok(None) => Ok(None),
-            Ok(Some(Frame::WebTransportStream(session_id))) => {
-                // Take the stream out of the framed reader and split it in half like Paul Allen
-                let stream = stream.into_inner();
-
-                Ok(Some(AcceptedBi::BidiStream(
-                    session_id,
-                    BidiStream::new(stream),
-                )))
-            }
-            // Make the underlying HTTP/3 connection handle the rest
-            frame => {
-                let req = {
-                    let mut conn = self.server_conn.lock().unwrap();
-                    conn.accept_with_frame(stream, frame)?
-                };
-                if let Some(req) = req {
-                    let (req, resp) = req.resolve().await?;
-                    Ok(Some(AcceptedBi::Request(req, resp)))
-                } else {
-                    Ok(None)
-                }
+        match accepted.transpose()? {
+            Some(Ok(stream)) => Ok(Some(AcceptedBi::BidiStream(self.session_id, stream))),
+            Some(Err(request)) => {
+                let (request, stream) = request.resolve().await?;
+                Ok(Some(AcceptedBi::Request(request, stream)))
             }
+            None => Ok(None),
         }
     }
 
     /// Open a new bidirectional stream
     pub fn open_bi(&self, session_id: SessionId) -> OpenBi<C, B> {
         OpenBi {
-            opener: &self.opener,
+            opener: &self.shared.opener,
+            flow: &self.flow,
+            reserved: false,
             stream: None,
             session_id,
         }
@@ -244,7 +445,9 @@ where
     /// Open a new unidirectional stream
     pub fn open_uni(&self, session_id: SessionId) -> OpenUni<C, B> {
         OpenUni {
-            opener: &self.opener,
+            opener: &self.shared.opener,
+            flow: &self.flow,
+            reserved: false,
             stream: None,
             session_id,
         }
@@ -254,6 +457,135 @@ where
     pub fn session_id(&self) -> SessionId {
         self.session_id
     }
+
+    /// Returns the WebTransport draft negotiated with the client
+    pub fn webtransport_draft(&self) -> WebTransportDraft {
+        self.draft
+    }
+
+    /// Returns the amount of data the client allows on the streams of the session
+    ///
+    /// The limit is raised by the `WT_MAX_DATA` capsules of the client. It is unlimited with
+    /// drafts without session-level flow control.
+    pub fn peer_max_data(&self) -> u64 {
+        self.flow.lock().unwrap().peer_max_data()
+    }
+
+    /// Receives the next capsule sent by the client on the *CONNECT* stream
+    ///
+    /// The limits of the session are raised by the `WT_MAX_DATA` and `WT_MAX_STREAMS` capsules
+    /// received here, and the `WT_DATA_BLOCKED` and `WT_STREAMS_BLOCKED` capsules are sent
+    /// while waiting for the next one. This must be polled for the streams of the session to
+    /// make progress once the limits of the client are reached.
+    ///
+    /// Once the client exceeded the limits of the session, the *CONNECT* stream is stopped and
+    /// this fails with `WT_FLOW_CONTROL_ERROR`.
+    ///
+    /// Returns `None` once the client finished the stream.
+    pub async fn recv_capsule(&self) -> Result<Option<Capsule>, Error>
+    where
+        B: From<Bytes>,
+    {
+        loop {
+            let received = poll_fn(|cx| {
+                if let Some(err) = self.flow.lock().unwrap().error() {
+                    self.stop_connect_stream(Code::WT_FLOW_CONTROL_ERROR);
+                    return Poll::Ready(Err(err));
+                }
+                if let Poll::Ready(blocked) = self.flow.lock().unwrap().poll_blocked_capsule(cx) {
+                    return Poll::Ready(Ok(Err(blocked)));
+                }
+                self.poll_recv_capsule(cx).map_ok(Ok)
+            })
+            .await?;
+
+            match received {
+                Ok(capsule) => return Ok(capsule),
+                Err(blocked) => self.send_capsule(&blocked).await?,
+            }
+        }
+    }
+
+    fn poll_recv_capsule(&self, cx: &mut Context<'_>) -> Poll<Result<Option<Capsule>, Error>> {
+        let mut reader = self.connect_recv.lock().unwrap();
+        let reader = &mut *reader;
+        loop {
+            if let Some(capsule) = Capsule::decode(&mut reader.buf)? {
+                self.flow.lock().unwrap().on_capsule(&capsule);
+                return Poll::Ready(Ok(Some(capsule)));
+            }
+            match ready!(reader.stream.poll_recv_data(cx))? {
+                Some(mut data) => {
+                    while data.has_remaining() {
+                        let chunk = data.chunk();
+                        reader.buf.extend_from_slice(chunk);
+                        let len = chunk.len();
+                        data.advance(len);
+                    }
+                }
+                None if reader.buf.is_empty() => return Poll::Ready(Ok(None)),
+                None => {
+                    return Poll::Ready(Err(Error::application(
+                        Code::H3_DATAGRAM_ERROR,
+                        "connect stream ended with a partial capsule",
+                        ErrorLevel::ConnectionError,
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Stops both sides of the *CONNECT* stream, terminating the session
+    fn stop_connect_stream(&self, code: Code) {
+        self.connect_recv.lock().unwrap().stream.stop_sending(code);
+        // A capsule being sent is cut short by the reset anyway
+        if let Some(mut send) = self.connect_send.try_lock() {
+            send.stop_stream(code);
+        }
+    }
+
+    /// Sends a capsule on the *CONNECT* stream
+    ///
+    /// Used to grant the client more data or streams with `WT_MAX_DATA` and `WT_MAX_STREAMS`
+    /// capsules, when the session was negotiated with a draft supporting them. The limits
+    /// enforced on the client are raised once the capsule is sent.
+    pub async fn send_capsule(&self, capsule: &Capsule) -> Result<(), Error>
+    where
+        B: From<Bytes>,
+    {
+        let mut buf = BytesMut::new();
+        capsule.encode(&mut buf);
+        let mut stream = self.connect_send.lock().await;
+        stream.send_data(buf.freeze().into()).await?;
+        self.flow.lock().unwrap().on_sent_capsule(capsule);
+        Ok(())
+    }
+
+    /// Closes the session with an application error code and a reason
+    ///
+    /// Sends a `CLOSE_WEBTRANSPORT_SESSION` capsule on the CONNECT stream, then finishes it.
+    /// The reason is truncated to 1024 bytes.
+    pub async fn close(self, code: ErrorCode, reason: &str) -> Result<(), Error>
+    where
+        B: From<Bytes>,
+    {
+        self.send_capsule(&Capsule::close_session(code, reason))
+            .await?;
+        self.connect_send.lock().await.finish().await
+    }
+}
+
+impl<C, B> Drop for WebTransportSession<C, B>
+where
+    Connection<C, B>: HandleDatagramsExt<C, B>,
+    C: quic::Connection<B> + RecvDatagramExt,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
+    C::BidiStream: quic::BidiStream<B>,
+    B: Buf,
+{
+    fn drop(&mut self) {
+        self.shared.remove_session(self.session_id);
+    }
 }
 
 /// Streams are opened, but the initial webtransport header has not been sent
@@ -272,6 +604,9 @@ pin_project! {
     /// Future for opening a bidi stream
     pub struct OpenBi<'a, C:quic::Connection<B>, B:Buf> {
         opener: &'a Mutex<C::OpenStreams>,
+        flow: &'a SessionFlow,
+        // Whether the stream was counted against the limit of the peer
+        reserved: bool,
         stream: Option<PendingStreams<C,B>>,
         session_id: SessionId,
     }
@@ -295,9 +630,14 @@ where
                     }
 
                     let (stream, _) = p.stream.take().unwrap();
-                    return Poll::Ready(Ok(stream));
+                    // The header of the stream is not subject to flow control
+                    return Poll::Ready(Ok(stream.with_flow((*p.flow).clone())));
                 }
                 None => {
+                    if !*p.reserved {
+                        ready!(p.flow.lock().unwrap().poll_reserve(cx, true));
+                        *p.reserved = true;
+                    }
                     let mut opener = (*p.opener).lock().unwrap();
                     // Open the stream first
                     let res = ready!(opener.poll_open_bidi(cx))?;
@@ -315,6 +655,9 @@ pin_project! {
     /// Opens a unidirectional stream
     pub struct OpenUni<'a, C: quic::Connection<B>, B:Buf> {
         opener: &'a Mutex<C::OpenStreams>,
+        flow: &'a SessionFlow,
+        // Whether the stream was counted against the limit of the peer
+        reserved: bool,
         stream: Option<PendingUniStreams<C, B>>,
         // Future for opening a uni stream
         session_id: SessionId,
@@ -339,9 +682,14 @@ where
                     }
                     let (send, buf) = p.stream.take().unwrap();
                     assert!(!buf.has_remaining());
-                    return Poll::Ready(Ok(send));
+                    // The header of the stream is not subject to flow control
+                    return Poll::Ready(Ok(send.with_flow((*p.flow).clone())));
                 }
                 None => {
+                    if !*p.reserved {
+                        ready!(p.flow.lock().unwrap().poll_reserve(cx, false));
+                        *p.reserved = true;
+                    }
                     let mut opener = (*p.opener).lock().unwrap();
                     let send = ready!(opener.poll_open_send(cx))?;
                     let send = BufRecvStream::new(send);
@@ -367,14 +715,14 @@ pub enum AcceptedBi<C: quic::Connection<B>, B: Buf> {
     Request(Request<()>, RequestStream<C::BidiStream, B>),
 }
 
-/// Future for [`Connection::read_datagram`]
+/// Future for [`WebTransportSession::accept_datagram`]
 pub struct ReadDatagram<'a, C, B>
 where
-    C: quic::Connection<B>,
+    C: quic::Connection<B> + RecvDatagramExt,
     B: Buf,
 {
-    conn: &'a Mutex<Connection<C, B>>,
-    _marker: PhantomData<B>,
+    shared: &'a Shared<C, B>,
+    session_id: SessionId,
 }
 
 impl<'a, C, B> Future for ReadDatagram<'a, C, B>
@@ -386,47 +734,43 @@ where
     type Output = Result<Option<(SessionId, C::Buf)>, Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut conn = self.conn.lock().unwrap();
-        match ready!(conn.inner.conn.poll_accept_datagram(cx))? {
-            Some(v) => {
-                let datagram = Datagram::decode(v)?;
-                Poll::Ready(Ok(Some((
-                    datagram.stream_id().into(),
-                    datagram.into_payload(),
-                ))))
-            }
-            None => Poll::Ready(Ok(None)),
-        }
+        let session_id = self.session_id;
+        let datagram = ready!(self.shared.poll_with(cx, |state| {
+            state.session(session_id)?.datagrams.try_recv()
+        }))?;
+        Poll::Ready(Ok(datagram.map(|datagram| (session_id, datagram))))
     }
 }
 
 /// Future for [`WebTransportSession::accept_uni`]
 pub struct AcceptUni<'a, C, B>
 where
-    C: quic::Connection<B>,
+    C: quic::Connection<B> + RecvDatagramExt,
     B: Buf,
 {
-    conn: &'a Mutex<Connection<C, B>>,
+    shared: &'a Shared<C, B>,
+    flow: &'a SessionFlow,
+    session_id: SessionId,
 }
 
 impl<'a, C, B> Future for AcceptUni<'a, C, B>
 where
-    C: quic::Connection<B>,
+    C: quic::Connection<B> + RecvDatagramExt,
     B: Buf,
+    <C as RecvDatagramExt>::Error: h3::quic::Error + 'static,
 {
     type Output = Result<Option<(SessionId, RecvStream<C::RecvStream, B>)>, Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut conn = self.conn.lock().unwrap();
-        conn.inner.poll_accept_recv(cx)?;
-
-        // Get the currently available streams
-        let streams = conn.inner.accepted_streams_mut();
-        if let Some((id, stream)) = streams.wt_uni_streams.pop() {
-            return Poll::Ready(Ok(Some((id, RecvStream::new(stream)))));
-        }
-
-        Poll::Pending
+        let session_id = self.session_id;
+        let stream = ready!(self.shared.poll_with(cx, |state| {
+            // Streams accepted within the limits are still handed out
+            if let Some(stream) = state.session(session_id)?.uni.pop_front() {
+                return Some(Ok(stream));
+            }
+            self.flow.lock().unwrap().error().map(Err)
+        }))?;
+        Poll::Ready(Ok(stream.transpose()?.map(|stream| (session_id, stream))))
     }
 }
 
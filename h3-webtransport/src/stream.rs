@@ -1,22 +1,35 @@
-use std::task::Poll;
+use std::task::{ready, Context, Poll};
 
 use bytes::{Buf, Bytes};
 use h3::{quic, stream::BufRecvStream};
 use pin_project_lite::pin_project;
 use tokio::io::ReadBuf;
 
+use crate::{
+    error::{ErrorCode, FlowControlError, StreamError},
+    flow::SessionFlow,
+};
+
 pin_project! {
     /// WebTransport receive stream
     pub struct RecvStream<S,B> {
         #[pin]
         stream: BufRecvStream<S, B>,
+        // Flow control of the session the stream belongs to
+        flow: Option<SessionFlow>,
     }
 }
 
 impl<S, B> RecvStream<S, B> {
     #[allow(missing_docs)]
     pub fn new(stream: BufRecvStream<S, B>) -> Self {
-        Self { stream }
+        Self { stream, flow: None }
+    }
+
+    /// Counts the data received against the limit of the session
+    pub(crate) fn with_flow(mut self, flow: SessionFlow) -> Self {
+        self.flow = Some(flow);
+        self
     }
 }
 
@@ -27,17 +40,19 @@ where
 {
     type Buf = Bytes;
 
-    type Error = S::Error;
+    type Error = StreamError;
 
     fn poll_data(
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
-        self.stream.poll_data(cx)
+        poll_data_limited(&mut self.stream, &self.flow, cx)
     }
 
+    /// Stops the stream with a WebTransport application error code
     fn stop_sending(&mut self, error_code: u64) {
-        self.stream.stop_sending(error_code)
+        self.stream
+            .stop_sending(ErrorCode::from_stream_code(error_code).to_http3())
     }
 
     fn recv_id(&self) -> quic::StreamId {
@@ -55,7 +70,9 @@ where
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_read(cx, buf)
+        let len = ready!(p.stream.poll_read(cx, buf))?;
+        count_recv(p.flow, len)?;
+        Poll::Ready(Ok(len))
     }
 }
 
@@ -69,7 +86,13 @@ where
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         let p = self.project();
-        p.stream.poll_read(cx, buf)
+        let filled = buf.filled().len();
+        ready!(p.stream.poll_read(cx, buf))?;
+        if let Err(err) = count_recv(p.flow, buf.filled().len() - filled) {
+            buf.set_filled(filled);
+            return Poll::Ready(Err(err.into()));
+        }
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -78,6 +101,8 @@ pin_project! {
     pub struct SendStream<S,B> {
         #[pin]
         stream: BufRecvStream<S ,B>,
+        // Flow control of the session the stream belongs to
+        flow: Option<SessionFlow>,
     }
 }
 
@@ -92,7 +117,44 @@ impl<S, B> std::fmt::Debug for SendStream<S, B> {
 impl<S, B> SendStream<S, B> {
     #[allow(missing_docs)]
     pub(crate) fn new(stream: BufRecvStream<S, B>) -> Self {
-        Self { stream }
+        Self { stream, flow: None }
+    }
+
+    /// Counts the data sent against the limit of the session
+    pub(crate) fn with_flow(mut self, flow: SessionFlow) -> Self {
+        self.flow = Some(flow);
+        self
+    }
+}
+
+impl<S, B> quic::SendStreamPriority<B> for SendStream<S, B>
+where
+    S: quic::SendStreamPriority<B>,
+    B: Buf,
+{
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error> {
+        self.stream.set_priority(priority).map_err(StreamError::new)
+    }
+
+    fn priority(&self) -> Result<i32, Self::Error> {
+        self.stream.priority().map_err(StreamError::new)
+    }
+}
+
+impl<S, B> SendStream<S, B>
+where
+    S: quic::SendStreamPriority<B>,
+    B: Buf,
+{
+    /// Sets the send order of the stream, like `sendOrder` in the WebTransport API of browsers
+    ///
+    /// Data of streams with a higher send order is sent first, so that urgent streams can
+    /// preempt bulk transfers. The order is clamped to the priorities of the QUIC stream.
+    ///
+    /// There is no equivalent of `sendGroup`: the order applies across every stream of the
+    /// connection.
+    pub fn set_send_order(&mut self, send_order: i64) -> Result<(), StreamError> {
+        quic::SendStreamPriority::set_priority(self, clamp_send_order(send_order))
     }
 }
 
@@ -106,7 +168,11 @@ where
         cx: &mut std::task::Context<'_>,
         buf: &mut D,
     ) -> Poll<Result<usize, Self::Error>> {
-        self.stream.poll_send(cx, buf)
+        let stream = &mut self.stream;
+        poll_send_limited(&self.flow, cx, buf.remaining(), |cx, len| {
+            let mut buf = Buf::take(&mut *buf, len);
+            stream.poll_send(cx, &mut buf).map_err(StreamError::new)
+        })
     }
 }
 
@@ -115,14 +181,16 @@ where
     S: quic::SendStream<B>,
     B: Buf,
 {
-    type Error = S::Error;
+    type Error = StreamError;
 
     fn poll_finish(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_finish(cx)
+        self.stream.poll_finish(cx).map_err(StreamError::new)
     }
 
+    /// Resets the stream with a WebTransport application error code
     fn reset(&mut self, reset_code: u64) {
-        self.stream.reset(reset_code)
+        self.stream
+            .reset(ErrorCode::from_stream_code(reset_code).to_http3())
     }
 
     fn send_id(&self) -> quic::StreamId {
@@ -130,11 +198,18 @@ where
     }
 
     fn send_data<T: Into<h3::stream::WriteBuf<B>>>(&mut self, data: T) -> Result<(), Self::Error> {
-        self.stream.send_data(data)
+        let data = data.into();
+        if let Some(flow) = &self.flow {
+            flow.lock()
+                .unwrap()
+                .send(data.remaining() as u64)
+                .map_err(StreamError::new)?;
+        }
+        self.stream.send_data(data).map_err(StreamError::new)
     }
 
     fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_ready(cx)
+        self.stream.poll_ready(cx).map_err(StreamError::new)
     }
 }
 
@@ -148,7 +223,10 @@ where
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_write(cx, buf)
+        let stream = p.stream;
+        poll_send_limited(p.flow, cx, buf.len(), |cx, len| {
+            stream.poll_write(cx, &buf[..len])
+        })
     }
 
     fn poll_flush(
@@ -178,7 +256,10 @@ where
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_write(cx, buf)
+        let stream = p.stream;
+        poll_send_limited(p.flow, cx, buf.len(), |cx, len| {
+            stream.poll_write(cx, &buf[..len])
+        })
     }
 
     fn poll_flush(
@@ -206,12 +287,20 @@ pin_project! {
     pub struct BidiStream<S, B> {
         #[pin]
         stream: BufRecvStream<S, B>,
+        // Flow control of the session the stream belongs to
+        flow: Option<SessionFlow>,
     }
 }
 
 impl<S, B> BidiStream<S, B> {
     pub(crate) fn new(stream: BufRecvStream<S, B>) -> Self {
-        Self { stream }
+        Self { stream, flow: None }
+    }
+
+    /// Counts the data sent and received against the limits of the session
+    pub(crate) fn with_flow(mut self, flow: SessionFlow) -> Self {
+        self.flow = Some(flow);
+        self
     }
 }
 
@@ -220,14 +309,16 @@ where
     S: quic::SendStream<B>,
     B: Buf,
 {
-    type Error = S::Error;
+    type Error = StreamError;
 
     fn poll_finish(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_finish(cx)
+        self.stream.poll_finish(cx).map_err(StreamError::new)
     }
 
+    /// Resets the stream with a WebTransport application error code
     fn reset(&mut self, reset_code: u64) {
-        self.stream.reset(reset_code)
+        self.stream
+            .reset(ErrorCode::from_stream_code(reset_code).to_http3())
     }
 
     fn send_id(&self) -> quic::StreamId {
@@ -235,11 +326,49 @@ where
     }
 
     fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.stream.poll_ready(cx)
+        self.stream.poll_ready(cx).map_err(StreamError::new)
     }
 
     fn send_data<T: Into<h3::stream::WriteBuf<B>>>(&mut self, data: T) -> Result<(), Self::Error> {
-        self.stream.send_data(data)
+        let data = data.into();
+        if let Some(flow) = &self.flow {
+            flow.lock()
+                .unwrap()
+                .send(data.remaining() as u64)
+                .map_err(StreamError::new)?;
+        }
+        self.stream.send_data(data).map_err(StreamError::new)
+    }
+}
+
+impl<S, B> quic::SendStreamPriority<B> for BidiStream<S, B>
+where
+    S: quic::SendStreamPriority<B>,
+    B: Buf,
+{
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error> {
+        self.stream.set_priority(priority).map_err(StreamError::new)
+    }
+
+    fn priority(&self) -> Result<i32, Self::Error> {
+        self.stream.priority().map_err(StreamError::new)
+    }
+}
+
+impl<S, B> BidiStream<S, B>
+where
+    S: quic::SendStreamPriority<B>,
+    B: Buf,
+{
+    /// Sets the send order of the stream, like `sendOrder` in the WebTransport API of browsers
+    ///
+    /// Data of streams with a higher send order is sent first, so that urgent streams can
+    /// preempt bulk transfers. The order is clamped to the priorities of the QUIC stream.
+    ///
+    /// There is no equivalent of `sendGroup`: the order applies across every stream of the
+    /// connection.
+    pub fn set_send_order(&mut self, send_order: i64) -> Result<(), StreamError> {
+        quic::SendStreamPriority::set_priority(self, clamp_send_order(send_order))
     }
 }
 
@@ -253,24 +382,30 @@ where
         cx: &mut std::task::Context<'_>,
         buf: &mut D,
     ) -> Poll<Result<usize, Self::Error>> {
-        self.stream.poll_send(cx, buf)
+        let stream = &mut self.stream;
+        poll_send_limited(&self.flow, cx, buf.remaining(), |cx, len| {
+            let mut buf = Buf::take(&mut *buf, len);
+            stream.poll_send(cx, &mut buf).map_err(StreamError::new)
+        })
     }
 }
 
 impl<S: quic::RecvStream, B> quic::RecvStream for BidiStream<S, B> {
     type Buf = Bytes;
 
-    type Error = S::Error;
+    type Error = StreamError;
 
     fn poll_data(
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
-        self.stream.poll_data(cx)
+        poll_data_limited(&mut self.stream, &self.flow, cx)
     }
 
+    /// Stops the stream with a WebTransport application error code
     fn stop_sending(&mut self, error_code: u64) {
-        self.stream.stop_sending(error_code)
+        self.stream
+            .stop_sending(ErrorCode::from_stream_code(error_code).to_http3())
     }
 
     fn recv_id(&self) -> quic::StreamId {
@@ -289,7 +424,10 @@ where
 
     fn split(self) -> (Self::SendStream, Self::RecvStream) {
         let (send, recv) = self.stream.split();
-        (SendStream::new(send), RecvStream::new(recv))
+        let (mut send, mut recv) = (SendStream::new(send), RecvStream::new(recv));
+        send.flow.clone_from(&self.flow);
+        recv.flow = self.flow;
+        (send, recv)
     }
 }
 
@@ -303,7 +441,9 @@ where
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_read(cx, buf)
+        let len = ready!(p.stream.poll_read(cx, buf))?;
+        count_recv(p.flow, len)?;
+        Poll::Ready(Ok(len))
     }
 }
 
@@ -317,7 +457,10 @@ where
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_write(cx, buf)
+        let stream = p.stream;
+        poll_send_limited(p.flow, cx, buf.len(), |cx, len| {
+            stream.poll_write(cx, &buf[..len])
+        })
     }
 
     fn poll_flush(
@@ -347,7 +490,13 @@ where
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         let p = self.project();
-        p.stream.poll_read(cx, buf)
+        let filled = buf.filled().len();
+        ready!(p.stream.poll_read(cx, buf))?;
+        if let Err(err) = count_recv(p.flow, buf.filled().len() - filled) {
+            buf.set_filled(filled);
+            return Poll::Ready(Err(err.into()));
+        }
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -361,7 +510,10 @@ where
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         let p = self.project();
-        p.stream.poll_write(cx, buf)
+        let stream = p.stream;
+        poll_send_limited(p.flow, cx, buf.len(), |cx, len| {
+            stream.poll_write(cx, &buf[..len])
+        })
     }
 
     fn poll_flush(
@@ -380,3 +532,64 @@ where
         p.stream.poll_shutdown(cx)
     }
 }
+
+/// Receives data, counting it against the limit of the session
+fn poll_data_limited<S, B>(
+    stream: &mut BufRecvStream<S, B>,
+    flow: &Option<SessionFlow>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<Option<Bytes>, StreamError>>
+where
+    S: quic::RecvStream,
+{
+    let data = ready!(quic::RecvStream::poll_data(stream, cx)).map_err(StreamError::new)?;
+    if let Some(data) = &data {
+        count_recv(flow, data.len()).map_err(StreamError::new)?;
+    }
+    Poll::Ready(Ok(data))
+}
+
+fn count_recv(flow: &Option<SessionFlow>, len: usize) -> Result<(), FlowControlError> {
+    match flow {
+        Some(flow) => flow.lock().unwrap().recv(len as u64),
+        None => Ok(()),
+    }
+}
+
+/// Sends at most `len` bytes with `send`, once the data limit of the session allows it
+///
+/// `send` is given how much it may send.
+fn poll_send_limited<E>(
+    flow: &Option<SessionFlow>,
+    cx: &mut Context<'_>,
+    len: usize,
+    send: impl FnOnce(&mut Context<'_>, usize) -> Poll<Result<usize, E>>,
+) -> Poll<Result<usize, E>> {
+    let Some(flow) = flow else {
+        return send(cx, len);
+    };
+    let reserved = ready!(flow.lock().unwrap().poll_reserve_data(cx, len as u64)) as usize;
+    let res = send(cx, reserved);
+    let sent = match res {
+        Poll::Ready(Ok(sent)) => sent,
+        _ => 0,
+    };
+    flow.lock().unwrap().release_data((reserved - sent) as u64);
+    res
+}
+
+fn clamp_send_order(send_order: i64) -> i32 {
+    send_order.clamp(i32::MIN.into(), i32::MAX.into()) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_order_is_clamped() {
+        assert_eq!(clamp_send_order(-3), -3);
+        assert_eq!(clamp_send_order(i64::MAX), i32::MAX);
+        assert_eq!(clamp_send_order(i64::MIN), i32::MIN);
+    }
+}
@@ -0,0 +1,48 @@
+//! Header fields of a typical request and response
+
+use h3::qpack::HeaderField;
+
+pub type Fields = Vec<HeaderField>;
+
+fn fields(fields: &[(&str, &str)]) -> Fields {
+    fields
+        .iter()
+        .map(|&field| HeaderField::from(field))
+        .collect()
+}
+
+pub fn request() -> Fields {
+    fields(&[
+        (":method", "GET"),
+        (":scheme", "https"),
+        (":authority", "www.example.com"),
+        (":path", "/index.html?query=value&other=12345"),
+        (
+            "user-agent",
+            "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Firefox/115.0",
+        ),
+        ("accept", "text/html,application/xhtml+xml,*/*;q=0.8"),
+        ("accept-language", "en-US,en;q=0.5"),
+        ("accept-encoding", "gzip, deflate, br"),
+        ("x-request-id", "c9b0f6e4-41a2-4b8e-9d3c-5f6e2a1b7c8d"),
+    ])
+}
+
+pub fn response() -> Fields {
+    fields(&[
+        (":status", "200"),
+        ("content-type", "text/html; charset=utf-8"),
+        ("content-length", "48213"),
+        ("date", "Mon, 19 Oct 2026 10:00:00 GMT"),
+        ("cache-control", "private, max-age=0"),
+        (
+            "set-cookie",
+            "session=4f8a9c2d7e1b6a3f5c0d9e8b7a6f5e4d3c2b1a0f; Path=/; Secure; HttpOnly",
+        ),
+        ("server", "h3"),
+    ])
+}
+
+pub fn cases() -> [(&'static str, Fields); 2] {
+    [("request", request()), ("response", response())]
+}
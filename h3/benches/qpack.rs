@@ -0,0 +1,48 @@
+//! Benchmarks of the stateless QPACK codec, which Huffman codes every string
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use h3::qpack::{decode_stateless, encode_stateless};
+
+mod fixtures;
+
+use fixtures::{cases, Fields};
+
+// Total length of the names and values
+fn size(fields: &Fields) -> usize {
+    fields.iter().map(|f| f.name.len() + f.value.len()).sum()
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_stateless");
+    for (name, fields) in cases() {
+        group.throughput(Throughput::Bytes(size(&fields) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &fields, |b, fields| {
+            let mut block = Vec::with_capacity(1024);
+            b.iter(|| {
+                block.clear();
+                encode_stateless(&mut block, black_box(fields)).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_stateless");
+    for (name, fields) in cases() {
+        let mut block = Vec::new();
+        encode_stateless(&mut block, &fields).unwrap();
+        let block = Bytes::from(block);
+
+        group.throughput(Throughput::Bytes(size(&fields) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &block, |b, block| {
+            b.iter(|| decode_stateless(&mut black_box(block).clone(), u64::MAX).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encoding, decoding);
+criterion_main!(benches);
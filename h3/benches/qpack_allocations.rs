@@ -0,0 +1,99 @@
+//! Counts the allocations made to decode a header block into the `http` types handed to the
+//! application, from the QPACK block to the `HeaderMap`
+//!
+//! Run with `cargo bench --bench qpack_allocations --features <backend feature>`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::Bytes;
+
+use h3::{
+    proto::headers::Header,
+    qpack::{decode_stateless, encode_stateless},
+};
+
+mod fixtures;
+
+use fixtures::Fields;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: usize = 10_000;
+
+fn decode(block: &Bytes) -> usize {
+    let decoded = decode_stateless(&mut block.clone(), u64::MAX).unwrap();
+    decoded.fields.len()
+}
+
+// Down to the `http` types handed to the application, as a server connection does
+fn decode_request(block: &Bytes) -> usize {
+    let decoded = decode_stateless(&mut block.clone(), u64::MAX).unwrap();
+    let header = Header::try_from(decoded.fields).unwrap();
+    header.into_request_parts().unwrap().3.len()
+}
+
+// Down to the `http` types handed to the application, as a client connection does
+fn decode_response(block: &Bytes) -> usize {
+    let decoded = decode_stateless(&mut block.clone(), u64::MAX).unwrap();
+    let header = Header::try_from(decoded.fields).unwrap();
+    header.into_response_parts().unwrap().1.len()
+}
+
+fn measure(name: &str, fields: &Fields, decode: fn(&Bytes) -> usize) {
+    let mut block = Vec::new();
+    encode_stateless(&mut block, fields).unwrap();
+    let block = Bytes::from(block);
+
+    let (allocations, allocated) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    for _ in 0..ITERATIONS {
+        black_box(decode(black_box(&block)));
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - allocated;
+
+    println!(
+        "{:<24} {:>6.1} allocations {:>6} bytes per block",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        allocated / ITERATIONS,
+    );
+}
+
+fn main() {
+    for (name, fields) in fixtures::cases() {
+        measure(&format!("decode_stateless/{}", name), &fields, decode);
+    }
+    measure("request", &fixtures::request(), decode_request);
+    measure("response", &fixtures::response(), decode_response);
+}
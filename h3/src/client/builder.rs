@@ -16,7 +16,10 @@ use crate::{
     quic::{self},
 };
 
-use super::connection::{Connection, SendRequest};
+use super::{
+    connection::{Connection, SendRequest},
+    in_flight::InFlight,
+};
 
 /// Start building a new HTTP/3 client
 pub fn builder() -> Builder {
@@ -87,6 +90,36 @@ impl Builder {
         self
     }
 
+    /// Set the capacity of the QPACK dynamic table the server may use to compress the headers
+    /// it sends
+    ///
+    /// Advertised as `SETTINGS_QPACK_MAX_TABLE_CAPACITY`. Zero by default, the server then
+    /// only references the static table.
+    pub fn qpack_max_table_capacity(&mut self, value: u64) -> &mut Self {
+        self.config.settings.qpack_max_table_capacity = value;
+        self
+    }
+
+    /// Set the number of streams the server may block, referencing QPACK dynamic table entries
+    /// not received yet
+    ///
+    /// Advertised as `SETTINGS_QPACK_BLOCKED_STREAMS`. Zero by default, the server then only
+    /// references entries the decoder acknowledged.
+    pub fn qpack_max_blocked_streams(&mut self, value: u64) -> &mut Self {
+        self.config.settings.qpack_max_blocked_streams = value;
+        self
+    }
+
+    /// Set the capacity of the QPACK dynamic table used to compress the headers sent to the
+    /// server
+    ///
+    /// The table is bounded by the capacity the server advertises. Zero by default, only the
+    /// static table is then referenced.
+    pub fn qpack_encoder_table_capacity(&mut self, value: u64) -> &mut Self {
+        self.config.qpack_encoder_table_capacity = value;
+        self
+    }
+
     /// Just like in HTTP/2, HTTP/3 also uses the concept of "grease"
     /// to prevent potential interoperability issues in the future.
     /// In HTTP/3, the concept of grease is used to ensure that the protocol can evolve
@@ -96,6 +129,32 @@ impl Builder {
         self
     }
 
+    /// Indicates that the client supports WebTransport sessions
+    ///
+    /// Opening WebTransport sessions also requires enabling datagrams with
+    /// [`Builder::enable_datagram`].
+    pub fn enable_webtransport(&mut self, value: bool) -> &mut Self {
+        self.config.settings.enable_webtransport = value;
+        self
+    }
+
+    /// Sets the maximum number of WebTransport sessions advertised to the server
+    ///
+    /// The newer WebTransport drafts, with session-level flow control, are only offered to the
+    /// server with a non zero limit.
+    pub fn max_webtransport_sessions(&mut self, value: u64) -> &mut Self {
+        self.config.settings.max_webtransport_sessions = value;
+        self
+    }
+
+    /// Indicates that the client supports HTTP/3 datagrams
+    ///
+    /// See: <https://www.rfc-editor.org/rfc/rfc9297#section-2.1.1>
+    pub fn enable_datagram(&mut self, value: bool) -> &mut Self {
+        self.config.settings.enable_datagram = value;
+        self
+    }
+
     /// Create a new HTTP/3 client from a `quic` connection
     pub async fn build<C, O, B>(
         &mut self,
@@ -108,6 +167,7 @@ impl Builder {
     {
         let open = quic.opener();
         let conn_state = SharedStateRef::default();
+        let in_flight = InFlight::default();
 
         let conn_waker = Some(future::poll_fn(|cx| Poll::Ready(cx.waker().clone())).await);
 
@@ -116,6 +176,7 @@ impl Builder {
                 inner: ConnectionInner::new(quic, conn_state.clone(), self.config).await?,
                 sent_closing: None,
                 recv_closing: None,
+                in_flight: in_flight.clone(),
             },
             SendRequest {
                 open,
@@ -124,6 +185,7 @@ impl Builder {
                 max_field_section_size: self.config.settings.max_field_section_size,
                 sender_count: Arc::new(AtomicUsize::new(1)),
                 send_grease_frame: self.config.send_grease,
+                in_flight,
                 _buf: PhantomData,
             },
         ))
@@ -1,14 +1,16 @@
 //! Client implementation of the HTTP/3 protocol
 
 use std::{
+    future::Future,
     marker::PhantomData,
+    pin::pin,
     sync::{atomic::AtomicUsize, Arc},
     task::{Context, Poll, Waker},
 };
 
 use bytes::{Buf, BytesMut};
 use futures_util::future;
-use http::request;
+use http::{request, Method};
 
 #[cfg(feature = "tracing")]
 use tracing::{info, instrument, trace};
@@ -17,13 +19,17 @@ use crate::{
     connection::{self, ConnectionInner, ConnectionState, SharedStateRef},
     error::{Code, Error, ErrorLevel},
     frame::FrameStream,
-    proto::{frame::Frame, headers::Header, push::PushId},
+    proto::{
+        frame::Frame,
+        headers::{content_length, Header},
+        push::PushId,
+    },
     qpack,
     quic::{self, StreamId},
     stream::{self, BufRecvStream},
 };
 
-use super::stream::RequestStream;
+use super::{in_flight::InFlight, stream::RequestStream};
 
 /// HTTP/3 request sender
 ///
@@ -115,6 +121,7 @@ where
     pub(super) conn_waker: Option<Waker>,
     pub(super) _buf: PhantomData<fn(B)>,
     pub(super) send_grease_frame: bool,
+    pub(super) in_flight: InFlight,
 }
 
 impl<T, B> SendRequest<T, B>
@@ -128,12 +135,7 @@ where
         &mut self,
         req: http::Request<()>,
     ) -> Result<RequestStream<T::BidiStream, B>, Error> {
-        let (peer_max_field_section_size, closing) = {
-            let state = self.conn_state.read("send request lock state");
-            (state.peer_config.max_field_section_size, state.closing)
-        };
-
-        if closing {
+        if self.conn_state.read("send request lock state").closing {
             return Err(Error::closing());
         }
 
@@ -145,6 +147,8 @@ where
             extensions,
             ..
         } = parts;
+        let is_head = method == Method::HEAD;
+        let content_length = content_length(&headers)?;
         let headers = Header::request(method, uri, headers, extensions)?;
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
@@ -154,6 +158,7 @@ where
         let mut stream = future::poll_fn(|cx| self.open.poll_open_bidi(cx))
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
+        let in_flight = self.in_flight.track(quic::SendStream::send_id(&stream));
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2
         //= type=TODO
@@ -167,33 +172,39 @@ where
         //# more cookie-pairs, before compression.
 
         let mut block = BytesMut::new();
-        let mem_size = qpack::encode_stateless(&mut block, headers)?;
-
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
-        if mem_size > peer_max_field_section_size {
-            return Err(Error::header_too_big(mem_size, peer_max_field_section_size));
-        }
+        self.conn_state
+            .write("send request encode headers")
+            .encode_headers(quic::SendStream::send_id(&stream), &mut block, headers)?;
 
         stream::write(&mut stream, Frame::Headers(block.freeze()))
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
 
+        let mut inner = connection::RequestStream::new(
+            FrameStream::new(BufRecvStream::new(stream)),
+            self.max_field_section_size,
+            self.conn_state.clone(),
+            self.send_grease_frame,
+        );
+        inner.send_content_length = content_length;
         let request_stream = RequestStream {
-            inner: connection::RequestStream::new(
-                FrameStream::new(BufRecvStream::new(stream)),
-                self.max_field_section_size,
-                self.conn_state.clone(),
-                self.send_grease_frame,
-            ),
+            inner,
+            is_head,
+            final_response: None,
+            blocked_headers: None,
+            _in_flight: in_flight,
         };
         // send the grease frame only once
         self.send_grease_frame = false;
         Ok(request_stream)
     }
+
+    /// Returns the QPACK counters and dynamic tables of the connection
+    ///
+    /// See [`qpack::Snapshot`].
+    pub fn qpack_snapshot(&self) -> qpack::Snapshot {
+        self.conn_state.read("qpack snapshot").qpack.snapshot()
+    }
 }
 
 impl<T, B> ConnectionState for SendRequest<T, B>
@@ -223,6 +234,7 @@ where
             conn_waker: self.conn_waker.clone(),
             _buf: PhantomData,
             send_grease_frame: self.send_grease_frame,
+            in_flight: self.in_flight.clone(),
         }
     }
 }
@@ -257,7 +269,8 @@ where
 /// [`shutdown()`] initiates a graceful shutdown of this connection. After calling it, no request
 /// initiation will be further allowed. Then [`poll_close()`] will resolve when all ongoing requests
 /// and push streams complete. Finally, a connection closure with `HTTP_NO_ERROR` code will be
-/// sent to the server.
+/// sent to the server. [`graceful_shutdown()`] does all of this, giving up on the requests still
+/// in flight after a deadline.
 ///
 /// # Examples
 ///
@@ -331,6 +344,7 @@ where
 /// ```
 /// [`poll_close()`]: struct.Connection.html#method.poll_close
 /// [`shutdown()`]: struct.Connection.html#method.shutdown
+/// [`graceful_shutdown()`]: struct.Connection.html#method.graceful_shutdown
 pub struct Connection<C, B>
 where
     C: quic::Connection<B>,
@@ -341,6 +355,26 @@ where
     pub(super) sent_closing: Option<PushId>,
     // Has a GOAWAY frame been received? If so, this is StreamId the last the remote will accept.
     pub(super) recv_closing: Option<StreamId>,
+    // Requests sent on this connection whose streams are still alive
+    pub(super) in_flight: InFlight,
+}
+
+/// Outcome of [`Connection::graceful_shutdown()`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    aborted: Vec<StreamId>,
+}
+
+impl ShutdownReport {
+    /// The streams of the requests still in flight when the connection was closed
+    pub fn aborted(&self) -> &[StreamId] {
+        &self.aborted
+    }
+
+    /// Returns `true` if every request completed before the connection was closed
+    pub fn is_complete(&self) -> bool {
+        self.aborted.is_empty()
+    }
 }
 
 impl<C, B> Connection<C, B>
@@ -355,6 +389,62 @@ where
         self.inner.shutdown(&mut self.sent_closing, PushId(0)).await
     }
 
+    /// Gracefully shuts the connection down
+    ///
+    /// Sends a GOAWAY frame, after which [`SendRequest::send_request()`] fails, then keeps
+    /// driving the connection until all the requests in flight complete or `deadline`
+    /// resolves. The connection is then closed with `H3_NO_ERROR`.
+    ///
+    /// A request is in flight until its [`RequestStream`] is dropped. The requests which did
+    /// not complete in time are listed in the returned report.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub async fn graceful_shutdown<F>(&mut self, deadline: F) -> Result<ShutdownReport, Error>
+    where
+        F: Future<Output = ()>,
+    {
+        self.shutdown(0).await?;
+
+        let mut deadline = pin!(deadline);
+        future::poll_fn(|cx| {
+            if let Poll::Ready(closed) = self.poll_close(cx) {
+                return Poll::Ready(closed);
+            }
+            if self.in_flight.poll_idle(cx).is_ready() {
+                return Poll::Ready(Ok(()));
+            }
+            deadline.as_mut().poll(cx).map(Ok)
+        })
+        .await?;
+
+        let report = ShutdownReport {
+            aborted: self.in_flight.streams(),
+        };
+
+        #[cfg(feature = "tracing")]
+        info!(
+            "Closing connection, {} requests aborted",
+            report.aborted.len()
+        );
+
+        self.inner.shared.write("graceful shutdown").error = Some(Error::closed());
+        self.inner.conn.close(Code::H3_NO_ERROR, b"");
+        Ok(report)
+    }
+
+    /// Returns the number of requests in flight
+    ///
+    /// A request is in flight until its [`RequestStream`] is dropped.
+    pub fn in_flight_requests(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Returns the QPACK counters and dynamic tables of the connection
+    ///
+    /// See [`qpack::Snapshot`].
+    pub fn qpack_snapshot(&self) -> qpack::Snapshot {
+        self.inner.shared.read("qpack snapshot").qpack.snapshot()
+    }
+
     /// Wait until the connection is closed
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn wait_idle(&mut self) -> Result<(), Error> {
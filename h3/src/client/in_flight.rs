@@ -0,0 +1,101 @@
+//! Tracking of the requests in flight, for graceful shutdowns
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::quic::StreamId;
+
+/// The request streams opened by the client and not dropped yet
+#[derive(Clone, Default)]
+pub(super) struct InFlight(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    streams: BTreeSet<StreamId>,
+    // The connection waiting for the requests to complete
+    waker: Option<Waker>,
+}
+
+impl InFlight {
+    /// Starts tracking a request, until the returned guard is dropped
+    pub(super) fn track(&self, id: StreamId) -> InFlightGuard {
+        self.0.lock().unwrap().streams.insert(id);
+        InFlightGuard {
+            _guard: Arc::new(Guard {
+                in_flight: self.clone(),
+                id,
+            }),
+        }
+    }
+
+    /// Resolves once no request is in flight
+    pub(super) fn poll_idle(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.streams.is_empty() {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    pub(super) fn streams(&self) -> Vec<StreamId> {
+        self.0.lock().unwrap().streams.iter().copied().collect()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.0.lock().unwrap().streams.len()
+    }
+}
+
+/// Keeps a request in flight, shared by both halves of a split request stream
+#[derive(Clone)]
+pub(super) struct InFlightGuard {
+    _guard: Arc<Guard>,
+}
+
+struct Guard {
+    in_flight: InFlight,
+    id: StreamId,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut state = self.in_flight.0.lock().unwrap();
+        state.streams.remove(&self.id);
+        if state.streams.is_empty() {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::task::noop_waker_ref;
+
+    use super::*;
+
+    #[test]
+    fn idle_once_guards_are_dropped() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let in_flight = InFlight::default();
+        assert!(in_flight.poll_idle(&mut cx).is_ready());
+
+        let first = in_flight.track(StreamId::try_from(0).unwrap());
+        let second = in_flight.track(StreamId::try_from(4).unwrap());
+        let split = second.clone();
+        assert_eq!(in_flight.len(), 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(in_flight.streams(), vec![StreamId::try_from(4).unwrap()]);
+        assert!(in_flight.poll_idle(&mut cx).is_pending());
+
+        drop(split);
+        assert!(in_flight.poll_idle(&mut cx).is_ready());
+    }
+}
@@ -1,6 +1,7 @@
 //! HTTP/3 client
 
 mod connection;
+mod in_flight;
 mod stream;
 
 mod builder;
@@ -8,5 +9,5 @@ mod builder;
 pub use builder::builder;
 pub use builder::new;
 pub use builder::Builder;
-pub use connection::{Connection, SendRequest};
-pub use stream::RequestStream;
+pub use connection::{Connection, SendRequest, ShutdownReport};
+pub use stream::{ContinueStatus, RequestStream};
@@ -1,6 +1,6 @@
-use bytes::Buf;
-use futures_util::future;
-use http::{HeaderMap, Response};
+use bytes::{Buf, Bytes};
+use futures_util::future::{self, Either};
+use http::{HeaderMap, Response, StatusCode};
 use quic::StreamId;
 #[cfg(feature = "tracing")]
 use tracing::instrument;
@@ -8,15 +8,22 @@ use tracing::instrument;
 use crate::{
     connection::{self, ConnectionState, SharedStateRef},
     error::{Code, Error, ErrorLevel},
-    proto::{frame::Frame, headers::Header},
+    proto::{
+        frame::Frame,
+        headers::{content_length, Header},
+    },
     qpack,
     quic::{self},
 };
 use std::{
     convert::TryFrom,
+    future::Future,
+    pin::pin,
     task::{Context, Poll},
 };
 
+use super::in_flight::InFlightGuard;
+
 /// Manage request bodies transfer, response and trailers.
 ///
 /// Once a request has been sent via [`crate::client::SendRequest::send_request()`], a response can be awaited by calling
@@ -71,6 +78,26 @@ use std::{
 /// [`stop_sending()`]: #method.stop_sending
 pub struct RequestStream<S, B> {
     pub(super) inner: connection::RequestStream<S, B>,
+    // Responses to HEAD requests carry no content, whatever their `content-length`
+    pub(super) is_head: bool,
+    // Final response read ahead by `recv_informational()`
+    pub(super) final_response: Option<Response<()>>,
+    // Response headers waiting for the QPACK entries they reference, kept if the receiving
+    // future is dropped
+    pub(super) blocked_headers: Option<Bytes>,
+    // Keeps the request in flight until both halves are dropped
+    pub(super) _in_flight: InFlightGuard,
+}
+
+/// Outcome of [`RequestStream::wait_continue()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContinueStatus {
+    /// The server sent a `100 Continue`, the request body can be sent
+    Continue,
+    /// The server did not answer in time, the request body can be sent
+    TimedOut,
+    /// The server sent a final response without waiting for the request body
+    FinalResponse,
 }
 
 impl<S, B> ConnectionState for RequestStream<S, B> {
@@ -87,18 +114,176 @@ where
     ///
     /// This should be called before trying to receive any data with [`recv_data()`].
     ///
+    /// Informational (1xx) responses not already received with [`recv_informational()`]
+    /// are skipped, so this always resolves to the final response.
+    ///
     /// [`recv_data()`]: #method.recv_data
+    /// [`recv_informational()`]: #method.recv_informational
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn recv_response(&mut self) -> Result<Response<()>, Error> {
-        let mut frame = future::poll_fn(|cx| self.inner.stream.poll_next(cx))
-            .await
-            .map_err(|e| self.maybe_conn_err(e))?
-            .ok_or_else(|| {
-                Code::H3_GENERAL_PROTOCOL_ERROR.with_reason(
-                    "Did not receive response headers",
-                    ErrorLevel::ConnectionError,
+        if let Some(resp) = self.final_response.take() {
+            return Ok(resp);
+        }
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+        //# A response MAY consist of multiple messages when and only when one or
+        //# more interim responses (1xx; see Section 15.2 of [HTTP]) precede a
+        //# final response to the same request.
+        loop {
+            let resp = self.recv_response_headers().await?;
+            if !resp.status().is_informational() {
+                return Ok(resp);
+            }
+        }
+    }
+
+    /// Receive the next informational (1xx) response, like 100 Continue or 103 Early Hints
+    ///
+    /// Returns `None` once the final response has been received, which is then available
+    /// through [`recv_response()`].
+    ///
+    /// [`recv_response()`]: #method.recv_response
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub async fn recv_informational(&mut self) -> Result<Option<Response<()>>, Error> {
+        if self.final_response.is_some() {
+            return Ok(None);
+        }
+
+        let resp = self.recv_response_headers().await?;
+        if resp.status().is_informational() {
+            Ok(Some(resp))
+        } else {
+            self.final_response = Some(resp);
+            Ok(None)
+        }
+    }
+
+    /// Wait for the server to accept a request sent with `expect: 100-continue`
+    ///
+    /// Resolves once the server sends a `100 Continue`, a final response, or `timeout`
+    /// completes, whichever comes first. The request body should only be sent on
+    /// [`ContinueStatus::Continue`] or [`ContinueStatus::TimedOut`]. On
+    /// [`ContinueStatus::FinalResponse`], the response is available through
+    /// [`recv_response()`].
+    ///
+    /// Other informational responses received meanwhile are skipped.
+    ///
+    /// [`recv_response()`]: #method.recv_response
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub async fn wait_continue<F>(&mut self, timeout: F) -> Result<ContinueStatus, Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut timeout = pin!(timeout);
+
+        loop {
+            let interim = {
+                let recv = pin!(self.recv_informational());
+                match future::select(recv, timeout.as_mut()).await {
+                    Either::Left((interim, _)) => interim?,
+                    Either::Right(_) => return Ok(ContinueStatus::TimedOut),
+                }
+            };
+
+            match interim {
+                Some(resp) if resp.status() == StatusCode::CONTINUE => {
+                    return Ok(ContinueStatus::Continue)
+                }
+                Some(_) => continue,
+                None => return Ok(ContinueStatus::FinalResponse),
+            }
+        }
+    }
+
+    /// Receive the next HEADERS frame of the response, be it informational or final
+    async fn recv_response_headers(&mut self) -> Result<Response<()>, Error> {
+        let encoded = match self.blocked_headers.take() {
+            Some(encoded) => encoded,
+            None => self.recv_headers_frame().await?,
+        };
+
+        // Kept until decoded, the headers may wait for the entries they reference
+        self.blocked_headers = Some(encoded);
+        let decoded = future::poll_fn(|cx| {
+            let encoded = self.blocked_headers.as_ref().expect("blocked headers");
+            self.inner
+                .conn_state
+                .write("response headers decode")
+                .qpack
+                .poll_decode(
+                    cx,
+                    self.inner.stream.id(),
+                    encoded,
+                    self.inner.max_field_section_size,
                 )
-            })?;
+        })
+        .await;
+        self.blocked_headers = None;
+
+        let decoded = match decoded {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+            //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+            //# the message header it will accept on an individual HTTP message.
+            Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
+                self.inner.stop_sending(Code::H3_REQUEST_CANCELLED);
+                return Err(Error::header_too_big(
+                    cancel_size,
+                    self.inner.max_field_section_size,
+                ));
+            }
+            Ok(decoded) => decoded,
+            Err(e) => return Err(e.into()),
+        };
+
+        let qpack::Decoded { fields, .. } = decoded;
+
+        let (status, headers) = Header::try_from(fields)?.into_response_parts()?;
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+        //# A response that is defined to have no content, as described in
+        //# Section 6.4.1 of [HTTP], can have a non-zero Content-Length header
+        //# field, even though no content is included in DATA frames.
+        if !self.is_head
+            && !status.is_informational()
+            && status != StatusCode::NO_CONTENT
+            && status != StatusCode::NOT_MODIFIED
+        {
+            self.inner.recv_content_length = match content_length(&headers) {
+                Ok(len) => len,
+                Err(err) => {
+                    self.inner.stop_sending(Code::H3_MESSAGE_ERROR);
+                    return Err(err.into());
+                }
+            };
+        }
+        let mut resp = Response::new(());
+        *resp.status_mut() = status;
+        *resp.headers_mut() = headers;
+        *resp.version_mut() = http::Version::HTTP_3;
+
+        Ok(resp)
+    }
+
+    /// Receive the next HEADERS frame of the response, still encoded
+    async fn recv_headers_frame(&mut self) -> Result<Bytes, Error> {
+        let frame = match future::poll_fn(|cx| self.inner.stream.poll_next(cx)).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                // Like a reset, the headers of this stream will not be decoded
+                self.inner
+                    .conn_state
+                    .write("response stream cancel")
+                    .qpack
+                    .cancel_stream(self.inner.stream.id());
+                return Err(self.maybe_conn_err(e));
+            }
+        };
+        let frame = frame.ok_or_else(|| {
+            Code::H3_GENERAL_PROTOCOL_ERROR.with_reason(
+                "Did not receive response headers",
+                ErrorLevel::ConnectionError,
+            )
+        })?;
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-7.2.5
         //= type=TODO
@@ -113,37 +298,13 @@ where
         //# mismatch, it MUST respond with a connection error of type
         //# H3_GENERAL_PROTOCOL_ERROR.
 
-        let decoded = if let Frame::Headers(ref mut encoded) = frame {
-            match qpack::decode_stateless(encoded, self.inner.max_field_section_size) {
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-                //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-                //# the message header it will accept on an individual HTTP message.
-                Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
-                    self.inner.stop_sending(Code::H3_REQUEST_CANCELLED);
-                    return Err(Error::header_too_big(
-                        cancel_size,
-                        self.inner.max_field_section_size,
-                    ));
-                }
-                Ok(decoded) => decoded,
-                Err(e) => return Err(e.into()),
-            }
-        } else {
-            return Err(Code::H3_FRAME_UNEXPECTED.with_reason(
+        match frame {
+            Frame::Headers(encoded) => Ok(encoded),
+            _ => Err(Code::H3_FRAME_UNEXPECTED.with_reason(
                 "First response frame is not headers",
                 ErrorLevel::ConnectionError,
-            ));
-        };
-
-        let qpack::Decoded { fields, .. } = decoded;
-
-        let (status, headers) = Header::try_from(fields)?.into_response_parts()?;
-        let mut resp = Response::new(());
-        *resp.status_mut() = status;
-        *resp.headers_mut() = headers;
-        *resp.version_mut() = http::Version::HTTP_3;
-
-        Ok(resp)
+            )),
+        }
     }
 
     /// Receive some of the request body.
@@ -252,6 +413,21 @@ where
         RequestStream<S::RecvStream, B>,
     ) {
         let (send, recv) = self.inner.split();
-        (RequestStream { inner: send }, RequestStream { inner: recv })
+        (
+            RequestStream {
+                inner: send,
+                is_head: false,
+                final_response: None,
+                blocked_headers: None,
+                _in_flight: self._in_flight.clone(),
+            },
+            RequestStream {
+                inner: recv,
+                is_head: self.is_head,
+                final_response: self.final_response,
+                blocked_headers: self.blocked_headers,
+                _in_flight: self._in_flight,
+            },
+        )
     }
 }
@@ -1,6 +1,9 @@
 use std::convert::TryFrom;
 
-use crate::proto::{frame, varint::VarInt};
+use crate::{
+    proto::{frame, varint::VarInt},
+    webtransport::WebTransportDraft,
+};
 
 /// Configures the HTTP/3 connection
 #[derive(Debug, Clone, Copy)]
@@ -15,6 +18,10 @@ pub struct Config {
     #[cfg(test)]
     pub(crate) send_settings: bool,
 
+    /// Capacity of the QPACK dynamic table used to encode the headers we send, bounded by the
+    /// peer's `SETTINGS_QPACK_MAX_TABLE_CAPACITY`
+    pub(crate) qpack_encoder_table_capacity: u64,
+
     /// HTTP/3 Settings
     pub settings: Settings,
 }
@@ -29,6 +36,14 @@ pub struct Settings {
     /// This means that the dynamic table used for HPACK compression can have a maximum size of 2^12 bytes, which is 4KB.
     pub(crate) max_field_section_size: u64,
 
+    /// Sets `SETTINGS_QPACK_MAX_TABLE_CAPACITY`, the capacity of the dynamic table the peer's
+    /// encoder may use for the headers it sends
+    pub(crate) qpack_max_table_capacity: u64,
+
+    /// Sets `SETTINGS_QPACK_BLOCKED_STREAMS`, the number of streams the peer's encoder may
+    /// block on entries not received yet
+    pub(crate) qpack_max_blocked_streams: u64,
+
     /// https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-3.1
     /// Sets `SETTINGS_ENABLE_WEBTRANSPORT` if enabled
     pub(crate) enable_webtransport: bool,
@@ -41,21 +56,48 @@ pub struct Settings {
     pub(crate) enable_datagram: bool,
     /// The maximum number of concurrent streams that can be opened by the peer.
     pub(crate) max_webtransport_sessions: u64,
+    /// Sets `SETTINGS_WT_INITIAL_MAX_DATA`, the amount of data the peer can send on the
+    /// streams of a WebTransport session before receiving a `WT_MAX_DATA` capsule
+    pub(crate) wt_initial_max_data: u64,
+    /// Sets `SETTINGS_WT_INITIAL_MAX_STREAMS_UNI`
+    pub(crate) wt_initial_max_streams_uni: u64,
+    /// Sets `SETTINGS_WT_INITIAL_MAX_STREAMS_BIDI`
+    pub(crate) wt_initial_max_streams_bidi: u64,
+    /// The WebTransport draft spoken by the peer, known once its settings are received
+    pub(crate) webtransport_draft: Option<WebTransportDraft>,
 }
 
 impl From<&frame::Settings> for Settings {
     fn from(settings: &frame::Settings) -> Self {
         let defaults: Self = Default::default();
+
+        // Newer drafts replace `SETTINGS_ENABLE_WEBTRANSPORT` by `SETTINGS_WT_MAX_SESSIONS`
+        let wt_max_sessions = settings
+            .get(frame::SettingId::WT_MAX_SESSIONS)
+            .filter(|value| *value != 0);
+        let enable_draft02 = settings
+            .get(frame::SettingId::ENABLE_WEBTRANSPORT)
+            .map(|value| value != 0)
+            .unwrap_or(defaults.enable_webtransport);
+        let webtransport_draft = match (wt_max_sessions, enable_draft02) {
+            (Some(_), _) => Some(WebTransportDraft::Draft07),
+            (None, true) => Some(WebTransportDraft::Draft02),
+            (None, false) => None,
+        };
+
         Self {
             max_field_section_size: settings
                 .get(frame::SettingId::MAX_HEADER_LIST_SIZE)
                 .unwrap_or(defaults.max_field_section_size),
-            enable_webtransport: settings
-                .get(frame::SettingId::ENABLE_WEBTRANSPORT)
-                .map(|value| value != 0)
-                .unwrap_or(defaults.enable_webtransport),
-            max_webtransport_sessions: settings
-                .get(frame::SettingId::WEBTRANSPORT_MAX_SESSIONS)
+            qpack_max_table_capacity: settings
+                .get(frame::SettingId::QPACK_MAX_TABLE_CAPACITY)
+                .unwrap_or(defaults.qpack_max_table_capacity),
+            qpack_max_blocked_streams: settings
+                .get(frame::SettingId::QPACK_MAX_BLOCKED_STREAMS)
+                .unwrap_or(defaults.qpack_max_blocked_streams),
+            enable_webtransport: webtransport_draft.is_some(),
+            max_webtransport_sessions: wt_max_sessions
+                .or_else(|| settings.get(frame::SettingId::WEBTRANSPORT_MAX_SESSIONS))
                 .unwrap_or(defaults.max_webtransport_sessions),
             enable_datagram: settings
                 .get(frame::SettingId::H3_DATAGRAM)
@@ -65,6 +107,16 @@ impl From<&frame::Settings> for Settings {
                 .get(frame::SettingId::ENABLE_CONNECT_PROTOCOL)
                 .map(|value| value != 0)
                 .unwrap_or(defaults.enable_extended_connect),
+            wt_initial_max_data: settings
+                .get(frame::SettingId::WT_INITIAL_MAX_DATA)
+                .unwrap_or(defaults.wt_initial_max_data),
+            wt_initial_max_streams_uni: settings
+                .get(frame::SettingId::WT_INITIAL_MAX_STREAMS_UNI)
+                .unwrap_or(defaults.wt_initial_max_streams_uni),
+            wt_initial_max_streams_bidi: settings
+                .get(frame::SettingId::WT_INITIAL_MAX_STREAMS_BIDI)
+                .unwrap_or(defaults.wt_initial_max_streams_bidi),
+            webtransport_draft,
         }
     }
 }
@@ -78,13 +130,20 @@ impl TryFrom<Config> for frame::Settings {
             send_grease,
             #[cfg(test)]
                 send_settings: _,
+            qpack_encoder_table_capacity: _,
             settings:
                 Settings {
                     max_field_section_size,
+                    qpack_max_table_capacity,
+                    qpack_max_blocked_streams,
                     enable_webtransport,
                     enable_extended_connect,
                     enable_datagram,
                     max_webtransport_sessions,
+                    wt_initial_max_data,
+                    wt_initial_max_streams_uni,
+                    wt_initial_max_streams_bidi,
+                    webtransport_draft: _,
                 },
         } = value;
 
@@ -116,6 +175,19 @@ impl TryFrom<Config> for frame::Settings {
             frame::SettingId::MAX_HEADER_LIST_SIZE,
             max_field_section_size,
         )?;
+        // Zero is the default, the setting is left out unless the peer may use a dynamic table
+        if qpack_max_table_capacity > 0 {
+            settings.insert(
+                frame::SettingId::QPACK_MAX_TABLE_CAPACITY,
+                qpack_max_table_capacity,
+            )?;
+        }
+        if qpack_max_blocked_streams > 0 {
+            settings.insert(
+                frame::SettingId::QPACK_MAX_BLOCKED_STREAMS,
+                qpack_max_blocked_streams,
+            )?;
+        }
         settings.insert(
             frame::SettingId::ENABLE_CONNECT_PROTOCOL,
             enable_extended_connect as u64,
@@ -130,6 +202,21 @@ impl TryFrom<Config> for frame::Settings {
             max_webtransport_sessions,
         )?;
 
+        // Advertise the newer drafts as well. A zero value disables WebTransport there, so
+        // without a session limit only the older drafts are offered.
+        if enable_webtransport && max_webtransport_sessions > 0 {
+            settings.insert(frame::SettingId::WT_MAX_SESSIONS, max_webtransport_sessions)?;
+            settings.insert(frame::SettingId::WT_INITIAL_MAX_DATA, wt_initial_max_data)?;
+            settings.insert(
+                frame::SettingId::WT_INITIAL_MAX_STREAMS_UNI,
+                wt_initial_max_streams_uni,
+            )?;
+            settings.insert(
+                frame::SettingId::WT_INITIAL_MAX_STREAMS_BIDI,
+                wt_initial_max_streams_bidi,
+            )?;
+        }
+
         Ok(settings)
     }
 }
@@ -138,15 +225,31 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             max_field_section_size: VarInt::MAX.0,
+            qpack_max_table_capacity: 0,
+            qpack_max_blocked_streams: 0,
             enable_webtransport: false,
             enable_extended_connect: false,
             enable_datagram: false,
             max_webtransport_sessions: 0,
+            wt_initial_max_data: VarInt::MAX.0,
+            wt_initial_max_streams_uni: VarInt::MAX.0,
+            wt_initial_max_streams_bidi: VarInt::MAX.0,
+            webtransport_draft: None,
         }
     }
 }
 
 impl Settings {
+    /// The capacity of the dynamic table the encoder may use, `SETTINGS_QPACK_MAX_TABLE_CAPACITY`
+    pub fn qpack_max_table_capacity(&self) -> u64 {
+        self.qpack_max_table_capacity
+    }
+
+    /// The number of streams the encoder may block, `SETTINGS_QPACK_BLOCKED_STREAMS`
+    pub fn qpack_max_blocked_streams(&self) -> u64 {
+        self.qpack_max_blocked_streams
+    }
+
     /// https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/#section-3.1
     /// Sets `SETTINGS_ENABLE_WEBTRANSPORT` if enabled
     pub fn enable_webtransport(&self) -> bool {
@@ -165,6 +268,37 @@ impl Settings {
     pub fn enable_extended_connect(&self) -> bool {
         self.enable_extended_connect
     }
+
+    /// The maximum number of WebTransport sessions on a connection
+    pub fn max_webtransport_sessions(&self) -> u64 {
+        self.max_webtransport_sessions
+    }
+
+    /// The amount of data which can be sent on the streams of a WebTransport session before
+    /// receiving a `WT_MAX_DATA` capsule
+    pub fn wt_initial_max_data(&self) -> u64 {
+        self.wt_initial_max_data
+    }
+
+    /// The number of unidirectional streams which can be opened in a WebTransport session
+    /// before receiving a `WT_MAX_STREAMS` capsule
+    pub fn wt_initial_max_streams_uni(&self) -> u64 {
+        self.wt_initial_max_streams_uni
+    }
+
+    /// The number of bidirectional streams which can be opened in a WebTransport session
+    /// before receiving a `WT_MAX_STREAMS` capsule
+    pub fn wt_initial_max_streams_bidi(&self) -> u64 {
+        self.wt_initial_max_streams_bidi
+    }
+
+    /// The WebTransport draft spoken by the peer
+    ///
+    /// Only known for the settings received from the peer. The newest draft supported by both
+    /// sides is picked, as an endpoint can advertise several of them.
+    pub fn webtransport_draft(&self) -> Option<WebTransportDraft> {
+        self.webtransport_draft
+    }
 }
 
 impl Default for Config {
@@ -173,7 +307,65 @@ impl Default for Config {
             send_grease: true,
             #[cfg(test)]
             send_settings: true,
+            qpack_encoder_table_capacity: 0,
             settings: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webtransport_draft_from_settings() {
+        let mut settings = frame::Settings::default();
+        settings
+            .insert(frame::SettingId::ENABLE_WEBTRANSPORT, 1)
+            .unwrap();
+        assert_eq!(
+            Settings::from(&settings).webtransport_draft(),
+            Some(WebTransportDraft::Draft02)
+        );
+
+        settings
+            .insert(frame::SettingId::WT_MAX_SESSIONS, 4)
+            .unwrap();
+        let peer = Settings::from(&settings);
+        assert_eq!(peer.webtransport_draft(), Some(WebTransportDraft::Draft07));
+        assert_eq!(peer.max_webtransport_sessions(), 4);
+
+        assert_eq!(
+            Settings::from(&frame::Settings::default()).webtransport_draft(),
+            None
+        );
+    }
+
+    #[test]
+    fn webtransport_settings_round_trip() {
+        let mut config = Config::default();
+        config.settings.enable_webtransport = true;
+        config.settings.max_webtransport_sessions = 1;
+        config.settings.wt_initial_max_data = 1024;
+        config.settings.wt_initial_max_streams_bidi = 8;
+
+        let peer = Settings::from(&frame::Settings::try_from(config).unwrap());
+        assert_eq!(peer.webtransport_draft(), Some(WebTransportDraft::Draft07));
+        assert_eq!(peer.wt_initial_max_data(), 1024);
+        assert_eq!(peer.wt_initial_max_streams_bidi(), 8);
+        assert_eq!(peer.wt_initial_max_streams_uni(), VarInt::MAX.0);
+    }
+
+    #[test]
+    fn newer_drafts_not_offered_without_session_limit() {
+        let mut config = Config::default();
+        config.settings.enable_webtransport = true;
+
+        let settings = frame::Settings::try_from(config).unwrap();
+        assert_eq!(settings.get(frame::SettingId::WT_MAX_SESSIONS), None);
+        assert_eq!(
+            Settings::from(&settings).webtransport_draft(),
+            Some(WebTransportDraft::Draft02)
+        );
+    }
+}
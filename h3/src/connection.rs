@@ -1,8 +1,10 @@
 use std::{
+    cmp,
+    collections::HashMap,
     convert::TryFrom,
     marker::PhantomData,
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
 
 use bytes::{Buf, Bytes, BytesMut};
@@ -15,7 +17,7 @@ use tracing::{instrument, warn};
 
 use crate::{
     config::{Config, Settings},
-    error::{Code, Error},
+    error::{Code, Error, ErrorLevel},
     frame::FrameStream,
     proto::{
         frame::{self, Frame, PayloadLen},
@@ -24,7 +26,7 @@ use crate::{
         varint::VarInt,
     },
     qpack,
-    quic::{self, SendStream},
+    quic::{self, RecvStream, SendStream, StreamId},
     stream::{self, AcceptRecvStream, AcceptedRecvStream, BufRecvStream, UniStreamHeader},
     webtransport::SessionId,
 };
@@ -38,6 +40,259 @@ pub struct SharedState {
     pub error: Option<Error>,
     // Has a GOAWAY frame been sent or received?
     pub closing: bool,
+    // QPACK encoder and decoder of the connection
+    pub(crate) qpack: QpackState,
+}
+
+impl SharedState {
+    /// Encodes a field section sent on `stream_id` into `block`
+    pub(crate) fn encode_headers(
+        &mut self,
+        stream_id: StreamId,
+        block: &mut BytesMut,
+        header: Header,
+    ) -> Result<(), Error> {
+        let max_size = self.peer_config.max_field_section_size;
+        let mem_size = self.qpack.encode(stream_id, block, header, max_size)?;
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+        //# An implementation that
+        //# has received this parameter SHOULD NOT send an HTTP message header
+        //# that exceeds the indicated size, as the peer will likely refuse to
+        //# process it.
+        if mem_size > max_size {
+            return Err(Error::header_too_big(mem_size, max_size));
+        }
+        Ok(())
+    }
+}
+
+/// QPACK state of a connection
+///
+/// Request streams decode and encode their field sections with it, the connection driver
+/// exchanges the resulting instructions on the encoder and decoder streams.
+#[derive(Default)]
+pub(crate) struct QpackState {
+    decoder: qpack::Decoder,
+    encoder: qpack::Encoder,
+    // Capacity we would like for the encoder's dynamic table
+    encoder_table_capacity: u64,
+    // Capacity of the encoder's dynamic table, zero until the peer allows one
+    encoder_capacity: usize,
+    // Instructions waiting to be sent on our decoder stream
+    decoder_instructions: BytesMut,
+    // Instructions waiting to be sent on our encoder stream
+    encoder_instructions: BytesMut,
+    // Wakes the connection driver when there are instructions to send
+    driver: Option<Waker>,
+    // Streams waiting for the insert count their field section requires
+    blocked: HashMap<StreamId, (usize, Waker)>,
+    // The SETTINGS_QPACK_BLOCKED_STREAMS we advertised
+    max_blocked_streams: usize,
+}
+
+impl QpackState {
+    fn new(config: &Config) -> Result<Self, Error> {
+        let max_table_capacity =
+            usize::try_from(config.settings.qpack_max_table_capacity).unwrap_or(usize::MAX);
+        let decoder = qpack::Decoder::new(max_table_capacity)
+            .map_err(|e| Code::H3_INTERNAL_ERROR.with_cause(e))?;
+
+        Ok(Self {
+            decoder,
+            encoder_table_capacity: config.qpack_encoder_table_capacity,
+            max_blocked_streams: usize::try_from(config.settings.qpack_max_blocked_streams)
+                .unwrap_or(usize::MAX),
+            ..Default::default()
+        })
+    }
+
+    /// Decodes a field section received on `stream_id`, once the entries it references are
+    /// received
+    ///
+    /// The stream is blocked until then, and woken by the connection driver.
+    pub(crate) fn poll_decode(
+        &mut self,
+        cx: &mut Context<'_>,
+        stream_id: StreamId,
+        encoded: &Bytes,
+        max_size: u64,
+    ) -> Poll<Result<qpack::Decoded, qpack::DecoderError>> {
+        match self.decode(stream_id, &mut encoded.clone(), max_size) {
+            Err(qpack::DecoderError::MissingRefs(required)) => {
+                //= https://www.rfc-editor.org/rfc/rfc9204#section-2.1.2
+                //# If a decoder encounters more blocked streams than it promised to
+                //# support, it MUST treat this as a connection error of type
+                //# QPACK_DECOMPRESSION_FAILED.
+                if !self.blocked.contains_key(&stream_id)
+                    && self.blocked.len() >= self.max_blocked_streams
+                {
+                    return Poll::Ready(Err(qpack::DecoderError::MissingRefs(required)));
+                }
+                self.blocked
+                    .insert(stream_id, (required, cx.waker().clone()));
+                Poll::Pending
+            }
+            decoded => {
+                self.blocked.remove(&stream_id);
+                Poll::Ready(decoded)
+            }
+        }
+    }
+
+    /// Decodes a field section received on `stream_id`
+    fn decode(
+        &mut self,
+        stream_id: StreamId,
+        encoded: &mut Bytes,
+        max_size: u64,
+    ) -> Result<qpack::Decoded, qpack::DecoderError> {
+        let decoded = match self.decoder.decode_header_limited(encoded, max_size) {
+            Ok(decoded) => decoded,
+            // The rest of the stream is not read
+            Err(e @ qpack::DecoderError::HeaderTooLong(_)) => {
+                self.cancel_stream(stream_id);
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.4.1
+        //# After processing an encoded field section whose declared Required
+        //# Insert Count is not zero, the decoder emits a Section Acknowledgment
+        //# instruction.
+        if decoded.dyn_ref {
+            self.decoder.ack_section(
+                stream_id.into_inner(),
+                &decoded,
+                &mut self.decoder_instructions,
+            );
+            self.wake_driver();
+        }
+        Ok(decoded)
+    }
+
+    /// Tells the peer's encoder the field sections of `stream_id` will not be decoded
+    pub(crate) fn cancel_stream(&mut self, stream_id: StreamId) {
+        self.blocked.remove(&stream_id);
+        // Without dynamic table, no section can hold references the encoder waits for
+        if self.decoder.max_table_capacity() == 0 {
+            return;
+        }
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.4.2
+        //# When a stream is reset or reading is abandoned, the decoder emits a
+        //# Stream Cancellation instruction.
+        qpack::stream_canceled(stream_id.into_inner(), &mut self.decoder_instructions);
+        self.wake_driver();
+    }
+
+    /// Encodes a field section sent on `stream_id`, returning its size
+    ///
+    /// Nothing is inserted in the dynamic table for a section larger than `max_size`, which
+    /// will not be sent.
+    fn encode(
+        &mut self,
+        stream_id: StreamId,
+        block: &mut BytesMut,
+        header: Header,
+        max_size: u64,
+    ) -> Result<u64, qpack::EncoderError> {
+        if self.encoder_capacity == 0 {
+            return qpack::encode_stateless(block, header);
+        }
+
+        let fields = header.into_iter().collect::<Vec<_>>();
+        let mem_size = fields.iter().map(|f| f.mem_size() as u64).sum();
+        if mem_size <= max_size {
+            self.encoder.encode(
+                stream_id.into_inner(),
+                block,
+                &mut self.encoder_instructions,
+                &fields,
+            )?;
+            if !self.encoder_instructions.is_empty() {
+                self.wake_driver();
+            }
+        }
+        Ok(mem_size)
+    }
+
+    /// Sizes the encoder's dynamic table from the peer's settings
+    fn on_peer_settings(&mut self, settings: &Settings) -> Result<(), qpack::EncoderError> {
+        let capacity = cmp::min(
+            self.encoder_table_capacity,
+            settings.qpack_max_table_capacity,
+        );
+        if capacity == 0 {
+            return Ok(());
+        }
+        let max_table_capacity = match usize::try_from(settings.qpack_max_table_capacity) {
+            Ok(max) if self.encoder.set_max_table_capacity(max).is_ok() => max,
+            // The table cannot be that large, only the static table is referenced
+            _ => return Ok(()),
+        };
+        // The encoder may block fewer streams than the peer allows, it tracks less than 2^16
+        let blocked_streams = usize::try_from(settings.qpack_max_blocked_streams)
+            .unwrap_or(usize::MAX)
+            .min(u16::MAX as usize - 1);
+        self.encoder.set_max_blocked_streams(blocked_streams)?;
+        self.encoder_capacity = cmp::min(capacity as usize, max_table_capacity);
+        self.encoder
+            .set_table_capacity(self.encoder_capacity, &mut self.encoder_instructions)
+    }
+
+    /// Takes the instructions to send on the decoder and encoder streams
+    ///
+    /// The entries inserted since the last call, and not acknowledged with a section, are
+    /// acknowledged with a single Insert Count Increment.
+    fn take_instructions(&mut self) -> Result<(BytesMut, BytesMut), qpack::DecoderError> {
+        self.decoder
+            .insert_count_increment(&mut self.decoder_instructions)?;
+        Ok((
+            self.decoder_instructions.split(),
+            self.encoder_instructions.split(),
+        ))
+    }
+
+    pub(crate) fn snapshot(&self) -> qpack::Snapshot {
+        let mut decoder = self.decoder.stats();
+        decoder.blocked_streams = self.blocked.len();
+        qpack::Snapshot {
+            encoder: self.encoder.stats(),
+            decoder,
+            encoder_table: self.encoder.table_entries(),
+            decoder_table: self.decoder.table_entries(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn encoder(&self) -> &qpack::Encoder {
+        &self.encoder
+    }
+
+    #[cfg(test)]
+    pub(crate) fn blocked_streams(&self) -> usize {
+        self.blocked.len()
+    }
+
+    /// Wakes the blocked streams whose field section can be decoded with `total_inserted`
+    /// entries
+    fn unblock(&mut self, total_inserted: usize) {
+        self.blocked.retain(|_, (required, waker)| {
+            if *required > total_inserted {
+                return true;
+            }
+            waker.wake_by_ref();
+            false
+        });
+    }
+
+    fn wake_driver(&mut self) {
+        if let Some(waker) = self.driver.take() {
+            waker.wake();
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -60,6 +315,7 @@ impl Default for SharedStateRef {
             peer_config: Default::default(),
             error: None,
             closing: false,
+            qpack: Default::default(),
         })))
     }
 }
@@ -111,9 +367,15 @@ where
     control_send: C::SendStream,
     control_recv: Option<FrameStream<C::RecvStream, B>>,
     decoder_send: Option<C::SendStream>,
-    decoder_recv: Option<AcceptedRecvStream<C::RecvStream, B>>,
+    decoder_recv: Option<BufRecvStream<C::RecvStream, B>>,
     encoder_send: Option<C::SendStream>,
-    encoder_recv: Option<AcceptedRecvStream<C::RecvStream, B>>,
+    encoder_recv: Option<BufRecvStream<C::RecvStream, B>>,
+    /// QPACK instructions received and not processed yet, as they are incomplete
+    decoder_recv_buf: BytesMut,
+    encoder_recv_buf: BytesMut,
+    /// QPACK instructions waiting for their stream to be writable
+    decoder_send_buf: BytesMut,
+    encoder_send_buf: BytesMut,
     /// Buffers incoming uni/recv streams which have yet to be claimed.
     ///
     /// This is opposed to discarding them by returning in `poll_accept_recv`, which may cause them to be missed by something else polling.
@@ -255,6 +517,7 @@ where
         //# The
         //# sender MUST NOT close the control stream, and the receiver MUST NOT
         //# request that the sender close the control stream.
+        shared.write("qpack state init").qpack = QpackState::new(&config)?;
         let mut conn_inner = Self {
             shared,
             conn,
@@ -262,6 +525,10 @@ where
             control_recv: None,
             decoder_recv: None,
             encoder_recv: None,
+            decoder_recv_buf: BytesMut::new(),
+            encoder_recv_buf: BytesMut::new(),
+            decoder_send_buf: BytesMut::new(),
+            encoder_send_buf: BytesMut::new(),
             pending_recv_streams: Vec::with_capacity(3),
             got_peer_settings: false,
             send_grease_frame: config.send_grease,
@@ -385,15 +652,20 @@ where
                     }
                     self.control_recv = Some(s);
                 }
-                enc @ AcceptedRecvStream::Encoder(_) => {
-                    if let Some(_prev) = self.encoder_recv.replace(enc) {
+                //= https://www.rfc-editor.org/rfc/rfc9204#section-4.2
+                //# Each endpoint MUST initiate, at most, one encoder stream and, at
+                //# most, one decoder stream.  Receipt of a second instance of either
+                //# stream type MUST be treated as a connection error of type
+                //# H3_STREAM_CREATION_ERROR.
+                AcceptedRecvStream::Encoder(s) => {
+                    if let Some(_prev) = self.encoder_recv.replace(s) {
                         return Err(
                             self.close(Code::H3_STREAM_CREATION_ERROR, "got two encoder streams")
                         );
                     }
                 }
-                dec @ AcceptedRecvStream::Decoder(_) => {
-                    if let Some(_prev) = self.decoder_recv.replace(dec) {
+                AcceptedRecvStream::Decoder(s) => {
+                    if let Some(_prev) = self.decoder_recv.replace(s) {
                         return Err(
                             self.close(Code::H3_STREAM_CREATION_ERROR, "got two decoder streams")
                         );
@@ -428,6 +700,7 @@ where
         let recv = {
             // TODO
             self.poll_accept_recv(cx)?;
+            self.poll_qpack(cx)?;
             if let Some(v) = &mut self.control_recv {
                 v
             } else {
@@ -473,8 +746,17 @@ where
                         //# any meaning upon receipt.
                         let mut shared = self.shared.write("connection settings write");
                         shared.peer_config = (&settings).into();
-
-                        Ok(Frame::Settings(settings))
+                        let peer_config = shared.peer_config;
+                        let qpack = shared.qpack.on_peer_settings(&peer_config);
+                        drop(shared);
+
+                        match qpack {
+                            Ok(()) => Ok(Frame::Settings(settings)),
+                            Err(e) => Err(self.close(
+                                Code::H3_INTERNAL_ERROR,
+                                format!("sizing the qpack dynamic table: {}", e),
+                            )),
+                        }
                     }
                     f @ Frame::Goaway(_) => Ok(f),
                     f @ Frame::CancelPush(_) | f @ Frame::MaxPushId(_) => {
@@ -541,6 +823,69 @@ where
         Poll::Ready(res)
     }
 
+    /// Processes the instructions received on the QPACK streams, and sends ours
+    ///
+    /// Request headers referencing dynamic table entries not received yet are decoded once
+    /// this reads them.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub fn poll_qpack(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.2
+        //# Closure of either unidirectional stream type MUST be treated as a
+        //# connection error of type H3_CLOSED_CRITICAL_STREAM.
+        if let Some(stream) = &mut self.encoder_recv {
+            if poll_read_instructions(stream, &mut self.encoder_recv_buf, cx)?.is_ready() {
+                return Err(self.close(Code::H3_CLOSED_CRITICAL_STREAM, "encoder stream closed"));
+            }
+        }
+        if let Some(stream) = &mut self.decoder_recv {
+            if poll_read_instructions(stream, &mut self.decoder_recv_buf, cx)?.is_ready() {
+                return Err(self.close(Code::H3_CLOSED_CRITICAL_STREAM, "decoder stream closed"));
+            }
+        }
+
+        let mut shared = self.shared.write("qpack instructions");
+        let encoder_stream = shared
+            .qpack
+            .decoder
+            .recv_encoder_instructions(&mut self.encoder_recv_buf);
+        if let Ok(total_inserted) = encoder_stream {
+            shared.qpack.unblock(total_inserted);
+        }
+        let decoder_stream = shared
+            .qpack
+            .encoder
+            .on_decoder_recv(&mut self.decoder_recv_buf);
+        shared.qpack.driver = Some(cx.waker().clone());
+        let instructions = shared.qpack.take_instructions();
+        drop(shared);
+
+        if let Err(e) = encoder_stream {
+            return Err(self.close(
+                Code::QPACK_ENCODER_STREAM_ERROR,
+                format!("on encoder stream: {}", e),
+            ));
+        }
+        if let Err(e) = decoder_stream {
+            return Err(self.close(
+                Code::QPACK_DECODER_STREAM_ERROR,
+                format!("on decoder stream: {}", e),
+            ));
+        }
+        let (decoder_instructions, encoder_instructions) =
+            instructions.map_err(|e| Code::H3_INTERNAL_ERROR.with_cause(e))?;
+
+        // Instructions are dropped if the stream could not be opened
+        if let Some(stream) = &mut self.decoder_send {
+            self.decoder_send_buf.unsplit(decoder_instructions);
+            poll_send_instructions(stream, &mut self.decoder_send_buf, cx)?;
+        }
+        if let Some(stream) = &mut self.encoder_send {
+            self.encoder_send_buf.unsplit(encoder_instructions);
+            poll_send_instructions(stream, &mut self.encoder_send_buf, cx)?;
+        }
+        Ok(())
+    }
+
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub(crate) fn process_goaway<T>(
         &mut self,
@@ -698,6 +1043,43 @@ where
     }
 }
 
+/// Reads the instructions received on a QPACK stream into `buf`, ready once it is finished
+fn poll_read_instructions<S, B>(
+    stream: &mut BufRecvStream<S, B>,
+    buf: &mut BytesMut,
+    cx: &mut Context<'_>,
+) -> Result<Poll<()>, Error>
+where
+    S: quic::RecvStream,
+{
+    loop {
+        match stream.poll_data(cx)? {
+            Poll::Ready(Some(data)) => buf.extend_from_slice(&data),
+            Poll::Ready(None) => return Ok(Poll::Ready(())),
+            Poll::Pending => return Ok(Poll::Pending),
+        }
+    }
+}
+
+/// Sends the instructions of `buf` on a QPACK stream, as far as it is writable
+fn poll_send_instructions<S, B>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    cx: &mut Context<'_>,
+) -> Result<(), Error>
+where
+    S: SendStream<B>,
+    B: Buf,
+{
+    loop {
+        match stream.poll_ready(cx)? {
+            Poll::Ready(()) if buf.is_empty() => return Ok(()),
+            Poll::Ready(()) => stream.send_data(stream::WriteBuf::split_from(buf))?,
+            Poll::Pending => return Ok(()),
+        }
+    }
+}
+
 #[allow(missing_docs)]
 pub struct RequestStream<S, B> {
     pub(super) stream: FrameStream<S, B>,
@@ -705,6 +1087,16 @@ pub struct RequestStream<S, B> {
     pub(super) conn_state: SharedStateRef,
     pub(super) max_field_section_size: u64,
     send_grease_frame: bool,
+    // `content-length` announced by the peer, and DATA payload received so far
+    pub(super) recv_content_length: Option<u64>,
+    recv_data_len: u64,
+    // `content-length` we announced, and DATA payload sent so far
+    pub(super) send_content_length: Option<u64>,
+    send_data_len: u64,
+    // Local limits on the message received from the peer
+    pub(super) max_body_size: Option<u64>,
+    pub(super) max_trailers_size: Option<u64>,
+    pub(super) max_header_fields: Option<usize>,
 }
 
 impl<S, B> RequestStream<S, B> {
@@ -721,6 +1113,13 @@ impl<S, B> RequestStream<S, B> {
             max_field_section_size,
             trailers: None,
             send_grease_frame: grease,
+            recv_content_length: None,
+            recv_data_len: 0,
+            send_content_length: None,
+            send_data_len: 0,
+            max_body_size: None,
+            max_trailers_size: None,
+            max_header_fields: None,
         }
     }
 }
@@ -748,8 +1147,13 @@ where
                 .map_err(|e| self.maybe_conn_err(e))?;
 
             match ready!(frame) {
-                Some(Frame::Data { .. }) => (),
+                Some(Frame::Data(PayloadLen(len))) => {
+                    self.recv_data_len = self.recv_data_len.saturating_add(len as u64);
+                    self.check_recv_content_length(false)?;
+                    self.check_max_body_size()?;
+                }
                 Some(Frame::Headers(encoded)) => {
+                    self.check_recv_content_length(true)?;
                     self.trailers = Some(encoded);
                     return Poll::Ready(Ok(None));
                 }
@@ -777,7 +1181,10 @@ where
                 //# of a MAX_PUSH_ID frame on any other stream MUST be treated as a
                 //# connection error of type H3_FRAME_UNEXPECTED.
                 Some(_) => return Poll::Ready(Err(Code::H3_FRAME_UNEXPECTED.into())),
-                None => return Poll::Ready(Ok(None)),
+                None => {
+                    self.check_recv_content_length(true)?;
+                    return Poll::Ready(Ok(None));
+                }
             }
         }
 
@@ -786,6 +1193,51 @@ where
             .map_err(|e| self.maybe_conn_err(e))
     }
 
+    /// Checks the DATA payload received so far against the local body size limit
+    fn check_max_body_size(&mut self) -> Result<(), Error> {
+        match self.max_body_size {
+            Some(max) if self.recv_data_len > max => {
+                self.stream.stop_sending(Code::H3_EXCESSIVE_LOAD);
+                Err(Code::H3_EXCESSIVE_LOAD.with_reason(
+                    format!("body exceeds the {} bytes limit", max),
+                    ErrorLevel::StreamError,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the DATA payload received so far against the peer's `content-length`
+    ///
+    /// `complete` tells whether the body has been entirely received.
+    fn check_recv_content_length(&mut self, complete: bool) -> Result<(), Error> {
+        let expected = match self.recv_content_length {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+        //# A request or response that is defined as having content when it
+        //# contains a Content-Length header field (Section 8.6 of [HTTP]) is
+        //# malformed if the value of the Content-Length header field does not
+        //# equal the sum of the DATA frame lengths received.
+        if self.recv_data_len > expected || (complete && self.recv_data_len < expected) {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+            //# Malformed requests or responses that are
+            //# detected MUST be treated as a stream error of type H3_MESSAGE_ERROR.
+            self.stream.stop_sending(Code::H3_MESSAGE_ERROR);
+            return Err(Code::H3_MESSAGE_ERROR.with_reason(
+                format!(
+                    "content-length is {} but received {} bytes of data",
+                    expected, self.recv_data_len
+                ),
+                ErrorLevel::StreamError,
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Receive some of the request body.
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn recv_data(&mut self) -> Result<Option<impl Buf>, Error> {
@@ -798,7 +1250,7 @@ where
         &mut self,
         cx: &mut Context<'_>,
     ) -> Poll<Result<Option<HeaderMap>, Error>> {
-        let mut trailers = if let Some(encoded) = self.trailers.take() {
+        let trailers = if let Some(encoded) = self.trailers.take() {
             encoded
         } else {
             let frame = futures_util::ready!(self.stream.poll_next(cx))
@@ -832,6 +1284,7 @@ where
                 None => return Poll::Ready(Ok(None)),
             }
         };
+        self.check_recv_content_length(true)?;
         if !self.stream.is_eos() {
             // Get the trailing frame
             match self
@@ -853,20 +1306,63 @@ where
             }
         }
 
-        let qpack::Decoded { fields, .. } =
-            match qpack::decode_stateless(&mut trailers, self.max_field_section_size) {
-                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-                //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-                //# the message header it will accept on an individual HTTP message.
-                Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
-                    return Poll::Ready(Err(Error::header_too_big(
-                        cancel_size,
-                        self.max_field_section_size,
-                    )))
-                }
-                Ok(decoded) => decoded,
-                Err(e) => return Poll::Ready(Err(e.into())),
-            };
+        let max_size = match self.max_trailers_size {
+            Some(max) if max < self.max_field_section_size => max,
+            _ => self.max_field_section_size,
+        };
+        let decoded = self.conn_state.write("trailers decode").qpack.poll_decode(
+            cx,
+            self.stream.id(),
+            &trailers,
+            max_size,
+        );
+        let decoded = match decoded {
+            Poll::Ready(decoded) => decoded,
+            Poll::Pending => {
+                // The trailers wait for the entries they reference
+                self.trailers = Some(trailers);
+                return Poll::Pending;
+            }
+        };
+        let qpack::Decoded { fields, .. } = match decoded {
+            Err(qpack::DecoderError::HeaderTooLong(cancel_size))
+                if max_size < self.max_field_section_size =>
+            {
+                self.stream.stop_sending(Code::H3_EXCESSIVE_LOAD);
+                return Poll::Ready(Err(Code::H3_EXCESSIVE_LOAD.with_reason(
+                    format!(
+                        "trailers of {} bytes exceed the {} bytes limit",
+                        cancel_size, max_size
+                    ),
+                    ErrorLevel::StreamError,
+                )));
+            }
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+            //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+            //# the message header it will accept on an individual HTTP message.
+            Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => {
+                return Poll::Ready(Err(Error::header_too_big(
+                    cancel_size,
+                    self.max_field_section_size,
+                )))
+            }
+            Ok(decoded) => decoded,
+            Err(e) => return Poll::Ready(Err(e.into())),
+        };
+
+        if let Some(max) = self.max_header_fields {
+            if fields.len() > max {
+                self.stream.stop_sending(Code::H3_EXCESSIVE_LOAD);
+                return Poll::Ready(Err(Code::H3_EXCESSIVE_LOAD.with_reason(
+                    format!(
+                        "{} trailer fields exceed the limit of {}",
+                        fields.len(),
+                        max
+                    ),
+                    ErrorLevel::StreamError,
+                )));
+            }
+        }
 
         Poll::Ready(Ok(Some(Header::try_from(fields)?.into_fields())))
     }
@@ -886,11 +1382,24 @@ where
     /// Send some data on the response body.
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn send_data(&mut self, buf: B) -> Result<(), Error> {
+        let len = buf.remaining() as u64;
+        if let Some(expected) = self.send_content_length {
+            if self.send_data_len.saturating_add(len) > expected {
+                return Err(Code::H3_MESSAGE_ERROR.with_reason(
+                    format!(
+                        "sending {} more bytes would exceed content-length {}",
+                        len, expected
+                    ),
+                    ErrorLevel::StreamError,
+                ));
+            }
+        }
         let frame = Frame::Data(buf);
 
         stream::write(&mut self.stream, frame)
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
+        self.send_data_len += len;
         Ok(())
     }
 
@@ -901,23 +1410,11 @@ where
         //= type=TODO
         //# Characters in field names MUST be
         //# converted to lowercase prior to their encoding.
+        self.check_send_content_length()?;
         let mut block = BytesMut::new();
-
-        let mem_size = qpack::encode_stateless(&mut block, Header::trailer(trailers))?;
-        let max_mem_size = self
-            .conn_state
-            .read("send_trailers shared state read")
-            .peer_config
-            .max_field_section_size;
-
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
-        if mem_size > max_mem_size {
-            return Err(Error::header_too_big(mem_size, max_mem_size));
-        }
+        self.conn_state
+            .write("send_trailers shared state write")
+            .encode_headers(self.stream.send_id(), &mut block, Header::trailer(trailers))?;
         stream::write(&mut self.stream, Frame::Headers(block.freeze()))
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
@@ -925,6 +1422,21 @@ where
         Ok(())
     }
 
+    /// Checks the DATA payload sent matches our `content-length`, before ending the body
+    fn check_send_content_length(&self) -> Result<(), Error> {
+        match self.send_content_length {
+            Some(expected) if self.send_data_len < expected => Err(Code::H3_MESSAGE_ERROR
+                .with_reason(
+                    format!(
+                        "content-length is {} but only {} bytes of data were sent",
+                        expected, self.send_data_len
+                    ),
+                    ErrorLevel::StreamError,
+                )),
+            _ => Ok(()),
+        }
+    }
+
     /// Stops a stream with an error code
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub fn stop_stream(&mut self, code: Code) {
@@ -934,6 +1446,7 @@ where
     #[allow(missing_docs)]
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn finish(&mut self) -> Result<(), Error> {
+        self.check_send_content_length()?;
         if self.send_grease_frame {
             // send a grease frame once per Connection
             stream::write(&mut self.stream, Frame::Grease)
@@ -969,6 +1482,13 @@ where
                 conn_state: self.conn_state.clone(),
                 max_field_section_size: 0,
                 send_grease_frame: self.send_grease_frame,
+                recv_content_length: None,
+                recv_data_len: 0,
+                send_content_length: self.send_content_length,
+                send_data_len: self.send_data_len,
+                max_body_size: None,
+                max_trailers_size: None,
+                max_header_fields: None,
             },
             RequestStream {
                 stream: recv,
@@ -976,6 +1496,13 @@ where
                 conn_state: self.conn_state,
                 max_field_section_size: self.max_field_section_size,
                 send_grease_frame: self.send_grease_frame,
+                recv_content_length: self.recv_content_length,
+                recv_data_len: self.recv_data_len,
+                send_content_length: None,
+                send_data_len: 0,
+                max_body_size: self.max_body_size,
+                max_trailers_size: self.max_trailers_size,
+                max_header_fields: self.max_header_fields,
             },
         )
     }
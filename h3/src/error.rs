@@ -72,6 +72,12 @@ pub enum Kind {
         actual_size: u64,
         max_size: u64,
     },
+    // Request answered with an error response, without reaching the application
+    #[non_exhaustive]
+    Rejected {
+        status: http::StatusCode,
+        reason: Box<str>,
+    },
     // Error from QUIC layer
     #[non_exhaustive]
     Transport(Arc<dyn quic::Error>),
@@ -189,6 +195,15 @@ codes! {
     /// The encoder failed to interpret a decoder instruction received on the
     /// decoder stream.
     (0x202, QPACK_DECODER_STREAM_ERROR);
+
+    /// A WebTransport session exceeded a session-level flow control limit.
+    /// See: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
+    (0x045d4487, WT_FLOW_CONTROL_ERROR);
+
+    /// A stream was rejected because too many streams were buffered for
+    /// WebTransport sessions which were not established yet.
+    /// See: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
+    (0x3994bd84, WT_BUFFERED_STREAM_REJECTED);
 }
 
 impl Code {
@@ -225,6 +240,13 @@ impl Error {
         Error::new(Kind::Transport(Arc::from(err.into())))
     }
 
+    /// Builds an application error, for the crates extending h3 like h3-webtransport
+    #[cfg(feature = "i-implement-a-third-party-backend-and-opt-into-breaking-changes")]
+    #[doc(hidden)]
+    pub fn application<S: Into<Box<str>>>(code: Code, reason: S, level: ErrorLevel) -> Error {
+        code.with_reason(reason, level)
+    }
+
     /// Returns the error code from the error if available
     pub fn try_get_code(&self) -> Option<Code> {
         match self.inner.kind {
@@ -242,6 +264,7 @@ impl Error {
                 reason: _,
                 level,
             } => level,
+            Kind::Rejected { .. } => ErrorLevel::StreamError,
             // return Connection error on other kinds
             _ => ErrorLevel::ConnectionError,
         }
@@ -254,6 +277,24 @@ impl Error {
         })
     }
 
+    pub(crate) fn rejected(status: http::StatusCode, reason: String) -> Self {
+        Error::new(Kind::Rejected {
+            status,
+            reason: reason.into_boxed_str(),
+        })
+    }
+
+    /// Returns the status of the response the request was answered with, when it was
+    /// rejected without reaching the application
+    ///
+    /// The response is complete, unlike a request reset with `H3_REQUEST_REJECTED`.
+    pub fn rejected_status(&self) -> Option<http::StatusCode> {
+        match self.inner.kind {
+            Kind::Rejected { status, .. } => Some(status),
+            _ => None,
+        }
+    }
+
     pub(crate) fn with_cause<E: Into<Cause>>(mut self, cause: E) -> Self {
         self.inner.cause = Some(Arc::from(cause.into()));
         self
@@ -317,6 +358,10 @@ impl fmt::Debug for Error {
                 builder.field("header_size", &actual_size);
                 builder.field("max_size", &max_size);
             }
+            Kind::Rejected { status, ref reason } => {
+                builder.field("rejected_status", &status);
+                builder.field("reason", reason);
+            }
         }
 
         if let Some(ref cause) = self.inner.cause {
@@ -351,6 +396,9 @@ impl fmt::Display for Error {
                 "issued header size {} o is beyond peer's limit {} o",
                 actual_size, max_size
             )?,
+            Kind::Rejected { status, ref reason } => {
+                write!(f, "request rejected with {}: {}", status, reason)?
+            }
         };
         if let Some(ref cause) = self.inner.cause {
             write!(f, "cause: {}", cause)?
@@ -381,14 +429,11 @@ impl From<qpack::EncoderError> for Error {
     }
 }
 
+// Only errors decoding a field section are converted, the connection is closed with
+// QPACK_ENCODER_STREAM_ERROR where the encoder instructions are read
 impl From<qpack::DecoderError> for Error {
     fn from(e: qpack::DecoderError) -> Self {
-        match e {
-            qpack::DecoderError::InvalidStaticIndex(_) => {
-                Self::from(Code::QPACK_DECOMPRESSION_FAILED).with_cause(e)
-            }
-            _ => Self::from(Code::QPACK_DECODER_STREAM_ERROR).with_cause(e),
-        }
+        Self::from(Code::QPACK_DECOMPRESSION_FAILED).with_cause(e)
     }
 }
 
@@ -265,7 +265,7 @@ impl From<frame::FrameError> for FrameStreamError {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     use assert_matches::assert_matches;
@@ -547,12 +547,12 @@ mod tests {
     // Helpers
 
     #[derive(Default)]
-    struct FakeRecv {
+    pub(crate) struct FakeRecv {
         chunks: VecDeque<Bytes>,
     }
 
     impl FakeRecv {
-        fn chunk(&mut self, buf: Bytes) -> &mut Self {
+        pub(crate) fn chunk(&mut self, buf: Bytes) -> &mut Self {
             self.chunks.push_back(buf);
             self
         }
@@ -579,7 +579,7 @@ mod tests {
     }
 
     #[derive(Debug)]
-    struct FakeError;
+    pub(crate) struct FakeError;
 
     impl quic::Error for FakeError {
         fn is_timeout(&self) -> bool {
@@ -7,7 +7,9 @@ pub mod client;
 mod config;
 pub mod error;
 pub mod ext;
+pub mod qpack;
 pub mod quic;
+pub mod rt;
 
 pub mod server;
 
@@ -42,8 +44,6 @@ mod stream;
 #[cfg(not(feature = "i-implement-a-third-party-backend-and-opt-into-breaking-changes"))]
 mod webtransport;
 
-#[allow(dead_code)]
-mod qpack;
 #[cfg(test)]
 mod tests;
 #[cfg(test)]
@@ -399,6 +399,10 @@ impl SettingId {
                 | SettingId::ENABLE_CONNECT_PROTOCOL
                 | SettingId::ENABLE_WEBTRANSPORT
                 | SettingId::WEBTRANSPORT_MAX_SESSIONS
+                | SettingId::WT_MAX_SESSIONS
+                | SettingId::WT_INITIAL_MAX_DATA
+                | SettingId::WT_INITIAL_MAX_STREAMS_UNI
+                | SettingId::WT_INITIAL_MAX_STREAMS_BIDI
                 | SettingId::H3_DATAGRAM,
         )
     }
@@ -448,9 +452,15 @@ setting_identifiers! {
     H3_SETTING_ENABLE_DATAGRAM_CHROME_SPECIFIC= 0xFFD277,
 
     WEBTRANSPORT_MAX_SESSIONS = 0x2b603743,
+
+    // https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/
+    WT_MAX_SESSIONS = 0xc671706a,
+    WT_INITIAL_MAX_DATA = 0x2b61,
+    WT_INITIAL_MAX_STREAMS_UNI = 0x2b64,
+    WT_INITIAL_MAX_STREAMS_BIDI = 0x2b65,
 }
 
-const SETTINGS_LEN: usize = 8;
+const SETTINGS_LEN: usize = 12;
 
 #[derive(Debug, PartialEq)]
 pub struct Settings {
@@ -660,6 +670,10 @@ mod tests {
                     (SettingId::NONE, 0),
                     (SettingId::NONE, 0),
                     (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
                 ],
                 len: 4,
             }),
@@ -677,6 +691,10 @@ mod tests {
                     (SettingId::NONE, 0),
                     (SettingId::NONE, 0),
                     (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
+                    (SettingId::NONE, 0),
                 ],
                 len: 3,
             }),
@@ -5,6 +5,7 @@ use std::{
     str::FromStr,
 };
 
+use bytes::Bytes;
 use http::{
     header::{self, HeaderName, HeaderValue},
     uri::{self, Authority, Parts, PathAndQuery, Scheme, Uri},
@@ -62,11 +63,11 @@ impl Header {
         let mut uri = Uri::builder();
 
         if let Some(path) = self.pseudo.path {
-            uri = uri.path_and_query(path.as_str().as_bytes());
+            uri = uri.path_and_query(path);
         }
 
         if let Some(scheme) = self.pseudo.scheme {
-            uri = uri.scheme(scheme.as_str().as_bytes());
+            uri = uri.scheme(scheme);
         }
 
         //= https://www.rfc-editor.org/rfc/rfc9114#section-4.3.1
@@ -82,14 +83,14 @@ impl Header {
         //# :authority pseudo-header or Host header fields.
         match (self.pseudo.authority, self.fields.get("host")) {
             (None, None) => return Err(HeaderError::MissingAuthority),
-            (Some(a), None) => uri = uri.authority(a.as_str().as_bytes()),
+            (Some(a), None) => uri = uri.authority(a),
             (None, Some(h)) => uri = uri.authority(h.as_bytes()),
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.3.1
             //# If both fields are present, they MUST contain the same value.
             (Some(a), Some(h)) if a.as_str() != h => {
                 return Err(HeaderError::ContradictedAuthority)
             }
-            (Some(_), Some(h)) => uri = uri.authority(h.as_bytes()),
+            (Some(a), Some(_)) => uri = uri.authority(a),
         }
 
         Ok((
@@ -162,27 +163,27 @@ impl Iterator for HeaderIter {
         //# regular header fields.
         if let Some(ref mut pseudo) = self.pseudo {
             if let Some(method) = pseudo.method.take() {
-                return Some((":method", method.as_str()).into());
+                return Some(pseudo_field(":method", method.as_str()));
             }
 
             if let Some(scheme) = pseudo.scheme.take() {
-                return Some((":scheme", scheme.as_str().as_bytes()).into());
+                return Some(pseudo_field(":scheme", scheme.as_str()));
             }
 
             if let Some(authority) = pseudo.authority.take() {
-                return Some((":authority", authority.as_str().as_bytes()).into());
+                return Some(pseudo_field(":authority", authority.as_str()));
             }
 
             if let Some(path) = pseudo.path.take() {
-                return Some((":path", path.as_str().as_bytes()).into());
+                return Some(pseudo_field(":path", path.as_str()));
             }
 
             if let Some(status) = pseudo.status.take() {
-                return Some((":status", status.as_str()).into());
+                return Some(pseudo_field(":status", status.as_str()));
             }
 
             if let Some(protocol) = pseudo.protocol.take() {
-                return Some((":protocol", protocol.as_str().as_bytes()).into());
+                return Some(pseudo_field(":protocol", protocol.as_str()));
             }
         }
 
@@ -193,7 +194,8 @@ impl Iterator for HeaderIter {
                 self.last_header_name = Some(new);
             }
             if let (Some(ref n), v) = (&self.last_header_name, header_value) {
-                return Some((n.as_str(), v.as_bytes()).into());
+                let field: HeaderField = (n.as_str(), v.as_bytes()).into();
+                return Some(field.with_sensitive(v.is_sensitive()));
             }
         }
 
@@ -201,6 +203,11 @@ impl Iterator for HeaderIter {
     }
 }
 
+// Pseudo-header names are static, only the value is copied
+fn pseudo_field(name: &'static str, value: &str) -> HeaderField {
+    HeaderField::new(name, Bytes::copy_from_slice(value.as_bytes()))
+}
+
 impl TryFrom<Vec<HeaderField>> for Header {
     type Error = HeaderError;
     fn try_from(headers: Vec<HeaderField>) -> Result<Self, Self::Error> {
@@ -208,6 +215,7 @@ impl TryFrom<Vec<HeaderField>> for Header {
         let mut pseudo = Pseudo::default();
 
         for field in headers.into_iter() {
+            let sensitive = field.sensitive;
             let (name, value) = field.into_inner();
             match Field::parse(name, value)? {
                 Field::Method(m) => {
@@ -230,7 +238,8 @@ impl TryFrom<Vec<HeaderField>> for Header {
                     pseudo.status = Some(s);
                     pseudo.len += 1;
                 }
-                Field::Header((n, v)) => {
+                Field::Header((n, mut v)) => {
+                    v.set_sensitive(sensitive);
                     fields.append(n, v);
                 }
                 Field::Protocol(p) => {
@@ -255,11 +264,9 @@ enum Field {
 }
 
 impl Field {
-    fn parse<N, V>(name: N, value: V) -> Result<Self, HeaderError>
-    where
-        N: AsRef<[u8]>,
-        V: AsRef<[u8]>,
-    {
+    // Values are moved into their `http` type without copying them, and without cloning them
+    // as cloning a `Bytes` built from a `Vec` allocates. Only non-standard names are copied.
+    fn parse(name: Bytes, value: Bytes) -> Result<Self, HeaderError> {
         let name = name.as_ref();
         if name.is_empty() {
             return Err(HeaderError::InvalidHeaderName("name is empty".into()));
@@ -283,8 +290,8 @@ impl Field {
         if name[0] != b':' {
             return Ok(Field::Header((
                 HeaderName::from_lowercase(name).map_err(|_| HeaderError::invalid_name(name))?,
-                HeaderValue::from_bytes(value.as_ref())
-                    .map_err(|_| HeaderError::invalid_value(name, value))?,
+                HeaderValue::from_maybe_shared(value)
+                    .map_err(|_| HeaderError::invalid_value_of(name))?,
             )));
         }
 
@@ -293,8 +300,14 @@ impl Field {
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.3.1
             //# If these fields are present, they MUST NOT be
             //# empty.
-            b":authority" => Field::Authority(try_value(name, value)?),
-            b":path" => Field::Path(try_value(name, value)?),
+            b":authority" => Field::Authority(
+                Authority::from_maybe_shared(value)
+                    .map_err(|_| HeaderError::invalid_value_of(name))?,
+            ),
+            b":path" => Field::Path(
+                PathAndQuery::from_maybe_shared(value)
+                    .map_err(|_| HeaderError::invalid_value_of(name))?,
+            ),
             b":method" => Field::Method(
                 Method::from_bytes(value.as_ref())
                     .map_err(|_| HeaderError::invalid_value(name, value))?,
@@ -309,6 +322,30 @@ impl Field {
     }
 }
 
+/// Parses the `content-length` header field, if any
+///
+/// Multiple `content-length` fields are accepted as long as they all carry the same value.
+pub fn content_length(fields: &HeaderMap) -> Result<Option<u64>, HeaderError> {
+    let mut length = None;
+
+    for value in fields.get_all(header::CONTENT_LENGTH) {
+        let parsed = std::str::from_utf8(value.as_bytes())
+            .ok()
+            .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| HeaderError::invalid_value(header::CONTENT_LENGTH, value))?;
+
+        match length {
+            Some(previous) if previous != parsed => {
+                return Err(HeaderError::invalid_value(header::CONTENT_LENGTH, value))
+            }
+            _ => length = Some(parsed),
+        }
+    }
+
+    Ok(length)
+}
+
 fn try_value<N, V, R>(name: N, value: V) -> Result<R, HeaderError>
 where
     N: AsRef<[u8]>,
@@ -459,6 +496,14 @@ impl HeaderError {
             value.as_ref()
         ))
     }
+
+    // For a value moved into the conversion which rejected it
+    fn invalid_value_of<N>(name: N) -> Self
+    where
+        N: AsRef<[u8]>,
+    {
+        HeaderError::InvalidHeaderValue(format!("{:?}", String::from_utf8_lossy(name.as_ref())))
+    }
 }
 
 impl std::error::Error for HeaderError {}
@@ -595,6 +640,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn header_values_are_not_copied() {
+        let value = Bytes::from(b"bar".to_vec());
+        let ptr = value.as_ptr();
+
+        let fields = Header::try_from(vec![HeaderField::new("foo", value)])
+            .unwrap()
+            .into_fields();
+        assert_eq!(fields["foo"].as_bytes().as_ptr(), ptr);
+    }
+
     #[test]
     fn preserves_duplicate_headers() {
         let headers = Header::try_from(vec![
@@ -614,12 +670,14 @@ mod tests {
                 .collect::<Vec<_>>(),
             vec![
                 HeaderField {
-                    name: std::borrow::Cow::Borrowed(b"set-cookie"),
-                    value: std::borrow::Cow::Borrowed(b"foo=foo")
+                    name: Bytes::from_static(b"set-cookie"),
+                    value: Bytes::from_static(b"foo=foo"),
+                    sensitive: false,
                 },
                 HeaderField {
-                    name: std::borrow::Cow::Borrowed(b"set-cookie"),
-                    value: std::borrow::Cow::Borrowed(b"bar=bar")
+                    name: Bytes::from_static(b"set-cookie"),
+                    value: Bytes::from_static(b"bar=bar"),
+                    sensitive: false,
                 }
             ]
         );
@@ -629,9 +687,70 @@ mod tests {
                 .filter(|h| h.name.as_ref() == b"other-header")
                 .collect::<Vec<_>>(),
             vec![HeaderField {
-                name: std::borrow::Cow::Borrowed(b"other-header"),
-                value: std::borrow::Cow::Borrowed(b"other-header-value")
+                name: Bytes::from_static(b"other-header"),
+                value: Bytes::from_static(b"other-header-value"),
+                sensitive: false,
             },]
         );
     }
+
+    #[test]
+    fn content_length_absent() {
+        assert_matches!(content_length(&HeaderMap::new()), Ok(None));
+    }
+
+    #[test]
+    fn content_length_duplicates() {
+        let mut fields = HeaderMap::new();
+        fields.append(header::CONTENT_LENGTH, HeaderValue::from_static("42"));
+        fields.append(header::CONTENT_LENGTH, HeaderValue::from_static("42"));
+        assert_matches!(content_length(&fields), Ok(Some(42)));
+
+        fields.append(header::CONTENT_LENGTH, HeaderValue::from_static("43"));
+        assert_matches!(
+            content_length(&fields),
+            Err(HeaderError::InvalidHeaderValue(_))
+        );
+    }
+
+    #[test]
+    fn content_length_invalid() {
+        for value in ["", "-1", "+1", "1.0", "0x10", "18446744073709551616"] {
+            let mut fields = HeaderMap::new();
+            fields.insert(header::CONTENT_LENGTH, HeaderValue::from_static(value));
+            assert_matches!(
+                content_length(&fields),
+                Err(HeaderError::InvalidHeaderValue(_))
+            );
+        }
+    }
+
+    #[test]
+    fn sensitive_values_are_never_indexed() {
+        let mut fields = HeaderMap::new();
+        let mut token = HeaderValue::from_static("secret");
+        token.set_sensitive(true);
+        fields.insert(header::AUTHORIZATION, token);
+        fields.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+
+        let header = Header::request(
+            Method::GET,
+            Uri::from_static("https://example.com/"),
+            fields,
+            Default::default(),
+        )
+        .unwrap();
+        let encoded: Vec<HeaderField> = header.into_iter().collect();
+        let sensitive = encoded
+            .iter()
+            .filter(|f| f.sensitive)
+            .map(|f| f.name.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(sensitive, vec![b"authorization".as_ref()]);
+
+        let decoded = Header::try_from(encoded).unwrap();
+        let (_, _, _, fields) = decoded.into_request_parts().unwrap();
+        assert!(fields[header::AUTHORIZATION].is_sensitive());
+        assert!(!fields[header::ACCEPT].is_sensitive());
+    }
 }
@@ -95,6 +95,8 @@ impl fmt::Display for StreamId {
 
 impl StreamId {
     pub(crate) const FIRST_REQUEST: Self = Self::new(0, Dir::Bi, Side::Client);
+    /// The largest request stream id which can be encoded
+    pub(crate) const LAST_REQUEST: Self = Self::new(VarInt::MAX.0 >> 2, Dir::Bi, Side::Client);
 
     /// Is this a client-initiated request?
     pub fn is_request(&self) -> bool {
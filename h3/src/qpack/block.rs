@@ -1,4 +1,4 @@
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 
 use super::{parse_error::ParseError, prefix_int, prefix_string};
 
@@ -128,25 +128,35 @@ impl HeaderPrefix {
         total_inserted: usize,
         max_table_size: usize,
     ) -> Result<(usize, usize), ParseError> {
-        if max_table_size == 0 {
-            return Ok((0, 0));
-        }
-
         // 4.5.1.1. Required Insert Count
         let required = if self.encoded_insert_count == 0 {
             0
         } else {
-            let mut insert_count = self.encoded_insert_count - 1;
             let max_entries = max_table_size / 32;
-            let mut wrapped = total_inserted % (2 * max_entries);
-
-            if wrapped >= insert_count + max_entries {
-                insert_count += 2 * max_entries;
-            } else if wrapped + max_entries < insert_count {
-                wrapped += 2 * max_entries;
+            let full_range = 2 * max_entries;
+
+            //= https://www.rfc-editor.org/rfc/rfc9204#section-4.5.1.1
+            //# If the decoder encounters a value of EncodedInsertCount that could
+            //# not have been produced by a conforming encoder, it MUST treat this as
+            //# a connection error of type QPACK_DECOMPRESSION_FAILED.
+            let invalid = ParseError::InvalidInsertCount(self.encoded_insert_count);
+            if self.encoded_insert_count > full_range {
+                return Err(invalid);
             }
 
-            insert_count + total_inserted - wrapped
+            let max_value = total_inserted + max_entries;
+            let max_wrapped = (max_value / full_range) * full_range;
+            let mut required = max_wrapped + self.encoded_insert_count - 1;
+            if required > max_value {
+                if required <= full_range {
+                    return Err(invalid);
+                }
+                required -= full_range;
+            }
+            if required == 0 {
+                return Err(invalid);
+            }
+            required
         };
 
         let base = if required == 0 {
@@ -267,25 +277,52 @@ impl IndexedWithPostBase {
     }
 }
 
+// The 'N' bit of the literal representations: an intermediary forwarding
+// such a field line must keep it a literal, with the 'N' bit set.
+// https://www.rfc-editor.org/rfc/rfc9204.html#name-never-indexed-literals
 #[derive(Debug, PartialEq)]
 pub enum LiteralWithNameRef {
-    Static { index: usize, value: Vec<u8> },
-    Dynamic { index: usize, value: Vec<u8> },
+    Static {
+        index: usize,
+        value: Bytes,
+        never_indexed: bool,
+    },
+    Dynamic {
+        index: usize,
+        value: Bytes,
+        never_indexed: bool,
+    },
 }
 
 impl LiteralWithNameRef {
-    pub fn new_static<T: Into<Vec<u8>>>(index: usize, value: T) -> Self {
+    pub fn new_static<T: Into<Bytes>>(index: usize, value: T) -> Self {
         LiteralWithNameRef::Static {
             index,
             value: value.into(),
+            never_indexed: false,
         }
     }
 
-    pub fn new_dynamic<T: Into<Vec<u8>>>(index: usize, value: T) -> Self {
+    pub fn new_dynamic<T: Into<Bytes>>(index: usize, value: T) -> Self {
         LiteralWithNameRef::Dynamic {
             index,
             value: value.into(),
+            never_indexed: false,
+        }
+    }
+
+    pub fn with_never_indexed(mut self, never_indexed: bool) -> Self {
+        match self {
+            LiteralWithNameRef::Static {
+                never_indexed: ref mut n,
+                ..
+            }
+            | LiteralWithNameRef::Dynamic {
+                never_indexed: ref mut n,
+                ..
+            } => *n = never_indexed,
         }
+        self
     }
 
     pub fn decode<R: Buf>(buf: &mut R) -> Result<Self, ParseError> {
@@ -297,10 +334,10 @@ impl LiteralWithNameRef {
                     ));
                 }
 
-                Ok(LiteralWithNameRef::new_static(
-                    i as usize,
-                    prefix_string::decode(8, buf)?,
-                ))
+                Ok(
+                    LiteralWithNameRef::new_static(i as usize, prefix_string::decode(8, buf)?)
+                        .with_never_indexed(f & 0b0010 != 0),
+                )
             }
             (f, i) if f & 0b0101 == 0b0100 => {
                 if i > (usize::MAX as u64) {
@@ -309,10 +346,10 @@ impl LiteralWithNameRef {
                     ));
                 }
 
-                Ok(LiteralWithNameRef::new_dynamic(
-                    i as usize,
-                    prefix_string::decode(8, buf)?,
-                ))
+                Ok(
+                    LiteralWithNameRef::new_dynamic(i as usize, prefix_string::decode(8, buf)?)
+                        .with_never_indexed(f & 0b0010 != 0),
+                )
             }
             (f, _) => Err(ParseError::InvalidPrefix(f)),
         }
@@ -320,12 +357,22 @@ impl LiteralWithNameRef {
 
     pub fn encode<W: BufMut>(&self, buf: &mut W) -> Result<(), prefix_string::Error> {
         match self {
-            LiteralWithNameRef::Static { index, value } => {
-                prefix_int::encode(4, 0b0101, *index as u64, buf);
+            LiteralWithNameRef::Static {
+                index,
+                value,
+                never_indexed,
+            } => {
+                let n = if *never_indexed { 0b0010 } else { 0 };
+                prefix_int::encode(4, 0b0101 | n, *index as u64, buf);
                 prefix_string::encode(8, 0, value, buf)?;
             }
-            LiteralWithNameRef::Dynamic { index, value } => {
-                prefix_int::encode(4, 0b0100, *index as u64, buf);
+            LiteralWithNameRef::Dynamic {
+                index,
+                value,
+                never_indexed,
+            } => {
+                let n = if *never_indexed { 0b0010 } else { 0 };
+                prefix_int::encode(4, 0b0100 | n, *index as u64, buf);
                 prefix_string::encode(8, 0, value, buf)?;
             }
         }
@@ -336,17 +383,24 @@ impl LiteralWithNameRef {
 #[derive(Debug, PartialEq)]
 pub struct LiteralWithPostBaseNameRef {
     pub index: usize,
-    pub value: Vec<u8>,
+    pub value: Bytes,
+    pub never_indexed: bool,
 }
 
 impl LiteralWithPostBaseNameRef {
-    pub fn new<T: Into<Vec<u8>>>(index: usize, value: T) -> Self {
+    pub fn new<T: Into<Bytes>>(index: usize, value: T) -> Self {
         LiteralWithPostBaseNameRef {
             index,
             value: value.into(),
+            never_indexed: false,
         }
     }
 
+    pub fn with_never_indexed(mut self, never_indexed: bool) -> Self {
+        self.never_indexed = never_indexed;
+        self
+    }
+
     pub fn decode<R: Buf>(buf: &mut R) -> Result<Self, ParseError> {
         match prefix_int::decode(3, buf)? {
             (f, i) if f & 0b1111_0000 == 0 => {
@@ -356,17 +410,18 @@ impl LiteralWithPostBaseNameRef {
                     ));
                 }
 
-                Ok(LiteralWithPostBaseNameRef::new(
-                    i as usize,
-                    prefix_string::decode(8, buf)?,
-                ))
+                Ok(
+                    LiteralWithPostBaseNameRef::new(i as usize, prefix_string::decode(8, buf)?)
+                        .with_never_indexed(f & 0b0001 != 0),
+                )
             }
             (f, _) => Err(ParseError::InvalidPrefix(f)),
         }
     }
 
     pub fn encode<W: BufMut>(&self, buf: &mut W) -> Result<(), prefix_string::Error> {
-        prefix_int::encode(3, 0b0000, self.index as u64, buf);
+        let n = if self.never_indexed { 0b0001 } else { 0 };
+        prefix_int::encode(3, n, self.index as u64, buf);
         prefix_string::encode(8, 0, &self.value, buf)?;
         Ok(())
     }
@@ -374,32 +429,42 @@ impl LiteralWithPostBaseNameRef {
 
 #[derive(Debug, PartialEq)]
 pub struct Literal {
-    pub name: Vec<u8>,
-    pub value: Vec<u8>,
+    pub name: Bytes,
+    pub value: Bytes,
+    pub never_indexed: bool,
 }
 
 impl Literal {
-    pub fn new<T: Into<Vec<u8>>>(name: T, value: T) -> Self {
+    pub fn new<T: Into<Bytes>>(name: T, value: T) -> Self {
         Literal {
             name: name.into(),
             value: value.into(),
+            never_indexed: false,
         }
     }
 
+    pub fn with_never_indexed(mut self, never_indexed: bool) -> Self {
+        self.never_indexed = never_indexed;
+        self
+    }
+
     pub fn decode<R: Buf>(buf: &mut R) -> Result<Self, ParseError> {
         if buf.remaining() < 1 {
             return Err(ParseError::Integer(prefix_int::Error::UnexpectedEnd));
         } else if buf.chunk()[0] & 0b1110_0000 != 0b0010_0000 {
             return Err(ParseError::InvalidPrefix(buf.chunk()[0]));
         }
+        let never_indexed = buf.chunk()[0] & 0b0001_0000 != 0;
         Ok(Literal::new(
             prefix_string::decode(4, buf)?,
             prefix_string::decode(8, buf)?,
-        ))
+        )
+        .with_never_indexed(never_indexed))
     }
 
     pub fn encode<W: BufMut>(&self, buf: &mut W) -> Result<(), prefix_string::Error> {
-        prefix_string::encode(4, 0b0010, &self.name, buf)?;
+        let n = if self.never_indexed { 0b0001 } else { 0 };
+        prefix_string::encode(4, 0b0010 | n, &self.name, buf)?;
         prefix_string::encode(8, 0, &self.value, buf)?;
         Ok(())
     }
@@ -467,6 +532,40 @@ mod test {
         assert_eq!(Literal::decode(&mut read), Ok(field));
     }
 
+    #[test]
+    fn literal_never_indexed() {
+        let field = Literal::new("foo", "bar").with_never_indexed(true);
+        let mut buf = vec![];
+        field.encode(&mut buf).unwrap();
+        assert_eq!(buf[0] & 0b1111_0000, 0b0011_0000);
+        let mut read = Cursor::new(&buf);
+        assert_eq!(Literal::decode(&mut read), Ok(field));
+    }
+
+    #[test]
+    fn literal_with_name_ref_never_indexed() {
+        for field in [
+            LiteralWithNameRef::new_static(42, "foo").with_never_indexed(true),
+            LiteralWithNameRef::new_dynamic(3, "foo").with_never_indexed(true),
+        ] {
+            let mut buf = vec![];
+            field.encode(&mut buf).unwrap();
+            assert_eq!(buf[0] & 0b0010_0000, 0b0010_0000);
+            let mut read = Cursor::new(&buf);
+            assert_eq!(LiteralWithNameRef::decode(&mut read), Ok(field));
+        }
+    }
+
+    #[test]
+    fn literal_with_post_base_name_ref_never_indexed() {
+        let field = LiteralWithPostBaseNameRef::new(3, "foo").with_never_indexed(true);
+        let mut buf = vec![];
+        field.encode(&mut buf).unwrap();
+        assert_eq!(buf[0] & 0b1111_1000, 0b0000_1000);
+        let mut read = Cursor::new(&buf);
+        assert_eq!(LiteralWithPostBaseNameRef::decode(&mut read), Ok(field));
+    }
+
     #[test]
     fn header_prefix() {
         let prefix = HeaderPrefix::new(10, 5, 12, TABLE_SIZE);
@@ -483,6 +582,35 @@ mod test {
         HeaderPrefix::new(10, 5, 12, 0).get(1, 0).unwrap();
     }
 
+    #[test]
+    fn header_prefix_wrapped() {
+        // 100 entries fit the table, the encoded insert count wraps every 200
+        let prefix = HeaderPrefix::new(450, 450, 450, 3200);
+        assert_eq!(prefix.encoded_insert_count, 51);
+        assert_eq!(prefix.get(430, 3200), Ok((450, 450)));
+    }
+
+    #[test]
+    fn header_prefix_invalid_insert_count() {
+        let prefix = |encoded_insert_count| HeaderPrefix {
+            encoded_insert_count,
+            sign_negative: false,
+            delta_base: 0,
+        };
+        // Larger than twice the number of entries the table holds
+        assert_eq!(
+            prefix(201).get(0, 3200),
+            Err(ParseError::InvalidInsertCount(201))
+        );
+        // No dynamic table
+        assert_eq!(prefix(1).get(0, 0), Err(ParseError::InvalidInsertCount(1)));
+        // More than a table ahead of the entries received
+        assert_eq!(
+            prefix(150).get(0, 3200),
+            Err(ParseError::InvalidInsertCount(150))
+        );
+    }
+
     #[test]
     fn base_index_too_small() {
         let mut buf = vec![];
@@ -1,5 +1,5 @@
 use bytes::{Buf, BufMut};
-use std::{convert::TryInto, fmt, io::Cursor, num::TryFromIntError};
+use std::{cmp, convert::TryInto, fmt, io::Cursor, num::TryFromIntError};
 
 #[cfg(feature = "tracing")]
 use tracing::trace;
@@ -8,6 +8,7 @@ use super::{
     dynamic::{DynamicTable, DynamicTableDecoder, Error as DynamicTableError},
     field::HeaderField,
     static_::{Error as StaticError, StaticTable},
+    stats::{Stats, TableEntry},
     vas,
 };
 
@@ -25,18 +26,32 @@ use super::{
 
 use super::{prefix_int, prefix_string};
 
+/// Decoding error
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    /// Malformed integer
     InvalidInteger(prefix_int::Error),
+    /// Malformed string
     InvalidString(prefix_string::Error),
+    /// Reference outside of the dynamic table
     InvalidIndex(vas::Error),
+    /// Dynamic table update refused, like a capacity above the maximum
     DynamicTable(DynamicTableError),
+    /// Reference outside of the static table
     InvalidStaticIndex(usize),
+    /// Unknown instruction
     UnknownPrefix(u8),
+    /// The section requires this insert count, the entries are not received yet
     MissingRefs(usize),
+    /// The base of the section is out of bounds
     BadBaseIndex(isize),
+    /// The encoded Required Insert Count could not be produced by a conforming encoder
+    BadRequiredInsertCount(usize),
+    /// Truncated input
     UnexpectedEnd,
+    /// The decoded size exceeds the maximum
     HeaderTooLong(u64),
+    /// Integer too large for this platform
     BufSize(TryFromIntError),
 }
 
@@ -53,6 +68,9 @@ impl std::fmt::Display for Error {
             Error::UnknownPrefix(p) => write!(f, "unknown instruction code: 0x{}", p),
             Error::MissingRefs(n) => write!(f, "missing {} refs to decode bloc", n),
             Error::BadBaseIndex(i) => write!(f, "out of bounds base index: {}", i),
+            Error::BadRequiredInsertCount(c) => {
+                write!(f, "invalid encoded required insert count: {}", c)
+            }
             Error::UnexpectedEnd => write!(f, "unexpected end"),
             Error::HeaderTooLong(_) => write!(f, "header too long"),
             Error::BufSize(_) => write!(f, "number in buffer wrong size"),
@@ -60,46 +78,97 @@ impl std::fmt::Display for Error {
     }
 }
 
+/// Writes a Section Acknowledgment to the decoder stream
+///
+/// Sent once a field section referencing the dynamic table is decoded.
 pub fn ack_header<W: BufMut>(stream_id: u64, decoder: &mut W) {
     HeaderAck(stream_id).encode(decoder);
 }
 
+/// Writes a Stream Cancellation to the decoder stream
+///
+/// Sent when a stream is reset or abandoned before its field sections are decoded.
 pub fn stream_canceled<W: BufMut>(stream_id: u64, decoder: &mut W) {
     StreamCancel(stream_id).encode(decoder);
 }
 
+/// A decoded field section
 #[derive(PartialEq, Debug)]
 pub struct Decoded {
     /// The decoded fields
     pub fields: Vec<HeaderField>,
     /// Whether one or more encoded fields were referencing the dynamic table
     pub dyn_ref: bool,
+    /// The number of dynamic table insertions the section depends on
+    pub required_insert_count: usize,
     /// Decoded size, calculated as stated in "4.1.1.3. Header Size Constraints"
     pub mem_size: u64,
 }
 
+/// Decodes field sections, with the dynamic table built from the peer's encoder stream
 pub struct Decoder {
     table: DynamicTable,
+    // Insert count the peer's encoder knows we received, through acknowledgments
+    acknowledged: usize,
+    stats: Stats,
 }
 
 impl Decoder {
-    // Decode field lines received on Request of Push stream.
+    /// Creates a decoder whose dynamic table is bounded by `max_table_capacity`
+    ///
+    /// `max_table_capacity` is the SETTINGS_QPACK_MAX_TABLE_CAPACITY sent to the peer. The
+    /// dynamic table stays empty until the peer's encoder sets its capacity.
+    pub fn new(max_table_capacity: usize) -> Result<Self, Error> {
+        let mut table = DynamicTable::new();
+        table.set_max_capacity(max_table_capacity)?;
+        Ok(Self {
+            table,
+            acknowledged: 0,
+            stats: Stats::default(),
+        })
+    }
+
+    /// The SETTINGS_QPACK_MAX_TABLE_CAPACITY the decoder was created with
+    pub fn max_table_capacity(&self) -> usize {
+        self.table.max_capacity()
+    }
+
+    /// Decodes a field section received on a request or push stream
+    ///
+    /// Fails with `Error::MissingRefs` when the section references entries not received yet,
+    /// it can be decoded again once `on_encoder_recv` inserted them.
+    pub fn decode_header<T: Buf>(&mut self, buf: &mut T) -> Result<Decoded, Error> {
+        self.decode_header_limited(buf, u64::MAX)
+    }
+
+    /// Decodes a field section, failing with `Error::HeaderTooLong` once the decoded size
+    /// exceeds `max_size`
     // https://www.rfc-editor.org/rfc/rfc9204.html#name-field-line-representations
-    pub fn decode_header<T: Buf>(&self, buf: &mut T) -> Result<Decoded, Error> {
+    pub fn decode_header_limited<T: Buf>(
+        &mut self,
+        buf: &mut T,
+        max_size: u64,
+    ) -> Result<Decoded, Error> {
         let (required_ref, base) = HeaderPrefix::decode(buf)?
-            .get(self.table.total_inserted(), self.table.max_mem_size())?;
+            .get(self.table.total_inserted(), self.table.max_capacity())?;
 
         if required_ref > self.table.total_inserted() {
             return Err(Error::MissingRefs(required_ref));
         }
 
-        let decoder_table = self.table.decoder(base);
+        let decoder_table = self.table.decoder(base, required_ref);
 
         let mut mem_size = 0;
         let mut fields = Vec::new();
         while buf.has_remaining() {
+            let first = buf.chunk()[0];
             let field = Self::parse_header_field(&decoder_table, buf)?;
+            self.stats.count_field_line(first);
             mem_size += field.mem_size() as u64;
+            // Cancel decoding if the header is considered too big
+            if mem_size > max_size {
+                return Err(Error::HeaderTooLong(mem_size));
+            }
             fields.push(field);
         }
 
@@ -107,17 +176,59 @@ impl Decoder {
             fields,
             mem_size,
             dyn_ref: required_ref > 0,
+            required_insert_count: required_ref,
         })
     }
 
-    // The receiving side of encoder stream
+    /// Counters of the field lines decoded so far, and the current state of the dynamic table
+    ///
+    /// `blocked_streams` is left to the caller, which keeps the blocked sections.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        self.table.fill_stats(&mut stats);
+        stats
+    }
+
+    /// Lists the entries of the dynamic table, from the oldest to the newest
+    pub fn table_entries(&self) -> Vec<TableEntry> {
+        self.table.entries()
+    }
+
+    /// Acknowledges a decoded section referencing the dynamic table
+    ///
+    /// Writes a Section Acknowledgment to `write`, the decoder stream. It also tells the
+    /// encoder about the insertions the section depends on, which no Insert Count Increment
+    /// has to acknowledge anymore.
+    pub fn ack_section<W: BufMut>(&mut self, stream_id: u64, decoded: &Decoded, write: &mut W) {
+        if decoded.required_insert_count == 0 {
+            return;
+        }
+        ack_header(stream_id, write);
+        self.acknowledged = cmp::max(self.acknowledged, decoded.required_insert_count);
+    }
+
+    /// Processes the instructions received on the peer's encoder stream
+    ///
+    /// Complete instructions are consumed from `read`, an incomplete one is left for the next
+    /// call. An Insert Count Increment for the new entries is written to `write`, the decoder
+    /// stream. Returns the number of entries inserted since the decoder was created.
     pub fn on_encoder_recv<R: Buf, W: BufMut>(
         &mut self,
         read: &mut R,
         write: &mut W,
     ) -> Result<usize, Error> {
-        let inserted_on_start = self.table.total_inserted();
-
+        let total_inserted = self.recv_encoder_instructions(read)?;
+        self.insert_count_increment(write)?;
+        Ok(total_inserted)
+    }
+
+    /// Processes the instructions received on the peer's encoder stream, without
+    /// acknowledging the new entries
+    ///
+    /// Lets the caller batch the acknowledgments with `insert_count_increment`, an encoder
+    /// cannot reference an entry without blocking the stream until it is acknowledged.
+    /// Returns the number of entries inserted since the decoder was created.
+    pub fn recv_encoder_instructions<R: Buf>(&mut self, read: &mut R) -> Result<usize, Error> {
         while let Some(instruction) = self.parse_instruction(read)? {
             #[cfg(feature = "tracing")]
             trace!("instruction {:?}", instruction);
@@ -125,19 +236,34 @@ impl Decoder {
             match instruction {
                 Instruction::Insert(field) => self.table.put(field)?,
                 Instruction::TableSizeUpdate(size) => {
+                    if size > self.table.max_capacity() {
+                        return Err(Error::DynamicTable(
+                            DynamicTableError::MaximumTableSizeTooLarge,
+                        ));
+                    }
                     self.table.set_max_size(size)?;
                 }
             }
         }
 
-        if self.table.total_inserted() != inserted_on_start {
-            InsertCountIncrement((self.table.total_inserted() - inserted_on_start).try_into()?)
-                .encode(write);
-        }
-
         Ok(self.table.total_inserted())
     }
 
+    /// Writes an Insert Count Increment for the entries not acknowledged yet to `write`, the
+    /// decoder stream
+    ///
+    /// Nothing is written when the encoder already knows about all the entries.
+    pub fn insert_count_increment<W: BufMut>(&mut self, write: &mut W) -> Result<(), Error> {
+        let total_inserted = self.table.total_inserted();
+        while total_inserted > self.acknowledged {
+            // Split in increments of at most 64, the largest `InsertCountIncrement` decodes
+            let increment = cmp::min(total_inserted - self.acknowledged, 64);
+            InsertCountIncrement(increment.try_into()?).encode(write);
+            self.acknowledged += increment;
+        }
+        Ok(())
+    }
+
     fn parse_instruction<R: Buf>(&self, read: &mut R) -> Result<Option<Instruction>, Error> {
         if read.remaining() < 1 {
             return Ok(None);
@@ -192,20 +318,32 @@ impl Decoder {
                 table.get_postbase(index)?.clone()
             }
             HeaderBlockField::LiteralWithNameRef => match LiteralWithNameRef::decode(buf)? {
-                LiteralWithNameRef::Static { index, value } => {
-                    StaticTable::get(index)?.with_value(value)
-                }
-                LiteralWithNameRef::Dynamic { index, value } => {
-                    table.get_relative(index)?.with_value(value)
-                }
+                LiteralWithNameRef::Static {
+                    index,
+                    value,
+                    never_indexed,
+                } => StaticTable::get(index)?
+                    .with_value(value)
+                    .with_sensitive(never_indexed),
+                LiteralWithNameRef::Dynamic {
+                    index,
+                    value,
+                    never_indexed,
+                } => table
+                    .get_relative(index)?
+                    .with_value(value)
+                    .with_sensitive(never_indexed),
             },
             HeaderBlockField::LiteralWithPostBaseNameRef => {
                 let literal = LiteralWithPostBaseNameRef::decode(buf)?;
-                table.get_postbase(literal.index)?.with_value(literal.value)
+                table
+                    .get_postbase(literal.index)?
+                    .with_value(literal.value)
+                    .with_sensitive(literal.never_indexed)
             }
             HeaderBlockField::Literal => {
                 let literal = Literal::decode(buf)?;
-                HeaderField::new(literal.name, literal.value)
+                HeaderField::new(literal.name, literal.value).with_sensitive(literal.never_indexed)
             }
             _ => return Err(Error::UnknownPrefix(first)),
         };
@@ -213,7 +351,19 @@ impl Decoder {
     }
 }
 
-// Decode field lines received on Request or Push stream.
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            table: DynamicTable::new(),
+            acknowledged: 0,
+            stats: Stats::default(),
+        }
+    }
+}
+
+/// Decodes a field section which only references the static table
+///
+/// Fails with `Error::HeaderTooLong` once the decoded size exceeds `max_size`.
 // https://www.rfc-editor.org/rfc/rfc9204.html#name-field-line-representations
 pub fn decode_stateless<T: Buf>(buf: &mut T, max_size: u64) -> Result<Decoded, Error> {
     let (required_ref, _base) = HeaderPrefix::decode(buf)?.get(0, 0)?;
@@ -234,13 +384,17 @@ pub fn decode_stateless<T: Buf>(buf: &mut T, max_size: u64) -> Result<Decoded, E
             },
             HeaderBlockField::LiteralWithNameRef => match LiteralWithNameRef::decode(buf)? {
                 LiteralWithNameRef::Dynamic { .. } => return Err(Error::MissingRefs(0)),
-                LiteralWithNameRef::Static { index, value } => {
-                    StaticTable::get(index)?.with_value(value)
-                }
+                LiteralWithNameRef::Static {
+                    index,
+                    value,
+                    never_indexed,
+                } => StaticTable::get(index)?
+                    .with_value(value)
+                    .with_sensitive(never_indexed),
             },
             HeaderBlockField::Literal => {
                 let literal = Literal::decode(buf)?;
-                HeaderField::new(literal.name, literal.value)
+                HeaderField::new(literal.name, literal.value).with_sensitive(literal.never_indexed)
             }
             _ => return Err(Error::UnknownPrefix(buf.chunk()[0])),
         };
@@ -256,13 +410,18 @@ pub fn decode_stateless<T: Buf>(buf: &mut T, max_size: u64) -> Result<Decoded, E
         fields,
         mem_size,
         dyn_ref: false,
+        required_insert_count: 0,
     })
 }
 
 #[cfg(test)]
 impl From<DynamicTable> for Decoder {
     fn from(table: DynamicTable) -> Self {
-        Self { table }
+        Self {
+            acknowledged: table.total_inserted(),
+            table,
+            stats: Stats::default(),
+        }
     }
 }
 
@@ -328,6 +487,7 @@ impl From<ParseError> for Error {
             ParseError::String(x) => Error::InvalidString(x),
             ParseError::InvalidPrefix(p) => Error::UnknownPrefix(p),
             ParseError::InvalidBase(b) => Error::BadBaseIndex(b),
+            ParseError::InvalidInsertCount(c) => Error::BadRequiredInsertCount(c),
         }
     }
 }
@@ -342,6 +502,7 @@ impl From<TryFromIntError> for Error {
 mod tests {
     use super::*;
     use crate::qpack::tests::helpers::{build_table_with_size, TABLE_SIZE};
+    use bytes::Bytes;
 
     #[test]
     fn test_header_too_long() {
@@ -373,7 +534,7 @@ mod tests {
         assert!(decoder.on_encoder_recv(&mut enc, &mut dec).is_ok());
 
         assert_eq!(
-            decoder.table.decoder(1).get_relative(0),
+            decoder.table.decoder(1, 1).get_relative(0),
             Ok(&StaticTable::get(1).unwrap().with_value("serial value"))
         );
 
@@ -441,7 +602,7 @@ mod tests {
         assert!(decoder.on_encoder_recv(&mut enc, &mut dec).is_ok());
 
         assert_eq!(
-            decoder.table.decoder(1).get_relative(0),
+            decoder.table.decoder(1, 1).get_relative(0),
             Ok(&HeaderField::new("key", "value"))
         );
 
@@ -558,9 +719,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn insert_count_increment_batched() {
+        let mut buf = vec![];
+        for n in 0..70 {
+            InsertWithoutNameRef::new(format!("foo{}", n), "bar".into())
+                .encode(&mut buf)
+                .unwrap();
+        }
+
+        let mut decoder = Decoder::from(build_table_with_size(0));
+        let mut enc = Cursor::new(&buf);
+        assert_eq!(decoder.recv_encoder_instructions(&mut enc), Ok(70));
+
+        let mut dec = vec![];
+        decoder.insert_count_increment(&mut dec).unwrap();
+        let mut dec_cursor = Cursor::new(&dec);
+        assert_eq!(
+            InsertCountIncrement::decode(&mut dec_cursor),
+            Ok(Some(InsertCountIncrement(64)))
+        );
+        assert_eq!(
+            InsertCountIncrement::decode(&mut dec_cursor),
+            Ok(Some(InsertCountIncrement(6)))
+        );
+        assert!(!dec_cursor.has_remaining());
+
+        let mut dec = vec![];
+        decoder.insert_count_increment(&mut dec).unwrap();
+        assert!(dec.is_empty());
+    }
+
+    #[test]
+    fn section_ack_covers_inserts() {
+        let mut buf = vec![];
+        InsertWithoutNameRef::new("foo1", "bar")
+            .encode(&mut buf)
+            .unwrap();
+        InsertWithoutNameRef::new("foo2", "bar")
+            .encode(&mut buf)
+            .unwrap();
+        let mut decoder = Decoder::from(build_table_with_size(0));
+        decoder
+            .recv_encoder_instructions(&mut Cursor::new(&buf))
+            .unwrap();
+
+        let mut block = vec![];
+        HeaderPrefix::new(1, 1, 2, TABLE_SIZE).encode(&mut block);
+        Indexed::Dynamic(0).encode(&mut block);
+        let decoded = decoder.decode_header(&mut Cursor::new(&block)).unwrap();
+        assert_eq!(decoded.required_insert_count, 1);
+
+        let mut dec = vec![];
+        decoder.ack_section(4, &decoded, &mut dec);
+        // Only the entry the section did not reference is left to acknowledge
+        decoder.insert_count_increment(&mut dec).unwrap();
+        let mut dec_cursor = Cursor::new(&dec);
+        assert_eq!(HeaderAck::decode(&mut dec_cursor), Ok(Some(HeaderAck(4))));
+        assert_eq!(
+            InsertCountIncrement::decode(&mut dec_cursor),
+            Ok(Some(InsertCountIncrement(1)))
+        );
+        assert!(!dec_cursor.has_remaining());
+    }
+
     #[test]
     fn largest_ref_too_big() {
-        let decoder = Decoder::from(build_table_with_size(0));
+        let mut decoder = Decoder::from(build_table_with_size(0));
         let mut buf = vec![];
         HeaderPrefix::new(8, 8, 10, TABLE_SIZE).encode(&mut buf);
 
@@ -591,7 +816,7 @@ mod tests {
         Indexed::Static(18).encode(&mut buf);
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(2));
+        let mut decoder = Decoder::from(build_table_with_size(2));
         let Decoded {
             fields, dyn_ref, ..
         } = decoder.decode_header(&mut read).unwrap();
@@ -623,7 +848,7 @@ mod tests {
         IndexedWithPostBase(1).encode(&mut buf);
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(4));
+        let mut decoder = Decoder::from(build_table_with_size(4));
         let Decoded {
             fields, dyn_ref, ..
         } = decoder.decode_header(&mut read).unwrap();
@@ -643,7 +868,7 @@ mod tests {
             .unwrap();
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(4));
+        let mut decoder = Decoder::from(build_table_with_size(4));
         let Decoded {
             fields, dyn_ref, ..
         } = decoder.decode_header(&mut read).unwrap();
@@ -660,17 +885,39 @@ mod tests {
     #[test]
     fn decode_post_base_name_ref_header_field() {
         let mut buf = vec![];
-        HeaderPrefix::new(2, 2, 4, TABLE_SIZE).encode(&mut buf);
+        HeaderPrefix::new(3, 2, 4, TABLE_SIZE).encode(&mut buf);
         LiteralWithPostBaseNameRef::new(0, "new bar3")
             .encode(&mut buf)
             .unwrap();
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(4));
+        let mut decoder = Decoder::from(build_table_with_size(4));
         let Decoded { fields, .. } = decoder.decode_header(&mut read).unwrap();
         assert_eq!(fields, &[field(3).with_value("new bar3")]);
     }
 
+    #[test]
+    fn decode_reference_above_required_insert_count() {
+        let mut decoder = Decoder::from(build_table_with_size(4));
+
+        // The base is ahead of the Required Insert Count, relative index 0 is the third entry
+        let mut buf = vec![];
+        HeaderPrefix::new(2, 3, 4, TABLE_SIZE).encode(&mut buf);
+        Indexed::Dynamic(0).encode(&mut buf);
+        assert_eq!(
+            decoder.decode_header(&mut Cursor::new(&buf)),
+            Err(Error::DynamicTable(DynamicTableError::BadRelativeIndex(0)))
+        );
+
+        let mut buf = vec![];
+        HeaderPrefix::new(2, 1, 4, TABLE_SIZE).encode(&mut buf);
+        IndexedWithPostBase(1).encode(&mut buf);
+        assert_eq!(
+            decoder.decode_header(&mut Cursor::new(&buf)),
+            Err(Error::DynamicTable(DynamicTableError::BadPostbaseIndex(1)))
+        );
+    }
+
     #[test]
     fn decode_without_name_ref_header_field() {
         let mut buf = vec![];
@@ -678,7 +925,7 @@ mod tests {
         Literal::new("foo", "bar").encode(&mut buf).unwrap();
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(0));
+        let mut decoder = Decoder::from(build_table_with_size(0));
         let Decoded { fields, .. } = decoder.decode_header(&mut read).unwrap();
         assert_eq!(
             fields,
@@ -686,6 +933,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_plain_literal_without_copy() {
+        let block = Bytes::from_static(&[0, 0, 0x23, b'f', b'o', b'o', 0x03, b'b', b'a', b'r']);
+
+        let Decoded { fields, .. } = decode_stateless(&mut block.clone(), u64::MAX).unwrap();
+        assert_eq!(fields, &[HeaderField::new("foo", "bar")]);
+        // Name and value point into the block
+        assert_eq!(fields[0].name.as_ptr(), block[3..].as_ptr());
+        assert_eq!(fields[0].value.as_ptr(), block[7..].as_ptr());
+    }
+
     // Largest Reference = 4
     //  |            Base Index = 0
     //  |                |
@@ -708,7 +966,7 @@ mod tests {
         IndexedWithPostBase(3).encode(&mut buf);
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(4));
+        let mut decoder = Decoder::from(build_table_with_size(4));
         let Decoded { fields, .. } = decoder.decode_header(&mut read).unwrap();
         assert_eq!(fields, &[field(1), field(2), field(3), field(4)]);
     }
@@ -731,7 +989,7 @@ mod tests {
         Indexed::Dynamic(10).encode(&mut buf);
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(build_table_with_size(max_entries + 10));
+        let mut decoder = Decoder::from(build_table_with_size(max_entries + 10));
         let Decoded { fields, .. } = decoder.decode_header(&mut read).expect("decode");
         assert_eq!(fields, &[field(max_entries - 5)]);
 
@@ -749,7 +1007,7 @@ mod tests {
         IndexedWithPostBase(4).encode(&mut buf);
 
         let mut read = Cursor::new(&buf);
-        let decoder = Decoder::from(table);
+        let mut decoder = Decoder::from(table);
         let Decoded { fields, .. } = decoder.decode_header(&mut read).unwrap();
         assert_eq!(fields, &[field(max_entries + 6), field(max_entries + 10)]);
     }
@@ -1,9 +1,12 @@
-use std::{
-    borrow::Cow,
-    collections::{btree_map::Entry as BTEntry, hash_map::Entry, BTreeMap, HashMap, VecDeque},
-};
+use std::collections::{btree_map::Entry as BTEntry, hash_map::Entry, BTreeMap, HashMap, VecDeque};
+
+use bytes::Bytes;
 
-use super::{field::HeaderField, static_::StaticTable};
+use super::{
+    field::HeaderField,
+    static_::StaticTable,
+    stats::{Stats, TableEntry},
+};
 use crate::qpack::vas::{self, VirtualAddressSpace};
 
 /**
@@ -12,6 +15,10 @@ use crate::qpack::vas::{self, VirtualAddressSpace};
 const SETTINGS_MAX_TABLE_CAPACITY_MAX: usize = 1_073_741_823; // 2^30 -1
 const SETTINGS_MAX_BLOCKED_STREAMS_MAX: usize = 65_535; // 2^16 - 1
 
+// Entries within the oldest quarter of the capacity are about to be evicted, the encoder
+// duplicates them instead of referencing them.
+const DRAINING_DIVISOR: usize = 4;
+
 #[derive(Debug, PartialEq)]
 pub enum Error {
     BadRelativeIndex(usize),
@@ -23,15 +30,26 @@ pub enum Error {
     UnknownStreamId(u64),
     NoTrackingData,
     InvalidTrackingCount,
+    BadInsertCountIncrement(usize),
 }
 
 pub struct DynamicTableDecoder<'a> {
     table: &'a DynamicTable,
     base: usize,
+    required: usize,
 }
 
+//= https://www.rfc-editor.org/rfc/rfc9204#section-2.2.3
+//# If the decoder encounters a reference in a field line representation
+//# to a dynamic table entry that has already been evicted or that has an
+//# absolute index greater than or equal to the declared Required Insert
+//# Count (Section 4.5.1), it MUST treat this as a connection error of
+//# type QPACK_DECOMPRESSION_FAILED.
 impl<'a> DynamicTableDecoder<'a> {
     pub(super) fn get_relative(&self, index: usize) -> Result<&HeaderField, Error> {
+        if self.base > self.required + index {
+            return Err(Error::BadRelativeIndex(index));
+        }
         let real_index = self.table.vas.relative_base(self.base, index)?;
         self.table
             .fields
@@ -40,6 +58,9 @@ impl<'a> DynamicTableDecoder<'a> {
     }
 
     pub(super) fn get_postbase(&self, index: usize) -> Result<&HeaderField, Error> {
+        if self.base + index >= self.required {
+            return Err(Error::BadPostbaseIndex(index));
+        }
         let real_index = self.table.vas.post_base(self.base, index)?;
         self.table
             .fields
@@ -54,6 +75,8 @@ pub struct DynamicTableEncoder<'a> {
     commited: bool,
     stream_id: u64,
     block_refs: HashMap<usize, usize>,
+    // Whether the block references an entry the decoder may not have received yet
+    blocking: bool,
 }
 
 impl<'a> Drop for DynamicTableEncoder<'a> {
@@ -69,8 +92,8 @@ impl<'a> Drop for DynamicTableEncoder<'a> {
 }
 
 impl<'a> DynamicTableEncoder<'a> {
-    pub(super) fn max_size(&self) -> usize {
-        self.table.max_size
+    pub(super) fn max_capacity(&self) -> usize {
+        self.table.max_capacity
     }
 
     pub(super) fn base(&self) -> usize {
@@ -82,8 +105,12 @@ impl<'a> DynamicTableEncoder<'a> {
     }
 
     pub(super) fn commit(&mut self, largest_ref: usize) {
-        self.table
-            .track_block(self.stream_id, self.block_refs.clone());
+        // The decoder only acknowledges sections with a non-zero Required Insert Count, an
+        // untracked empty block would be released in place of the next one of the stream.
+        if !self.block_refs.is_empty() {
+            self.table
+                .track_block(self.stream_id, self.block_refs.clone());
+        }
         self.table.register_blocked(largest_ref);
         self.commited = true;
     }
@@ -92,7 +119,39 @@ impl<'a> DynamicTableEncoder<'a> {
         self.lookup_result(self.table.field_map.get(field).cloned())
     }
 
+    /// Whether `field` is in the table, about to be evicted
+    pub(super) fn is_draining(&self, field: &HeaderField) -> bool {
+        match self.table.field_map.get(field) {
+            Some(absolute) => self.table.is_draining(*absolute),
+            None => false,
+        }
+    }
+
+    /// Whether `field` can be inserted without evicting a referenced entry, and referenced
+    /// within the blocked streams budget
+    pub(super) fn can_insert(&mut self, field: &HeaderField) -> bool {
+        self.can_block()
+            && self.table.max_size > 0
+            && matches!(self.table.can_free(field.mem_size()), Ok(Some(_)))
+    }
+
+    // A block already blocking the stream can reference any entry, others need a place in
+    // the blocked streams budget.
+    fn can_block(&self) -> bool {
+        self.blocking || self.table.blocked_count < self.table.blocked_max
+    }
+
     fn lookup_result(&mut self, absolute: Option<usize>) -> DynamicLookupResult {
+        let absolute = match absolute {
+            Some(absolute) if absolute > self.table.largest_known_received => {
+                if !self.can_block() {
+                    return DynamicLookupResult::NotFound;
+                }
+                self.blocking = true;
+                Some(absolute)
+            }
+            absolute => absolute,
+        };
         match absolute {
             Some(absolute) if absolute <= self.base => {
                 self.track_ref(absolute);
@@ -113,12 +172,16 @@ impl<'a> DynamicTableEncoder<'a> {
     }
 
     pub(super) fn insert(&mut self, field: &HeaderField) -> Result<DynamicInsertionResult, Error> {
-        if self.table.blocked_count >= self.table.blocked_max {
+        if !self.can_block() {
             return Ok(DynamicInsertionResult::NotInserted(
                 self.find_name(&field.name),
             ));
         }
 
+        // Looked up before inserting, as the insertion may evict the entry it duplicates: the
+        // decoder reads the entry before inserting its copy.
+        let duplicated = self.table.field_map.get(field).copied();
+
         let index = match self.table.insert(field.clone()) {
             Ok(Some(index)) => index,
             Err(Error::MaxTableSizeReached) | Ok(None) => {
@@ -128,35 +191,22 @@ impl<'a> DynamicTableEncoder<'a> {
             }
             Err(e) => return Err(e),
         };
+        self.blocking = true;
         self.track_ref(index);
 
-        let field_index = match self.table.field_map.entry(field.clone()) {
-            Entry::Occupied(mut e) => {
-                let ref_index = e.insert(index);
-                self.table
-                    .name_map
-                    .entry(field.name.clone())
-                    .and_modify(|i| *i = index);
-
-                Some((
-                    ref_index,
-                    DynamicInsertionResult::Duplicated {
-                        relative: index - ref_index - 1,
-                        postbase: index - self.base - 1,
-                        absolute: index,
-                    },
-                ))
-            }
-            Entry::Vacant(e) => {
-                e.insert(index);
-                None
+        if let Some(ref_index) = duplicated {
+            self.table.field_map.insert(field.clone(), index);
+            self.table.name_map.insert(field.name.clone(), index);
+            if !self.table.vas.evicted(ref_index) {
+                self.track_ref(ref_index);
             }
-        };
-
-        if let Some((ref_index, result)) = field_index {
-            self.track_ref(ref_index);
-            return Ok(result);
+            return Ok(DynamicInsertionResult::Duplicated {
+                relative: index - ref_index - 1,
+                postbase: index - self.base - 1,
+                absolute: index,
+            });
         }
+        self.table.field_map.insert(field.clone(), index);
 
         if let Some(static_idx) = StaticTable::find_name(&field.name) {
             return Ok(DynamicInsertionResult::InsertedWithStaticNameRef {
@@ -188,7 +238,7 @@ impl<'a> DynamicTableEncoder<'a> {
         Ok(result)
     }
 
-    fn find_name(&mut self, name: &[u8]) -> DynamicLookupResult {
+    pub(super) fn find_name(&mut self, name: &[u8]) -> DynamicLookupResult {
         if let Some(index) = StaticTable::find_name(name) {
             return DynamicLookupResult::Static(index);
         }
@@ -242,9 +292,11 @@ pub struct DynamicTable {
     fields: VecDeque<HeaderField>,
     curr_size: usize,
     max_size: usize,
+    // The SETTINGS_QPACK_MAX_TABLE_CAPACITY of the decoder, which bounds `max_size`
+    max_capacity: usize,
     vas: VirtualAddressSpace,
     field_map: HashMap<HeaderField, usize>,
-    name_map: HashMap<Cow<'static, [u8]>, usize>,
+    name_map: HashMap<Bytes, usize>,
     track_map: BTreeMap<usize, usize>,
     track_blocks: HashMap<u64, VecDeque<HashMap<usize, usize>>>,
     largest_known_received: usize,
@@ -258,8 +310,13 @@ impl DynamicTable {
         DynamicTable::default()
     }
 
-    pub fn decoder(&self, base: usize) -> DynamicTableDecoder {
-        DynamicTableDecoder { table: self, base }
+    /// Resolves the references of a field section with this `base` and Required Insert Count
+    pub fn decoder(&self, base: usize, required: usize) -> DynamicTableDecoder<'_> {
+        DynamicTableDecoder {
+            table: self,
+            base,
+            required,
+        }
     }
 
     pub fn encoder(&mut self, stream_id: u64) -> DynamicTableEncoder {
@@ -276,6 +333,7 @@ impl DynamicTable {
             block_refs: HashMap::new(),
             commited: false,
             stream_id,
+            blocking: false,
         }
     }
 
@@ -288,6 +346,14 @@ impl DynamicTable {
         Ok(())
     }
 
+    pub fn set_max_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        if capacity > SETTINGS_MAX_TABLE_CAPACITY_MAX {
+            return Err(Error::MaximumTableSizeTooLarge);
+        }
+        self.max_capacity = capacity;
+        Ok(())
+    }
+
     pub fn set_max_size(&mut self, size: usize) -> Result<(), Error> {
         if size > SETTINGS_MAX_TABLE_CAPACITY_MAX {
             return Err(Error::MaximumTableSizeTooLarge);
@@ -357,6 +423,24 @@ impl DynamicTable {
         Ok(())
     }
 
+    /// Releases the references of the oldest block of a stream, acknowledged by the decoder
+    ///
+    /// The decoder received every entry the block references.
+    pub(super) fn ack_block(&mut self, stream_id: u64) -> Result<(), Error> {
+        let required = self
+            .track_blocks
+            .get(&stream_id)
+            .and_then(|blocks| blocks.front())
+            .and_then(|refs| refs.keys().max().copied())
+            .unwrap_or(0);
+        self.untrack_block(stream_id)?;
+
+        if required > self.largest_known_received {
+            self.update_largest_received(required - self.largest_known_received);
+        }
+        Ok(())
+    }
+
     fn insert(&mut self, field: HeaderField) -> Result<Option<usize>, Error> {
         if self.max_size == 0 {
             return Ok(None);
@@ -398,6 +482,21 @@ impl DynamicTable {
         Ok(())
     }
 
+    // An entry is draining when inserting a quarter of the capacity would evict it
+    fn is_draining(&self, absolute: usize) -> bool {
+        let position = match self.vas.position(absolute) {
+            Ok(position) => position,
+            Err(_) => return false,
+        };
+        let older: usize = self
+            .fields
+            .iter()
+            .take(position)
+            .map(HeaderField::mem_size)
+            .sum();
+        self.max_size - self.curr_size + older < self.max_size / DRAINING_DIVISOR
+    }
+
     fn can_free(&mut self, required: usize) -> Result<Option<usize>, Error> {
         if required > self.max_size {
             return Err(Error::MaxTableSizeReached);
@@ -498,6 +597,20 @@ impl DynamicTable {
         }
     }
 
+    /// Applies an Insert Count Increment received on the decoder stream
+    pub(super) fn insert_count_increment(&mut self, increment: usize) -> Result<(), Error> {
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.4.3
+        //# An encoder that receives an Increment field equal to zero, or one
+        //# that increases the Known Received Count beyond what the encoder has
+        //# sent, MUST treat this as a connection error of type
+        //# QPACK_DECODER_STREAM_ERROR.
+        if increment == 0 || self.largest_known_received + increment > self.total_inserted() {
+            return Err(Error::BadInsertCountIncrement(increment));
+        }
+        self.update_largest_received(increment);
+        Ok(())
+    }
+
     pub fn update_largest_received(&mut self, increment: usize) {
         self.largest_known_received += increment;
 
@@ -516,9 +629,50 @@ impl DynamicTable {
         }
     }
 
+    pub(super) fn blocked_count(&self) -> usize {
+        self.blocked_count
+    }
+
+    /// Sets the table figures of `stats`
+    pub(super) fn fill_stats(&self, stats: &mut Stats) {
+        stats.evictions = self.vas.total_dropped() as u64;
+        stats.table_entries = self.fields.len();
+        stats.table_size = self.curr_size;
+        stats.table_capacity = self.max_size;
+    }
+
+    /// The entries, from the oldest to the newest
+    pub(super) fn entries(&self) -> Vec<TableEntry> {
+        let dropped = self.vas.total_dropped();
+        self.fields
+            .iter()
+            .enumerate()
+            .map(|(position, field)| TableEntry {
+                index: dropped + position,
+                size: field.mem_size(),
+                field: field.clone(),
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
     pub(super) fn max_mem_size(&self) -> usize {
         self.max_size
     }
+
+    #[cfg(test)]
+    pub(super) fn tracked_blocks(&self) -> usize {
+        self.track_blocks.values().map(VecDeque::len).sum()
+    }
+
+    #[cfg(test)]
+    pub(super) fn largest_known_received(&self) -> usize {
+        self.largest_known_received
+    }
+
+    pub(super) fn max_capacity(&self) -> usize {
+        self.max_capacity
+    }
 }
 
 impl From<vas::Error> for Error {
@@ -11,10 +11,12 @@ use super::{
         DynamicInsertionResult, DynamicLookupResult, DynamicTable, DynamicTableEncoder,
         Error as DynamicTableError,
     },
+    indexing::{Indexing, IndexingPolicy, InsertBelowSize},
     parse_error::ParseError,
     prefix_int::Error as IntError,
     prefix_string::Error as StringError,
     static_::StaticTable,
+    stats::{Stats, TableEntry},
     stream::{
         DecoderInstruction, Duplicate, DynamicTableSizeUpdate, HeaderAck, InsertCountIncrement,
         InsertWithNameRef, InsertWithoutNameRef, StreamCancel,
@@ -22,11 +24,16 @@ use super::{
     HeaderField,
 };
 
+/// Encoding error
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    /// Dynamic table update refused, like a capacity above the maximum
     Insertion(DynamicTableError),
+    /// String too long to be encoded
     InvalidString(StringError),
+    /// Malformed integer in a decoder instruction
     InvalidInteger(IntError),
+    /// Unknown decoder instruction
     UnknownDecoderInstruction(u8),
 }
 
@@ -45,11 +52,70 @@ impl std::fmt::Display for Error {
     }
 }
 
+/// Encodes field sections, inserting fields in the dynamic table through the encoder stream
+///
+/// Only the static table is referenced until `set_table_capacity` gives the dynamic table a
+/// capacity, within the limits the peer's decoder set with `set_max_table_capacity` and
+/// `set_max_blocked_streams`.
 pub struct Encoder {
     table: DynamicTable,
+    policy: Box<dyn IndexingPolicy>,
+    stats: Stats,
 }
 
 impl Encoder {
+    /// Creates an encoder which only references the static table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the SETTINGS_QPACK_MAX_TABLE_CAPACITY received from the peer
+    pub fn set_max_table_capacity(&mut self, max_table_capacity: usize) -> Result<(), Error> {
+        self.table.set_max_capacity(max_table_capacity)?;
+        Ok(())
+    }
+
+    /// Sets the SETTINGS_QPACK_BLOCKED_STREAMS received from the peer
+    ///
+    /// No more streams than this may reference entries the decoder did not acknowledge.
+    pub fn set_max_blocked_streams(&mut self, max_blocked_streams: usize) -> Result<(), Error> {
+        self.table.set_max_blocked(max_blocked_streams)?;
+        Ok(())
+    }
+
+    /// Sets the capacity of the dynamic table, writing the instruction to the encoder stream
+    ///
+    /// The capacity cannot exceed the maximum set by the peer. Reducing it evicts entries,
+    /// which fails while they are referenced by unacknowledged sections.
+    pub fn set_table_capacity<W: BufMut>(
+        &mut self,
+        capacity: usize,
+        encoder_buf: &mut W,
+    ) -> Result<(), Error> {
+        if capacity > self.table.max_capacity() {
+            return Err(Error::Insertion(
+                DynamicTableError::MaximumTableSizeTooLarge,
+            ));
+        }
+        self.table.set_max_size(capacity)?;
+        DynamicTableSizeUpdate(capacity).encode(encoder_buf);
+        Ok(())
+    }
+
+    /// Sets the policy deciding which fields are inserted in the dynamic table
+    ///
+    /// By default, every field which fits in the table is inserted.
+    pub fn set_indexing_policy<P>(&mut self, policy: P)
+    where
+        P: IndexingPolicy + 'static,
+    {
+        self.policy = Box::new(policy);
+    }
+
+    /// Encodes `fields` for the stream `stream_id` into `block`
+    ///
+    /// Instructions inserting fields in the dynamic table are written to `encoder_buf`, they
+    /// must reach the peer's encoder stream. Returns the Required Insert Count of the section.
     pub fn encode<W, T, H>(
         &mut self,
         stream_id: u64,
@@ -67,9 +133,18 @@ impl Encoder {
         let mut encoder = self.table.encoder(stream_id);
 
         for field in fields {
-            if let Some(reference) =
-                Self::encode_field(&mut encoder, &mut block_buf, encoder_buf, field.as_ref())?
-            {
+            let start = block_buf.len();
+            let reference = Self::encode_field(
+                &mut encoder,
+                self.policy.as_mut(),
+                &mut block_buf,
+                encoder_buf,
+                &mut self.stats,
+                field.as_ref(),
+            )?;
+            self.stats
+                .count_encoded_field_line(block_buf[start], field.as_ref());
+            if let Some(reference) = reference {
                 required_ref = cmp::max(required_ref, reference);
             }
         }
@@ -78,7 +153,7 @@ impl Encoder {
             required_ref,
             encoder.base(),
             encoder.total_inserted(),
-            encoder.max_size(),
+            encoder.max_capacity(),
         )
         .encode(block);
         block.put(block_buf.as_slice());
@@ -88,10 +163,15 @@ impl Encoder {
         Ok(required_ref)
     }
 
+    /// Processes the instructions received on the peer's decoder stream
+    ///
+    /// Acknowledgments let the encoder reference or evict the entries of acknowledged
+    /// sections. Complete instructions are consumed from `read`, an incomplete one is left for
+    /// the next call.
     pub fn on_decoder_recv<R: Buf>(&mut self, read: &mut R) -> Result<(), Error> {
         while let Some(instruction) = Action::parse(read)? {
             match instruction {
-                Action::Untrack(stream_id) => self.table.untrack_block(stream_id)?,
+                Action::Untrack(stream_id) => self.table.ack_block(stream_id)?,
                 Action::StreamCancel(stream_id) => {
                     // Untrack block twice, as this stream might have a trailer in addition to
                     // the header. Failures are ignored as blocks might have been acked before
@@ -101,30 +181,90 @@ impl Encoder {
                     }
                 }
                 Action::ReceivedRefIncrement(increment) => {
-                    self.table.update_largest_received(increment)
+                    self.table.insert_count_increment(increment)?
                 }
             }
         }
         Ok(())
     }
 
+    /// Counters of the field lines encoded so far, and the current state of the dynamic table
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        self.table.fill_stats(&mut stats);
+        stats.blocked_streams = self.table.blocked_count();
+        stats
+    }
+
+    /// Lists the entries of the dynamic table, from the oldest to the newest
+    pub fn table_entries(&self) -> Vec<TableEntry> {
+        self.table.entries()
+    }
+
+    /// Sections referencing the dynamic table the decoder did not acknowledge yet
+    #[cfg(test)]
+    pub(crate) fn unacked_sections(&self) -> usize {
+        self.table.tracked_blocks()
+    }
+
+    /// The entries inserted, and how many of them the decoder acknowledged
+    #[cfg(test)]
+    pub(crate) fn insert_counts(&self) -> (usize, usize) {
+        (
+            self.table.total_inserted(),
+            self.table.largest_known_received(),
+        )
+    }
+
     fn encode_field<W: BufMut>(
         table: &mut DynamicTableEncoder,
+        policy: &mut dyn IndexingPolicy,
         block: &mut Vec<u8>,
         encoder: &mut W,
+        stats: &mut Stats,
         field: &HeaderField,
     ) -> Result<Option<usize>, Error> {
+        if field.sensitive {
+            return Self::encode_never_indexed(table, block, field);
+        }
+
+        let indexing = policy.indexing(field);
+        if indexing == Indexing::Literal {
+            Literal::new(field.name.clone(), field.value.clone()).encode(block)?;
+            return Ok(None);
+        }
+
         if let Some(index) = StaticTable::find(field) {
             Indexed::Static(index).encode(block);
             return Ok(None);
         }
 
-        if let DynamicLookupResult::Relative { index, absolute } = table.find(field) {
-            Indexed::Dynamic(index).encode(block);
-            return Ok(Some(absolute));
+        if indexing == Indexing::Static {
+            match StaticTable::find_name(&field.name) {
+                Some(index) => {
+                    LiteralWithNameRef::new_static(index, field.value.clone()).encode(block)?
+                }
+                None => Literal::new(field.name.clone(), field.value.clone()).encode(block)?,
+            }
+            return Ok(None);
+        }
+
+        // An entry about to be evicted is duplicated, so the field can still be referenced
+        // by the next blocks.
+        let insert = indexing == Indexing::Insert;
+        let refresh = insert && table.is_draining(field) && table.can_insert(field);
+        if !refresh {
+            if let DynamicLookupResult::Relative { index, absolute } = table.find(field) {
+                Indexed::Dynamic(index).encode(block);
+                return Ok(Some(absolute));
+            }
         }
 
-        let reference = match table.insert(field)? {
+        let inserted = match insert {
+            true => table.insert(field)?,
+            false => DynamicInsertionResult::NotInserted(table.find_name(&field.name)),
+        };
+        let reference = match inserted {
             DynamicInsertionResult::Duplicated {
                 relative,
                 postbase,
@@ -137,6 +277,8 @@ impl Encoder {
             DynamicInsertionResult::Inserted { postbase, absolute } => {
                 InsertWithoutNameRef::new(field.name.clone(), field.value.clone())
                     .encode(encoder)?;
+                stats.count_huffman(&field.name);
+                stats.count_huffman(&field.value);
                 IndexedWithPostBase(postbase).encode(block);
                 Some(absolute)
             }
@@ -146,6 +288,7 @@ impl Encoder {
                 absolute,
             } => {
                 InsertWithNameRef::new_static(index, field.value.clone()).encode(encoder)?;
+                stats.count_huffman(&field.value);
                 IndexedWithPostBase(postbase).encode(block);
                 Some(absolute)
             }
@@ -155,6 +298,7 @@ impl Encoder {
                 absolute,
             } => {
                 InsertWithNameRef::new_dynamic(relative, field.value.clone()).encode(encoder)?;
+                stats.count_huffman(&field.value);
                 IndexedWithPostBase(postbase).encode(block);
                 Some(absolute)
             }
@@ -179,16 +323,58 @@ impl Encoder {
         };
         Ok(reference)
     }
+
+    // A sensitive field is always sent as a literal with the 'N' bit set, its value is
+    // never inserted in the dynamic table. Only its name may reference a table entry.
+    // https://www.rfc-editor.org/rfc/rfc9204.html#name-never-indexed-literals
+    fn encode_never_indexed(
+        table: &mut DynamicTableEncoder,
+        block: &mut Vec<u8>,
+        field: &HeaderField,
+    ) -> Result<Option<usize>, Error> {
+        let reference = match table.find_name(&field.name) {
+            DynamicLookupResult::Static(index) => {
+                LiteralWithNameRef::new_static(index, field.value.clone())
+                    .with_never_indexed(true)
+                    .encode(block)?;
+                None
+            }
+            DynamicLookupResult::Relative { index, absolute } => {
+                LiteralWithNameRef::new_dynamic(index, field.value.clone())
+                    .with_never_indexed(true)
+                    .encode(block)?;
+                Some(absolute)
+            }
+            DynamicLookupResult::PostBase { index, absolute } => {
+                LiteralWithPostBaseNameRef::new(index, field.value.clone())
+                    .with_never_indexed(true)
+                    .encode(block)?;
+                Some(absolute)
+            }
+            DynamicLookupResult::NotFound => {
+                Literal::new(field.name.clone(), field.value.clone())
+                    .with_never_indexed(true)
+                    .encode(block)?;
+                None
+            }
+        };
+        Ok(reference)
+    }
 }
 
 impl Default for Encoder {
     fn default() -> Self {
         Self {
             table: DynamicTable::new(),
+            policy: Box::new(InsertBelowSize::new(usize::MAX)),
+            stats: Stats::default(),
         }
     }
 }
 
+/// Encodes a field section only referencing the static table
+///
+/// Returns the size of the fields, as counted against SETTINGS_MAX_FIELD_SECTION_SIZE.
 pub fn encode_stateless<W, T, H>(block: &mut W, fields: T) -> Result<u64, Error>
 where
     W: BufMut,
@@ -201,12 +387,16 @@ where
     for field in fields {
         let field = field.as_ref();
 
-        if let Some(index) = StaticTable::find(field) {
+        if let Some(index) = StaticTable::find(field).filter(|_| !field.sensitive) {
             Indexed::Static(index).encode(block);
         } else if let Some(index) = StaticTable::find_name(&field.name) {
-            LiteralWithNameRef::new_static(index, field.value.clone()).encode(block)?;
+            LiteralWithNameRef::new_static(index, field.value.clone())
+                .with_never_indexed(field.sensitive)
+                .encode(block)?;
         } else {
-            Literal::new(field.name.clone(), field.value.clone()).encode(block)?;
+            Literal::new(field.name.clone(), field.value.clone())
+                .with_never_indexed(field.sensitive)
+                .encode(block)?;
         }
 
         size += field.mem_size() as u64;
@@ -217,7 +407,10 @@ where
 #[cfg(test)]
 impl From<DynamicTable> for Encoder {
     fn from(table: DynamicTable) -> Encoder {
-        Encoder { table }
+        Encoder {
+            table,
+            ..Default::default()
+        }
     }
 }
 
@@ -258,16 +451,6 @@ impl Action {
     }
 }
 
-pub fn set_dynamic_table_size<W: BufMut>(
-    table: &mut DynamicTable,
-    encoder: &mut W,
-    size: usize,
-) -> Result<(), Error> {
-    table.set_max_size(size)?;
-    DynamicTableSizeUpdate(size).encode(encoder);
-    Ok(())
-}
-
 impl From<DynamicTableError> for Error {
     fn from(e: DynamicTableError) -> Self {
         Error::Insertion(e)
@@ -295,7 +478,10 @@ impl From<ParseError> for Error {
 mod tests {
     use super::*;
 
-    use crate::qpack::tests::helpers::{build_table, TABLE_SIZE};
+    use crate::qpack::{
+        indexing::{InsertRepeated, NeverIndex, StaticOnly},
+        tests::helpers::{build_table, build_table_with_size, TABLE_SIZE},
+    };
 
     #[allow(clippy::type_complexity)]
     fn check_encode_field(
@@ -324,8 +510,17 @@ mod tests {
         let mut block = Vec::new();
         let mut enc_table = table.encoder(stream_id);
 
+        let mut policy = InsertBelowSize::new(usize::MAX);
         for field in field {
-            Encoder::encode_field(&mut enc_table, &mut block, &mut encoder, field).unwrap();
+            Encoder::encode_field(
+                &mut enc_table,
+                &mut policy,
+                &mut block,
+                &mut encoder,
+                &mut Stats::default(),
+                field,
+            )
+            .unwrap();
         }
 
         enc_table.commit(field.len());
@@ -413,6 +608,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn encode_sensitive_never_indexed() {
+        let field = HeaderField::new("foo", "bar").with_sensitive(true);
+        check_encode_field(&[], &[field], &|mut b, e| {
+            assert_eq!(
+                Literal::decode(&mut b),
+                Ok(Literal::new("foo", "bar").with_never_indexed(true))
+            );
+            assert_eq!(e.get_ref().len(), 0);
+        });
+    }
+
+    #[test]
+    fn encode_sensitive_static_match() {
+        let field = HeaderField::new("cookie", "").with_sensitive(true);
+        check_encode_field(&[], &[field], &|mut b, e| {
+            assert_eq!(
+                LiteralWithNameRef::decode(&mut b),
+                Ok(LiteralWithNameRef::new_static(5, "").with_never_indexed(true))
+            );
+            assert_eq!(e.get_ref().len(), 0);
+        });
+    }
+
+    #[test]
+    fn encode_sensitive_dynamic_match() {
+        let field = HeaderField::new("foo", "bar");
+        let sensitive = field.clone().with_sensitive(true);
+        check_encode_field(&[field], &[sensitive], &|mut b, e| {
+            assert_eq!(
+                LiteralWithNameRef::decode(&mut b),
+                Ok(LiteralWithNameRef::new_dynamic(0, "bar").with_never_indexed(true))
+            );
+            assert_eq!(e.get_ref().len(), 0);
+        });
+    }
+
     #[test]
     fn encode_literal_nameref() {
         let mut table = build_table();
@@ -540,6 +772,173 @@ mod tests {
         assert_eq!(read_block.get_ref().len() as u64, read_block.position());
     }
 
+    fn encode_block(
+        encoder: &mut Encoder,
+        stream_id: u64,
+        fields: &[HeaderField],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut block = Vec::new();
+        let mut encoder_buf = Vec::new();
+        encoder
+            .encode(stream_id, &mut block, &mut encoder_buf, fields)
+            .unwrap();
+        (block, encoder_buf)
+    }
+
+    #[test]
+    fn encode_policy_never_index() {
+        let mut encoder = Encoder::from(build_table());
+        encoder.set_indexing_policy(NeverIndex);
+
+        let fields = [
+            HeaderField::new(":method", "GET"),
+            HeaderField::new("foo", "bar"),
+        ];
+        let (block, encoder_buf) = encode_block(&mut encoder, 1, &fields);
+
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(0, TABLE_SIZE),
+            Ok((0, 0))
+        );
+        assert_eq!(
+            Literal::decode(&mut block),
+            Ok(Literal::new(":method", "GET"))
+        );
+        assert_eq!(Literal::decode(&mut block), Ok(Literal::new("foo", "bar")));
+        assert!(encoder_buf.is_empty());
+    }
+
+    #[test]
+    fn encode_policy_static_only() {
+        let mut encoder = Encoder::from(build_table());
+        encoder.set_indexing_policy(StaticOnly);
+
+        let fields = [
+            HeaderField::new(":method", "GET"),
+            HeaderField::new(":path", "/index.html"),
+            HeaderField::new("foo", "bar"),
+        ];
+        let (block, encoder_buf) = encode_block(&mut encoder, 1, &fields);
+
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(0, TABLE_SIZE),
+            Ok((0, 0))
+        );
+        assert_eq!(Indexed::decode(&mut block), Ok(Indexed::Static(17)));
+        assert_eq!(
+            LiteralWithNameRef::decode(&mut block),
+            Ok(LiteralWithNameRef::new_static(1, "/index.html"))
+        );
+        assert_eq!(Literal::decode(&mut block), Ok(Literal::new("foo", "bar")));
+        assert!(encoder_buf.is_empty());
+    }
+
+    #[test]
+    fn encode_policy_insert_repeated() {
+        let mut encoder = Encoder::from(build_table());
+        encoder.set_indexing_policy(InsertRepeated::new(2));
+        let fields = [HeaderField::new("foo", "bar")];
+
+        let (block, encoder_buf) = encode_block(&mut encoder, 1, &fields);
+        let mut block = Cursor::new(&block);
+        HeaderPrefix::decode(&mut block).unwrap();
+        assert_eq!(Literal::decode(&mut block), Ok(Literal::new("foo", "bar")));
+        assert!(encoder_buf.is_empty());
+
+        let (block, encoder_buf) = encode_block(&mut encoder, 2, &fields);
+        let mut block = Cursor::new(&block);
+        HeaderPrefix::decode(&mut block).unwrap();
+        assert_eq!(
+            IndexedWithPostBase::decode(&mut block),
+            Ok(IndexedWithPostBase(0))
+        );
+        assert_eq!(
+            InsertWithoutNameRef::decode(&mut Cursor::new(&encoder_buf)),
+            Ok(Some(InsertWithoutNameRef::new("foo", "bar")))
+        );
+    }
+
+    #[test]
+    fn encode_literal_when_blocked_streams_budget_exhausted() {
+        let mut table = build_table();
+        table.set_max_blocked(1).unwrap();
+        let mut encoder = Encoder::from(table);
+        let field = HeaderField::new("foo", "bar");
+
+        // Inserting blocks the stream, until the decoder acknowledges the block
+        let (_, encoder_buf) = encode_block(&mut encoder, 1, std::slice::from_ref(&field));
+        assert!(!encoder_buf.is_empty());
+
+        let (block, encoder_buf) =
+            encode_block(&mut encoder, 2, &[field.clone(), field.with_value("baz")]);
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(1, TABLE_SIZE),
+            Ok((0, 0))
+        );
+        assert_eq!(Literal::decode(&mut block), Ok(Literal::new("foo", "bar")));
+        assert_eq!(Literal::decode(&mut block), Ok(Literal::new("foo", "baz")));
+        assert!(encoder_buf.is_empty());
+
+        let mut ack = Vec::new();
+        HeaderAck(1).encode(&mut ack);
+        encoder.on_decoder_recv(&mut Cursor::new(&ack)).unwrap();
+
+        // The entry is known to be received, referencing it does not block
+        let (block, _) = encode_block(&mut encoder, 3, &[field]);
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(1, TABLE_SIZE),
+            Ok((1, 1))
+        );
+        assert_eq!(Indexed::decode(&mut block), Ok(Indexed::Dynamic(0)));
+        assert_eq!(encoder.table.blocked_count(), 0);
+    }
+
+    #[test]
+    fn encode_duplicate_draining_entry() {
+        let mut table = build_table();
+        // Room for 3 entries of 38 bytes
+        table.set_max_size(128).unwrap();
+        let fields = [
+            HeaderField::new("foo", "bar"),
+            HeaderField::new("foo", "baz"),
+            HeaderField::new("foo", "qux"),
+        ];
+        for field in &fields {
+            table.put(field.clone()).unwrap();
+        }
+        let mut encoder = Encoder::from(table);
+
+        // The oldest entry is evicted by the next insertion
+        let (block, encoder_buf) = encode_block(&mut encoder, 1, &fields[..1]);
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(3, 128),
+            Ok((4, 3))
+        );
+        assert_eq!(
+            IndexedWithPostBase::decode(&mut block),
+            Ok(IndexedWithPostBase(0))
+        );
+        assert_eq!(
+            Duplicate::decode(&mut Cursor::new(&encoder_buf)),
+            Ok(Some(Duplicate(2)))
+        );
+
+        // Entries further from eviction are referenced
+        let (block, encoder_buf) = encode_block(&mut encoder, 2, &fields[2..]);
+        let mut block = Cursor::new(&block);
+        assert_eq!(
+            HeaderPrefix::decode(&mut block).unwrap().get(4, 128),
+            Ok((3, 3))
+        );
+        assert_eq!(Indexed::decode(&mut block), Ok(Indexed::Dynamic(0)));
+        assert!(encoder_buf.is_empty());
+    }
+
     #[test]
     fn decoder_block_ack() {
         let mut table = build_table();
@@ -570,6 +969,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decoder_block_ack_after_static_block() {
+        let mut table = build_table();
+        table.set_max_blocked(1).unwrap();
+        let mut encoder = Encoder::from(table);
+
+        // The decoder does not acknowledge a block referencing only the static table
+        encode_block(&mut encoder, 1, &[HeaderField::new(":method", "GET")]);
+        assert_eq!(encoder.unacked_sections(), 0);
+
+        // So the acknowledgment of the next block of the stream releases its entry
+        encode_block(&mut encoder, 1, &[HeaderField::new("foo", "bar")]);
+        assert_eq!(encoder.unacked_sections(), 1);
+
+        let mut buf = vec![];
+        HeaderAck(1).encode(&mut buf);
+        encoder.on_decoder_recv(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(encoder.unacked_sections(), 0);
+        assert_eq!(encoder.insert_counts(), (1, 1));
+    }
+
     #[test]
     fn decoder_stream_cacnceled() {
         let mut table = build_table();
@@ -639,11 +1059,34 @@ mod tests {
             Ok(Some(Action::ReceivedRefIncrement(4)))
         );
 
-        let mut encoder = Encoder {
-            table: build_table(),
-        };
+        let mut encoder = Encoder::from(build_table_with_size(4));
 
         let mut cur = Cursor::new(&buf);
         assert_eq!(encoder.on_decoder_recv(&mut cur), Ok(()));
     }
+
+    #[test]
+    fn insert_count_invalid() {
+        let mut encoder = Encoder::from(build_table_with_size(4));
+
+        let mut buf = vec![];
+        InsertCountIncrement(0).encode(&mut buf);
+        assert_eq!(
+            encoder.on_decoder_recv(&mut Cursor::new(&buf)),
+            Err(Error::Insertion(
+                DynamicTableError::BadInsertCountIncrement(0)
+            ))
+        );
+
+        // Only 4 entries were sent
+        let mut buf = vec![];
+        InsertCountIncrement(3).encode(&mut buf);
+        InsertCountIncrement(2).encode(&mut buf);
+        assert_eq!(
+            encoder.on_decoder_recv(&mut Cursor::new(&buf)),
+            Err(Error::Insertion(
+                DynamicTableError::BadInsertCountIncrement(2)
+            ))
+        );
+    }
 }
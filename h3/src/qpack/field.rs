@@ -1,7 +1,6 @@
-use std::{
-    borrow::Cow,
-    fmt::{Display, Formatter},
-};
+use std::fmt::{Display, Formatter};
+
+use bytes::Bytes;
 
 /**
  * https://tools.ietf.org/html/rfc7541
@@ -9,39 +8,59 @@ use std::{
  */
 pub const ESTIMATED_OVERHEAD_BYTES: usize = 32;
 
+/// A field line
+///
+/// Name and value are shared `Bytes`: decoded fields point into the received field section,
+/// the static table or the dynamic table entry they reference, and cloning one does not copy.
 #[derive(Debug, PartialEq, Clone, Hash, Eq)]
 pub struct HeaderField {
-    pub name: Cow<'static, [u8]>,
-    pub value: Cow<'static, [u8]>,
+    /// Name, in lowercase for HTTP/3
+    pub name: Bytes,
+    /// Value
+    pub value: Bytes,
+    /// Never index this field, neither in the dynamic table nor by intermediaries
+    pub sensitive: bool,
 }
 
 impl HeaderField {
+    /// Creates a field, sharing `name` and `value` when they are `Bytes`
     pub fn new<T, S>(name: T, value: S) -> HeaderField
     where
-        T: Into<Vec<u8>>,
-        S: Into<Vec<u8>>,
+        T: Into<Bytes>,
+        S: Into<Bytes>,
     {
         HeaderField {
-            name: Cow::Owned(name.into()),
-            value: Cow::Owned(value.into()),
+            name: name.into(),
+            value: value.into(),
+            sensitive: false,
         }
     }
 
+    /// Sets whether the field is sensitive
+    pub fn with_sensitive(mut self, sensitive: bool) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    /// Size of the field, as counted in the dynamic table and against the field section size
     pub fn mem_size(&self) -> usize {
         self.name.len() + self.value.len() + ESTIMATED_OVERHEAD_BYTES
     }
 
+    /// A field with the same name and another value, which is not sensitive
     pub fn with_value<T>(&self, value: T) -> Self
     where
-        T: Into<Vec<u8>>,
+        T: Into<Bytes>,
     {
         Self {
             name: self.name.clone(),
-            value: Cow::Owned(value.into()),
+            value: value.into(),
+            sensitive: false,
         }
     }
 
-    pub fn into_inner(self) -> (Cow<'static, [u8]>, Cow<'static, [u8]>) {
+    /// Name and value
+    pub fn into_inner(self) -> (Bytes, Bytes) {
         (self.name, self.value)
     }
 }
@@ -74,6 +93,7 @@ impl From<HeaderField> for String {
     }
 }
 
+/// Copies `name` and `value`, use `HeaderField::new` to share `Bytes`
 impl<N, V> From<(N, V)> for HeaderField
 where
     N: AsRef<[u8]>,
@@ -82,9 +102,9 @@ where
     fn from(header: (N, V)) -> Self {
         let (name, value) = header;
         Self {
-            // FIXME: could avoid allocation if HeaderField had a lifetime
-            name: Cow::Owned(Vec::from(name.as_ref())),
-            value: Cow::Owned(Vec::from(value.as_ref())),
+            name: Bytes::copy_from_slice(name.as_ref()),
+            value: Bytes::copy_from_slice(value.as_ref()),
+            sensitive: false,
         }
     }
 }
@@ -103,8 +123,9 @@ mod tests {
     #[test]
     fn test_field_size_is_offset_by_32() {
         let field = HeaderField {
-            name: Cow::Borrowed(b"Name"),
-            value: Cow::Borrowed(b"Value"),
+            name: Bytes::from_static(b"Name"),
+            value: Bytes::from_static(b"Value"),
+            sensitive: false,
         };
         assert_eq!(field.mem_size(), 4 + 5 + 32);
     }
@@ -112,14 +133,16 @@ mod tests {
     #[test]
     fn with_value() {
         let field = HeaderField {
-            name: Cow::Borrowed(b"Name"),
-            value: Cow::Borrowed(b"Value"),
+            name: Bytes::from_static(b"Name"),
+            value: Bytes::from_static(b"Value"),
+            sensitive: false,
         };
         assert_eq!(
             field.with_value("New value"),
             HeaderField {
-                name: Cow::Borrowed(b"Name"),
-                value: Cow::Borrowed(b"New value"),
+                name: Bytes::from_static(b"Name"),
+                value: Bytes::from_static(b"New value"),
+                sensitive: false,
             }
         );
     }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use super::HeaderField;
+
+/// How the encoder represents a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indexing {
+    /// A literal, without referencing any table
+    Literal,
+    /// A reference to the static table, or a literal
+    Static,
+    /// A reference to an entry already in the dynamic table, or a literal
+    Reference,
+    /// A reference to the dynamic table, inserting the field when not there yet
+    Insert,
+}
+
+/// Decides which fields the encoder inserts in the dynamic table
+///
+/// The policy is asked about every field which is not sensitive. Whatever it decides, a
+/// reference is only emitted when the peer's blocked streams budget allows it, a literal
+/// otherwise.
+pub trait IndexingPolicy: Send + Sync {
+    /// How to encode `field`
+    fn indexing(&mut self, field: &HeaderField) -> Indexing;
+}
+
+/// Encodes every field as a literal
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NeverIndex;
+
+impl IndexingPolicy for NeverIndex {
+    fn indexing(&mut self, _: &HeaderField) -> Indexing {
+        Indexing::Literal
+    }
+}
+
+/// Only references the static table, the dynamic table is left empty
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StaticOnly;
+
+impl IndexingPolicy for StaticOnly {
+    fn indexing(&mut self, _: &HeaderField) -> Indexing {
+        Indexing::Static
+    }
+}
+
+/// Inserts the fields whose size, as counted by the dynamic table, is at most `max_size`
+#[derive(Debug, Clone, Copy)]
+pub struct InsertBelowSize {
+    max_size: usize,
+}
+
+impl InsertBelowSize {
+    /// Inserts the fields of at most `max_size`
+    pub fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+impl IndexingPolicy for InsertBelowSize {
+    fn indexing(&mut self, field: &HeaderField) -> Indexing {
+        match field.mem_size() <= self.max_size {
+            true => Indexing::Insert,
+            false => Indexing::Reference,
+        }
+    }
+}
+
+/// Number of distinct fields `InsertRepeated` counts before starting over
+const MAX_TRACKED_FIELDS: usize = 1024;
+
+/// Inserts the fields once they were seen `sightings` times
+///
+/// Fields seen once, like request ids or dates, are not worth a table entry.
+#[derive(Debug, Clone)]
+pub struct InsertRepeated {
+    sightings: usize,
+    // Sightings by name and value, so the decisions only depend on the fields encoded
+    seen: HashMap<(Bytes, Bytes), usize>,
+}
+
+impl InsertRepeated {
+    /// Inserts the fields seen `sightings` times
+    pub fn new(sightings: usize) -> Self {
+        Self {
+            sightings,
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl IndexingPolicy for InsertRepeated {
+    fn indexing(&mut self, field: &HeaderField) -> Indexing {
+        let key = (field.name.clone(), field.value.clone());
+        if self.seen.len() >= MAX_TRACKED_FIELDS && !self.seen.contains_key(&key) {
+            self.seen.clear();
+        }
+        let seen = self.seen.entry(key).or_insert(0);
+        *seen += 1;
+        match *seen >= self.sightings {
+            true => Indexing::Insert,
+            false => Indexing::Reference,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_repeated_after_sightings() {
+        let mut policy = InsertRepeated::new(2);
+        let field = HeaderField::new("foo", "bar");
+
+        assert_eq!(policy.indexing(&field), Indexing::Reference);
+        assert_eq!(
+            policy.indexing(&field.with_value("baz")),
+            Indexing::Reference
+        );
+        assert_eq!(policy.indexing(&field), Indexing::Insert);
+        assert_eq!(policy.indexing(&field), Indexing::Insert);
+    }
+
+    #[test]
+    fn insert_repeated_bounds_its_memory() {
+        let mut policy = InsertRepeated::new(2);
+        for i in 0..MAX_TRACKED_FIELDS * 2 {
+            policy.indexing(&HeaderField::new("x-id", i.to_string()));
+        }
+        assert!(policy.seen.len() <= MAX_TRACKED_FIELDS);
+    }
+
+    #[test]
+    fn insert_below_size() {
+        let mut policy = InsertBelowSize::new(40);
+        assert_eq!(
+            policy.indexing(&HeaderField::new("foo", "bar")),
+            Indexing::Insert
+        );
+        assert_eq!(
+            policy.indexing(&HeaderField::new("foo", "a-longer-value")),
+            Indexing::Reference
+        );
+    }
+}
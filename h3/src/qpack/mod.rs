@@ -1,14 +1,68 @@
+//! QPACK field compression, as defined by RFC 9204
+//!
+//! Field sections are encoded and decoded independently of any connection. The stateless
+//! functions only reference the static table, as the request streams of `h3` connections do.
+//! `Encoder` and `Decoder` also maintain a dynamic table, kept in sync through the
+//! instructions they exchange on the encoder and decoder streams, which the caller carries.
+//! Their `stats` count the field lines by representation, and `table_entries` lists the
+//! dynamic table for debugging.
+//!
+//! ```
+//! use h3::qpack::{decode_stateless, encode_stateless, HeaderField};
+//!
+//! let fields = [HeaderField::new(":method", "GET"), HeaderField::new("x-id", "42")];
+//! let mut block = Vec::new();
+//! encode_stateless(&mut block, &fields).unwrap();
+//!
+//! let decoded = decode_stateless(&mut block.as_slice(), u64::MAX).unwrap();
+//! assert_eq!(decoded.fields, fields);
+//! ```
+//!
+//! With a dynamic table, each side also applies the instructions of the other:
+//!
+//! ```
+//! use h3::qpack::{ack_header, Decoder, Encoder, HeaderField};
+//!
+//! // Both sides advertised SETTINGS_QPACK_MAX_TABLE_CAPACITY = 4096 and
+//! // SETTINGS_QPACK_BLOCKED_STREAMS = 16
+//! let mut encoder = Encoder::new();
+//! encoder.set_max_table_capacity(4096).unwrap();
+//! encoder.set_max_blocked_streams(16).unwrap();
+//! let mut decoder = Decoder::new(4096).unwrap();
+//!
+//! let (mut encoder_stream, mut decoder_stream) = (Vec::new(), Vec::new());
+//! encoder.set_table_capacity(4096, &mut encoder_stream).unwrap();
+//!
+//! let fields = [HeaderField::new("x-session", "4f8a9c2d")];
+//! let mut block = Vec::new();
+//! encoder.encode(0, &mut block, &mut encoder_stream, &fields).unwrap();
+//!
+//! decoder.on_encoder_recv(&mut encoder_stream.as_slice(), &mut decoder_stream).unwrap();
+//! let decoded = decoder.decode_header(&mut block.as_slice()).unwrap();
+//! assert_eq!(decoded.fields, fields);
+//! if decoded.dyn_ref {
+//!     ack_header(0, &mut decoder_stream);
+//! }
+//! encoder.on_decoder_recv(&mut decoder_stream.as_slice()).unwrap();
+//! ```
+
 pub use self::{
-    decoder::{decode_stateless, Decoded, Error as DecoderError},
-    encoder::{encode_stateless, Error as EncoderError},
+    decoder::{
+        ack_header, decode_stateless, stream_canceled, Decoded, Decoder, Error as DecoderError,
+    },
+    encoder::{encode_stateless, Encoder, Error as EncoderError},
     field::HeaderField,
+    indexing::{Indexing, IndexingPolicy, InsertBelowSize, InsertRepeated, NeverIndex, StaticOnly},
+    stats::{Snapshot, Stats, TableEntry},
 };
 
 mod block;
 mod dynamic;
 mod field;
+mod indexing;
 mod parse_error;
 mod static_;
+mod stats;
 mod stream;
 mod vas;
 
@@ -21,9 +75,12 @@ mod prefix_string;
 #[cfg(test)]
 mod tests;
 
+/// Encoding or decoding error
 #[derive(Debug)]
 pub enum Error {
+    /// Encoding error
     Encoder(EncoderError),
+    /// Decoding error
     Decoder(DecoderError),
 }
 
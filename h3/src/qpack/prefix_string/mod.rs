@@ -1,21 +1,24 @@
+mod huffman;
+
+// Bit by bit implementation of the Huffman code, the reference `huffman` is tested against
+#[cfg(test)]
 mod bitwin;
+#[cfg(test)]
 mod decode;
+#[cfg(test)]
 mod encode;
 
 use std::convert::TryInto;
 use std::fmt;
 use std::num::TryFromIntError;
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 
-pub use self::bitwin::BitWindow;
+pub use self::huffman::Error as HuffmanDecodingError;
 
-pub use self::{
-    decode::{Error as HuffmanDecodingError, HpackStringDecode},
-    encode::{Error as HuffmanEncodingError, HpackStringEncode},
-};
+#[cfg(test)]
+use self::{bitwin::BitWindow, decode::HpackStringDecode, encode::HpackStringEncode};
 
-use crate::proto::coding::BufMutExt;
 use crate::qpack::prefix_int::{self, Error as IntegerError};
 
 #[derive(Debug, PartialEq)]
@@ -23,7 +26,6 @@ pub enum Error {
     UnexpectedEnd,
     Integer(IntegerError),
     HuffmanDecoding(HuffmanDecodingError),
-    HuffmanEncoding(HuffmanEncodingError),
     BufSize(TryFromIntError),
 }
 
@@ -33,13 +35,13 @@ impl std::fmt::Display for Error {
             Error::UnexpectedEnd => write!(f, "unexpected end"),
             Error::Integer(e) => write!(f, "could not parse integer: {}", e),
             Error::HuffmanDecoding(e) => write!(f, "Huffman decode failed: {:?}", e),
-            Error::HuffmanEncoding(e) => write!(f, "Huffman encode failed: {:?}", e),
             Error::BufSize(_) => write!(f, "number in buffer wrong size"),
         }
     }
 }
 
-pub fn decode<B: Buf>(size: u8, buf: &mut B) -> Result<Vec<u8>, Error> {
+/// Decodes a string, a plain one is taken from `buf` without copying it when `buf` is `Bytes`
+pub fn decode<B: Buf>(size: u8, buf: &mut B) -> Result<Bytes, Error> {
     let (flags, len) = prefix_int::decode(size - 1, buf)?;
     let len: usize = len.try_into()?;
     if buf.remaining() < len {
@@ -48,30 +50,26 @@ pub fn decode<B: Buf>(size: u8, buf: &mut B) -> Result<Vec<u8>, Error> {
 
     let payload = buf.copy_to_bytes(len);
     let value = if flags & 1 == 0 {
-        payload.into_iter().collect()
+        payload
     } else {
         let mut decoded = Vec::new();
-        for byte in payload.into_iter().collect::<Vec<u8>>().hpack_decode() {
-            decoded.push(byte?);
-        }
-        decoded
+        huffman::decode(&payload, &mut decoded)?;
+        decoded.into()
     };
     Ok(value)
 }
 
 pub fn encode<B: BufMut>(size: u8, flags: u8, value: &[u8], buf: &mut B) -> Result<(), Error> {
-    let encoded = Vec::from(value).hpack_encode()?;
+    let mut encoded = Vec::new();
+    huffman::encode(value, &mut encoded);
     prefix_int::encode(size - 1, flags << 1 | 1, encoded.len().try_into()?, buf);
-    for byte in encoded {
-        buf.write(byte);
-    }
+    buf.put_slice(&encoded);
     Ok(())
 }
 
-impl From<HuffmanEncodingError> for Error {
-    fn from(error: HuffmanEncodingError) -> Self {
-        Error::HuffmanEncoding(error)
-    }
+/// Bytes saved by `encode` on `value`, compared to sending it as is
+pub fn huffman_savings(value: &[u8]) -> i64 {
+    value.len() as i64 - huffman::encoded_len(value) as i64
 }
 
 impl From<IntegerError> for Error {
@@ -124,7 +122,7 @@ mod tests {
                 127
             ]
         );
-        assert_eq!(decode(6, &mut read).unwrap(), b"name without ref");
+        assert_eq!(decode(6, &mut read).unwrap(), &b"name without ref"[..]);
     }
 
     #[test]
@@ -136,7 +134,7 @@ mod tests {
             &buf,
             &[0b1000_1010, 168, 116, 149, 79, 6, 76, 234, 88, 89, 127]
         );
-        assert_eq!(decode(8, &mut read).unwrap(), b"name with ref");
+        assert_eq!(decode(8, &mut read).unwrap(), &b"name with ref"[..]);
     }
 
     #[test]
@@ -145,14 +143,14 @@ mod tests {
         encode(8, 0b01, b"", &mut buf).unwrap();
         let mut read = Cursor::new(&buf);
         assert_eq!(&buf, &[0b1000_0000]);
-        assert_eq!(decode(8, &mut read).unwrap(), b"");
+        assert_eq!(decode(8, &mut read).unwrap(), &b""[..]);
     }
 
     #[test]
     fn decode_non_huffman() {
         let buf = vec![0b0100_0011, b'b', b'a', b'r'];
         let mut read = Cursor::new(&buf);
-        assert_eq!(decode(6, &mut read).unwrap(), b"bar");
+        assert_eq!(decode(6, &mut read).unwrap(), &b"bar"[..]);
     }
 
     #[test]
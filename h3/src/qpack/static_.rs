@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use bytes::Bytes;
 
 use super::field::HeaderField;
 
@@ -191,14 +191,15 @@ macro_rules! decl_fields {
         [
             $(
             HeaderField {
-                name: Cow::Borrowed($key),
-                value: Cow::Borrowed($value)
+                name: Bytes::from_static($key),
+                value: Bytes::from_static($value),
+                sensitive: false,
             },
         )* ]
     }
 }
 
-const PREDEFINED_HEADERS: [HeaderField; 99] = decl_fields![
+static PREDEFINED_HEADERS: [HeaderField; 99] = decl_fields![
     (b":authority", b""),
     (b":path", b"/"),
     (b"age", b"0"),
@@ -0,0 +1,105 @@
+use std::fmt::{Display, Formatter};
+
+use super::{block::HeaderBlockField, prefix_string, HeaderField};
+
+/// Counters of an encoder or a decoder, for tuning the QPACK settings
+///
+/// Field lines are counted by representation as they are encoded or decoded. The table
+/// figures are taken when the counters are read.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Field lines referencing a static table entry
+    pub static_hits: u64,
+    /// Field lines referencing a dynamic table entry
+    pub dynamic_hits: u64,
+    /// Literal field lines whose name references a static or dynamic table entry
+    pub literal_with_name_ref: u64,
+    /// Literal field lines, name included
+    pub literals: u64,
+    /// Bytes saved by Huffman coding the strings of field lines and insert instructions
+    ///
+    /// Only counted by the encoder, negative when the Huffman code made strings longer.
+    pub huffman_savings: i64,
+    /// Entries evicted from the dynamic table
+    pub evictions: u64,
+    /// Streams currently blocked on dynamic table entries
+    ///
+    /// For an encoder, the streams whose sections reference unacknowledged entries. For a
+    /// decoder, the streams waiting for entries from the encoder stream.
+    pub blocked_streams: usize,
+    /// Entries in the dynamic table
+    pub table_entries: usize,
+    /// Size of the dynamic table entries, as defined in RFC 9204 3.2.1
+    pub table_size: usize,
+    /// Current capacity of the dynamic table
+    pub table_capacity: usize,
+}
+
+impl Stats {
+    /// Counts a field line from its first byte
+    pub(super) fn count_field_line(&mut self, first: u8) {
+        match HeaderBlockField::decode(first) {
+            HeaderBlockField::Indexed if first & 0b0100_0000 != 0 => self.static_hits += 1,
+            HeaderBlockField::Indexed | HeaderBlockField::IndexedWithPostBase => {
+                self.dynamic_hits += 1
+            }
+            HeaderBlockField::LiteralWithNameRef | HeaderBlockField::LiteralWithPostBaseNameRef => {
+                self.literal_with_name_ref += 1
+            }
+            HeaderBlockField::Literal => self.literals += 1,
+            HeaderBlockField::Unknown => (),
+        }
+    }
+
+    /// Counts an encoded field line, and the bytes Huffman coding saved on its strings
+    pub(super) fn count_encoded_field_line(&mut self, first: u8, field: &HeaderField) {
+        self.count_field_line(first);
+        match HeaderBlockField::decode(first) {
+            HeaderBlockField::LiteralWithNameRef | HeaderBlockField::LiteralWithPostBaseNameRef => {
+                self.count_huffman(&field.value)
+            }
+            HeaderBlockField::Literal => {
+                self.count_huffman(&field.name);
+                self.count_huffman(&field.value);
+            }
+            _ => (),
+        }
+    }
+
+    /// Counts the bytes Huffman coding saved on `string`
+    pub(super) fn count_huffman(&mut self, string: &[u8]) {
+        self.huffman_savings += prefix_string::huffman_savings(string);
+    }
+}
+
+/// An entry of a dynamic table, as listed by `Encoder::table_entries`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableEntry {
+    /// Absolute index, the first entry inserted in the table being 0
+    pub index: usize,
+    /// Size of the entry, as defined in RFC 9204 3.2.1
+    pub size: usize,
+    /// The field
+    pub field: HeaderField,
+}
+
+impl Display for TableEntry {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "[{}] (s = {}) {}", self.index, self.size, self.field)
+    }
+}
+
+/// The QPACK state of a connection, for debugging
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// Counters of the encoder, compressing the sections sent
+    pub encoder: Stats,
+    /// Counters of the decoder, decompressing the sections received
+    pub decoder: Stats,
+    /// Dynamic table of the encoder, from the oldest entry to the newest
+    pub encoder_table: Vec<TableEntry>,
+    /// Dynamic table of the decoder, from the oldest entry to the newest
+    pub decoder_table: Vec<TableEntry>,
+}
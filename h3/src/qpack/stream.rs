@@ -1,4 +1,4 @@
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 use std::convert::TryInto;
 
 use super::{
@@ -71,19 +71,19 @@ impl EncoderInstruction {
 
 #[derive(Debug, PartialEq)]
 pub enum InsertWithNameRef {
-    Static { index: usize, value: Vec<u8> },
-    Dynamic { index: usize, value: Vec<u8> },
+    Static { index: usize, value: Bytes },
+    Dynamic { index: usize, value: Bytes },
 }
 
 impl InsertWithNameRef {
-    pub fn new_static<T: Into<Vec<u8>>>(index: usize, value: T) -> Self {
+    pub fn new_static<T: Into<Bytes>>(index: usize, value: T) -> Self {
         InsertWithNameRef::Static {
             index,
             value: value.into(),
         }
     }
 
-    pub fn new_dynamic<T: Into<Vec<u8>>>(index: usize, value: T) -> Self {
+    pub fn new_dynamic<T: Into<Bytes>>(index: usize, value: T) -> Self {
         InsertWithNameRef::Dynamic {
             index,
             value: value.into(),
@@ -131,12 +131,12 @@ impl InsertWithNameRef {
 
 #[derive(Debug, PartialEq)]
 pub struct InsertWithoutNameRef {
-    pub name: Vec<u8>,
-    pub value: Vec<u8>,
+    pub name: Bytes,
+    pub value: Bytes,
 }
 
 impl InsertWithoutNameRef {
-    pub fn new<T: Into<Vec<u8>>>(name: T, value: T) -> Self {
+    pub fn new<T: Into<Bytes>>(name: T, value: T) -> Self {
         Self {
             name: name.into(),
             value: value.into(),
@@ -1,6 +1,10 @@
-use crate::qpack::decoder::Decoder;
-use crate::qpack::encoder::Encoder;
-use crate::qpack::{dynamic::DynamicTable, Decoded, DecoderError, HeaderField};
+use crate::qpack::decoder::{ack_header, decode_stateless, Decoder};
+use crate::qpack::encoder::{encode_stateless, Encoder};
+use crate::qpack::indexing::InsertRepeated;
+use crate::qpack::{
+    dynamic::{DynamicTable, Error as DynamicTableError},
+    prefix_string, Decoded, DecoderError, EncoderError, HeaderField, Stats,
+};
 use std::io::Cursor;
 
 pub mod helpers {
@@ -10,6 +14,7 @@ pub mod helpers {
 
     pub fn build_table() -> DynamicTable {
         let mut table = DynamicTable::new();
+        table.set_max_capacity(TABLE_SIZE).unwrap();
         table.set_max_size(TABLE_SIZE).unwrap();
         table.set_max_blocked(100).unwrap();
         table
@@ -17,6 +22,7 @@ pub mod helpers {
 
     pub fn build_table_with_size(n_field: usize) -> DynamicTable {
         let mut table = DynamicTable::new();
+        table.set_max_capacity(TABLE_SIZE).unwrap();
         table.set_max_size(TABLE_SIZE).unwrap();
         table.set_max_blocked(100).unwrap();
 
@@ -60,20 +66,63 @@ fn codec_basic_get() {
     encoder.on_decoder_recv(&mut dec_cur).unwrap();
 }
 
+#[test]
+fn codec_sensitive_never_indexed() {
+    let mut encoder = Encoder::from(helpers::build_table());
+    let mut decoder = Decoder::from(helpers::build_table());
+
+    let mut block_buf = vec![];
+    let mut enc_buf = vec![];
+    let mut dec_buf = vec![];
+
+    let header = vec![
+        HeaderField::new(":method", "GET"),
+        HeaderField::new("authorization", "secret").with_sensitive(true),
+        HeaderField::new("x-token", "secret").with_sensitive(true),
+        HeaderField::new("foo", "bar"),
+    ];
+
+    encoder
+        .encode(42, &mut block_buf, &mut enc_buf, header.clone())
+        .unwrap();
+
+    let mut enc_cur = Cursor::new(&mut enc_buf);
+    // Only the non-sensitive field made it to the dynamic table
+    assert_eq!(decoder.on_encoder_recv(&mut enc_cur, &mut dec_buf), Ok(1));
+
+    let mut block_cur = Cursor::new(&mut block_buf);
+    let Decoded { fields, .. } = decoder.decode_header(&mut block_cur).unwrap();
+    assert_eq!(fields, header);
+}
+
+#[test]
+fn codec_sensitive_stateless() {
+    let header = vec![
+        HeaderField::new("cookie", "").with_sensitive(true),
+        HeaderField::new("x-token", "secret").with_sensitive(true),
+    ];
+
+    let mut block_buf = vec![];
+    encode_stateless(&mut block_buf, header.clone()).unwrap();
+
+    let mut block_cur = Cursor::new(&mut block_buf);
+    let Decoded { fields, .. } = decode_stateless(&mut block_cur, u64::MAX).unwrap();
+    assert_eq!(fields, header);
+}
+
 const TABLE_SIZE: usize = 4096;
 #[test]
 fn blocked_header() {
-    let mut enc_table = DynamicTable::new();
-    enc_table.set_max_size(TABLE_SIZE).unwrap();
-    enc_table.set_max_blocked(100).unwrap();
-    let mut encoder = Encoder::from(enc_table);
-    let mut dec_table = DynamicTable::new();
-    dec_table.set_max_size(TABLE_SIZE).unwrap();
-    dec_table.set_max_blocked(100).unwrap();
-    let decoder = Decoder::from(dec_table);
+    let mut encoder = Encoder::new();
+    encoder.set_max_table_capacity(TABLE_SIZE).unwrap();
+    encoder.set_max_blocked_streams(100).unwrap();
+    let mut decoder = Decoder::new(TABLE_SIZE).unwrap();
 
     let mut block_buf = vec![];
     let mut enc_buf = vec![];
+    encoder
+        .set_table_capacity(TABLE_SIZE, &mut enc_buf)
+        .unwrap();
 
     encoder
         .encode(
@@ -91,6 +140,28 @@ fn blocked_header() {
     );
 }
 
+#[test]
+fn table_capacity_bounded_by_settings() {
+    let mut encoder = Encoder::new();
+    encoder.set_max_table_capacity(512).unwrap();
+    assert_eq!(
+        encoder.set_table_capacity(513, &mut vec![]),
+        Err(EncoderError::Insertion(
+            DynamicTableError::MaximumTableSizeTooLarge
+        ))
+    );
+
+    let mut enc_buf = vec![];
+    encoder.set_table_capacity(512, &mut enc_buf).unwrap();
+    let mut decoder = Decoder::new(256).unwrap();
+    assert_eq!(
+        decoder.on_encoder_recv(&mut Cursor::new(&enc_buf), &mut vec![]),
+        Err(DecoderError::DynamicTable(
+            DynamicTableError::MaximumTableSizeTooLarge
+        ))
+    );
+}
+
 #[test]
 fn codec_table_size_0() {
     let mut enc_table = DynamicTable::new();
@@ -161,3 +232,144 @@ fn codec_table_full() {
     let mut dec_cur = Cursor::new(&mut dec_buf);
     encoder.on_decoder_recv(&mut dec_cur).unwrap();
 }
+
+#[test]
+fn codec_evicting_table_round_trip() {
+    let mut encoder = Encoder::new();
+    encoder.set_max_table_capacity(256).unwrap();
+    encoder.set_max_blocked_streams(2).unwrap();
+    encoder.set_indexing_policy(InsertRepeated::new(2));
+    let mut decoder = Decoder::new(256).unwrap();
+
+    let mut enc_buf = vec![];
+    encoder.set_table_capacity(256, &mut enc_buf).unwrap();
+    decoder
+        .on_encoder_recv(&mut Cursor::new(&enc_buf), &mut vec![])
+        .unwrap();
+    let mut inserted = 0;
+    let mut dyn_refs = 0;
+
+    for i in 0..200u64 {
+        let header = vec![
+            HeaderField::new(":method", "GET"),
+            HeaderField::new(":path", format!("/{}", i % 7)),
+            HeaderField::new("x-session", format!("{}", i % 5)),
+            HeaderField::new("x-request-id", format!("{}", i)),
+        ];
+        let stream_id = i * 4;
+
+        let mut block_buf = vec![];
+        let mut enc_buf = vec![];
+        let mut dec_buf = vec![];
+        encoder
+            .encode(stream_id, &mut block_buf, &mut enc_buf, header.clone())
+            .unwrap();
+
+        inserted = decoder
+            .on_encoder_recv(&mut Cursor::new(&mut enc_buf), &mut dec_buf)
+            .unwrap();
+        let Decoded {
+            fields, dyn_ref, ..
+        } = decoder
+            .decode_header(&mut Cursor::new(&mut block_buf))
+            .unwrap();
+        assert_eq!(fields, header);
+        if dyn_ref {
+            ack_header(stream_id, &mut dec_buf);
+            dyn_refs += 1;
+        }
+
+        encoder
+            .on_decoder_recv(&mut Cursor::new(&mut dec_buf))
+            .unwrap();
+    }
+    // Way more than the table holds, the repeated fields were duplicated as they drained
+    assert!(inserted > 100);
+    assert!(dyn_refs > 150);
+}
+
+#[test]
+fn codec_stats() {
+    let mut encoder = Encoder::new();
+    encoder.set_max_table_capacity(4096).unwrap();
+    encoder.set_max_blocked_streams(1).unwrap();
+    let mut decoder = Decoder::new(4096).unwrap();
+
+    let mut enc_buf = vec![];
+    let mut dec_buf = vec![];
+    encoder.set_table_capacity(4096, &mut enc_buf).unwrap();
+
+    let header = vec![
+        HeaderField::new(":method", "GET"),
+        HeaderField::new(":path", "/stats"),
+        HeaderField::new("x-trace", "abc"),
+        HeaderField::new("authorization", "secret").with_sensitive(true),
+        HeaderField::new("x-secret", "1").with_sensitive(true),
+    ];
+    let mut block_buf = vec![];
+    encoder
+        .encode(4, &mut block_buf, &mut enc_buf, header.clone())
+        .unwrap();
+
+    let huffman_savings = ["/stats", "x-trace", "abc", "secret", "x-secret", "1"]
+        .iter()
+        .map(|s| prefix_string::huffman_savings(s.as_bytes()))
+        .sum();
+    assert_eq!(
+        encoder.stats(),
+        Stats {
+            static_hits: 1,
+            dynamic_hits: 2,
+            literal_with_name_ref: 1,
+            literals: 1,
+            huffman_savings,
+            evictions: 0,
+            blocked_streams: 1,
+            table_entries: 2,
+            table_size: 85,
+            table_capacity: 4096,
+        }
+    );
+
+    decoder
+        .on_encoder_recv(&mut Cursor::new(&enc_buf), &mut dec_buf)
+        .unwrap();
+    let decoded = decoder.decode_header(&mut Cursor::new(&block_buf)).unwrap();
+    assert_eq!(decoded.fields, header);
+    assert_eq!(
+        decoder.stats(),
+        Stats {
+            huffman_savings: 0,
+            blocked_streams: 0,
+            ..encoder.stats()
+        }
+    );
+
+    let entries = decoder.table_entries();
+    assert_eq!(entries, encoder.table_entries());
+    assert_eq!(
+        entries.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        [
+            "[0] (s = 43) \":path\": \"/stats\"",
+            "[1] (s = 42) \"x-trace\": \"abc\"",
+        ]
+    );
+
+    // Once acknowledged, the entries can be evicted
+    decoder.ack_section(4, &decoded, &mut dec_buf);
+    encoder.on_decoder_recv(&mut Cursor::new(&dec_buf)).unwrap();
+    let mut enc_buf = vec![];
+    encoder.set_table_capacity(0, &mut enc_buf).unwrap();
+    decoder
+        .on_encoder_recv(&mut Cursor::new(&enc_buf), &mut vec![])
+        .unwrap();
+
+    for stats in [encoder.stats(), decoder.stats()] {
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.blocked_streams, 0);
+        assert_eq!(stats.table_entries, 0);
+        assert_eq!(stats.table_size, 0);
+        assert_eq!(stats.table_capacity, 0);
+    }
+    assert!(decoder.table_entries().is_empty());
+}
@@ -133,6 +133,15 @@ impl VirtualAddressSpace {
         }
     }
 
+    /// Position in the container of the entry with the absolute `index`
+    pub fn position(&self, index: AbsoluteIndex) -> Result<usize, Error> {
+        if index <= self.dropped || index > self.inserted {
+            Err(Error::Index(index))
+        } else {
+            Ok(index - self.dropped - 1)
+        }
+    }
+
     pub fn largest_ref(&self) -> usize {
         self.inserted - self.dropped
     }
@@ -140,6 +149,10 @@ impl VirtualAddressSpace {
     pub fn total_inserted(&self) -> usize {
         self.inserted
     }
+
+    pub fn total_dropped(&self) -> usize {
+        self.dropped
+    }
 }
 
 #[cfg(test)]
@@ -118,6 +118,18 @@ pub trait SendStreamUnframed<B: Buf>: SendStream<B> {
     ) -> Poll<Result<usize, Self::Error>>;
 }
 
+/// Optional trait to schedule the data of a stream relative to the other streams of the connection
+pub trait SendStreamPriority<B: Buf>: SendStream<B> {
+    /// Sets the priority of the stream
+    ///
+    /// Buffered data of streams with a higher priority is sent before the data of streams
+    /// with a lower priority. Streams start with a priority of 0.
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error>;
+
+    /// Returns the priority of the stream
+    fn priority(&self) -> Result<i32, Self::Error>;
+}
+
 /// A trait describing the "receive" actions of a QUIC stream.
 pub trait RecvStream {
     /// The type of `Buf` for data received on this stream.
@@ -0,0 +1,84 @@
+//! Runtime components
+//!
+//! h3 does not depend on a specific async runtime. Features needing one, such as
+//! timeouts, are provided with the traits of this module.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// A timer creating [`Sleep`] futures
+///
+/// # Example
+///
+/// A timer for the tokio runtime:
+///
+/// ```rust
+/// #[derive(Clone)]
+/// struct TokioTimer;
+///
+/// impl h3::rt::Timer for TokioTimer {
+///     fn sleep(&self, duration: std::time::Duration) -> std::pin::Pin<Box<dyn h3::rt::Sleep>> {
+///         Box::pin(tokio::time::sleep(duration))
+///     }
+/// }
+/// ```
+pub trait Timer {
+    /// Returns a future resolving once `duration` has elapsed
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Sleep>>;
+}
+
+/// A future returned by [`Timer::sleep()`]
+pub trait Sleep: Future<Output = ()> + Send + Sync {}
+
+impl<T: Future<Output = ()> + Send + Sync> Sleep for T {}
+
+/// A timeout that is restarted every time some progress is made
+pub(crate) struct IdleTimer {
+    timer: Arc<dyn Timer + Send + Sync>,
+    duration: Duration,
+    sleep: Option<Pin<Box<dyn Sleep>>>,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timer: Arc<dyn Timer + Send + Sync>, duration: Duration) -> Self {
+        Self {
+            timer,
+            duration,
+            sleep: None,
+        }
+    }
+
+    /// Polls for the expiration of the timeout, starting it if needed
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let timer = &self.timer;
+        let duration = self.duration;
+        self.sleep
+            .get_or_insert_with(|| timer.sleep(duration))
+            .as_mut()
+            .poll(cx)
+    }
+
+    /// Stops the timeout, it starts over on the next poll
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl fmt::Debug for IdleTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdleTimer")
+            .field("duration", &self.duration)
+            .field("started", &self.sleep.is_some())
+            .finish()
+    }
+}
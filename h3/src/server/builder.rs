@@ -21,7 +21,7 @@
 //! }
 //! ```
 
-use std::{collections::HashSet, result::Result};
+use std::{collections::HashSet, pin::Pin, result::Result, sync::Arc, time::Duration};
 
 use bytes::Buf;
 
@@ -32,6 +32,7 @@ use crate::{
     connection::{ConnectionInner, SharedStateRef},
     error::Error,
     quic::{self},
+    rt::{IdleTimer, Sleep, Timer},
 };
 
 use super::connection::Connection;
@@ -47,6 +48,55 @@ pub fn builder() -> Builder {
 /// Builder of HTTP/3 server connections.
 pub struct Builder {
     pub(crate) config: Config,
+    pub(super) limits: RequestLimits,
+    pub(super) timeouts: Timeouts,
+}
+
+/// Limits applied to the requests of a server connection
+///
+/// Unlike settings, they are not advertised to the client.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct RequestLimits {
+    pub(super) max_request_body_size: Option<u64>,
+    pub(super) max_trailers_size: Option<u64>,
+    pub(super) max_header_fields: Option<usize>,
+    pub(super) max_concurrent_requests: Option<usize>,
+}
+
+/// Timeouts applied to a server connection and its requests
+#[derive(Clone, Default)]
+pub(super) struct Timeouts {
+    pub(super) timer: Option<Arc<dyn Timer + Send + Sync>>,
+    pub(super) header_read: Option<Duration>,
+    pub(super) body_idle: Option<Duration>,
+    pub(super) connection_idle: Option<Duration>,
+    pub(super) shutdown_interval: Option<Duration>,
+}
+
+/// Time between the two GOAWAY frames of a graceful shutdown, covering a few round trips
+const DEFAULT_SHUTDOWN_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Timeouts {
+    fn idle_timer(&self, duration: Option<Duration>) -> Option<IdleTimer> {
+        Some(IdleTimer::new(self.timer.clone()?, duration?))
+    }
+
+    pub(super) fn header_read(&self) -> Option<IdleTimer> {
+        self.idle_timer(self.header_read)
+    }
+
+    pub(super) fn body_idle(&self) -> Option<IdleTimer> {
+        self.idle_timer(self.body_idle)
+    }
+
+    pub(super) fn connection_idle(&self) -> Option<IdleTimer> {
+        self.idle_timer(self.connection_idle)
+    }
+
+    pub(super) fn shutdown_interval(&self) -> Option<Pin<Box<dyn Sleep>>> {
+        let interval = self.shutdown_interval.unwrap_or(DEFAULT_SHUTDOWN_INTERVAL);
+        Some(self.timer.as_ref()?.sleep(interval))
+    }
 }
 
 impl Builder {
@@ -54,6 +104,8 @@ impl Builder {
     pub(super) fn new() -> Self {
         Builder {
             config: Default::default(),
+            limits: Default::default(),
+            timeouts: Default::default(),
         }
     }
 
@@ -75,6 +127,36 @@ impl Builder {
         self
     }
 
+    /// Set the capacity of the QPACK dynamic table the client may use to compress the headers
+    /// it sends
+    ///
+    /// Advertised as `SETTINGS_QPACK_MAX_TABLE_CAPACITY`. Zero by default, the client then
+    /// only references the static table.
+    pub fn qpack_max_table_capacity(&mut self, value: u64) -> &mut Self {
+        self.config.settings.qpack_max_table_capacity = value;
+        self
+    }
+
+    /// Set the number of streams the client may block, referencing QPACK dynamic table entries
+    /// not received yet
+    ///
+    /// Advertised as `SETTINGS_QPACK_BLOCKED_STREAMS`. Zero by default, the client then only
+    /// references entries the decoder acknowledged.
+    pub fn qpack_max_blocked_streams(&mut self, value: u64) -> &mut Self {
+        self.config.settings.qpack_max_blocked_streams = value;
+        self
+    }
+
+    /// Set the capacity of the QPACK dynamic table used to compress the headers sent to the
+    /// client
+    ///
+    /// The table is bounded by the capacity the client advertises. Zero by default, only the
+    /// static table is then referenced.
+    pub fn qpack_encoder_table_capacity(&mut self, value: u64) -> &mut Self {
+        self.config.qpack_encoder_table_capacity = value;
+        self
+    }
+
     /// Send grease values to the Client.
     /// See [setting](https://www.rfc-editor.org/rfc/rfc9114.html#settings-parameters), [frame](https://www.rfc-editor.org/rfc/rfc9114.html#frame-reserved) and [stream](https://www.rfc-editor.org/rfc/rfc9114.html#stream-grease) for more information.
     #[inline]
@@ -104,11 +186,44 @@ impl Builder {
     }
 
     /// Limits the maximum number of WebTransport sessions
+    ///
+    /// The newer WebTransport drafts, with session-level flow control, are only offered to the
+    /// client with a non zero limit.
     pub fn max_webtransport_sessions(&mut self, value: u64) -> &mut Self {
         self.config.settings.max_webtransport_sessions = value;
         self
     }
 
+    /// Set the amount of data the client can send on the streams of a WebTransport session
+    /// before being granted more with a `WT_MAX_DATA` capsule
+    ///
+    /// Only applies to the WebTransport drafts with session-level flow control. Unlimited by
+    /// default.
+    pub fn wt_initial_max_data(&mut self, value: u64) -> &mut Self {
+        self.config.settings.wt_initial_max_data = value;
+        self
+    }
+
+    /// Set the number of unidirectional streams the client can open in a WebTransport session
+    /// before being granted more with a `WT_MAX_STREAMS` capsule
+    ///
+    /// Only applies to the WebTransport drafts with session-level flow control. Unlimited by
+    /// default.
+    pub fn wt_initial_max_streams_uni(&mut self, value: u64) -> &mut Self {
+        self.config.settings.wt_initial_max_streams_uni = value;
+        self
+    }
+
+    /// Set the number of bidirectional streams the client can open in a WebTransport session
+    /// before being granted more with a `WT_MAX_STREAMS` capsule
+    ///
+    /// Only applies to the WebTransport drafts with session-level flow control. Unlimited by
+    /// default.
+    pub fn wt_initial_max_streams_bidi(&mut self, value: u64) -> &mut Self {
+        self.config.settings.wt_initial_max_streams_bidi = value;
+        self
+    }
+
     /// Indicates that the client or server supports HTTP/3 datagrams
     ///
     /// See: <https://www.rfc-editor.org/rfc/rfc9297#section-2.1.1>
@@ -116,6 +231,98 @@ impl Builder {
         self.config.settings.enable_datagram = value;
         self
     }
+
+    /// Set the maximum size of a request body, in bytes
+    ///
+    /// A request announcing a larger `content-length` is answered with
+    /// `413 Payload Too Large` before being handed to the application. Receiving more
+    /// body data than this fails with `H3_EXCESSIVE_LOAD`.
+    pub fn max_request_body_size(&mut self, value: u64) -> &mut Self {
+        self.limits.max_request_body_size = Some(value);
+        self
+    }
+
+    /// Set the maximum size of the request trailers, in bytes
+    ///
+    /// This is computed the same way as [`Builder::max_field_section_size()`], which also
+    /// applies to trailers. Larger trailers fail with `H3_EXCESSIVE_LOAD`.
+    pub fn max_trailers_size(&mut self, value: u64) -> &mut Self {
+        self.limits.max_trailers_size = Some(value);
+        self
+    }
+
+    /// Set the maximum number of fields in the request headers or trailers
+    ///
+    /// Requests with more header fields are answered with
+    /// `431 Request Header Fields Too Large`. Trailers with more fields fail with
+    /// `H3_EXCESSIVE_LOAD`.
+    pub fn max_header_fields(&mut self, value: usize) -> &mut Self {
+        self.limits.max_header_fields = Some(value);
+        self
+    }
+
+    /// Set the maximum number of requests processed concurrently on a connection
+    ///
+    /// Requests beyond this limit are rejected with `H3_REQUEST_REJECTED`, so the client
+    /// can safely retry them. This comes on top of the stream credit granted by the QUIC
+    /// layer.
+    pub fn max_concurrent_requests(&mut self, value: usize) -> &mut Self {
+        self.limits.max_concurrent_requests = Some(value);
+        self
+    }
+
+    /// Set the timer used to enforce timeouts
+    ///
+    /// Without a timer, the timeouts configured on this builder have no effect, and
+    /// [`Connection::graceful_shutdown()`] fails.
+    pub fn timer<T>(&mut self, timer: T) -> &mut Self
+    where
+        T: Timer + Send + Sync + 'static,
+    {
+        self.timeouts.timer = Some(Arc::new(timer));
+        self
+    }
+
+    /// Whether a timer was set with [`Builder::timer()`]
+    pub fn has_timer(&self) -> bool {
+        self.timeouts.timer.is_some()
+    }
+
+    /// Set the maximum time to receive the request headers once a request stream is opened
+    ///
+    /// When it elapses, the request stream is cancelled with `H3_REQUEST_INCOMPLETE` and
+    /// [`Connection::accept()`] returns a stream error.
+    pub fn header_read_timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeouts.header_read = Some(value);
+        self
+    }
+
+    /// Set the maximum time to wait for the next piece of a request body or its trailers
+    ///
+    /// When it elapses, the client is asked to stop sending with `H3_REQUEST_INCOMPLETE`,
+    /// and receiving data fails with this error.
+    pub fn body_idle_timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeouts.body_idle = Some(value);
+        self
+    }
+
+    /// Set the maximum time a connection is kept open without any ongoing request
+    ///
+    /// When it elapses, a graceful shutdown is started by sending a GOAWAY frame, and
+    /// [`Connection::accept()`] returns `None`.
+    pub fn connection_idle_timeout(&mut self, value: Duration) -> &mut Self {
+        self.timeouts.connection_idle = Some(value);
+        self
+    }
+
+    /// Set the time waited between the two GOAWAY frames of [`Connection::graceful_shutdown()`]
+    ///
+    /// It should be at least one round trip, for the requests sent before the client received
+    /// the first GOAWAY frame to arrive. Defaults to 100ms.
+    pub fn graceful_shutdown_interval(&mut self, value: Duration) -> &mut Self {
+        self.timeouts.shutdown_interval = Some(value);
+        self
+    }
 }
 
 impl Builder {
@@ -131,12 +338,18 @@ impl Builder {
         Ok(Connection {
             inner: ConnectionInner::new(conn, SharedStateRef::default(), self.config).await?,
             max_field_section_size: self.config.settings.max_field_section_size,
+            limits: self.limits,
+            idle_timer: self.timeouts.connection_idle(),
+            timeouts: self.timeouts.clone(),
             request_end_send: sender,
             request_end_recv: receiver,
             ongoing_streams: HashSet::new(),
             sent_closing: None,
             recv_closing: None,
             last_accepted_stream: None,
+            pending_request: None,
+            pending_resolve: None,
+            announced: None,
         })
     }
 }
@@ -4,8 +4,9 @@
 
 use std::{
     collections::HashSet,
-    future::poll_fn,
+    future::{poll_fn, Future},
     option::Option,
+    pin::{pin, Pin},
     result::Result,
     sync::Arc,
     task::{ready, Context, Poll},
@@ -27,6 +28,7 @@ use crate::{
     },
     qpack,
     quic::{self, SendStream as _},
+    rt::{IdleTimer, Sleep},
     stream::BufRecvStream,
 };
 
@@ -35,7 +37,20 @@ use crate::server::request::ResolveRequest;
 #[cfg(feature = "tracing")]
 use tracing::{instrument, trace, warn};
 
-use super::stream::RequestStream;
+use super::{
+    builder::{RequestLimits, Timeouts},
+    stream::RequestStream,
+};
+
+/// Request stream accepted before its headers are received, with their timer
+pub(super) type PendingRequest<S, B> = (FrameStream<S, B>, Option<IdleTimer>);
+
+/// First phase of a graceful shutdown started with [`Connection::announce_shutdown()`]
+pub(super) enum Announced {
+    // Requests are accepted until the interval elapses
+    Waiting(Pin<Box<dyn Sleep>>),
+    Elapsed,
+}
 
 /// Server connection driver
 ///
@@ -52,6 +67,10 @@ where
     /// TODO: temporarily break encapsulation for `WebTransportSession`
     pub inner: ConnectionInner<C, B>,
     pub(super) max_field_section_size: u64,
+    pub(super) limits: RequestLimits,
+    pub(super) timeouts: Timeouts,
+    // Started when there is no ongoing request, a graceful shutdown begins when it expires.
+    pub(super) idle_timer: Option<IdleTimer>,
     // List of all incoming streams that are currently running.
     pub(super) ongoing_streams: HashSet<StreamId>,
     // Let the streams tell us when they are no longer running.
@@ -63,6 +82,13 @@ where
     pub(super) recv_closing: Option<PushId>,
     // The id of the last stream received by this connection.
     pub(super) last_accepted_stream: Option<StreamId>,
+    // Request stream whose headers are being read, kept when an accept is cancelled so the
+    // next one resumes it.
+    pub(super) pending_request: Option<PendingRequest<C::BidiStream, B>>,
+    // Request whose headers wait for the QPACK entries they reference, kept like the above
+    pub(super) pending_resolve: Option<ResolveRequest<C, B>>,
+    // Set once the first GOAWAY of a graceful shutdown was sent by `announce_shutdown()`
+    pub(super) announced: Option<Announced>,
 }
 
 impl<C, B> ConnectionState for Connection<C, B>
@@ -95,6 +121,13 @@ where
     pub fn close<T: AsRef<str>>(&mut self, code: Code, reason: T) -> Error {
         self.inner.close(code, reason)
     }
+
+    /// Returns the QPACK counters and dynamic tables of the connection
+    ///
+    /// See [`qpack::Snapshot`].
+    pub fn qpack_snapshot(&self) -> qpack::Snapshot {
+        self.inner.shared.read("qpack snapshot").qpack.snapshot()
+    }
 }
 
 impl<C, B> Connection<C, B>
@@ -107,38 +140,149 @@ where
     /// It returns a tuple with a [`http::Request`] and an [`RequestStream`].
     /// The [`http::Request`] is the received request from the client.
     /// The [`RequestStream`] can be used to send the response.
+    ///
+    /// Dropping the returned future before it completes does not lose a request whose headers
+    /// are being received: the next call resumes reading them, and
+    /// [`Connection::graceful_shutdown()`] rejects it.
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn accept(
         &mut self,
     ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error> {
-        // Accept the incoming stream
-        let mut stream = match poll_fn(|cx| self.poll_accept_request_stream(cx)).await {
-            Ok(Some(s)) => FrameStream::new(BufRecvStream::new(s)),
-            Ok(None) => {
-                // We always send a last GoAway frame to the client, so it knows which was the last
-                // non-rejected request.
-                self.shutdown(0).await?;
-                return Ok(None);
+        self.accept_polling(|_, _| Ok(())).await
+    }
+
+    /// Accept an incoming request, calling `poll` each time the connection is polled
+    ///
+    /// This lets extensions process what else the connection receives, such as datagrams,
+    /// while waiting for requests. An error returned by `poll` ends the accept like a
+    /// connection error.
+    #[cfg(feature = "i-implement-a-third-party-backend-and-opt-into-breaking-changes")]
+    pub async fn accept_with<F>(
+        &mut self,
+        poll: F,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error>
+    where
+        F: FnMut(&mut Self, &mut Context<'_>) -> Result<(), Error>,
+    {
+        self.accept_polling(poll).await
+    }
+
+    async fn accept_polling<F>(
+        &mut self,
+        mut poll: F,
+    ) -> Result<Option<(Request<()>, RequestStream<C::BidiStream, B>)>, Error>
+    where
+        F: FnMut(&mut Self, &mut Context<'_>) -> Result<(), Error>,
+    {
+        if self.pending_resolve.is_none() {
+            if self.pending_request.is_none() {
+                // Accept the incoming stream
+                let stream = poll_fn(|cx| {
+                    poll(self, cx)?;
+                    self.poll_accept_request_stream(cx)
+                })
+                .await;
+                match stream {
+                    // Bound the time the client may take to send the request headers, so an
+                    // opened request stream cannot hold the connection forever.
+                    Ok(Some(s)) => {
+                        let stream = FrameStream::new(BufRecvStream::new(s));
+                        self.pending_request = Some((stream, self.timeouts.header_read()));
+                    }
+                    Ok(None) => {
+                        // We always send a last GoAway frame to the client, so it knows which was the last
+                        // non-rejected request.
+                        self.shutdown(0).await?;
+                        return Ok(None);
+                    }
+                    Err(err) => return self.accept_error(err),
+                }
             }
-            Err(err) => {
-                match err.inner.kind {
-                    crate::error::Kind::Closed => return Ok(None),
-                    crate::error::Kind::Application {
-                        code,
-                        reason,
-                        level: ErrorLevel::ConnectionError,
-                    } => return Err(self.inner.close(code, reason.unwrap_or_default())),
-                    _ => return Err(err),
-                };
+
+            // The stream stays in the connection while its headers are read, so cancelling the
+            // accept does not lose it.
+            let frame = poll_fn(|cx| {
+                poll(self, cx)?;
+                let (stream, header_timer) = self
+                    .pending_request
+                    .as_mut()
+                    .expect("pending request stream");
+                if let Poll::Ready(frame) = stream.poll_next(cx) {
+                    return Poll::Ready(Ok(Some(frame)));
+                }
+                match header_timer.as_mut() {
+                    Some(timer) => timer.poll_expired(cx).map(|_| Ok(None)),
+                    None => Poll::Pending,
+                }
+            })
+            .await;
+            let (mut stream, _) = self.pending_request.take().expect("pending request stream");
+            let frame = match frame {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "request headers not received in time on {}",
+                        stream.send_id()
+                    );
+
+                    //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+                    //# If a client-initiated
+                    //# stream terminates without enough of the HTTP message to provide a
+                    //# complete response, the server SHOULD abort its response stream with
+                    //# the error code H3_REQUEST_INCOMPLETE.
+                    stream.stop_sending(Code::H3_REQUEST_INCOMPLETE);
+                    stream.reset(Code::H3_REQUEST_INCOMPLETE.value());
+                    self.inner
+                        .shared
+                        .write("request stream cancel")
+                        .qpack
+                        .cancel_stream(stream.send_id());
+                    self.ongoing_streams.remove(&stream.send_id());
+                    return Err(Code::H3_REQUEST_INCOMPLETE.with_reason(
+                        "request headers not received in time",
+                        ErrorLevel::StreamError,
+                    ));
+                }
+                Err(err) => return self.accept_error(err),
+            };
+            match self.accept_with_frame(stream, frame)? {
+                Some(req) => self.pending_resolve = Some(req),
+                None => return Ok(None),
             }
-        };
+        }
+
+        // Headers referencing QPACK entries not received yet wait for the encoder stream,
+        // which the connection reads meanwhile
+        let decoded = poll_fn(|cx| {
+            poll(self, cx)?;
+            let _ = self.poll_control(cx)?;
+            self.pending_resolve
+                .as_mut()
+                .expect("pending request headers")
+                .poll_decode(cx)
+        })
+        .await;
+        let req = self
+            .pending_resolve
+            .take()
+            .expect("pending request headers");
+        if let Err(err) = decoded {
+            return self.accept_error(err);
+        }
+        Ok(Some(req.resolve().await?))
+    }
 
-        let frame = poll_fn(|cx| stream.poll_next(cx)).await;
-        let req = self.accept_with_frame(stream, frame)?;
-        if let Some(req) = req {
-            Ok(Some(req.resolve().await?))
-        } else {
-            Ok(None)
+    /// Ends an accept failing with `err`, closing the connection on connection errors
+    fn accept_error<T>(&mut self, err: Error) -> Result<Option<T>, Error> {
+        match err.inner.kind {
+            crate::error::Kind::Closed => Ok(None),
+            crate::error::Kind::Application {
+                code,
+                reason,
+                level: ErrorLevel::ConnectionError,
+            } => Err(self.inner.close(code, reason.unwrap_or_default())),
+            _ => Err(err),
         }
     }
 
@@ -154,7 +298,7 @@ where
         mut stream: FrameStream<C::BidiStream, B>,
         frame: Result<Option<Frame<PayloadLen>>, FrameStreamError>,
     ) -> Result<Option<ResolveRequest<C, B>>, Error> {
-        let mut encoded = match frame {
+        let encoded = match frame {
             Ok(Some(Frame::Headers(h))) => h,
 
             //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
@@ -188,6 +332,13 @@ where
                 ));
             }
             Err(e) => {
+                // Like a reset, the headers of this stream will not be decoded
+                self.inner
+                    .shared
+                    .write("request stream cancel")
+                    .qpack
+                    .cancel_stream(stream.send_id());
+
                 let err: Error = e.into();
                 if err.is_closed() {
                     return Ok(None);
@@ -217,7 +368,7 @@ where
             }
         };
 
-        let mut request_stream = RequestStream {
+        let request_stream = RequestStream {
             request_end: Arc::new(RequestEnd {
                 request_end: self.request_end_send.clone(),
                 stream_id: stream.send_id(),
@@ -228,52 +379,22 @@ where
                 self.inner.shared.clone(),
                 self.inner.send_grease_frame,
             ),
+            final_response_sent: false,
+            expect_continue: false,
+            sending_continue: false,
+            auto_continue: Some(RequestStream::poll_send_continue),
+            is_head: false,
+            body_timer: self.timeouts.body_idle(),
         };
 
-        let decoded = match qpack::decode_stateless(&mut encoded, self.max_field_section_size) {
-            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-            //# An HTTP/3 implementation MAY impose a limit on the maximum size of
-            //# the message header it will accept on an individual HTTP message.
-            Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => Err(cancel_size),
-            Ok(decoded) => {
-                // send the grease frame only once
-                self.inner.send_grease_frame = false;
-                Ok(decoded)
-            }
-            Err(e) => {
-                let err: Error = e.into();
-                if err.is_closed() {
-                    return Ok(None);
-                }
-                match err.inner.kind {
-                    crate::error::Kind::Closed => return Ok(None),
-                    crate::error::Kind::Application {
-                        code,
-                        reason,
-                        level: ErrorLevel::ConnectionError,
-                    } => {
-                        return Err(self.inner.close(
-                            code,
-                            reason.unwrap_or_else(|| String::into_boxed_str(String::from(""))),
-                        ))
-                    }
-                    crate::error::Kind::Application {
-                        code,
-                        reason: _,
-                        level: ErrorLevel::StreamError,
-                    } => {
-                        request_stream.stop_stream(code);
-                        return Err(err);
-                    }
-                    _ => return Err(err),
-                };
-            }
-        };
+        // send the grease frame only once
+        self.inner.send_grease_frame = false;
 
         Ok(Some(ResolveRequest::new(
             request_stream,
-            decoded,
+            encoded,
             self.max_field_section_size,
+            self.limits,
         )))
     }
 
@@ -290,6 +411,206 @@ where
         self.inner.shutdown(&mut self.sent_closing, max_id).await
     }
 
+    /// Gracefully shuts the connection down, once the application stopped accepting requests
+    ///
+    /// Sends a GOAWAY frame with the largest stream id, so the client stops sending new
+    /// requests, then another one with the id of the last accepted request. The second one is
+    /// sent after the interval set with [`crate::server::Builder::graceful_shutdown_interval()`],
+    /// leaving the client a round trip to receive the first one. Requests the client sent in
+    /// the meantime are accepted, the ones received after the second GOAWAY are rejected with
+    /// `H3_REQUEST_REJECTED`, telling the client they were not processed and can be retried.
+    ///
+    /// The connection keeps running until every accepted [`RequestStream`] is dropped, or until
+    /// `deadline` resolves. It is then closed with `H3_NO_ERROR`, after another interval for
+    /// the last responses to be delivered.
+    ///
+    /// When the shutdown was started with [`Connection::announce_shutdown()`], the first
+    /// GOAWAY frame is not sent again, and the second one is sent once the interval started
+    /// then elapsed.
+    ///
+    /// The intervals are measured with the timer set with [`crate::server::Builder::timer()`].
+    /// Without one, this fails with `H3_INTERNAL_ERROR` before sending anything, and
+    /// [`Connection::shutdown()`] can be used instead.
+    ///
+    /// See [connection shutdown](https://www.rfc-editor.org/rfc/rfc9114.html#connection-shutdown) for more information.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub async fn graceful_shutdown<F>(&mut self, deadline: F) -> Result<ShutdownStats, Error>
+    where
+        F: Future<Output = ()>,
+    {
+        let announced = self.announced.is_some();
+        let mut interval = match self.announced.take() {
+            Some(Announced::Waiting(interval)) => Some(interval),
+            Some(Announced::Elapsed) => None,
+            None => Some(
+                self.timeouts
+                    .shutdown_interval()
+                    .ok_or_else(timer_missing)?,
+            ),
+        };
+
+        // Requests the application is already done with are not counted
+        poll_fn(|cx| {
+            let _ = self.poll_requests_completion(cx);
+            Poll::Ready(())
+        })
+        .await;
+        let mut rejected = 0;
+        // The application stopped accepting before the headers of this request were read
+        if let Some((mut stream, _)) = self.pending_request.take() {
+            stream.stop_sending(Code::H3_REQUEST_REJECTED);
+            stream.reset(Code::H3_REQUEST_REJECTED.value());
+            self.ongoing_streams.remove(&stream.send_id());
+            rejected += 1;
+        }
+        if let Some(req) = self.pending_resolve.take() {
+            let stream_id = req.reject_undecoded(Code::H3_REQUEST_REJECTED);
+            self.ongoing_streams.remove(&stream_id);
+            rejected += 1;
+        }
+        let accepted = self.ongoing_streams.len();
+        let mut deadline = pin!(deadline);
+
+        if !announced {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-5.2
+            //# An endpoint that is attempting to gracefully shut down a connection
+            //# can send a GOAWAY frame with a value set to the maximum possible
+            //# value (2^62-4 for servers, 2^62-1 for clients).
+            self.inner
+                .shutdown(&mut self.sent_closing, StreamId::LAST_REQUEST)
+                .await?;
+        }
+
+        let mut done = poll_fn(|cx| {
+            if let Poll::Ready(done) = self.poll_reject_requests(cx, &mut rejected) {
+                return Poll::Ready(done);
+            }
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Ok(true));
+            }
+            match interval.as_mut() {
+                Some(interval) => interval.as_mut().poll(cx).map(|()| Ok(false)),
+                None => Poll::Ready(Ok(false)),
+            }
+        })
+        .await;
+
+        if let Ok(false) = done {
+            if let Err(err) = self.shutdown(0).await {
+                done = Err(err);
+            }
+        }
+        if let Ok(false) = done {
+            let mut linger = None;
+            done = poll_fn(|cx| {
+                if let Poll::Ready(done) = self.poll_reject_requests(cx, &mut rejected) {
+                    return Poll::Ready(done);
+                }
+                if deadline.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Ok(true));
+                }
+                if self.poll_requests_completion(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                // Closing a QUIC connection discards the data not sent yet, so the last
+                // responses are given a round trip to be delivered
+                linger
+                    .get_or_insert_with(|| {
+                        self.timeouts
+                            .shutdown_interval()
+                            .expect("graceful shutdown timer")
+                    })
+                    .as_mut()
+                    .poll(cx)
+                    .map(|()| Ok(true))
+            })
+            .await;
+        }
+
+        let cancelled = self.ongoing_streams.len();
+        let stats = ShutdownStats {
+            completed: accepted.saturating_sub(cancelled),
+            cancelled,
+            rejected,
+        };
+
+        #[cfg(feature = "tracing")]
+        trace!("graceful shutdown done: {:?}", stats);
+
+        self.inner.close(Code::H3_NO_ERROR, "");
+        done.map(|_| stats)
+    }
+
+    /// Starts a graceful shutdown, still accepting the requests sent in the meantime
+    ///
+    /// Sends a GOAWAY frame with the largest stream id, so the client stops sending new
+    /// requests. [`Connection::accept()`] keeps returning the requests the client sent before
+    /// receiving it, until the interval set with
+    /// [`crate::server::Builder::graceful_shutdown_interval()`] elapses. It then sends the
+    /// GOAWAY frame with the id of the last accepted request and returns `None`, after which
+    /// [`Connection::graceful_shutdown()`] drains the accepted requests.
+    ///
+    /// Like [`Connection::graceful_shutdown()`], this fails with `H3_INTERNAL_ERROR` when no
+    /// timer is configured.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
+    pub async fn announce_shutdown(&mut self) -> Result<(), Error> {
+        if self.announced.is_some() {
+            return Ok(());
+        }
+        let interval = self
+            .timeouts
+            .shutdown_interval()
+            .ok_or_else(timer_missing)?;
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-5.2
+        //# An endpoint that is attempting to gracefully shut down a connection
+        //# can send a GOAWAY frame with a value set to the maximum possible
+        //# value (2^62-4 for servers, 2^62-1 for clients).
+        self.inner
+            .shutdown(&mut self.sent_closing, StreamId::LAST_REQUEST)
+            .await?;
+        self.announced = Some(Announced::Waiting(interval));
+        Ok(())
+    }
+
+    /// Polls for the end of the interval started by `announce_shutdown()`
+    fn poll_announced(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match &mut self.announced {
+            Some(Announced::Waiting(interval)) => {
+                ready!(interval.as_mut().poll(cx));
+                self.announced = Some(Announced::Elapsed);
+                Poll::Ready(())
+            }
+            Some(Announced::Elapsed) => Poll::Ready(()),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Rejects the requests received during a graceful shutdown
+    ///
+    /// Returns `true` once the client closed the connection, or is shutting it down as well.
+    fn poll_reject_requests(
+        &mut self,
+        cx: &mut Context<'_>,
+        rejected: &mut usize,
+    ) -> Poll<Result<bool, Error>> {
+        loop {
+            match self.poll_accept_request_stream(cx) {
+                // Sent before the last GOAWAY was received, but the application is done
+                Poll::Ready(Ok(Some(mut s))) => {
+                    s.stop_sending(Code::H3_REQUEST_REJECTED.value());
+                    s.reset(Code::H3_REQUEST_REJECTED.value());
+                    self.ongoing_streams.remove(&s.send_id());
+                    *rejected += 1;
+                }
+                Poll::Ready(Ok(None)) => return Poll::Ready(Ok(true)),
+                Poll::Ready(Err(err)) if err.is_closed() => return Poll::Ready(Ok(true)),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
     /// Accepts an incoming bidirectional stream.
     ///
     /// This could be either a *Request* or a *WebTransportBiStream*, the first frame's type
@@ -312,6 +633,16 @@ where
                     };
 
                     if done {
+                        Poll::Ready(Ok(None))
+                    } else if conn.is_pending() && self.poll_announced(cx).is_ready() {
+                        #[cfg(feature = "tracing")]
+                        trace!("announced shutdown interval elapsed");
+
+                        Poll::Ready(Ok(None))
+                    } else if conn.is_pending() && self.poll_idle_timeout(cx).is_ready() {
+                        #[cfg(feature = "tracing")]
+                        trace!("connection idle timeout, starting graceful shutdown");
+
                         Poll::Ready(Ok(None))
                     } else {
                         // Wait for all the requests to be finished, request_end_recv will wake
@@ -333,6 +664,24 @@ where
                             continue;
                         }
                     }
+                    // Reject requests beyond the concurrency limit before any processing, so
+                    // the client knows it can retry them.
+                    if let Some(max) = self.limits.max_concurrent_requests {
+                        if self.ongoing_streams.len() >= max {
+                            #[cfg(feature = "tracing")]
+                            warn!(
+                                "rejecting request {}: too many concurrent requests",
+                                s.send_id()
+                            );
+
+                            s.stop_sending(Code::H3_REQUEST_REJECTED.value());
+                            s.reset(Code::H3_REQUEST_REJECTED.value());
+                            continue;
+                        }
+                    }
+                    if let Some(timer) = self.idle_timer.as_mut() {
+                        timer.reset();
+                    }
                     self.last_accepted_stream = Some(s.send_id());
                     self.ongoing_streams.insert(s.send_id());
                     Poll::Ready(Ok(Some(s)))
@@ -393,6 +742,18 @@ where
         Poll::Ready(Ok(frame))
     }
 
+    /// Polls the idle timeout, which only runs while there is no ongoing request
+    fn poll_idle_timeout(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(timer) = self.idle_timer.as_mut() else {
+            return Poll::Pending;
+        };
+        if !self.ongoing_streams.is_empty() || self.sent_closing.is_some() {
+            timer.reset();
+            return Poll::Pending;
+        }
+        timer.poll_expired(cx)
+    }
+
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     fn poll_requests_completion(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         loop {
@@ -441,6 +802,39 @@ where
 //# parallelism, at least 100 request streams SHOULD be permitted at a
 //# time.
 
+/// Error of the shutdowns which need a timer to measure their intervals
+fn timer_missing() -> Error {
+    Code::H3_INTERNAL_ERROR.with_reason(
+        "graceful shutdown needs a timer, see Builder::timer()",
+        ErrorLevel::ConnectionError,
+    )
+}
+
+/// Outcome of [`Connection::graceful_shutdown()`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownStats {
+    completed: usize,
+    cancelled: usize,
+    rejected: usize,
+}
+
+impl ShutdownStats {
+    /// The number of accepted requests which completed before the connection was closed
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    /// The number of accepted requests still running when the deadline expired
+    pub fn cancelled(&self) -> usize {
+        self.cancelled
+    }
+
+    /// The number of requests received during the shutdown and rejected
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+}
+
 pub(super) struct RequestEnd {
     pub(super) request_end: mpsc::UnboundedSender<StreamId>,
     pub(super) stream_id: StreamId,
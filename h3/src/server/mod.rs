@@ -57,5 +57,6 @@ mod stream;
 
 pub use builder::builder;
 pub use builder::Builder;
-pub use connection::Connection;
+pub use connection::{Connection, ShutdownStats};
+pub use request::ResolveRequest;
 pub use stream::RequestStream;
@@ -1,41 +1,150 @@
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    future::poll_fn,
+    task::{ready, Context, Poll},
+};
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 use http::{Request, StatusCode};
 
 #[cfg(feature = "tracing")]
 use tracing::instrument;
 
-use crate::{error::Code, proto::headers::Header, qpack, quic, Error};
+use crate::{
+    error::{Code, ErrorLevel, Kind},
+    proto::headers::{content_length, Header},
+    qpack, quic, Error,
+};
 
-use super::stream::RequestStream;
+use super::{builder::RequestLimits, stream::RequestStream};
 
+/// A request whose headers have been received, but not validated yet
+///
+/// Returned by [`super::Connection::accept_with_frame()`], use [`ResolveRequest::resolve()`]
+/// to get the request.
 pub struct ResolveRequest<C: quic::Connection<B>, B: Buf> {
     request_stream: RequestStream<C::BidiStream, B>,
+    // The request headers, decoded once the QPACK entries they reference are received
+    encoded: Bytes,
     // Ok or `REQUEST_HEADER_FIELDS_TO_LARGE` which needs to be sent
-    decoded: Result<qpack::Decoded, u64>,
+    decoded: Option<Result<qpack::Decoded, u64>>,
     max_field_section_size: u64,
+    limits: RequestLimits,
 }
 
 impl<B: Buf, C: quic::Connection<B>> ResolveRequest<C, B> {
-    pub fn new(
+    pub(super) fn new(
         request_stream: RequestStream<C::BidiStream, B>,
-        decoded: Result<qpack::Decoded, u64>,
+        encoded: Bytes,
         max_field_section_size: u64,
+        limits: RequestLimits,
     ) -> Self {
         Self {
             request_stream,
-            decoded,
+            encoded,
+            decoded: None,
             max_field_section_size,
+            limits,
         }
     }
 
+    /// Decodes the request headers, once the QPACK entries they reference are received
+    ///
+    /// The connection must be polled meanwhile, it reads the entries from the encoder stream.
+    /// [`ResolveRequest::resolve()`] does not wait once this is ready. The request stream is
+    /// reset when the headers fail to decode with a stream error.
+    pub fn poll_decode(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.decoded.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let decoded = ready!(self
+            .request_stream
+            .inner
+            .conn_state
+            .write("request headers decode")
+            .qpack
+            .poll_decode(
+                cx,
+                self.request_stream.send_id(),
+                &self.encoded,
+                self.max_field_section_size,
+            ));
+        self.decoded = Some(match decoded {
+            //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
+            //# An HTTP/3 implementation MAY impose a limit on the maximum size of
+            //# the message header it will accept on an individual HTTP message.
+            Err(qpack::DecoderError::HeaderTooLong(cancel_size)) => Err(cancel_size),
+            Ok(decoded) => Ok(decoded),
+            Err(e) => {
+                let err: Error = e.into();
+                if let Kind::Application {
+                    code,
+                    level: ErrorLevel::StreamError,
+                    ..
+                } = err.inner.kind
+                {
+                    self.cancel(code);
+                }
+                return Poll::Ready(Err(err));
+            }
+        });
+        Poll::Ready(Ok(()))
+    }
+
+    /// Rejects the request before its headers are decoded, returning its stream id
+    pub(super) fn reject_undecoded(mut self, code: Code) -> quic::StreamId {
+        self.cancel(code)
+    }
+
+    fn cancel(&mut self, code: Code) -> quic::StreamId {
+        let stream_id = self.request_stream.send_id();
+        self.request_stream
+            .inner
+            .conn_state
+            .write("request stream cancel")
+            .qpack
+            .cancel_stream(stream_id);
+        self.request_stream.stop_sending(code);
+        self.request_stream.stop_stream(code);
+        stream_id
+    }
+
+    /// Answers a request exceeding a limit, without waiting for the rest of it
+    ///
+    /// The returned error carries the status of the response, the stream is not reset.
+    async fn reject(
+        request_stream: &mut RequestStream<C::BidiStream, B>,
+        status: StatusCode,
+        reason: String,
+    ) -> Error {
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1
+        //# When the server does not need to receive the remainder of the
+        //# request, it MAY abort reading the request stream, send a complete
+        //# response, and cleanly close the sending part of the stream.
+        request_stream.stop_sending(Code::H3_NO_ERROR);
+
+        let response = http::Response::builder()
+            .status(status)
+            .body(())
+            .expect("request rejection response");
+        if let Err(e) = request_stream.send_response(response).await {
+            return e;
+        }
+        if let Err(e) = request_stream.finish().await {
+            return e;
+        }
+
+        Error::rejected(status, reason)
+    }
+
     /// Finishes the resolution of the request
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn resolve(
         mut self,
     ) -> Result<(Request<()>, RequestStream<C::BidiStream, B>), Error> {
-        let fields = match self.decoded {
+        poll_fn(|cx| self.poll_decode(cx)).await?;
+        let fields = match self.decoded.take().expect("decoded request headers") {
             Ok(v) => v.fields,
             Err(cancel_size) => {
                 // Send and await the error response
@@ -55,6 +164,14 @@ impl<B: Buf, C: quic::Connection<B>> ResolveRequest<C, B> {
             }
         };
 
+        if let Some(max) = self.limits.max_header_fields {
+            if fields.len() > max {
+                let reason = format!("{} header fields exceed the limit of {}", fields.len(), max);
+                let status = StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+                return Err(Self::reject(&mut self.request_stream, status, reason).await);
+            }
+        }
+
         // Parse the request headers
         let (method, uri, protocol, headers) = match Header::try_from(fields) {
             Ok(header) => match header.into_request_parts() {
@@ -80,6 +197,38 @@ impl<B: Buf, C: quic::Connection<B>> ResolveRequest<C, B> {
             }
         };
 
+        self.request_stream.inner.recv_content_length = match content_length(&headers) {
+            Ok(len) => len,
+            Err(err) => {
+                //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+                //# Malformed requests or responses that are
+                //# detected MUST be treated as a stream error of type H3_MESSAGE_ERROR.
+                let error: Error = err.into();
+                self.request_stream
+                    .stop_stream(error.try_get_code().unwrap_or(Code::H3_MESSAGE_ERROR));
+                return Err(error);
+            }
+        };
+
+        if let (Some(len), Some(max)) = (
+            self.request_stream.inner.recv_content_length,
+            self.limits.max_request_body_size,
+        ) {
+            if len > max {
+                let reason = format!("content-length {} exceeds the limit of {}", len, max);
+                let status = StatusCode::PAYLOAD_TOO_LARGE;
+                return Err(Self::reject(&mut self.request_stream, status, reason).await);
+            }
+        }
+        self.request_stream.inner.max_body_size = self.limits.max_request_body_size;
+        self.request_stream.inner.max_trailers_size = self.limits.max_trailers_size;
+        self.request_stream.inner.max_header_fields = self.limits.max_header_fields;
+
+        self.request_stream.is_head = method == http::Method::HEAD;
+        self.request_stream.expect_continue = headers
+            .get(http::header::EXPECT)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"));
+
         //  request_stream.stop_stream(Code::H3_MESSAGE_ERROR).await;
         let mut req = http::Request::new(());
         *req.method_mut() = method;
@@ -14,20 +14,23 @@ use std::sync::Arc;
 use std::{
     option::Option,
     result::Result,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
 };
 
 use bytes::BytesMut;
 use futures_util::future;
-use http::{response, HeaderMap, Response};
+use http::{response, HeaderMap, Response, StatusCode};
 
 use quic::StreamId;
 
 use crate::{
-    error::Code,
-    proto::{frame::Frame, headers::Header},
-    qpack,
+    error::{Code, ErrorLevel},
+    proto::{
+        frame::Frame,
+        headers::{content_length, Header},
+    },
     quic::SendStream as _,
+    rt::IdleTimer,
     stream::{self},
 };
 
@@ -42,8 +45,23 @@ use tracing::{error, instrument};
 pub struct RequestStream<S, B> {
     pub(super) inner: crate::connection::RequestStream<S, B>,
     pub(super) request_end: Arc<RequestEnd>,
+    // Has the final (non 1xx) response been sent?
+    pub(super) final_response_sent: bool,
+    // Did the client send `expect: 100-continue`, with no `100 Continue` sent yet?
+    pub(super) expect_continue: bool,
+    // Is a `100 Continue` written to the stream, but not flushed yet?
+    pub(super) sending_continue: bool,
+    // Sends the `100 Continue` on the first body read, when the stream has a send side
+    pub(super) auto_continue: Option<PollContinue<S, B>>,
+    // Is this a HEAD request, whose response has no content?
+    pub(super) is_head: bool,
+    // Bounds the time waiting for the next piece of the request body
+    pub(super) body_timer: Option<IdleTimer>,
 }
 
+pub(super) type PollContinue<S, B> =
+    fn(&mut RequestStream<S, B>, &mut Context<'_>) -> Poll<Result<(), Error>>;
+
 impl<S, B> AsMut<crate::connection::RequestStream<S, B>> for RequestStream<S, B> {
     fn as_mut(&mut self) -> &mut crate::connection::RequestStream<S, B> {
         &mut self.inner
@@ -64,7 +82,7 @@ where
     /// Receive data sent from the client
     #[cfg_attr(feature = "tracing", instrument(skip_all, level = "trace"))]
     pub async fn recv_data(&mut self) -> Result<Option<impl Buf>, Error> {
-        self.inner.recv_data().await
+        future::poll_fn(|cx| self.poll_recv_data(cx)).await
     }
 
     /// Poll for data sent from the client
@@ -73,7 +91,12 @@ where
         &mut self,
         cx: &mut Context<'_>,
     ) -> Poll<Result<Option<impl Buf>, Error>> {
-        self.inner.poll_recv_data(cx)
+        if let Some(poll_continue) = self.auto_continue {
+            ready!(poll_continue(self, cx))?;
+        }
+        let poll = self.inner.poll_recv_data(cx);
+        self.poll_body_timeout(cx, poll.is_pending())?;
+        poll
     }
 
     /// Receive an optional set of trailers for the request
@@ -88,7 +111,31 @@ where
         &mut self,
         cx: &mut Context<'_>,
     ) -> Poll<Result<Option<HeaderMap>, Error>> {
-        self.inner.poll_recv_trailers(cx)
+        let poll = self.inner.poll_recv_trailers(cx);
+        self.poll_body_timeout(cx, poll.is_pending())?;
+        poll
+    }
+
+    /// Fails when the client has not made progress on the request body in time
+    fn poll_body_timeout(&mut self, cx: &mut Context<'_>, pending: bool) -> Result<(), Error> {
+        let Some(timer) = self.body_timer.as_mut() else {
+            return Ok(());
+        };
+        if !pending {
+            timer.reset();
+            return Ok(());
+        }
+        if timer.poll_expired(cx).is_pending() {
+            return Ok(());
+        }
+
+        let duration = timer.duration();
+        timer.reset();
+        self.inner.stream.stop_sending(Code::H3_REQUEST_INCOMPLETE);
+        Err(Code::H3_REQUEST_INCOMPLETE.with_reason(
+            format!("no request body received for {:?}", duration),
+            ErrorLevel::StreamError,
+        ))
     }
 
     /// Tell the peer to stop sending into the underlying QUIC stream
@@ -111,40 +158,139 @@ where
     /// Send the HTTP/3 response
     ///
     /// This should be called before trying to send any data with
-    /// [`RequestStream::send_data`].
+    /// [`RequestStream::send_data`]. Only one final response can be sent, informational
+    /// (1xx) responses are sent with [`RequestStream::send_informational`].
     pub async fn send_response(&mut self, resp: Response<()>) -> Result<(), Error> {
         let (parts, _) = resp.into_parts();
         let response::Parts {
             status, headers, ..
         } = parts;
-        let headers = Header::response(status, headers);
 
-        let mut block = BytesMut::new();
-        let mem_size = qpack::encode_stateless(&mut block, headers)?;
+        if self.final_response_sent {
+            return Err(Code::H3_MESSAGE_ERROR
+                .with_reason("final response already sent", ErrorLevel::StreamError));
+        }
+        if status.is_informational() {
+            return Err(Code::H3_MESSAGE_ERROR.with_reason(
+                format!(
+                    "{} is not a final status, use send_informational() instead",
+                    status
+                ),
+                ErrorLevel::StreamError,
+            ));
+        }
+        let content_length = content_length(&headers)?;
 
-        let max_mem_size = self
-            .inner
-            .conn_state
-            .read("send_response")
-            .peer_config
-            .max_field_section_size;
-
-        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.2.2
-        //# An implementation that
-        //# has received this parameter SHOULD NOT send an HTTP message header
-        //# that exceeds the indicated size, as the peer will likely refuse to
-        //# process it.
-        if mem_size > max_mem_size {
-            return Err(Error::header_too_big(mem_size, max_mem_size));
+        self.send_response_headers(status, headers).await?;
+        // Like on the client side, these responses have no content whatever their
+        // content-length says
+        if !self.is_head && status != StatusCode::NO_CONTENT && status != StatusCode::NOT_MODIFIED {
+            self.inner.send_content_length = content_length;
         }
+        self.final_response_sent = true;
 
-        stream::write(&mut self.inner.stream, Frame::Headers(block.freeze()))
+        Ok(())
+    }
+
+    /// Send an informational (1xx) response, like 103 Early Hints
+    ///
+    /// This can be called any number of times, but only before the final response is sent
+    /// with [`RequestStream::send_response`].
+    pub async fn send_informational(&mut self, resp: Response<()>) -> Result<(), Error> {
+        let (parts, _) = resp.into_parts();
+        let response::Parts {
+            status, headers, ..
+        } = parts;
+
+        if self.final_response_sent {
+            return Err(Code::H3_MESSAGE_ERROR.with_reason(
+                "informational response after the final response",
+                ErrorLevel::StreamError,
+            ));
+        }
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.5
+        //# HTTP/3 does not support the HTTP Upgrade mechanism (Section 7.8 of
+        //# [HTTP]) or the 101 (Switching Protocols) informational status code
+        //# (Section 15.2.2 of [HTTP]).
+        if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+            return Err(Code::H3_MESSAGE_ERROR.with_reason(
+                format!("{} is not a valid informational status", status),
+                ErrorLevel::StreamError,
+            ));
+        }
+
+        self.send_response_headers(status, headers).await?;
+        if status == StatusCode::CONTINUE {
+            self.expect_continue = false;
+        }
+
+        Ok(())
+    }
+
+    /// Send a `100 Continue` if the client is waiting for one
+    ///
+    /// A client sending `expect: 100-continue` holds its request body back until the server
+    /// answers. Like hyper does for HTTP/1, [`RequestStream::recv_data`] sends it on the first
+    /// body read, so this is only needed to let the body through early, or on the send half
+    /// of a [split](RequestStream::split) stream. Sending a final response instead rejects
+    /// the body without it ever being transferred.
+    ///
+    /// Does nothing if the client did not ask for it, or if a `100 Continue` or the final
+    /// response has already been sent.
+    pub async fn send_continue(&mut self) -> Result<(), Error> {
+        future::poll_fn(|cx| self.poll_send_continue(cx)).await
+    }
+
+    pub(super) fn poll_send_continue(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if !self.sending_continue {
+            if !self.expect_continue || self.final_response_sent {
+                return Poll::Ready(Ok(()));
+            }
+            let frame = self.response_headers_frame(StatusCode::CONTINUE, HeaderMap::new())?;
+            self.inner
+                .stream
+                .send_data(frame)
+                .map_err(|e| self.maybe_conn_err(e))?;
+            self.sending_continue = true;
+        }
+
+        ready!(self.inner.stream.poll_ready(cx)).map_err(|e| self.maybe_conn_err(e))?;
+        self.sending_continue = false;
+        self.expect_continue = false;
+
+        Poll::Ready(Ok(()))
+    }
+
+    async fn send_response_headers(
+        &mut self,
+        status: StatusCode,
+        headers: HeaderMap,
+    ) -> Result<(), Error> {
+        let frame = self.response_headers_frame(status, headers)?;
+        stream::write(&mut self.inner.stream, frame)
             .await
             .map_err(|e| self.maybe_conn_err(e))?;
 
         Ok(())
     }
 
+    fn response_headers_frame(
+        &self,
+        status: StatusCode,
+        headers: HeaderMap,
+    ) -> Result<Frame<B>, Error> {
+        let headers = Header::response(status, headers);
+
+        let mut block = BytesMut::new();
+        self.inner
+            .conn_state
+            .write("send_response")
+            .encode_headers(self.inner.stream.send_id(), &mut block, headers)?;
+
+        Ok(Frame::Headers(block.freeze()))
+    }
+
     /// Send some data on the response body.
     pub async fn send_data(&mut self, buf: B) -> Result<(), Error> {
         self.inner.send_data(buf).await
@@ -203,10 +349,22 @@ where
             RequestStream {
                 inner: send,
                 request_end: self.request_end.clone(),
+                final_response_sent: self.final_response_sent,
+                expect_continue: self.expect_continue,
+                sending_continue: self.sending_continue,
+                auto_continue: None,
+                is_head: self.is_head,
+                body_timer: None,
             },
             RequestStream {
                 inner: recv,
                 request_end: self.request_end,
+                final_response_sent: self.final_response_sent,
+                expect_continue: false,
+                sending_continue: false,
+                auto_continue: None,
+                is_head: self.is_head,
+                body_timer: self.body_timer,
             },
         )
     }
@@ -4,7 +4,7 @@ use std::{
     task::{Context, Poll},
 };
 
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures_util::{future, ready};
 use pin_project_lite::pin_project;
 use tokio::io::ReadBuf;
@@ -19,7 +19,7 @@ use crate::{
         stream::StreamType,
         varint::VarInt,
     },
-    quic::{self, BidiStream, RecvStream, SendStream, SendStreamUnframed},
+    quic::{self, BidiStream, RecvStream, SendStream, SendStreamPriority, SendStreamUnframed},
     webtransport::SessionId,
     Error,
 };
@@ -38,7 +38,16 @@ where
     Ok(())
 }
 
-const WRITE_BUF_ENCODE_SIZE: usize = StreamType::MAX_ENCODED_SIZE + Frame::MAX_ENCODED_SIZE;
+const WRITE_BUF_ENCODE_SIZE: usize = {
+    let frame = StreamType::MAX_ENCODED_SIZE + Frame::MAX_ENCODED_SIZE;
+    // The control stream header carries a whole SETTINGS frame
+    let control = StreamType::MAX_ENCODED_SIZE + 2 * VarInt::MAX_SIZE + Settings::MAX_ENCODED_SIZE;
+    if frame > control {
+        frame
+    } else {
+        control
+    }
+};
 
 /// Wrap frames to encode their header on the stack before sending them on the wire
 ///
@@ -61,6 +70,22 @@ impl<B> WriteBuf<B>
 where
     B: Buf,
 {
+    /// Takes the beginning of `data`, as much as the buffer holds
+    ///
+    /// For the instructions sent on the QPACK encoder and decoder streams, which are not
+    /// framed.
+    pub(crate) fn split_from(data: &mut BytesMut) -> Self {
+        let len = data.len().min(WRITE_BUF_ENCODE_SIZE);
+        let mut this = Self {
+            buf: [0; WRITE_BUF_ENCODE_SIZE],
+            len,
+            pos: 0,
+            frame: None,
+        };
+        this.buf[..len].copy_from_slice(&data.split_to(len));
+        this
+    }
+
     fn encode_stream_type(&mut self, ty: StreamType) {
         let mut buf_mut = &mut self.buf[self.len..];
 
@@ -331,27 +356,24 @@ where
                 None => (),
             };
 
-            if ready!(self.stream.poll_read(cx))? {
-                return Poll::Ready(Err(Code::H3_STREAM_CREATION_ERROR.with_reason(
-                    "Stream closed before type received",
-                    ErrorLevel::ConnectionError,
-                )));
-            };
-
-            let mut buf = self.stream.buf_mut();
+            let buf = self.stream.buf_mut();
             if self.expected.is_none() && buf.remaining() >= 1 {
                 self.expected = Some(VarInt::encoded_size(buf.chunk()[0]));
             }
 
-            if let Some(expected) = self.expected {
-                // Poll for more data
-                if buf.remaining() < expected {
-                    continue;
-                }
-            } else {
+            // Poll for more data, unless the next varint is already buffered
+            if !matches!(self.expected, Some(expected) if buf.remaining() >= expected) {
+                if ready!(self.stream.poll_read(cx))? {
+                    return Poll::Ready(Err(Code::H3_STREAM_CREATION_ERROR.with_reason(
+                        "Stream closed before type received",
+                        ErrorLevel::ConnectionError,
+                    )));
+                };
                 continue;
             }
 
+            let mut buf = self.stream.buf_mut();
+
             // Parse ty and then id
             if self.ty.is_none() {
                 // Parse StreamType
@@ -521,6 +543,20 @@ where
     }
 }
 
+impl<S, B> SendStreamPriority<B> for BufRecvStream<S, B>
+where
+    B: Buf,
+    S: SendStreamPriority<B>,
+{
+    fn set_priority(&mut self, priority: i32) -> Result<(), Self::Error> {
+        self.stream.set_priority(priority)
+    }
+
+    fn priority(&self) -> Result<i32, Self::Error> {
+        self.stream.priority()
+    }
+}
+
 impl<S, B> SendStreamUnframed<B> for BufRecvStream<S, B>
 where
     B: Buf,
@@ -688,9 +724,13 @@ where
 
 #[cfg(test)]
 mod tests {
+    use assert_matches::assert_matches;
+    use bytes::BytesMut;
+    use futures_util::future;
     use quinn_proto::coding::BufExt;
 
     use super::*;
+    use crate::{frame::tests::FakeRecv, proto::frame::SettingId};
 
     #[test]
     fn write_wt_uni_header() {
@@ -706,6 +746,45 @@ mod tests {
         println!("Got id: {id}");
     }
 
+    #[test]
+    fn write_control_header_with_full_settings() {
+        let mut settings = Settings::default();
+        let mut id = VarInt::MAX.0;
+        while settings.insert(SettingId(id), VarInt::MAX.0).is_ok() {
+            id -= 1;
+        }
+
+        let mut w = WriteBuf::<Bytes>::from(UniStreamHeader::Control(settings));
+        assert_eq!(w.get_var().unwrap(), 0);
+
+        // Frame type, length, then every identifier and value on 8 bytes
+        assert_eq!(w.remaining(), 1 + 2 + Settings::MAX_ENCODED_SIZE);
+        let mut buf = w.copy_to_bytes(w.remaining());
+        assert_matches!(Frame::decode(&mut buf), Ok(Frame::Settings(_)));
+        assert!(!buf.has_remaining());
+    }
+
+    #[tokio::test]
+    async fn accept_wt_uni_header_and_fin_in_one_chunk() {
+        let mut buf = BytesMut::new();
+        StreamType::WEBTRANSPORT_UNI.encode(&mut buf);
+        VarInt::from_u32(4).encode(&mut buf);
+
+        let mut recv = FakeRecv::default();
+        recv.chunk(buf.freeze());
+        let mut stream = AcceptRecvStream::<_, Bytes>::new(recv);
+
+        future::poll_fn(|cx| stream.poll_type(cx))
+            .await
+            .expect("stream type");
+        match stream.into_stream() {
+            Ok(AcceptedRecvStream::WebTransportUni(id, _)) => {
+                assert_eq!(id, SessionId::from_varint(VarInt(4)))
+            }
+            _ => panic!("expected a webtransport stream"),
+        }
+    }
+
     #[test]
     fn write_buf_encode_streamtype() {
         let wbuf = WriteBuf::<Bytes>::from(StreamType::ENCODER);
@@ -25,7 +25,7 @@ use crate::{
 };
 
 use super::h3_quinn;
-use super::{init_tracing, Pair};
+use super::{init_tracing, Pair, TokioTimer};
 
 #[tokio::test]
 async fn connect() {
@@ -593,6 +593,52 @@ async fn goaway_from_server_not_request_id() {
     tokio::select! { _ = server_fut => panic!("client resolved first"), _ = client_fut => () };
 }
 
+#[tokio::test]
+async fn connection_idle_timeout() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+        let mut request_stream = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        request_stream.finish().await.unwrap();
+        let response = request_stream.recv_response().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .connection_idle_timeout(Duration::from_millis(100))
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming.accept().await.unwrap().unwrap();
+        // The timeout does not run while a request is ongoing
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .unwrap();
+        request_stream.finish().await.unwrap();
+        drop(request_stream);
+
+        let start = tokio::time::Instant::now();
+        assert!(incoming.accept().await.unwrap().is_none());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 #[tokio::test]
 async fn graceful_shutdown_server_rejects() {
     init_tracing();
@@ -625,7 +671,11 @@ async fn graceful_shutdown_server_rejects() {
 
     let server_fut = async {
         let conn = server.next().await;
-        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
         let (_, stream) = incoming.accept().await.unwrap().unwrap();
         response(stream).await;
         incoming.shutdown(0).await.unwrap();
@@ -766,6 +816,362 @@ async fn graceful_shutdown_client() {
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn graceful_shutdown_client_waits_for_in_flight() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+
+        let mut in_flight = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(driver.in_flight_requests(), 1);
+
+        let complete = async move {
+            assert_matches!(in_flight.recv_response().await, Ok(_));
+            drop(in_flight);
+            assert_matches!(
+                request(send_request).await.unwrap_err().kind(),
+                Kind::Closing
+            );
+        };
+        let shutdown = driver.graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)));
+
+        let (report, ()) = tokio::join!(shutdown, complete);
+        assert!(report.unwrap().is_complete());
+        assert_eq!(driver.in_flight_requests(), 0);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let (_, stream) = incoming.accept().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        response(stream).await;
+        assert!(incoming.accept().await.unwrap().is_none());
+        server.endpoint.wait_idle().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_client_deadline() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut send_request) = client::new(pair.client().await).await.unwrap();
+
+        let mut aborted = send_request
+            .send_request(Request::get("http://no.way").body(()).unwrap())
+            .await
+            .unwrap();
+        let id = aborted.id();
+
+        let report = driver
+            .graceful_shutdown(tokio::time::sleep(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        assert_eq!(report.aborted(), &[id]);
+        assert_matches!(aborted.recv_response().await, Err(_));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        // Never respond to the request
+        let (_, _stream) = incoming.accept().await.unwrap().unwrap();
+        let _ = incoming.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_drains_requests() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        // The connection is closed once both requests are done
+        let first = request(send_request.clone());
+        let second = request(send_request);
+        let driver = future::poll_fn(|cx| driver.poll_close(cx));
+
+        let (first, second, _) = tokio::join!(first, second, driver);
+        assert_matches!(first, Ok(_));
+        assert_matches!(second, Ok(_));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+        let (_, first) = incoming.accept().await.unwrap().unwrap();
+        let (_, second) = incoming.accept().await.unwrap().unwrap();
+
+        let respond = async {
+            response(first).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            response(second).await;
+        };
+        let shutdown = incoming.graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)));
+
+        let (stats, ()) = tokio::join!(shutdown, respond);
+        let stats = stats.unwrap();
+        assert_eq!(stats.completed(), 2);
+        assert_eq!(stats.cancelled(), 0);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_deadline() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        let first = request(send_request.clone());
+        let second = request(send_request.clone());
+        let driver = future::poll_fn(|cx| driver.poll_close(cx));
+
+        let (first, second, _) = tokio::join!(first, second, driver);
+        assert_matches!(first, Ok(_));
+        assert_matches!(second, Err(_));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+        let (_, first) = incoming.accept().await.unwrap().unwrap();
+        let (_, _never_answered) = incoming.accept().await.unwrap().unwrap();
+        response(first).await;
+
+        let stats = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        // The first request was done before the shutdown
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.cancelled(), 1);
+        assert_eq!(stats.rejected(), 0);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_interval() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+
+    let client_fut = async {
+        // The client keeps the connection open, the server does not wait for it
+        let (_driver, _send_request) = client::new(pair.client().await).await.unwrap();
+        let _ = done_rx.await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .graceful_shutdown_interval(Duration::from_millis(50))
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+
+        let start = tokio::time::Instant::now();
+        let stats = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        // Between the GOAWAY frames, then before closing
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_secs(5));
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.cancelled(), 0);
+        done_tx.send(()).unwrap();
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_ignores_finished_requests() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        let first = request(send_request);
+        let driver = future::poll_fn(|cx| driver.poll_close(cx));
+
+        let (first, _) = tokio::join!(first, driver);
+        assert_matches!(first, Ok(_));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+        let (_, first) = incoming.accept().await.unwrap().unwrap();
+        // Done before the shutdown starts
+        response(first).await;
+
+        let stats = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.cancelled(), 0);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_rejects_request_of_cancelled_accept() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        // Only the type of the HEADERS frame
+        send.write_all(&[0x01]).await.unwrap();
+
+        let mut buf = [0; 1];
+        assert_matches!(
+            recv.read(&mut buf).await,
+            Err(quinn::ReadError::Reset(code)) if Code::H3_REQUEST_REJECTED == code.into_inner()
+        );
+        connection.close(Code::H3_NO_ERROR.value().try_into().unwrap(), b"");
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+        // Cancelled while the headers are being read
+        tokio::select! {
+            _ = incoming.accept() => panic!("request accepted without headers"),
+            _ = tokio::time::sleep(Duration::from_millis(100)) => (),
+        }
+
+        let stats = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(stats.rejected(), 1);
+        assert_eq!(stats.completed(), 0);
+        assert_eq!(stats.cancelled(), 0);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_announced() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (announced_tx, announced_rx) = oneshot::channel::<()>();
+
+    let client_fut = async {
+        let (mut driver, send_request) = client::new(pair.client().await).await.unwrap();
+        // Sent before the client processed the first GOAWAY, the driver is not polled yet
+        announced_rx.await.unwrap();
+        let first = request(send_request);
+        let driver = future::poll_fn(|cx| driver.poll_close(cx));
+
+        let (first, _) = tokio::join!(first, driver);
+        assert_matches!(first, Ok(_));
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .timer(TokioTimer)
+            .graceful_shutdown_interval(Duration::from_millis(200))
+            .build::<_, Bytes>(conn)
+            .await
+            .unwrap();
+
+        incoming.announce_shutdown().await.unwrap();
+        announced_tx.send(()).unwrap();
+        let (_, first) = incoming.accept().await.unwrap().unwrap();
+        response(first).await;
+        // Once the interval elapsed
+        assert!(incoming.accept().await.unwrap().is_none());
+
+        let stats = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(stats.rejected(), 0);
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_server_needs_timer() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+
+    let client_fut = async {
+        let (mut driver, _send_request) = client::new(pair.client().await).await.unwrap();
+        tokio::select! {
+            _ = done_rx => (),
+            _ = future::poll_fn(|cx| driver.poll_close(cx)) => panic!("connection closed"),
+        }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        let err = incoming
+            .graceful_shutdown(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.try_get_code(), Some(Code::H3_INTERNAL_ERROR));
+        done_tx.send(()).unwrap();
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 async fn request<T, O, B>(mut send_request: T) -> Result<Response<()>, Error>
 where
     T: BorrowMut<SendRequest<O, B>>,
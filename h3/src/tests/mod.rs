@@ -6,14 +6,18 @@
 // concerned, but quic traits wouldn't match between the "h3" crate that
 // comes before h3_quinn and the one that comes after and runs the tests
 #[path = "../../../h3-quinn/src/lib.rs"]
+// The features of h3_quinn are not declared by this crate
+#[allow(unknown_lints, unexpected_cfgs)]
 mod h3_quinn;
 
 mod connection;
+mod qpack;
 mod request;
 
 use std::{
     convert::TryInto,
     net::{Ipv6Addr, ToSocketAddrs},
+    pin::Pin,
     sync::Arc,
     time::Duration,
 };
@@ -22,7 +26,7 @@ use bytes::Bytes;
 use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 
-use crate::quic;
+use crate::{quic, rt};
 use h3_quinn::{quinn::TransportConfig, Connection};
 
 pub fn init_tracing() {
@@ -33,6 +37,14 @@ pub fn init_tracing() {
         .try_init();
 }
 
+pub struct TokioTimer;
+
+impl rt::Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn rt::Sleep>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
 pub struct Pair {
     port: u16,
     cert: CertificateDer<'static>,
@@ -0,0 +1,830 @@
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use bytes::{Bytes, BytesMut};
+use futures_util::future;
+use http::{HeaderMap, Request, Response};
+use tokio::sync::oneshot;
+
+use crate::{
+    client,
+    connection::{ConnectionState, SharedStateRef},
+    error::{Code, Kind},
+    proto::{
+        coding::Encode as _,
+        frame::{Frame, SettingId, Settings},
+        stream::StreamType,
+        varint::VarInt,
+    },
+    qpack::{self, HeaderField},
+    quic, server,
+};
+
+use super::{init_tracing, Pair, TokioTimer};
+
+#[tokio::test]
+async fn encoder_receives_section_acknowledgment() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server_inner();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::builder()
+            .qpack_encoder_table_capacity(4096)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let state = client.shared_state().clone();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            // The dynamic table is only used once the server's settings are received
+            wait_for(|| {
+                state
+                    .read("peer settings")
+                    .peer_config
+                    .qpack_max_table_capacity()
+                    > 0
+            })
+            .await;
+
+            let mut request_stream = client
+                .send_request(
+                    Request::get("http://localhost/salut")
+                        .header("x-session", "4f8a9c2d1e")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("finish");
+
+            // The acknowledgment releases the entries the section references
+            wait_for(|| {
+                let shared = state.read("qpack state");
+                let encoder = shared.qpack.encoder();
+                let (inserted, acknowledged) = encoder.insert_counts();
+                inserted > 0 && inserted == acknowledged && encoder.unacked_sections() == 0
+            })
+            .await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.accept().await.unwrap().await.unwrap();
+
+        let mut settings = Settings::default();
+        settings
+            .insert(SettingId::QPACK_MAX_TABLE_CAPACITY, 4096)
+            .unwrap();
+        settings
+            .insert(SettingId::QPACK_MAX_BLOCKED_STREAMS, 16)
+            .unwrap();
+        let _control_stream = open_control_stream(&conn, settings).await;
+
+        let mut decoder_stream = conn.open_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::DECODER.encode(&mut buf);
+        decoder_stream.write_all(&buf[..]).await.unwrap();
+
+        let (mut encoder_stream, _client_streams) = accept_uni(&conn, StreamType::ENCODER).await;
+
+        let (_send, mut recv) = conn.accept_bi().await.unwrap();
+        let stream_id = recv.id().0;
+        let mut frames = Bytes::from(recv.read_to_end(1024).await.unwrap());
+        let block = match Frame::decode(&mut frames).unwrap() {
+            Frame::Headers(block) => block,
+            f => panic!("unexpected frame: {:?}", f),
+        };
+
+        // Read the encoder stream until the section can be decoded
+        let mut decoder = qpack::Decoder::new(4096).unwrap();
+        let mut instructions = BytesMut::new();
+        let decoded = loop {
+            match decoder.decode_header(&mut block.clone()) {
+                Ok(decoded) => break decoded,
+                Err(qpack::DecoderError::MissingRefs(_)) => {
+                    let chunk = encoder_stream.read_chunk(1024, true).await.unwrap();
+                    instructions.extend_from_slice(&chunk.expect("encoder stream data").bytes);
+                    decoder
+                        .recv_encoder_instructions(&mut instructions)
+                        .unwrap();
+                }
+                Err(e) => panic!("decode error: {}", e),
+            }
+        };
+        assert!(decoded.dyn_ref);
+        assert!(decoded
+            .fields
+            .contains(&HeaderField::new("x-session", "4f8a9c2d1e")));
+
+        let mut buf = BytesMut::new();
+        decoder.ack_section(stream_id, &decoded, &mut buf);
+        decoder_stream.write_all(&buf[..]).await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn decoder_sends_instructions() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        let (mut decoder_stream, _server_streams) = accept_uni(&conn, StreamType::DECODER).await;
+        let mut decoder_buf = BytesMut::new();
+
+        let mut encoder = qpack::Encoder::new();
+        encoder.set_max_table_capacity(4096).unwrap();
+        encoder.set_max_blocked_streams(16).unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::ENCODER.encode(&mut buf);
+        encoder.set_table_capacity(4096, &mut buf).unwrap();
+
+        let fields = request_fields();
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        let mut block = BytesMut::new();
+        encoder
+            .encode(send.id().0, &mut block, &mut buf, &fields)
+            .unwrap();
+        let mut encoder_stream = conn.open_uni().await.unwrap();
+        encoder_stream.write_all(&buf[..]).await.unwrap();
+
+        // The entries are acknowledged with an Insert Count Increment before any section
+        // references them
+        recv_decoder_instructions(&mut decoder_stream, &mut decoder_buf, &mut encoder, |e| {
+            let (inserted, acknowledged) = e.insert_counts();
+            inserted > 0 && inserted == acknowledged
+        })
+        .await;
+
+        let mut buf = BytesMut::new();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.4.1
+        //= type=test
+        //# After processing an encoded field section whose declared Required
+        //# Insert Count is not zero, the decoder emits a Section Acknowledgment
+        //# instruction.
+        recv_decoder_instructions(&mut decoder_stream, &mut decoder_buf, &mut encoder, |e| {
+            e.unacked_sections() == 0
+        })
+        .await;
+
+        // The server gives up reading a request whose headers do not arrive in time
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        let mut block = BytesMut::new();
+        let mut buf = BytesMut::new();
+        encoder
+            .encode(send.id().0, &mut block, &mut buf, &fields)
+            .unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(encoder.unacked_sections(), 1);
+
+        let mut buf = BytesMut::new();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        send.write_all(&buf[..2]).await.unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.4.2
+        //= type=test
+        //# When a stream is reset or reading is abandoned, the decoder emits a
+        //# Stream Cancellation instruction.
+        recv_decoder_instructions(&mut decoder_stream, &mut decoder_buf, &mut encoder, |e| {
+            e.unacked_sections() == 0
+        })
+        .await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .timer(TokioTimer)
+            .header_read_timeout(Duration::from_millis(100))
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (request, _request_stream) = incoming.accept().await.expect("accept").unwrap();
+        assert_eq!(request.headers()["x-session"], "4f8a9c2d1e");
+
+        // The reset request fails to be accepted, the connection keeps going
+        let err = incoming.accept().await.map(|_| ()).unwrap_err();
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_REQUEST_INCOMPLETE,
+                ..
+            }
+        );
+        let _ = incoming.accept().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn dynamic_table_with_blocked_streams() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::builder()
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(16)
+            .qpack_encoder_table_capacity(4096)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let state = client.shared_state().clone();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            wait_for(|| {
+                state
+                    .read("peer settings")
+                    .peer_config
+                    .qpack_max_table_capacity()
+                    > 0
+            })
+            .await;
+
+            for _ in 0..3 {
+                let mut request_stream = client
+                    .send_request(
+                        Request::get("http://localhost/salut")
+                            .header("x-session", "4f8a9c2d1e")
+                            .body(())
+                            .unwrap(),
+                    )
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("finish");
+
+                let response = request_stream.recv_response().await.expect("response");
+                assert_eq!(response.headers()["x-served-by"], "h3");
+                assert!(request_stream.recv_data().await.unwrap().is_none());
+                let trailers = request_stream
+                    .recv_trailers()
+                    .await
+                    .expect("recv trailers")
+                    .expect("trailers");
+                assert_eq!(trailers["x-checksum"], "9c2d");
+            }
+
+            wait_for(|| {
+                let shared = state.read("qpack state");
+                let encoder = shared.qpack.encoder();
+                let (inserted, acknowledged) = encoder.insert_counts();
+                inserted > 0 && inserted == acknowledged && encoder.unacked_sections() == 0
+            })
+            .await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(16)
+            .qpack_encoder_table_capacity(4096)
+            .build(conn)
+            .await
+            .unwrap();
+
+        while let Some((request, mut request_stream)) = incoming.accept().await.expect("accept") {
+            assert_eq!(request.headers()["x-session"], "4f8a9c2d1e");
+            // The next accept sends the entries the response references
+            request_stream
+                .send_response(
+                    Response::builder()
+                        .header("x-served-by", "h3")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("send_response");
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", "9c2d".parse().unwrap());
+            request_stream
+                .send_trailers(trailers)
+                .await
+                .expect("send_trailers");
+            request_stream.finish().await.expect("finish");
+        }
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn qpack_snapshot() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::builder()
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(16)
+            .qpack_encoder_table_capacity(4096)
+            .build::<_, _, Bytes>(pair.client().await)
+            .await
+            .expect("client init");
+        let state = client.shared_state().clone();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            wait_for(|| {
+                state
+                    .read("peer settings")
+                    .peer_config
+                    .qpack_max_table_capacity()
+                    > 0
+            })
+            .await;
+
+            for _ in 0..2 {
+                let mut request_stream = client
+                    .send_request(
+                        Request::get("http://localhost/salut")
+                            .header("x-session", "4f8a9c2d1e")
+                            .body(())
+                            .unwrap(),
+                    )
+                    .await
+                    .expect("request");
+                request_stream.finish().await.expect("finish");
+                let response = request_stream.recv_response().await.expect("response");
+                assert_eq!(response.headers()["x-served-by"], "h3");
+            }
+
+            let snapshot = client.qpack_snapshot();
+            assert!(snapshot.encoder.static_hits > 0);
+            assert!(snapshot.encoder.dynamic_hits > 0);
+            assert!(snapshot.encoder.huffman_savings > 0);
+            assert_eq!(snapshot.encoder.table_capacity, 4096);
+            assert!(snapshot
+                .encoder_table
+                .iter()
+                .any(|e| e.field == HeaderField::new("x-session", "4f8a9c2d1e")));
+            assert!(snapshot.decoder.dynamic_hits > 0);
+            assert_eq!(snapshot.decoder.blocked_streams, 0);
+            assert!(snapshot
+                .decoder_table
+                .iter()
+                .any(|e| e.field == HeaderField::new("x-served-by", "h3")));
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+
+        // The driver and the request handles share the same state
+        let snapshot = driver.qpack_snapshot();
+        assert_eq!(
+            snapshot.encoder_table,
+            client.qpack_snapshot().encoder_table
+        );
+        assert_eq!(snapshot.decoder.table_entries, snapshot.decoder_table.len());
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(16)
+            .qpack_encoder_table_capacity(4096)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let mut requests = 0;
+        while let Some((_, mut request_stream)) = incoming.accept().await.expect("accept") {
+            requests += 1;
+            if requests == 2 {
+                let snapshot = incoming.qpack_snapshot();
+                assert!(snapshot.decoder.dynamic_hits > 0);
+                assert!(snapshot
+                    .decoder_table
+                    .iter()
+                    .any(|e| e.field == HeaderField::new("x-session", "4f8a9c2d1e")));
+                assert!(snapshot
+                    .encoder_table
+                    .iter()
+                    .any(|e| e.field == HeaderField::new("x-served-by", "h3")));
+            }
+            // The next accept sends the entries the response references
+            request_stream
+                .send_response(
+                    Response::builder()
+                        .header("x-served-by", "h3")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("finish");
+        }
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn request_blocked_until_entries_received() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+    let (state_send, state_recv) = oneshot::channel();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        let (mut decoder_stream, _server_streams) = accept_uni(&conn, StreamType::DECODER).await;
+        let mut decoder_buf = BytesMut::new();
+
+        let mut encoder = qpack::Encoder::new();
+        encoder.set_max_table_capacity(4096).unwrap();
+        encoder.set_max_blocked_streams(1).unwrap();
+        let mut instructions = BytesMut::new();
+        StreamType::ENCODER.encode(&mut instructions);
+        encoder.set_table_capacity(4096, &mut instructions).unwrap();
+
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        let mut block = BytesMut::new();
+        encoder
+            .encode(send.id().0, &mut block, &mut instructions, request_fields())
+            .unwrap();
+        let mut buf = BytesMut::new();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().unwrap();
+
+        // The entries are only sent once the server waits for them
+        let state: SharedStateRef = state_recv.await.unwrap();
+        wait_for(|| state.read("blocked streams").qpack.blocked_streams() == 1).await;
+        let mut encoder_stream = conn.open_uni().await.unwrap();
+        encoder_stream.write_all(&instructions[..]).await.unwrap();
+
+        recv_decoder_instructions(&mut decoder_stream, &mut decoder_buf, &mut encoder, |e| {
+            e.unacked_sections() == 0
+        })
+        .await;
+        assert_eq!(state.read("blocked streams").qpack.blocked_streams(), 0);
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .qpack_max_blocked_streams(1)
+            .build(conn)
+            .await
+            .unwrap();
+        assert!(state_send.send(incoming.shared_state().clone()).is_ok());
+
+        let (request, _request_stream) = incoming.accept().await.expect("accept").unwrap();
+        assert_eq!(request.headers()["x-session"], "4f8a9c2d1e");
+        let _ = incoming.accept().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn too_many_blocked_streams() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        // The server does not allow any blocked stream, the entries are never sent
+        let mut encoder = qpack::Encoder::new();
+        encoder.set_max_table_capacity(4096).unwrap();
+        encoder.set_max_blocked_streams(1).unwrap();
+        let mut instructions = BytesMut::new();
+        encoder.set_table_capacity(4096, &mut instructions).unwrap();
+
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        let mut block = BytesMut::new();
+        encoder
+            .encode(send.id().0, &mut block, &mut instructions, request_fields())
+            .unwrap();
+        let mut buf = BytesMut::new();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().unwrap();
+
+        assert_closed(&conn, Code::QPACK_DECOMPRESSION_FAILED).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .build(conn)
+            .await
+            .unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-2.1.2
+        //= type=test
+        //# If a decoder encounters more blocked streams than it promised to
+        //# support, it MUST treat this as a connection error of type
+        //# QPACK_DECOMPRESSION_FAILED.
+        assert_accept_fails(&mut incoming, Code::QPACK_DECOMPRESSION_FAILED).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn invalid_encoder_instruction() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        // The server did not advertise any dynamic table capacity
+        let mut encoder = qpack::Encoder::new();
+        encoder.set_max_table_capacity(4096).unwrap();
+        let mut buf = BytesMut::new();
+        StreamType::ENCODER.encode(&mut buf);
+        encoder.set_table_capacity(4096, &mut buf).unwrap();
+        let mut encoder_stream = conn.open_uni().await.unwrap();
+        encoder_stream.write_all(&buf[..]).await.unwrap();
+
+        assert_closed(&conn, Code::QPACK_ENCODER_STREAM_ERROR).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert_accept_fails(&mut incoming, Code::QPACK_ENCODER_STREAM_ERROR).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn invalid_decoder_instruction() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        // The server never sent a field section on this stream
+        let mut buf = BytesMut::new();
+        StreamType::DECODER.encode(&mut buf);
+        qpack::ack_header(0, &mut buf);
+        let mut decoder_stream = conn.open_uni().await.unwrap();
+        decoder_stream.write_all(&buf[..]).await.unwrap();
+
+        assert_closed(&conn, Code::QPACK_DECODER_STREAM_ERROR).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert_accept_fails(&mut incoming, Code::QPACK_DECODER_STREAM_ERROR).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn second_encoder_stream() {
+    //= https://www.rfc-editor.org/rfc/rfc9204#section-4.2
+    //= type=test
+    //# Each endpoint MUST initiate, at most, one encoder stream and, at
+    //# most, one decoder stream.  Receipt of a second instance of either
+    //# stream type MUST be treated as a connection error of type
+    //# H3_STREAM_CREATION_ERROR.
+    qpack_streams_rejected(
+        StreamType::ENCODER,
+        2,
+        false,
+        Code::H3_STREAM_CREATION_ERROR,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn second_decoder_stream() {
+    qpack_streams_rejected(
+        StreamType::DECODER,
+        2,
+        false,
+        Code::H3_STREAM_CREATION_ERROR,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn encoder_stream_closed() {
+    //= https://www.rfc-editor.org/rfc/rfc9204#section-4.2
+    //= type=test
+    //# Closure of either unidirectional stream type MUST be treated as a
+    //# connection error of type H3_CLOSED_CRITICAL_STREAM.
+    qpack_streams_rejected(
+        StreamType::ENCODER,
+        1,
+        true,
+        Code::H3_CLOSED_CRITICAL_STREAM,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn decoder_stream_closed() {
+    qpack_streams_rejected(
+        StreamType::DECODER,
+        1,
+        true,
+        Code::H3_CLOSED_CRITICAL_STREAM,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn invalid_required_insert_count() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        // An encoded Required Insert Count of 1 with no entry inserted decodes to 0
+        let (mut send, _recv) = conn.open_bi().await.unwrap();
+        let mut buf = BytesMut::new();
+        Frame::headers(&[0x01, 0x00][..]).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().unwrap();
+
+        assert_closed(&conn, Code::QPACK_DECOMPRESSION_FAILED).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::builder()
+            .qpack_max_table_capacity(4096)
+            .build(conn)
+            .await
+            .unwrap();
+
+        //= https://www.rfc-editor.org/rfc/rfc9204#section-4.5.1.1
+        //= type=test
+        //# If the decoder encounters a value of EncodedInsertCount that could
+        //# not have been produced by a conforming encoder, it MUST treat this as
+        //# a connection error of type QPACK_DECOMPRESSION_FAILED.
+        assert_accept_fails(&mut incoming, Code::QPACK_DECOMPRESSION_FAILED).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+/// Opens `count` QPACK streams of type `ty` to an h3 server, finished if `finish`, which must
+/// close the connection with `code`
+async fn qpack_streams_rejected(ty: StreamType, count: usize, finish: bool, code: Code) {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let conn = pair.client_inner().await;
+        let _control_stream = open_control_stream(&conn, Settings::default()).await;
+
+        let mut streams = Vec::new();
+        for _ in 0..count {
+            let mut buf = BytesMut::new();
+            ty.encode(&mut buf);
+            let mut stream = conn.open_uni().await.unwrap();
+            stream.write_all(&buf[..]).await.unwrap();
+            if finish {
+                stream.finish().unwrap();
+            }
+            streams.push(stream);
+        }
+
+        assert_closed(&conn, code).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming = server::Connection::new(conn).await.unwrap();
+        assert_accept_fails(&mut incoming, code).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+/// Opens the control stream of a raw connection, sending `settings`
+///
+/// The stream is returned, dropping it would finish the peer's critical stream.
+async fn open_control_stream(conn: &quinn::Connection, settings: Settings) -> quinn::SendStream {
+    let mut control_stream = conn.open_uni().await.unwrap();
+    let mut buf = BytesMut::new();
+    StreamType::CONTROL.encode(&mut buf);
+    Frame::<Bytes>::Settings(settings).encode(&mut buf);
+    control_stream.write_all(&buf[..]).await.unwrap();
+    control_stream
+}
+
+async fn assert_closed(conn: &quinn::Connection, code: Code) {
+    assert_matches!(
+        conn.closed().await,
+        quinn::ConnectionError::ApplicationClosed(close)
+            if close.error_code.into_inner() == code.value()
+    );
+}
+
+async fn assert_accept_fails<C: quic::Connection<Bytes>>(
+    incoming: &mut server::Connection<C, Bytes>,
+    code: Code,
+) {
+    let err = incoming.accept().await.map(|_| ()).unwrap_err();
+    assert_matches!(err.kind(), Kind::Application { code: c, .. } if c == code);
+}
+
+fn request_fields() -> [HeaderField; 5] {
+    [
+        HeaderField::new(":method", "GET"),
+        HeaderField::new(":scheme", "https"),
+        HeaderField::new(":authority", "localhost"),
+        HeaderField::new(":path", "/salut"),
+        HeaderField::new("x-session", "4f8a9c2d1e"),
+    ]
+}
+
+/// Accepts the unidirectional streams of `conn` until one of type `ty`
+///
+/// The other streams are returned too, dropping them would stop the peer's critical streams.
+async fn accept_uni(
+    conn: &quinn::Connection,
+    ty: StreamType,
+) -> (quinn::RecvStream, Vec<quinn::RecvStream>) {
+    let mut others = Vec::new();
+    loop {
+        let mut stream = conn.accept_uni().await.unwrap();
+        let mut buf = BytesMut::new();
+        let stream_type = loop {
+            if let Ok(value) = VarInt::decode(&mut buf.clone()) {
+                break value;
+            }
+            let chunk = stream.read_chunk(1, true).await.unwrap();
+            buf.extend_from_slice(&chunk.expect("stream type").bytes);
+        };
+        if stream_type.0 == ty.value() {
+            return (stream, others);
+        }
+        others.push(stream);
+    }
+}
+
+/// Applies the instructions read on the decoder stream to `encoder` until `done` holds
+async fn recv_decoder_instructions<F>(
+    stream: &mut quinn::RecvStream,
+    buf: &mut BytesMut,
+    encoder: &mut qpack::Encoder,
+    done: F,
+) where
+    F: Fn(&qpack::Encoder) -> bool,
+{
+    while !done(encoder) {
+        let chunk = stream.read_chunk(1024, true).await.unwrap();
+        buf.extend_from_slice(&chunk.expect("decoder stream data").bytes);
+        encoder.on_decoder_recv(buf).unwrap();
+    }
+}
+
+async fn wait_for<F: Fn() -> bool>(condition: F) {
+    while !condition() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
@@ -20,7 +20,7 @@ use crate::{
 };
 
 use super::h3_quinn;
-use super::{init_tracing, Pair};
+use super::{init_tracing, Pair, TokioTimer};
 
 #[tokio::test]
 async fn get() {
@@ -977,6 +977,1296 @@ async fn post_timeout_server_recv_data() {
     tokio::join!(server_fut, client_fut);
 }
 
+#[tokio::test]
+async fn informational_responses() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            for link in ["</style.css>", "</script.js>"] {
+                let interim = request_stream
+                    .recv_informational()
+                    .await
+                    .expect("recv informational")
+                    .expect("early hints");
+                assert_eq!(interim.status(), StatusCode::EARLY_HINTS);
+                assert_eq!(interim.headers()["link"], link);
+            }
+            assert_matches!(request_stream.recv_informational().await, Ok(None));
+            assert_matches!(request_stream.recv_informational().await, Ok(None));
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+            assert_eq!(body.chunk(), b"wonderful hypertext");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // Informational responses are not final responses
+        assert_matches!(
+            request_stream
+                .send_response(
+                    Response::builder()
+                        .status(StatusCode::EARLY_HINTS)
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .unwrap_err()
+                .kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+        for link in ["</style.css>", "</script.js>"] {
+            request_stream
+                .send_informational(
+                    Response::builder()
+                        .status(StatusCode::EARLY_HINTS)
+                        .header("link", link)
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("send_informational");
+        }
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        assert_matches!(
+            request_stream
+                .send_response(Response::builder().status(500).body(()).unwrap())
+                .await
+                .unwrap_err()
+                .kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+        assert_matches!(
+            request_stream
+                .send_informational(
+                    Response::builder()
+                        .status(StatusCode::CONTINUE)
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .unwrap_err()
+                .kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+        request_stream
+            .send_data("wonderful hypertext".into())
+            .await
+            .expect("send_data");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn informational_responses_skipped_by_recv_response() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        for status in [StatusCode::SWITCHING_PROTOCOLS, StatusCode::OK] {
+            assert_matches!(
+                request_stream
+                    .send_informational(Response::builder().status(status).body(()).unwrap())
+                    .await
+                    .unwrap_err()
+                    .kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
+            );
+        }
+        request_stream
+            .send_informational(
+                Response::builder()
+                    .status(StatusCode::PROCESSING)
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .expect("send_informational");
+        request_stream
+            .send_response(Response::builder().status(204).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn expect_continue() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("expect", "100-continue")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            let status = request_stream
+                .wait_continue(tokio::time::sleep(Duration::from_secs(10)))
+                .await
+                .expect("wait continue");
+            assert_eq!(status, client::ContinueStatus::Continue);
+
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream.send_continue().await.expect("send continue");
+        // Only sent once
+        request_stream.send_continue().await.expect("send continue");
+
+        let request_body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("server recv body");
+        assert_eq!(request_body.chunk(), b"wonderful json");
+
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn expect_continue_on_recv_data() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("expect", "100-continue")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            let status = request_stream
+                .wait_continue(tokio::time::sleep(Duration::from_secs(10)))
+                .await
+                .expect("wait continue");
+            assert_eq!(status, client::ContinueStatus::Continue);
+
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // The first body read lets the client send its body
+        let request_body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("server recv body");
+        assert_eq!(request_body.chunk(), b"wonderful json");
+
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn expect_continue_rejected() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("expect", "100-continue")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            let status = request_stream
+                .wait_continue(tokio::time::sleep(Duration::from_secs(10)))
+                .await
+                .expect("wait continue");
+            assert_eq!(status, client::ContinueStatus::FinalResponse);
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .expect("send_response");
+        // The final response has been sent, so this does nothing
+        request_stream.send_continue().await.expect("send continue");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn expect_continue_timeout() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("expect", "100-continue")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            let status = request_stream
+                .wait_continue(tokio::time::sleep(Duration::from_millis(50)))
+                .await
+                .expect("wait continue");
+            assert_eq!(status, client::ContinueStatus::TimedOut);
+
+            request_stream
+                .send_data("wonderful json".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        // Only read the body, which sends `100 Continue`, once the client gave up waiting
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let request_body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("server recv body");
+        assert_eq!(request_body.chunk(), b"wonderful json");
+
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn limit_concurrent_requests() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut first = client
+                .send_request(Request::get("http://localhost/first").body(()).unwrap())
+                .await
+                .expect("request");
+            first.finish().await.expect("client finish");
+
+            let mut second = client
+                .send_request(Request::get("http://localhost/second").body(()).unwrap())
+                .await
+                .expect("request");
+            let err = second.recv_response().await.unwrap_err();
+            assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_REJECTED));
+
+            let response = first.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_concurrent_requests(1)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (request, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert_eq!(request.uri().path(), "/first");
+
+        // The second request is rejected while the first one is in flight
+        let respond = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            request_stream
+                .send_response(Response::builder().status(200).body(()).unwrap())
+                .await
+                .expect("send_response");
+            request_stream.finish().await.expect("finish");
+        };
+        tokio::select! { _ = incoming_req.accept() => panic!("accepted"), _ = respond => () };
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn limit_request_body_content_length() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("content-length", "5")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_request_body_size(4)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let err = incoming_req.accept().await.map(|_| ()).unwrap_err();
+        assert_eq!(err.rejected_status(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn limit_request_body_data() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream
+                .send_data("fada".into())
+                .await
+                .expect("send_data");
+            request_stream
+                .send_data("fada".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("client finish");
+            let _ = request_stream.recv_response().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_request_body_size(6)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        assert_eq!(body.chunk(), b"fada");
+        let err = request_stream.recv_data().await.map(|_| ()).unwrap_err();
+        assert_eq!(err.try_get_code(), Some(Code::H3_EXCESSIVE_LOAD));
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn limit_header_fields() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::get("http://localhost/salut")
+                        .header("one", "1")
+                        .header("two", "2")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(
+                response.status(),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_header_fields(5)
+            .build(conn)
+            .await
+            .unwrap();
+
+        // 4 pseudo-headers and 2 fields
+        let err = incoming_req.accept().await.map(|_| ()).unwrap_err();
+        assert_eq!(
+            err.rejected_status(),
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn limit_trailers_size() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            let mut trailers = HeaderMap::new();
+            trailers.insert("trailer", "a rather long trailer value".parse().unwrap());
+            request_stream
+                .send_trailers(trailers)
+                .await
+                .expect("send_trailers");
+            request_stream.finish().await.expect("client finish");
+            let _ = request_stream.recv_response().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .max_trailers_size(32)
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        assert!(request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .is_none());
+        let err = request_stream.recv_trailers().await.unwrap_err();
+        assert_eq!(err.try_get_code(), Some(Code::H3_EXCESSIVE_LOAD));
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn header_read_timeout() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, mut req_recv) = connection.open_bi().await.unwrap();
+
+        // Only the beginning of a HEADERS frame
+        let mut buf = BytesMut::new();
+        FrameType::HEADERS.encode(&mut buf);
+        buf.put_u8(42);
+        req_send.write_all(&buf[..]).await.unwrap();
+
+        let (mut driver, _send) = client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let read_fut = async {
+            let err = req_recv.read_to_end(1024).await.unwrap_err();
+            assert_matches!(
+                err,
+                quinn::ReadToEndError::Read(quinn::ReadError::Reset(code))
+                    if code.into_inner() == Code::H3_REQUEST_INCOMPLETE.value()
+            );
+        };
+        tokio::select! { _ = read_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .timer(TokioTimer)
+            .header_read_timeout(Duration::from_millis(100))
+            .build(conn)
+            .await
+            .unwrap();
+
+        let err = incoming_req.accept().await.map(|_| ()).unwrap_err();
+        assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_INCOMPLETE));
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn body_idle_timeout() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::post("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream
+                .send_data("fada".into())
+                .await
+                .expect("send_data");
+            let _ = request_stream.recv_response().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::builder()
+            .timer(TokioTimer)
+            .body_idle_timeout(Duration::from_millis(100))
+            .build(conn)
+            .await
+            .unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        assert_eq!(body.chunk(), b"fada");
+        let err = request_stream.recv_data().await.map(|_| ()).unwrap_err();
+        assert_eq!(err.try_get_code(), Some(Code::H3_REQUEST_INCOMPLETE));
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn content_length_send_data_exceeds() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("content-length", "6")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            request_stream
+                .send_data("fada".into())
+                .await
+                .expect("send_data");
+            let err = request_stream
+                .send_data("fada".into())
+                .await
+                .expect_err("exceeding content-length");
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
+            );
+            request_stream.recv_response().await.expect("recv response");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn content_length_server_recv_early_fin() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut")
+                .header("content-length", "10")
+                .body(())
+                .unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        req_send.write_all(&buf[..]).await.unwrap();
+        req_send.finish().unwrap();
+
+        let (mut driver, _send) = client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        assert_eq!(body.chunk(), b"fada");
+
+        //= https://www.rfc-editor.org/rfc/rfc9114#section-4.1.2
+        //= type=test
+        //# A request or response that is defined as having content when it
+        //# contains a Content-Length header field (Section 8.6 of [HTTP]) is
+        //# malformed if the value of the Content-Length header field does not
+        //# equal the sum of the DATA frame lengths received.
+        let err = request_stream
+            .recv_data()
+            .await
+            .map(|_| ())
+            .expect_err("early fin");
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn content_length_server_recv_early_trailers() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut")
+                .header("content-length", "10")
+                .body(())
+                .unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("trailer", "value".parse().unwrap());
+        trailers_encode(&mut buf, trailers);
+        req_send.write_all(&buf[..]).await.unwrap();
+        req_send.finish().unwrap();
+
+        let (mut driver, _send) = client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let body = request_stream
+            .recv_data()
+            .await
+            .expect("recv data")
+            .expect("body");
+        assert_eq!(body.chunk(), b"fada");
+        let err = request_stream
+            .recv_data()
+            .await
+            .map(|_| ())
+            .expect_err("trailers before the end of the body");
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn content_length_client_finish_short_body() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(
+                    Request::post("http://localhost/salut")
+                        .header("content-length", "10")
+                        .body(())
+                        .unwrap(),
+                )
+                .await
+                .expect("request");
+
+            request_stream
+                .send_data("fada".into())
+                .await
+                .expect("send_data");
+            let err = request_stream.finish().await.expect_err("short body");
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
+            );
+            let err = request_stream
+                .send_trailers(HeaderMap::new())
+                .await
+                .expect_err("short body");
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
+            );
+
+            // Completing the body allows to finish
+            request_stream
+                .send_data("fadafa".into())
+                .await
+                .expect("send_data");
+            request_stream.finish().await.expect("finish");
+            request_stream.recv_response().await.expect("recv response");
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let mut received = 0;
+        while let Some(data) = request_stream.recv_data().await.expect("recv data") {
+            received += data.remaining();
+        }
+        assert_eq!(received, 10);
+        request_stream
+            .send_response(Response::builder().status(200).body(()).unwrap())
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn content_length_server_finish_short_body() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+            let _ = request_stream.recv_response().await;
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => () }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .header("content-length", "10")
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream
+            .send_data("fada".into())
+            .await
+            .expect("send_data");
+        let err = request_stream.finish().await.expect_err("short body");
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn content_length_server_recv_exceeds() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let connection = pair.client_inner().await;
+        let (mut req_send, _req_recv) = connection.open_bi().await.unwrap();
+
+        let mut buf = BytesMut::new();
+        request_encode(
+            &mut buf,
+            Request::post("http://localhost/salut")
+                .header("content-length", "2")
+                .body(())
+                .unwrap(),
+        );
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        req_send.write_all(&buf[..]).await.unwrap();
+        req_send.finish().unwrap();
+
+        let (mut driver, _send) = client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        let err = request_stream
+            .recv_data()
+            .await
+            .map(|_| ())
+            .expect_err("content-length exceeded");
+        assert_matches!(
+            err.kind(),
+            Kind::Application {
+                code: Code::H3_MESSAGE_ERROR,
+                ..
+            }
+        );
+    };
+
+    tokio::select! { _ = server_fut => (), _ = client_fut => panic!("client resolved first") };
+}
+
+#[tokio::test]
+async fn content_length_client_recv_early_fin() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = request_stream
+                .recv_data()
+                .await
+                .expect("recv data")
+                .expect("body");
+            assert_eq!(body.chunk(), b"fada");
+            let err = request_stream
+                .recv_data()
+                .await
+                .map(|_| ())
+                .expect_err("early fin");
+            assert_matches!(
+                err.kind(),
+                Kind::Application {
+                    code: Code::H3_MESSAGE_ERROR,
+                    ..
+                }
+            );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.endpoint.accept().await.unwrap().await.unwrap();
+        let (mut send, mut recv) = conn.accept_bi().await.unwrap();
+        // Read the request headers up to the client's FIN
+        recv.read_to_end(1024).await.unwrap();
+
+        let mut buf = BytesMut::new();
+        let headers = Header::response(StatusCode::OK, {
+            let mut fields = HeaderMap::new();
+            fields.insert("content-length", "10".parse().unwrap());
+            fields
+        });
+        let mut block = BytesMut::new();
+        qpack::encode_stateless(&mut block, headers).unwrap();
+        Frame::headers(block).encode_with_payload(&mut buf);
+        Frame::Data(Bytes::from("fada")).encode_with_payload(&mut buf);
+        send.write_all(&buf[..]).await.unwrap();
+        send.finish().unwrap();
+
+        conn.closed().await;
+    };
+
+    tokio::select! { _ = server_fut => panic!("server resolved first"), _ = client_fut => () };
+}
+
+#[tokio::test]
+async fn content_length_head_response() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::head("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_matches!(
+                request_stream.recv_data().await.map(|b| b.is_none()),
+                Ok(true)
+            );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(200)
+                    .header("content-length", "10")
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
+#[tokio::test]
+async fn content_length_no_content_response() {
+    init_tracing();
+    let mut pair = Pair::default();
+    let mut server = pair.server();
+
+    let client_fut = async {
+        let (mut driver, mut client) = client::new(pair.client().await).await.expect("client init");
+        let drive_fut = async { future::poll_fn(|cx| driver.poll_close(cx)).await };
+        let req_fut = async {
+            let mut request_stream = client
+                .send_request(Request::get("http://localhost/salut").body(()).unwrap())
+                .await
+                .expect("request");
+            request_stream.finish().await.expect("client finish");
+
+            let response = request_stream.recv_response().await.expect("recv response");
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            assert_matches!(
+                request_stream.recv_data().await.map(|b| b.is_none()),
+                Ok(true)
+            );
+        };
+        tokio::select! { _ = req_fut => (), _ = drive_fut => panic!("driver resolved first") }
+    };
+
+    let server_fut = async {
+        let conn = server.next().await;
+        let mut incoming_req = server::Connection::new(conn).await.unwrap();
+
+        let (_, mut request_stream) = incoming_req.accept().await.expect("accept").unwrap();
+        request_stream
+            .send_response(
+                Response::builder()
+                    .status(204)
+                    .header("content-length", "10")
+                    .body(())
+                    .expect("build response"),
+            )
+            .await
+            .expect("send_response");
+        request_stream.finish().await.expect("finish");
+
+        let _ = incoming_req.accept().await;
+    };
+
+    tokio::join!(server_fut, client_fut);
+}
+
 // 4.1. HTTP Message Exchanges
 
 // An HTTP message (request or response) consists of:
@@ -1,2 +1,14 @@
 mod session_id;
 pub use session_id::SessionId;
+
+/// Version of the WebTransport over HTTP/3 draft
+///
+/// See: <https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3/>
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum WebTransportDraft {
+    /// Negotiated with `SETTINGS_ENABLE_WEBTRANSPORT`, without session-level flow control
+    Draft02,
+    /// Negotiated with `SETTINGS_WT_MAX_SESSIONS`, with session-level flow control
+    Draft07,
+}
@@ -45,6 +45,27 @@ impl Decode for SessionId {
 
 impl From<StreamId> for SessionId {
     fn from(value: StreamId) -> Self {
-        Self(value.index())
+        Self(value.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_stream_id_is_the_stream_id() {
+        // The 3rd client-initiated bidirectional stream carries the CONNECT request
+        let stream_id = StreamId::try_from(8).unwrap();
+        assert_eq!(stream_id.index(), 2);
+
+        let session_id = SessionId::from(stream_id);
+        assert_eq!(session_id.into_inner(), 8);
+
+        // Streams of the session refer to it with the same value on the wire
+        let mut buf = Vec::new();
+        session_id.encode(&mut buf);
+        assert_eq!(SessionId::decode(&mut &buf[..]).unwrap(), session_id);
+        assert_eq!(buf, [8]);
     }
 }
@@ -0,0 +1,379 @@
+//! Interoperability of the QPACK codec with the offline corpus of the QPACK interop suite
+//!
+//! The layout of `tests/qifs` follows https://github.com/qpackers/qifs: the header lists are
+//! in `qifs/<name>.qif`, and their encodings by other implementations in
+//! `encoded/qpack-06/<implementation>/<name>.out.<table capacity>.<blocked streams>.<ack>`.
+//! The header lists of `local/` are only used for the round trip of the h3 encoder. See
+//! `tests/qifs/README.md` about vendoring the upstream files.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Buf, BufMut};
+
+use h3::qpack::{Decoder, DecoderError, Encoder, HeaderField};
+
+/// The settings an encoded file was produced with
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    table_capacity: usize,
+    blocked_streams: usize,
+    // Whether the encoder considered each section acknowledged once encoded
+    immediate_ack: bool,
+}
+
+impl Settings {
+    /// Parses the `<table capacity>.<blocked streams>.<ack>` suffix of an encoded file
+    fn parse(suffix: &str) -> Self {
+        let params = suffix
+            .split('.')
+            .map(|p| p.parse().expect("numeric setting"))
+            .collect::<Vec<usize>>();
+        match params[..] {
+            [table_capacity, blocked_streams, ack] => Self {
+                table_capacity,
+                blocked_streams,
+                immediate_ack: ack == 1,
+            },
+            _ => panic!("bad encoded file settings: {}", suffix),
+        }
+    }
+
+    fn suffix(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            self.table_capacity, self.blocked_streams, self.immediate_ack as u8
+        )
+    }
+}
+
+// The combinations the encodings are produced with
+const SETTINGS: [Settings; 8] = [
+    Settings {
+        table_capacity: 0,
+        blocked_streams: 0,
+        immediate_ack: false,
+    },
+    Settings {
+        table_capacity: 256,
+        blocked_streams: 0,
+        immediate_ack: false,
+    },
+    Settings {
+        table_capacity: 256,
+        blocked_streams: 100,
+        immediate_ack: true,
+    },
+    Settings {
+        table_capacity: 512,
+        blocked_streams: 100,
+        immediate_ack: false,
+    },
+    Settings {
+        table_capacity: 4096,
+        blocked_streams: 0,
+        immediate_ack: false,
+    },
+    Settings {
+        table_capacity: 4096,
+        blocked_streams: 0,
+        immediate_ack: true,
+    },
+    Settings {
+        table_capacity: 4096,
+        blocked_streams: 100,
+        immediate_ack: false,
+    },
+    Settings {
+        table_capacity: 4096,
+        blocked_streams: 100,
+        immediate_ack: true,
+    },
+];
+
+// The implementations whose encodings must be vendored, at least
+const IMPLEMENTATIONS: [&str; 3] = ["ls-qpack", "nghttp3", "quinn"];
+
+#[test]
+#[ignore = "needs the upstream corpus, see tests/qifs/README.md"]
+fn decode_corpus() {
+    let implementations = dir_entries(&corpus_dir().join("encoded/qpack-06"));
+    for name in IMPLEMENTATIONS {
+        assert!(
+            implementations.iter().any(|dir| dir.ends_with(name)),
+            "encodings of {} not vendored",
+            name
+        );
+    }
+
+    for implementation in implementations {
+        let mut table_capacities = BTreeSet::new();
+        let mut blocked_streams = BTreeSet::new();
+        for file in dir_entries(&implementation) {
+            let file_name = file.file_name().unwrap().to_str().unwrap();
+            let (name, suffix) = file_name.split_once(".out.").expect("encoded file name");
+            let header_lists =
+                read_qif(&corpus_dir().join("qifs").join(name).with_extension("qif"));
+
+            let settings = Settings::parse(suffix);
+            table_capacities.insert(settings.table_capacity);
+            blocked_streams.insert(settings.blocked_streams);
+
+            let decoded_lists = decode(&fs::read(&file).unwrap(), settings)
+                .unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+            assert_eq!(
+                decoded_lists.len(),
+                header_lists.len(),
+                "{}",
+                file.display()
+            );
+            for (i, (decoded, expected)) in decoded_lists.iter().zip(&header_lists).enumerate() {
+                assert_eq!(decoded, expected, "{}, section {}", file.display(), i + 1);
+            }
+        }
+
+        // The dynamic table and the blocked streams are only exercised with several settings
+        assert!(
+            table_capacities.len() > 1 && blocked_streams.len() > 1,
+            "{}: table capacities {:?}, blocked streams {:?}",
+            implementation.display(),
+            table_capacities,
+            blocked_streams
+        );
+    }
+}
+
+/// Decodes what the h3 encoder produces for every header list, the upstream ones included
+/// once vendored
+#[test]
+fn round_trip() {
+    for qif in qif_files() {
+        let header_lists = read_qif(&qif);
+        for settings in SETTINGS {
+            let encoded = encode(&header_lists, settings);
+            let decoded = decode(&encoded, settings)
+                .unwrap_or_else(|e| panic!("{} with {:?}: {}", qif.display(), settings, e));
+            assert_eq!(
+                decoded,
+                header_lists,
+                "{} with {:?}",
+                qif.display(),
+                settings
+            );
+        }
+    }
+}
+
+/// Writes the encodings of h3 in the corpus layout, for submitting them upstream
+///
+/// Run with `cargo test -p h3 --test qifs generate_h3_encodings -- --ignored`, the files
+/// are written in `encoded/qpack-06/h3` of the test's temporary directory.
+#[test]
+#[ignore = "writes the h3 encodings"]
+fn generate_h3_encodings() {
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("qifs/encoded/qpack-06/h3");
+    fs::create_dir_all(&out_dir).unwrap();
+    for qif in dir_entries(&corpus_dir().join("qifs")) {
+        let header_lists = read_qif(&qif);
+        let name = qif.file_stem().unwrap().to_str().unwrap();
+        for settings in SETTINGS {
+            let file = out_dir.join(format!("{}.out.{}", name, settings.suffix()));
+            fs::write(file, encode(&header_lists, settings)).unwrap();
+        }
+    }
+}
+
+/// Decodes an encoded file into its header lists, ordered by stream ID
+///
+/// Sections referencing entries the encoder stream did not bring yet are blocked, no more
+/// than the settings allow.
+fn decode(mut encoded: &[u8], settings: Settings) -> Result<Vec<Vec<HeaderField>>, String> {
+    let mut decoder = Decoder::new(settings.table_capacity).map_err(|e| e.to_string())?;
+    let mut encoder_stream = Vec::new();
+    let mut blocked = VecDeque::new();
+    let mut header_lists = Vec::new();
+
+    while encoded.has_remaining() {
+        if encoded.remaining() < 12 {
+            return Err("truncated block header".into());
+        }
+        let stream_id = encoded.get_u64();
+        let len = encoded.get_u32() as usize;
+        if encoded.remaining() < len {
+            return Err(format!("truncated block of stream {}", stream_id));
+        }
+        let data = &encoded[..len];
+        encoded.advance(len);
+
+        if stream_id == 0 {
+            encoder_stream.extend_from_slice(data);
+            let mut read = encoder_stream.as_slice();
+            decoder
+                .recv_encoder_instructions(&mut read)
+                .map_err(|e| format!("encoder stream: {}", e))?;
+            let consumed = encoder_stream.len() - read.len();
+            encoder_stream.drain(..consumed);
+
+            // Retry the blocked sections with the new entries
+            for (stream_id, data) in std::mem::take(&mut blocked) {
+                decode_section(
+                    &mut decoder,
+                    stream_id,
+                    data,
+                    &mut blocked,
+                    &mut header_lists,
+                )?;
+            }
+        } else {
+            decode_section(
+                &mut decoder,
+                stream_id,
+                data,
+                &mut blocked,
+                &mut header_lists,
+            )?;
+        }
+        if blocked.len() > settings.blocked_streams {
+            return Err(format!("{} streams blocked", blocked.len()));
+        }
+    }
+
+    if !blocked.is_empty() {
+        return Err(format!("{} sections never unblocked", blocked.len()));
+    }
+    if !encoder_stream.is_empty() {
+        return Err("truncated encoder stream".into());
+    }
+    header_lists.sort_by_key(|(stream_id, _)| *stream_id);
+    Ok(header_lists.into_iter().map(|(_, fields)| fields).collect())
+}
+
+fn decode_section<'a>(
+    decoder: &mut Decoder,
+    stream_id: u64,
+    data: &'a [u8],
+    blocked: &mut VecDeque<(u64, &'a [u8])>,
+    header_lists: &mut Vec<(u64, Vec<HeaderField>)>,
+) -> Result<(), String> {
+    match decoder.decode_header(&mut &data[..]) {
+        Ok(decoded) => header_lists.push((stream_id, decoded.fields)),
+        Err(DecoderError::MissingRefs(_)) => blocked.push_back((stream_id, data)),
+        Err(e) => return Err(format!("section of stream {}: {}", stream_id, e)),
+    }
+    Ok(())
+}
+
+/// Encodes header lists into the format of the corpus' encoded files
+///
+/// Each section is written before the encoder instructions it depends on, its stream is
+/// blocked until they are decoded.
+fn encode(header_lists: &[Vec<HeaderField>], settings: Settings) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder
+        .set_max_table_capacity(settings.table_capacity)
+        .unwrap();
+    encoder
+        .set_max_blocked_streams(settings.blocked_streams)
+        .unwrap();
+    // Acknowledges the sections and the entries as soon as they are encoded
+    let mut peer_decoder = Decoder::new(settings.table_capacity).unwrap();
+
+    let mut encoded = Vec::new();
+    let mut instructions = Vec::new();
+    if settings.table_capacity > 0 {
+        encoder
+            .set_table_capacity(settings.table_capacity, &mut instructions)
+            .unwrap();
+    }
+    for (i, fields) in header_lists.iter().enumerate() {
+        let stream_id = i as u64 + 1;
+        let mut block = Vec::new();
+        encoder
+            .encode(stream_id, &mut block, &mut instructions, fields)
+            .unwrap();
+        write_block(&mut encoded, stream_id, &block);
+        if !instructions.is_empty() {
+            write_block(&mut encoded, 0, &instructions);
+        }
+
+        if settings.immediate_ack {
+            let mut decoder_stream = Vec::new();
+            peer_decoder
+                .recv_encoder_instructions(&mut instructions.as_slice())
+                .unwrap();
+            let decoded = peer_decoder.decode_header(&mut block.as_slice()).unwrap();
+            peer_decoder.ack_section(stream_id, &decoded, &mut decoder_stream);
+            peer_decoder
+                .insert_count_increment(&mut decoder_stream)
+                .unwrap();
+            encoder
+                .on_decoder_recv(&mut decoder_stream.as_slice())
+                .unwrap();
+        }
+        instructions.clear();
+    }
+    encoded
+}
+
+fn write_block(encoded: &mut Vec<u8>, stream_id: u64, data: &[u8]) {
+    encoded.put_u64(stream_id);
+    encoded.put_u32(data.len() as u32);
+    encoded.put_slice(data);
+}
+
+/// Reads the header lists of a `.qif` file
+///
+/// The lists are separated by empty lines, each line holds a name and a value separated by a
+/// tab. Lines starting with `#` are comments.
+fn read_qif(path: &Path) -> Vec<Vec<HeaderField>> {
+    let content = fs::read_to_string(path).unwrap();
+    let mut header_lists = Vec::new();
+    let mut fields = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            if !fields.is_empty() {
+                header_lists.push(std::mem::take(&mut fields));
+            }
+            continue;
+        }
+        let (name, value) = line
+            .split_once('\t')
+            .unwrap_or_else(|| panic!("{}: bad line {:?}", path.display(), line));
+        fields.push(HeaderField::new(name.to_owned(), value.to_owned()));
+    }
+    if !fields.is_empty() {
+        header_lists.push(fields);
+    }
+    header_lists
+}
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/qifs")
+}
+
+/// The upstream header lists, if vendored, then the local ones
+fn qif_files() -> Vec<PathBuf> {
+    let mut files = dir_entries(&corpus_dir().join("qifs"));
+    files.extend(dir_entries(&corpus_dir().join("local")));
+    files
+}
+
+/// The sorted entries of `dir`, none when it does not exist
+fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => panic!("{}: {}", dir.display(), e),
+    };
+    let mut entries = entries
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+}